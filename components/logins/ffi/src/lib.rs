@@ -93,6 +93,44 @@ pub unsafe extern "C" fn sync15_passwords_state_new_with_hex_key(
     })
 }
 
+/// Changes the encryption key of the database. A null `old_key` encrypts an
+/// unencrypted database, and a null `new_key` decrypts an encrypted one.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_rekey(
+    handle: u64,
+    old_key: FfiStr<'_>,
+    new_key: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("sync15_passwords_rekey");
+    ENGINES.call_with_result_mut(error, handle, |state| {
+        state.rekey(old_key.as_opt_str(), new_key.as_opt_str())
+    })
+}
+
+/// Same as sync15_passwords_rekey, but automatically hex-encodes the keys,
+/// like sync15_passwords_state_new_with_hex_key. A key_len of 0 means the
+/// database is, or will be, unencrypted.
+#[no_mangle]
+pub unsafe extern "C" fn sync15_passwords_rekey_with_hex_key(
+    handle: u64,
+    old_key: *const u8,
+    old_key_len: u32,
+    new_key: *const u8,
+    new_key_len: u32,
+    error: &mut ExternError,
+) {
+    log::debug!("sync15_passwords_rekey_with_hex_key");
+    ENGINES.call_with_result_mut(error, handle, |state| {
+        let old_key = bytes_to_key_string(old_key, old_key_len as usize);
+        let new_key = bytes_to_key_string(new_key, new_key_len as usize);
+        state.rekey(
+            old_key.as_ref().map(String::as_str),
+            new_key.as_ref().map(String::as_str),
+        )
+    })
+}
+
 // indirection to help `?` figure out the target error type
 fn parse_url(url: &str) -> sync15::Result<url::Url> {
     Ok(url::Url::parse(url)?)
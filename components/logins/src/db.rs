@@ -6,7 +6,7 @@ use crate::error::*;
 use crate::login::{LocalLogin, Login, MirrorLogin, SyncLoginData, SyncStatus};
 use crate::schema;
 use crate::update_plan::UpdatePlan;
-use crate::util;
+use crate::util::{self, path_with_suffix, remove_file_if_exists};
 use lazy_static::lazy_static;
 use rusqlite::{
     types::{FromSql, ToSql},
@@ -14,8 +14,9 @@ use rusqlite::{
 };
 use sql_support::{self, ConnExt};
 use std::collections::HashSet;
+use std::fs;
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::time::SystemTime;
 use sync15::{
@@ -23,6 +24,16 @@ use sync15::{
     Store,
 };
 
+// SQLcipher pre-4.0.0 compatibility. Using SHA1 still is less than ideal, but
+// should be fine. Real uses of this (lockbox, etc) use a real random string
+// for the encryption key, so the reduced KDF iteration count is fine.
+const CIPHER_PRAGMAS: &[&str] = &[
+    "cipher_page_size = 1024",
+    "kdf_iter = 64000",
+    "cipher_hmac_algorithm = HMAC_SHA1",
+    "cipher_kdf_algorithm = PBKDF2_HMAC_SHA1",
+];
+
 pub struct LoginDb {
     pub db: Connection,
 }
@@ -44,18 +55,14 @@ impl LoginDb {
                 "
                 PRAGMA key = '{}';
                 PRAGMA secure_delete = true;
-
-                -- SQLcipher pre-4.0.0 compatibility. Using SHA1 still
-                -- is less than ideal, but should be fine. Real uses of
-                -- this (lockbox, etc) use a real random string for the
-                -- encryption key, so the reduced KDF iteration count
-                -- is fine.
-                PRAGMA cipher_page_size = 1024;
-                PRAGMA kdf_iter = 64000;
-                PRAGMA cipher_hmac_algorithm = HMAC_SHA1;
-                PRAGMA cipher_kdf_algorithm = PBKDF2_HMAC_SHA1;
+                {}
             ",
-                sql_support::escape_string_for_pragma(key)
+                sql_support::escape_string_for_pragma(key),
+                CIPHER_PRAGMAS
+                    .iter()
+                    .map(|pragma| format!("PRAGMA {};", pragma))
+                    .collect::<Vec<_>>()
+                    .join("\n")
             )
         } else {
             "".to_owned()
@@ -101,6 +108,96 @@ impl LoginDb {
             .execute_batch("PRAGMA cipher_memory_security = false;")?;
         Ok(())
     }
+
+    /// Changes the encryption key of the database from `old_key` to
+    /// `new_key`. Passing `None` for `old_key` encrypts an unencrypted
+    /// database, and `None` for `new_key` decrypts an encrypted one. Fails
+    /// with `WrongEncryptionKey` if `old_key` is wrong. On failure, the
+    /// database and this connection are left unchanged.
+    pub fn rekey(&mut self, old_key: Option<&str>, new_key: Option<&str>) -> Result<()> {
+        let path = match self.path()? {
+            Some(path) => path,
+            None => return Err(ErrorKind::CannotRekeyMemoryDatabase.into()),
+        };
+        // SQLCipher doesn't tell us the current key, so check the old one
+        // by opening the database with it.
+        LoginDb::open(&path, old_key).map_err(|e| match e.kind() {
+            ErrorKind::SqlError(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::NotADatabase =>
+            {
+                ErrorKind::WrongEncryptionKey.into()
+            }
+            _ => e,
+        })?;
+        if old_key == new_key {
+            return Ok(());
+        }
+        if let (Some(_), Some(new_key)) = (old_key, new_key) {
+            self.execute_batch(&format!(
+                "PRAGMA rekey = '{}';",
+                sql_support::escape_string_for_pragma(new_key)
+            ))?;
+            return Ok(());
+        }
+
+        // SQLCipher can only encrypt or decrypt a database by exporting it
+        // to a new one, which then replaces the old database.
+        let export_path = path_with_suffix(&path, ".rekey");
+        let pragmas = if new_key.is_some() {
+            CIPHER_PRAGMAS
+        } else {
+            &[]
+        };
+        let exported = remove_file_if_exists(&export_path).and_then(|()| {
+            sql_support::export_database(&self.db, &export_path, new_key, pragmas)
+                .map_err(Error::from)
+        });
+        if let Err(e) = exported {
+            let _ = fs::remove_file(&export_path);
+            return Err(e);
+        }
+        // Keep the original database around until we've opened the new
+        // one, so that we can put it back if anything goes wrong. Our
+        // connection stays usable while the file is renamed.
+        let backup_path = path_with_suffix(&path, ".rekey-old");
+        let moved_aside = remove_file_if_exists(&backup_path)
+            .and_then(|()| fs::rename(&path, &backup_path).map_err(Error::from));
+        if let Err(e) = moved_aside {
+            let _ = fs::remove_file(&export_path);
+            return Err(e);
+        }
+        let replaced = fs::rename(&export_path, &path)
+            .map_err(Error::from)
+            .and_then(|()| LoginDb::open(&path, new_key));
+        match replaced {
+            Ok(db) => {
+                *self = db;
+                let _ = fs::remove_file(&backup_path);
+                Ok(())
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&export_path);
+                let _ = fs::remove_file(&path);
+                fs::rename(&backup_path, &path)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the path to the database file, or `None` if it's in memory.
+    fn path(&self) -> Result<Option<PathBuf>> {
+        let file: String = self.query_row_and_then_named(
+            "SELECT file FROM pragma_database_list WHERE name = 'main'",
+            &[],
+            |row| row.get_checked(0),
+            false,
+        )?;
+        Ok(if file.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(file))
+        })
+    }
 }
 
 impl ConnExt for LoginDb {
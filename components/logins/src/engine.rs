@@ -77,6 +77,11 @@ impl PasswordEngine {
         self.db.disable_mem_security()
     }
 
+    /// Changes the encryption key of the database. See `LoginDb::rekey`.
+    pub fn rekey(&mut self, old_key: Option<&str>, new_key: Option<&str>) -> Result<()> {
+        self.db.rekey(old_key, new_key)
+    }
+
     // This is basically exposed just for sync_pass_sql, but it doesn't seem
     // unreasonable.
     pub fn conn(&self) -> &rusqlite::Connection {
@@ -234,6 +239,77 @@ mod test {
         // Should be two even though we updated twice
         assert_eq!(b_after_update.times_used, 2);
     }
+
+    #[test]
+    fn test_rekey() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("logins.sqlite");
+        let login = Login {
+            id: "aaaaaaaaaaaa".into(),
+            hostname: "https://www.example.com".into(),
+            form_submit_url: Some("https://www.example.com/login".into()),
+            username: "coolperson21".into(),
+            password: "p4ssw0rd".into(),
+            username_field: "user_input".into(),
+            password_field: "pass_input".into(),
+            ..Login::default()
+        };
+        let assert_has_login = |engine: &PasswordEngine| {
+            let fetched = engine
+                .get(&login.id)
+                .expect("Not to error getting login")
+                .expect("login to exist");
+            assert_logins_equiv(&login, &fetched);
+        };
+
+        {
+            let mut engine = PasswordEngine::new(&path, None).expect("should open unencrypted");
+            engine.add(login.clone()).expect("added login");
+
+            match engine
+                .rekey(Some("wrong"), Some("secret"))
+                .unwrap_err()
+                .kind()
+            {
+                ErrorKind::WrongEncryptionKey => {}
+                e => panic!("Wrong error: {:?}", e),
+            }
+            assert_has_login(&engine);
+            engine.rekey(None, Some("secret")).expect("should encrypt");
+            assert_has_login(&engine);
+            assert!(!dir.path().join("logins.sqlite.rekey").exists());
+            assert!(!dir.path().join("logins.sqlite.rekey-old").exists());
+            engine
+                .rekey(Some("secret"), Some("secret2"))
+                .expect("should change key");
+            assert_has_login(&engine);
+        }
+
+        assert!(
+            PasswordEngine::new(&path, Some("secret")).is_err(),
+            "should fail to open with the old key"
+        );
+        {
+            let mut engine =
+                PasswordEngine::new(&path, Some("secret2")).expect("should open with the new key");
+            assert_has_login(&engine);
+            engine.rekey(Some("secret2"), None).expect("should decrypt");
+            assert_has_login(&engine);
+        }
+
+        let engine = PasswordEngine::new(&path, None).expect("should open unencrypted");
+        assert_has_login(&engine);
+
+        let mut engine = PasswordEngine::new_in_memory(Some("secret")).unwrap();
+        match engine
+            .rekey(Some("secret"), Some("secret2"))
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::CannotRekeyMemoryDatabase => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+    }
 }
 
 #[test]
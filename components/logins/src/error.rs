@@ -91,6 +91,17 @@ pub enum ErrorKind {
 
     #[fail(display = "Error parsing URL: {}", _0)]
     UrlParseError(#[fail(cause)] url::ParseError),
+
+    #[fail(display = "IO error: {}", _0)]
+    IoError(#[fail(cause)] std::io::Error),
+
+    // Changing the key copies the database to a new file, and checks the old
+    // key by reopening it, which we can't do for memory databases.
+    #[fail(display = "Can't change the encryption key of an in-memory database")]
+    CannotRekeyMemoryDatabase,
+
+    #[fail(display = "The old encryption key is wrong")]
+    WrongEncryptionKey,
 }
 
 macro_rules! impl_from_error {
@@ -116,6 +127,7 @@ impl_from_error! {
     (JsonError, serde_json::Error),
     (UrlParseError, url::ParseError),
     (SqlError, rusqlite::Error),
+    (IoError, std::io::Error),
     (InvalidLogin, InvalidLogin)
 }
 
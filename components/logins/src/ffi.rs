@@ -34,6 +34,10 @@ pub mod error_codes {
 
     /// A request to the sync server failed.
     pub const NETWORK: i32 = 6;
+
+    /// Returned from `rekey()` when the old encryption key doesn't match the
+    /// database's key.
+    pub const WRONG_ENCRYPTION_KEY: i32 = 7;
 }
 
 fn get_code(err: &Error) -> ErrorCode {
@@ -60,6 +64,10 @@ fn get_code(err: &Error) -> ErrorCode {
             log::error!("Invalid login: {}", desc);
             ErrorCode::new(error_codes::INVALID_LOGIN)
         }
+        ErrorKind::WrongEncryptionKey => {
+            log::error!("Wrong encryption key");
+            ErrorCode::new(error_codes::WRONG_ENCRYPTION_KEY)
+        }
         // We can't destructure `err` without bringing in the libsqlite3_sys crate
         // (and I'd really rather not) so we can't put this in the match.
         ErrorKind::SqlError(rusqlite::Error::SqliteFailure(err, _))
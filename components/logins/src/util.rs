@@ -4,6 +4,7 @@
 
 use crate::error::*;
 use rusqlite::Row;
+use std::path::{Path, PathBuf};
 use std::time;
 use url::Url;
 
@@ -32,6 +33,22 @@ pub fn system_time_ms_i64(t: time::SystemTime) -> i64 {
     duration_ms_i64(t.duration_since(time::UNIX_EPOCH).unwrap_or_default())
 }
 
+/// Appends a suffix to a path, like SQLite does for the `-journal` file next
+/// to a database.
+pub(crate) fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Removes the file at `path`, if there is one.
+pub(crate) fn remove_file_if_exists(path: &Path) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
 // Unfortunately, there's not a better way to turn on logging in tests AFAICT
 #[cfg(test)]
 pub(crate) fn init_test_logging() {
@@ -227,6 +227,15 @@ internal interface LibPlacesFFI : Library {
         err: RustError.ByReference
     )
 
+    fun places_api_rekey(
+        handle: PlacesApiHandle,
+        old_key: String?,
+        new_key: String?,
+        out_err: RustError.ByReference
+    )
+
+    fun places_api_close(handle: PlacesApiHandle, out_err: RustError.ByReference)
+
     /** Destroy connection created using `places_connection_new` */
     fun places_connection_destroy(handle: PlacesConnectionHandle, out_err: RustError.ByReference)
 
@@ -57,6 +57,35 @@ class PlacesApi(path: String, encryptionKey: String? = null) : PlacesManager, Au
         return writeConn
     }
 
+    /**
+     * Changes the key used to encrypt the database. A null [oldKey] encrypts
+     * an unencrypted database, and a null [newKey] decrypts an encrypted one.
+     *
+     * Reader connections should be closed first, since they can't read the
+     * database once its key changes. The writer returned by [getWriter] stays
+     * usable.
+     *
+     * @throws WrongEncryptionKey if [oldKey] isn't the current key.
+     */
+    @Synchronized
+    fun rekey(oldKey: String?, newKey: String?) {
+        // The write connection has to be returned to change the key, so we
+        // give the writer a new one afterwards.
+        val writeHandle = this.writeConn.takeHandle()
+        try {
+            rustCall(this) { error ->
+                LibPlacesFFI.INSTANCE.places_api_return_write_conn(handle.get(), writeHandle, error)
+            }
+            rustCall(this) { error ->
+                LibPlacesFFI.INSTANCE.places_api_rekey(handle.get(), oldKey, newKey, error)
+            }
+        } finally {
+            writeConn.setHandle(rustCall(this) { error ->
+                LibPlacesFFI.INSTANCE.places_connection_new(handle.get(), READ_WRITE, error)
+            })
+        }
+    }
+
     @Synchronized
     override fun close() {
         // Take the write connection's handle and clear its reference to us.
@@ -73,6 +102,15 @@ class PlacesApi(path: String, encryptionKey: String? = null) : PlacesManager, Au
                     // Ignore it.
                 }
             }
+            // Close the database now, instead of when the last reference to
+            // it goes away, so that it can be opened again right away.
+            try {
+                rustCall(this) { error ->
+                    LibPlacesFFI.INSTANCE.places_api_close(handle, error)
+                }
+            } catch (e: PlacesException) {
+                // Ignore it.
+            }
             rustCall(this) { error ->
                 LibPlacesFFI.INSTANCE.places_api_destroy(handle, error)
             }
@@ -440,6 +478,14 @@ class PlacesWriterConnection internal constructor(connHandle: Long, api: PlacesA
         interruptHandle.close()
         return handle
     }
+
+    @Synchronized
+    internal fun setHandle(newHandle: PlacesConnectionHandle) {
+        this.handle.set(newHandle)
+        interruptHandle = InterruptHandle(rustCall { err ->
+            LibPlacesFFI.INSTANCE.places_new_interrupt_handle(newHandle, err)
+        }!!)
+    }
 }
 
 /**
@@ -677,6 +723,12 @@ open class UrlParseFailed(msg: String) : PlacesException(msg)
 open class PlacesConnectionBusy(msg: String) : PlacesException(msg)
 open class OperationInterrupted(msg: String) : PlacesException(msg)
 
+/**
+ * Thrown when changing the encryption key with the wrong old key, or when
+ * opening a database that's already open with a different key.
+ */
+open class WrongEncryptionKey(msg: String) : PlacesException(msg)
+
 @SuppressWarnings("MagicNumber")
 enum class VisitType(val type: Int) {
     /** This isn't a visit, but a request to update meta data about a page */
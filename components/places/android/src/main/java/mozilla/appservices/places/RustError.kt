@@ -51,6 +51,7 @@ internal open class RustError : Structure() {
             3 -> return PlacesConnectionBusy(message)
             4 -> return OperationInterrupted(message)
             5 -> return BookmarksCorruption(message)
+            6 -> return WrongEncryptionKey(message)
 
             64 -> return InvalidParent(message)
             65 -> return UnknownBookmarkItem(message)
@@ -11,6 +11,7 @@ import org.robolectric.RobolectricTestRunner
 import org.robolectric.annotation.Config
 import org.junit.Test
 import org.junit.Assert.assertEquals
+import org.junit.Assert.fail
 import org.junit.Before
 
 @RunWith(RobolectricTestRunner::class)
@@ -20,12 +21,14 @@ class PlacesConnectionTest {
     @JvmField
     val dbFolder = TemporaryFolder()
 
+    lateinit var dbPath: String
     lateinit var api: PlacesApi
     lateinit var db: PlacesWriterConnection
 
     @Before
     fun initAPI() {
-        api = PlacesApi(path = dbFolder.newFile().absolutePath)
+        dbPath = dbFolder.newFile().absolutePath
+        api = PlacesApi(path = dbPath)
         db = api.getWriter()
     }
 
@@ -106,6 +109,33 @@ class PlacesConnectionTest {
         }
     }
 
+    @Test
+    fun testRekey() {
+        db.noteObservation(VisitObservation(url = "https://www.example.com/", visitType = VisitType.LINK))
+
+        api.rekey(oldKey = null, newKey = "key")
+        // The writer should still work after changing the key.
+        assertEquals(listOf(true), db.getVisited(listOf("https://www.example.com/")))
+
+        try {
+            api.rekey(oldKey = "wrong key", newKey = null)
+            fail("Should have thrown")
+        } catch (e: WrongEncryptionKey) {
+            // Expected.
+        }
+
+        // The database is already open with a different key.
+        try {
+            PlacesApi(path = dbPath, encryptionKey = "other key")
+            fail("Should have thrown")
+        } catch (e: WrongEncryptionKey) {
+            // Expected.
+        }
+
+        api.rekey(oldKey = "key", newKey = null)
+        assertEquals(listOf(true), db.getVisited(listOf("https://www.example.com/")))
+    }
+
     @Test
     fun testNoteObservationBadUrl() {
         try {
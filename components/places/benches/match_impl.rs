@@ -36,5 +36,54 @@ fn bench_match_anywhere(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_match_anywhere);
+fn bench_match_unicode(c: &mut Criterion) {
+    c.bench_function("match anywhere title folded", |b| {
+        let matcher = AutocompleteMatch {
+            search_str: "strasse",
+            url_str: "https://de.wikipedia.org/wiki/Stra%C3%9Fe",
+            title_str: "Straße – Wikipedia",
+            tags: "",
+            visit_count: 100,
+            typed: false,
+            bookmarked: false,
+            open_page_count: 0,
+            match_behavior: MatchBehavior::Anywhere,
+            search_behavior: SearchBehavior::default(),
+        };
+        b.iter(|| matcher.invoke())
+    });
+    c.bench_function("match boundary title diacritics", |b| {
+        let matcher = AutocompleteMatch {
+            search_str: "οδυσσεια notpresent",
+            url_str:
+                "https://el.wikipedia.org/wiki/%CE%9F%CE%B4%CF%8D%CF%83%CF%83%CE%B5%CE%B9%CE%B1",
+            title_str: "Οδύσσεια – Βικιπαίδεια",
+            tags: "",
+            visit_count: 100,
+            typed: false,
+            bookmarked: false,
+            open_page_count: 0,
+            match_behavior: MatchBehavior::Boundary,
+            search_behavior: SearchBehavior::default(),
+        };
+        b.iter(|| matcher.invoke())
+    });
+    c.bench_function("match boundary title cjk", |b| {
+        let matcher = AutocompleteMatch {
+            search_str: "世界",
+            url_str: "https://zh.wikipedia.org/wiki/%E4%B8%96%E7%95%8C",
+            title_str: "你好世界 - 维基百科，自由的百科全书",
+            tags: "",
+            visit_count: 100,
+            typed: false,
+            bookmarked: false,
+            open_page_count: 0,
+            match_behavior: MatchBehavior::Boundary,
+            search_behavior: SearchBehavior::default(),
+        };
+        b.iter(|| matcher.invoke())
+    });
+}
+
+criterion_group!(benches, bench_match_anywhere, bench_match_unicode);
 criterion_main!(benches);
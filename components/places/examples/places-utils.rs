@@ -5,10 +5,11 @@
 #![allow(unknown_lints)]
 
 use cli_support::fxa_creds::{get_cli_fxa, get_default_fxa_config};
+use places::api::places_api::GLOBAL_STATE_META_KEY;
 use places::bookmark_sync::store::BookmarksStore;
 use places::history_sync::store::HistoryStore;
 use places::storage::bookmarks::{
-    fetch_tree, insert_tree, BookmarkNode, BookmarkRootGuid, BookmarkTreeNode, FolderNode,
+    self, fetch_tree, insert_tree, BookmarkNode, BookmarkRootGuid, BookmarkTreeNode, FolderNode,
     SeparatorNode,
 };
 use places::types::{BookmarkType, SyncGuid, Timestamp};
@@ -19,7 +20,7 @@ use serde_derive::*;
 use sql_support::ConnExt;
 use std::cell::Cell;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Read, Write};
 use structopt::StructOpt;
 use sync15::{sync_multiple, telemetry, Store};
 use url::Url;
@@ -157,6 +158,31 @@ fn run_native_export(db: &PlacesDb, filename: String) -> Result<()> {
     Ok(())
 }
 
+fn run_html_import(db: &PlacesDb, filename: String) -> Result<()> {
+    println!("import from {}", filename);
+
+    let mut html = String::new();
+    File::open(filename)?.read_to_string(&mut html)?;
+    bookmarks::html::import_html(db, &html)?;
+    Ok(())
+}
+
+fn run_html_export(db: &PlacesDb, filename: String) -> Result<()> {
+    println!("export to {}", filename);
+
+    let html = bookmarks::html::export_html(db)?;
+    File::create(filename)?.write_all(html.as_bytes())?;
+    Ok(())
+}
+
+fn run_places_import(db: &PlacesDb, filename: String) -> Result<()> {
+    println!("import from {}", filename);
+
+    let metrics = places::import::import_desktop_places(db, filename)?;
+    println!("imported {:#?}", metrics);
+    Ok(())
+}
+
 fn sync(
     api: &PlacesApi,
     mut engine_names: Vec<String>,
@@ -200,10 +226,7 @@ fn sync(
     }
 
     // now the syncs.
-    // XXX - unfortunately, history stores global meta in a `history_global_state`,
-    // but that's a global that should be shared between history and bookmarks.
-    // We should consider changing that key name?
-    // Even more unfortunate, places::storage::get_meta is `pub(crate)`, so we
+    // XXX - unfortunately, places::storage::get_meta is `pub(crate)`, so we
     // can't use it here.
     // Ultimately though, this really needs to be on PlacesApi.
     use rusqlite::types::{FromSql, ToSql};
@@ -224,7 +247,7 @@ fn sync(
         Ok(res)
     }
 
-    let meta_key_name = "history_global_state";
+    let meta_key_name = GLOBAL_STATE_META_KEY;
     let global_state: Cell<Option<String>> = Cell::new(get_meta(&conn, meta_key_name)?);
     let client_info = Cell::new(None);
     let mut sync_ping = telemetry::SyncTelemetryPing::new();
@@ -323,6 +346,30 @@ enum Command {
         /// Imports bookmarks from a desktop export
         input_file: String,
     },
+
+    #[structopt(name = "import-html-bookmarks")]
+    /// Import bookmarks from a `bookmarks.html` file, as exported by most browsers
+    ImportHtmlBookmarks {
+        #[structopt(name = "input-file", long, short = "i")]
+        /// The name of the file to read.
+        input_file: String,
+    },
+
+    #[structopt(name = "export-html-bookmarks")]
+    /// Exports bookmarks as a `bookmarks.html` file, which most browsers can import
+    ExportHtmlBookmarks {
+        #[structopt(name = "output-file", long, short = "o")]
+        /// The name of the output file where the html will be written.
+        output_file: String,
+    },
+
+    #[structopt(name = "import-desktop-places")]
+    /// Import history and bookmarks from a desktop `places.sqlite`
+    ImportDesktopPlaces {
+        #[structopt(name = "input-file", long, short = "i")]
+        /// The path to the `places.sqlite` file to read.
+        input_file: String,
+    },
 }
 
 fn main() -> Result<()> {
@@ -346,5 +393,8 @@ fn main() -> Result<()> {
         Command::ExportBookmarks { output_file } => run_native_export(&db, output_file),
         Command::ImportBookmarks { input_file } => run_native_import(&db, input_file),
         Command::ImportDesktopBookmarks { input_file } => run_desktop_import(&db, input_file),
+        Command::ImportHtmlBookmarks { input_file } => run_html_import(&db, input_file),
+        Command::ExportHtmlBookmarks { output_file } => run_html_export(&db, output_file),
+        Command::ImportDesktopPlaces { input_file } => run_places_import(&db, input_file),
     }
 }
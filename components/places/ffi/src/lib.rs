@@ -11,15 +11,25 @@ use ffi_support::{
     define_box_destructor, define_bytebuffer_destructor, define_handle_map_deleter,
     define_string_destructor, ByteBuffer, ConcurrentHandleMap, ExternError, FfiStr,
 };
+use places::backup;
 use places::error::*;
 use places::msg_types::BookmarkNodeList;
+use places::observers::{
+    BookmarkEvent, BookmarkObserver, ChangeSource, HistoryEvent, HistoryObserver, ObserverId,
+};
 use places::storage::bookmarks;
-use places::types::SyncGuid;
-use places::{db::PlacesInterruptHandle, storage, ConnectionType, PlacesApi, PlacesDb};
+use places::types::{SyncGuid, VisitTransitionSet};
+use places::{
+    db::PlacesInterruptHandle, storage, CancellationToken, ConnectionType, PlacesApi, PlacesDb,
+};
+use std::ffi::CString;
 use std::os::raw::c_char;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use places::api::matcher::{match_url, search_frecent, SearchParams};
+use places::api::matcher::{match_url, search_frecent, search_frecent_cancellable, SearchParams};
+use places::api::{keywords, tags};
 
 // indirection to help `?` figure out the target error type
 fn parse_url(url: &str) -> sync15::Result<url::Url> {
@@ -61,6 +71,44 @@ pub extern "C" fn places_api_new(
     })
 }
 
+/// Instantiate a places API, checking the integrity of the database first,
+/// and recovering it if it's corrupt. Returned api must be freed with
+/// `places_api_destroy`.
+#[no_mangle]
+pub extern "C" fn places_api_new_with_integrity_check(
+    db_path: FfiStr<'_>,
+    encryption_key: FfiStr<'_>,
+    error: &mut ExternError,
+) -> u64 {
+    log::debug!("places_api_new_with_integrity_check");
+    APIS.insert_with_result(error, || {
+        let path = db_path.as_str();
+        let key = encryption_key.as_opt_str();
+        PlacesApi::new_with_integrity_check(path, key)
+    })
+}
+
+/// Instantiate a places API that calculates frecencies using custom settings.
+/// `frecency_settings_json` is a `FrecencySettings` represented as JSON; any
+/// missing settings use their default values. Returned api must be freed with
+/// `places_api_destroy`.
+#[no_mangle]
+pub extern "C" fn places_api_new_with_frecency_settings(
+    db_path: FfiStr<'_>,
+    encryption_key: FfiStr<'_>,
+    frecency_settings_json: FfiStr<'_>,
+    error: &mut ExternError,
+) -> u64 {
+    log::debug!("places_api_new_with_frecency_settings");
+    APIS.insert_with_result(error, || -> places::Result<_> {
+        let path = db_path.as_str();
+        let key = encryption_key.as_opt_str();
+        let settings: places::frecency::FrecencySettings =
+            serde_json::from_str(frecency_settings_json.as_str())?;
+        PlacesApi::new_with_frecency_settings(path, key, &settings)
+    })
+}
+
 /// Instantiate a places connection. Returned connection must be freed with
 /// `places_connection_destroy`, although it should be noted that this will
 /// not destroy, or even close, connections already obtained from this
@@ -106,6 +154,64 @@ pub extern "C" fn places_api_return_write_conn(
     })
 }
 
+/// Changes the encryption key of the database. A null `old_key` encrypts an
+/// unencrypted database, and a null `new_key` decrypts an encrypted one. The
+/// write connection must have been returned with
+/// `places_api_return_write_conn`, and other connections should be closed.
+#[no_mangle]
+pub extern "C" fn places_api_rekey(
+    handle: u64,
+    old_key: FfiStr<'_>,
+    new_key: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("places_api_rekey");
+    APIS.call_with_result(error, handle, |api| {
+        api.rekey(old_key.as_opt_str(), new_key.as_opt_str())
+    })
+}
+
+/// Creates or drops the full-text index using the API's read-write
+/// connection. Fails if that connection is checked out, in which case use
+/// `places_set_use_fts_index` with its handle instead.
+#[no_mangle]
+pub extern "C" fn places_api_set_use_fts_index(
+    handle: u64,
+    enabled: u8, // JNA has issues with bools...
+    error: &mut ExternError,
+) {
+    log::debug!("places_api_set_use_fts_index");
+    APIS.call_with_result(error, handle, |api| api.set_use_fts_index(enabled != 0))
+}
+
+/// Closes the API, so that the database can be opened again, possibly with a
+/// different key. The write connection must have been returned with
+/// `places_api_return_write_conn`, and read-only connections obtained from
+/// this API must be destroyed first, or this fails with an error and the API
+/// stays open. The handle must still be freed with `places_api_destroy`.
+#[no_mangle]
+pub extern "C" fn places_api_close(handle: u64, error: &mut ExternError) {
+    log::debug!("places_api_close");
+    APIS.call_with_result(error, handle, |api| api.close())
+}
+
+/// Returns a `RecoveryReport` as JSON if the database was corrupt when the
+/// API was created, or null if it wasn't. Returned string must be freed
+/// using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn places_api_get_recovery_report(
+    handle: u64,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_api_get_recovery_report");
+    APIS.call_with_result(error, handle, |api| -> places::Result<_> {
+        Ok(match api.recovery_report() {
+            Some(report) => Some(serde_json::to_string(report)?),
+            None => None,
+        })
+    })
+}
+
 /// Get the interrupt handle for a connection. Must be destroyed with
 /// `places_interrupt_handle_destroy`.
 #[no_mangle]
@@ -159,6 +265,136 @@ pub extern "C" fn places_query_autocomplete(
     })
 }
 
+/// Like `places_query_autocomplete`, but runs the query on a read-only
+/// connection from the API's pool, so concurrent queries don't need their own
+/// connection handles. Returned string must be freed using
+/// `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn places_api_query_autocomplete(
+    handle: u64,
+    search: FfiStr<'_>,
+    limit: u32,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_api_query_autocomplete");
+    APIS.call_with_result(error, handle, |api| -> places::Result<_> {
+        let conn = api.checkout_reader()?;
+        let res = search_frecent(
+            &conn,
+            SearchParams {
+                search_string: search.into_string(),
+                limit,
+            },
+        )?;
+        Ok(serde_json::to_string(&res)?)
+    })
+}
+
+/// Like `places_api_query_autocomplete`, but the query can be stopped with
+/// `places_cancel`, without affecting other queries. Returns a
+/// `CancellableSearchResults` as a JSON string; a cancelled query returns the
+/// results found so far, with `partial` set. Returned string must be freed
+/// using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn places_api_query_autocomplete_cancellable(
+    handle: u64,
+    search: FfiStr<'_>,
+    limit: u32,
+    token: &CancellationToken,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_api_query_autocomplete_cancellable");
+    APIS.call_with_result(error, handle, |api| -> places::Result<_> {
+        let conn = api.checkout_reader()?;
+        let res = search_frecent_cancellable(
+            &conn,
+            SearchParams {
+                search_string: search.into_string(),
+                limit,
+            },
+            token,
+        )?;
+        Ok(serde_json::to_string(&res)?)
+    })
+}
+
+/// Create a token for cancelling a single query. Must be destroyed with
+/// `places_cancellation_token_destroy`, after the query has finished.
+#[no_mangle]
+pub extern "C" fn places_new_cancellation_token(error: &mut ExternError) -> *mut CancellationToken {
+    ffi_support::call_with_output(error, CancellationToken::new)
+}
+
+#[no_mangle]
+pub extern "C" fn places_cancel(token: &CancellationToken, error: &mut ExternError) {
+    ffi_support::call_with_output(error, || token.cancel())
+}
+
+/// Like `places_match_url`, but runs the query on a read-only connection from
+/// the API's pool. Returned string must be freed using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn places_api_match_url(
+    handle: u64,
+    search: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_api_match_url");
+    APIS.call_with_result(error, handle, |api| -> places::Result<_> {
+        let conn = api.checkout_reader()?;
+        match_url(&conn, search.as_str())
+    })
+}
+
+/// Sets how long, in milliseconds, the read-write connection remembers visited
+/// urls, so that reloads of them aren't recorded as new visits. Pass 0 to
+/// record every visit.
+#[no_mangle]
+pub extern "C" fn places_api_set_recent_visit_window(
+    handle: u64,
+    window_ms: u64,
+    error: &mut ExternError,
+) {
+    log::debug!("places_api_set_recent_visit_window");
+    APIS.call_with_output(error, handle, |api| {
+        api.set_recent_visit_window(Duration::from_millis(window_ms))
+    })
+}
+
+/// Sets the maximum number of read-only connections the API keeps in its pool
+/// for `places_api_query_autocomplete` and `places_api_match_url`.
+#[no_mangle]
+pub extern "C" fn places_api_set_reader_pool_size(handle: u64, size: u32, error: &mut ExternError) {
+    log::debug!("places_api_set_reader_pool_size");
+    APIS.call_with_output(error, handle, |api| api.set_reader_pool_size(size as usize))
+}
+
+/// Sets how long, in milliseconds, `places_api_query_autocomplete` and
+/// `places_api_match_url` wait for a pooled reader to be returned, if all of
+/// them are in use, before failing with `DATABASE_BUSY`.
+#[no_mangle]
+pub extern "C" fn places_api_set_reader_checkout_timeout(
+    handle: u64,
+    timeout_ms: u64,
+    error: &mut ExternError,
+) {
+    log::debug!("places_api_set_reader_checkout_timeout");
+    APIS.call_with_output(error, handle, |api| {
+        api.set_reader_checkout_timeout(Duration::from_millis(timeout_ms))
+    })
+}
+
+/// Get an interrupt handle that interrupts the queries running on the API's
+/// pooled readers when it's passed to `places_interrupt`. Must be destroyed
+/// with `places_interrupt_handle_destroy`.
+#[no_mangle]
+pub extern "C" fn places_api_new_reader_interrupt_handle(
+    handle: u64,
+    error: &mut ExternError,
+) -> *mut PlacesInterruptHandle {
+    log::debug!("places_api_new_reader_interrupt_handle");
+    APIS.call_with_output(error, handle, |api| api.new_reader_interrupt_handle())
+}
+
 /// Execute a query, returning a URL string or null. Returned string must be freed
 /// using `places_destroy_string`. Returns null if no match is found.
 #[no_mangle]
@@ -282,7 +518,83 @@ pub extern "C" fn places_wipe_local(handle: u64, error: &mut ExternError) {
 #[no_mangle]
 pub extern "C" fn places_run_maintenance(handle: u64, error: &mut ExternError) {
     log::debug!("places_run_maintenance");
-    CONNECTIONS.call_with_result(error, handle, |conn| storage::run_maintenance(conn))
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        storage::run_maintenance(conn)?;
+        Ok(())
+    })
+}
+
+/// Like `places_run_maintenance`, but returns a `BookmarkRepairs` describing
+/// what was wrong with the bookmarks tree as JSON, which must be freed using
+/// `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn places_run_maintenance_with_report(
+    handle: u64,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_run_maintenance_with_report");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let repairs = storage::run_maintenance(conn)?;
+        Ok(serde_json::to_string(&repairs)?)
+    })
+}
+
+/// Expires old history until the database is within the limits of the
+/// `ExpirationPolicy` given as JSON, or until `budget_ms` milliseconds have
+/// passed. Returns an `ExpirationResult` as JSON, which must be freed using
+/// `places_destroy_string`. Can be interrupted with `places_interrupt`.
+#[no_mangle]
+pub extern "C" fn places_expire_history(
+    handle: u64,
+    policy_json: FfiStr<'_>,
+    budget_ms: i64,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_expire_history");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let policy: storage::expiration::ExpirationPolicy =
+            serde_json::from_str(policy_json.as_str())?;
+        let result = storage::expiration::expire_history(
+            conn,
+            &policy,
+            std::time::Duration::from_millis(budget_ms.max(0) as u64),
+        )?;
+        Ok(serde_json::to_string(&result)?)
+    })
+}
+
+/// Recalculates up to `limit` stale frecencies, stopping early once
+/// `deadline` (in milliseconds since the epoch) passes, and returns the number
+/// of stale frecencies that remain. Can be interrupted with `places_interrupt`.
+/// Fails with `InvalidConnectionType` if `handle` is a read-only connection.
+#[no_mangle]
+pub extern "C" fn places_recalculate_stale_frecencies(
+    handle: u64,
+    limit: u32,
+    deadline: i64,
+    error: &mut ExternError,
+) -> u32 {
+    log::debug!("places_recalculate_stale_frecencies");
+    CONNECTIONS.call_with_result(error, handle, |conn| {
+        storage::history::recalculate_stale_frecencies(
+            conn,
+            limit,
+            places::Timestamp(deadline.max(0) as u64),
+        )
+    })
+}
+
+/// Creates or drops the full-text index, which all searches use when it
+/// exists. `handle` must be the read-write connection. See
+/// `PlacesDb::set_use_fts_index`.
+#[no_mangle]
+pub extern "C" fn places_set_use_fts_index(
+    handle: u64,
+    enabled: u8, // JNA has issues with bools...
+    error: &mut ExternError,
+) {
+    log::debug!("places_set_use_fts_index");
+    CONNECTIONS.call_with_result(error, handle, |conn| conn.set_use_fts_index(enabled != 0))
 }
 
 #[no_mangle]
@@ -318,6 +630,239 @@ pub extern "C" fn places_get_visit_infos(
     })
 }
 
+/// Returns up to `count` visits, newest first, as a
+/// `HistoryVisitInfosWithBound` protobuf. `excluded_types` is a bitfield of
+/// `1 << visit_type` for the visit types to skip. See
+/// `places::storage::history::get_visit_page`.
+#[no_mangle]
+pub extern "C" fn places_get_visit_page(
+    handle: u64,
+    bound: i64,
+    offset: i64,
+    count: i64,
+    excluded_types: i32,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("places_get_visit_page");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        Ok(storage::history::get_visit_page(
+            conn,
+            places::Timestamp(bound.max(0) as u64),
+            offset.max(0),
+            count.max(0),
+            VisitTransitionSet::from_u16(excluded_types as u16),
+        )?)
+    })
+}
+
+/// Like `places_get_visit_page`, but returns the visits grouped by day, as a
+/// `HistoryVisitGroups` protobuf. Days start at midnight in the time zone
+/// `utc_offset_minutes` ahead of UTC.
+#[no_mangle]
+pub extern "C" fn places_get_visit_page_by_day(
+    handle: u64,
+    bound: i64,
+    offset: i64,
+    count: i64,
+    excluded_types: i32,
+    utc_offset_minutes: i32,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("places_get_visit_page_by_day");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let page = storage::history::get_visit_page(
+            conn,
+            places::Timestamp(bound.max(0) as u64),
+            offset.max(0),
+            count.max(0),
+            VisitTransitionSet::from_u16(excluded_types as u16),
+        )?;
+        Ok(places::api::history::group_visits_by_day(
+            page,
+            utc_offset_minutes,
+        ))
+    })
+}
+
+/// Like `places_get_visit_page`, but returns the visits grouped by host, as a
+/// `HistoryVisitGroups` protobuf.
+#[no_mangle]
+pub extern "C" fn places_get_visit_page_by_host(
+    handle: u64,
+    bound: i64,
+    offset: i64,
+    count: i64,
+    excluded_types: i32,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("places_get_visit_page_by_host");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let page = storage::history::get_visit_page(
+            conn,
+            places::Timestamp(bound.max(0) as u64),
+            offset.max(0),
+            count.max(0),
+            VisitTransitionSet::from_u16(excluded_types as u16),
+        )?;
+        Ok(places::api::history::group_visits_by_host(page))
+    })
+}
+
+/// Returns up to `limit` top sites as a `TopFrecentSiteInfos` protobuf.
+/// `blocked_hosts_json` is an optional JSON array of hosts to skip. See
+/// `places::api::history::get_top_frecent_site_infos`.
+#[no_mangle]
+pub extern "C" fn places_get_top_frecent_site_infos(
+    handle: u64,
+    limit: i32,
+    frecency_threshold: i64,
+    blocked_hosts_json: FfiStr<'_>,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("places_get_top_frecent_site_infos");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let blocked_hosts: Vec<String> = match blocked_hosts_json.as_opt_str() {
+            Some(json) => serde_json::from_str(json)?,
+            None => Vec::new(),
+        };
+        Ok(places::api::history::get_top_frecent_site_infos(
+            conn,
+            limit.max(0) as u32,
+            frecency_threshold,
+            &blocked_hosts,
+        )?)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn places_tag_url(
+    handle: u64,
+    url: FfiStr<'_>,
+    tag: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("places_tag_url");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let url = parse_url(url.as_str())?;
+        tags::tag_url(conn, &url, tag.as_str())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn places_untag_url(
+    handle: u64,
+    url: FfiStr<'_>,
+    tag: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("places_untag_url");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let url = parse_url(url.as_str())?;
+        tags::untag_url(conn, &url, tag.as_str())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn places_get_tags_for_url(
+    handle: u64,
+    url: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_get_tags_for_url");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let url = parse_url(url.as_str())?;
+        let tags = tags::get_tags_for_url(conn, &url)?;
+        Ok(serde_json::to_string(&tags)?)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn places_get_urls_with_tag(
+    handle: u64,
+    tag: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_get_urls_with_tag");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let urls = tags::get_urls_with_tag(conn, tag.as_str())?
+            .into_iter()
+            .map(url::Url::into_string)
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_string(&urls)?)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn places_rename_tag(
+    handle: u64,
+    old_tag: FfiStr<'_>,
+    new_tag: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("places_rename_tag");
+    CONNECTIONS.call_with_result(error, handle, |conn| {
+        tags::rename_tag(conn, old_tag.as_str(), new_tag.as_str())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn places_delete_tag(handle: u64, tag: FfiStr<'_>, error: &mut ExternError) {
+    log::debug!("places_delete_tag");
+    CONNECTIONS.call_with_result(error, handle, |conn| tags::remove_tag(conn, tag.as_str()))
+}
+
+#[no_mangle]
+pub extern "C" fn places_set_keyword(
+    handle: u64,
+    url: FfiStr<'_>,
+    keyword: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("places_set_keyword");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let url = parse_url(url.as_str())?;
+        keywords::set_keyword(conn, &url, keyword.as_str())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn places_remove_keyword(handle: u64, keyword: FfiStr<'_>, error: &mut ExternError) {
+    log::debug!("places_remove_keyword");
+    CONNECTIONS.call_with_result(error, handle, |conn| {
+        keywords::remove_keyword(conn, keyword.as_str())
+    })
+}
+
+/// Returns the keyword for a URL, or null if it doesn't have one. Returned
+/// string must be freed using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn places_get_keyword_for_url(
+    handle: u64,
+    url: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_get_keyword_for_url");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let url = parse_url(url.as_str())?;
+        keywords::get_keyword_for_url(conn, &url)
+    })
+}
+
+/// Returns the URL for a keyword, or null if the keyword doesn't exist.
+/// Returned string must be freed using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn places_get_url_for_keyword(
+    handle: u64,
+    keyword: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("places_get_url_for_keyword");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let url = keywords::get_url_for_keyword(conn, keyword.as_str())?;
+        Ok(url.map(url::Url::into_string))
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_history_sync(
     handle: u64,
@@ -342,6 +887,57 @@ pub extern "C" fn sync15_history_sync(
     })
 }
 
+#[no_mangle]
+pub extern "C" fn sync15_bookmarks_sync(
+    handle: u64,
+    key_id: FfiStr<'_>,
+    access_token: FfiStr<'_>,
+    sync_key: FfiStr<'_>,
+    tokenserver_url: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("sync15_bookmarks_sync");
+    APIS.call_with_result(error, handle, |api| -> places::Result<_> {
+        // Note that api.sync_bookmarks returns a SyncPing which we drop on the floor.
+        api.sync_bookmarks(
+            &sync15::Sync15StorageClientInit {
+                key_id: key_id.into_string(),
+                access_token: access_token.into_string(),
+                tokenserver_url: parse_url(tokenserver_url.as_str())?,
+            },
+            &sync15::KeyBundle::from_ksync_base64(sync_key.as_str())?,
+        )?;
+        Ok(())
+    })
+}
+
+/// Syncs history and bookmarks together, so the token server and
+/// `meta/global` are only fetched once. If both fail, the history error is
+/// returned.
+#[no_mangle]
+pub extern "C" fn sync15_places_sync_all(
+    handle: u64,
+    key_id: FfiStr<'_>,
+    access_token: FfiStr<'_>,
+    sync_key: FfiStr<'_>,
+    tokenserver_url: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("sync15_places_sync_all");
+    APIS.call_with_result(error, handle, |api| -> places::Result<_> {
+        // Note that api.sync_all returns a SyncPing which we drop on the floor.
+        api.sync_all(
+            &sync15::Sync15StorageClientInit {
+                key_id: key_id.into_string(),
+                access_token: access_token.into_string(),
+                tokenserver_url: parse_url(tokenserver_url.as_str())?,
+            },
+            &sync15::KeyBundle::from_ksync_base64(sync_key.as_str())?,
+        )?;
+        Ok(())
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn bookmarks_get_tree(
     handle: u64,
@@ -458,9 +1054,182 @@ pub extern "C" fn bookmarks_search(
     })
 }
 
+/// Imports a Netscape `bookmarks.html` file, given as a string, into the
+/// bookmarks tree, including tags and keywords.
+#[no_mangle]
+pub extern "C" fn bookmarks_import_html(handle: u64, html: FfiStr<'_>, error: &mut ExternError) {
+    log::debug!("bookmarks_import_html");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        bookmarks::html::import_html(conn, html.as_str())
+    })
+}
+
+/// Exports all bookmarks as a Netscape `bookmarks.html` file. The result must
+/// be freed using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn bookmarks_export_html(handle: u64, error: &mut ExternError) -> *mut c_char {
+    log::debug!("bookmarks_export_html");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        bookmarks::html::export_html(conn)
+    })
+}
+
+/// Writes a backup of all bookmarks to the directory at `dir_path`, keeping
+/// at most `max_backups` backups. Returns the new backup's `BackupInfo` as
+/// JSON, which must be freed using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn bookmarks_create_backup(
+    handle: u64,
+    dir_path: FfiStr<'_>,
+    max_backups: u32,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("bookmarks_create_backup");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let info = backup::create_backup(conn, Path::new(dir_path.as_str()), max_backups as usize)?;
+        Ok(serde_json::to_string(&info)?)
+    })
+}
+
+/// Lists the backups in the directory at `dir_path`, newest first, as a JSON
+/// array of `BackupInfo`s, which must be freed using `places_destroy_string`.
+#[no_mangle]
+pub extern "C" fn bookmarks_list_backups(
+    dir_path: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("bookmarks_list_backups");
+    ffi_support::call_with_result(error, || -> places::Result<_> {
+        let backups = backup::list_backups(Path::new(dir_path.as_str()))?;
+        Ok(serde_json::to_string(&backups)?)
+    })
+}
+
+/// Replaces all bookmarks with the contents of the backup at `backup_path`,
+/// and resets the bookmark sync state.
+#[no_mangle]
+pub extern "C" fn bookmarks_restore_backup(
+    handle: u64,
+    backup_path: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("bookmarks_restore_backup");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        backup::restore_backup(conn, Path::new(backup_path.as_str()))
+    })
+}
+
+/// The type of the callback for bookmark observers. It's called with a JSON
+/// object like `{"source": "local", "events": [...]}`, where `source` is
+/// either `"local"` or `"sync"`, and `events` are the serialized
+/// `BookmarkEvent`s. The string must not be freed, and is only valid until
+/// the callback returns.
+///
+/// The callback is called on the thread that made the change, while the
+/// connection that made it is still in use, so it must not call back into
+/// places on that thread.
+pub type BookmarkObserverCallback = unsafe extern "C" fn(*const c_char);
+
+struct FfiBookmarkObserver(BookmarkObserverCallback);
+
+impl BookmarkObserver for FfiBookmarkObserver {
+    fn on_bookmark_events(&self, source: ChangeSource, events: &[BookmarkEvent]) {
+        let json = serde_json::json!({
+            "source": source,
+            "events": events,
+        });
+        match CString::new(json.to_string()) {
+            Ok(s) => unsafe { (self.0)(s.as_ptr()) },
+            Err(e) => log::error!("Failed to notify bookmark observer: {}", e),
+        }
+    }
+}
+
+/// Registers a callback to be notified about bookmark changes made on any
+/// connection of the places API, including by syncing. Returns an ID for
+/// `bookmarks_unregister_observer`.
+#[no_mangle]
+pub extern "C" fn bookmarks_register_observer(
+    handle: u64,
+    callback: BookmarkObserverCallback,
+    error: &mut ExternError,
+) -> u64 {
+    log::debug!("bookmarks_register_observer");
+    APIS.call_with_output(error, handle, |api| {
+        let id = api.register_bookmark_observer(Arc::new(FfiBookmarkObserver(callback)));
+        id.0 as u64
+    })
+}
+
+/// Unregisters a bookmark observer. Returns 0 if the observer wasn't
+/// registered.
+#[no_mangle]
+pub extern "C" fn bookmarks_unregister_observer(
+    handle: u64,
+    observer_id: u64,
+    error: &mut ExternError,
+) -> u8 {
+    log::debug!("bookmarks_unregister_observer");
+    APIS.call_with_output(error, handle, |api| {
+        api.unregister_bookmark_observer(ObserverId(observer_id as usize))
+    })
+}
+
+/// The type of the callback for history observers. Like
+/// `BookmarkObserverCallback`, it's called with a JSON object like
+/// `{"source": "local", "events": [...]}`, where `events` are the serialized
+/// `HistoryEvent`s, and has the same restrictions.
+pub type HistoryObserverCallback = unsafe extern "C" fn(*const c_char);
+
+struct FfiHistoryObserver(HistoryObserverCallback);
+
+impl HistoryObserver for FfiHistoryObserver {
+    fn on_history_events(&self, source: ChangeSource, events: &[HistoryEvent]) {
+        let json = serde_json::json!({
+            "source": source,
+            "events": events,
+        });
+        match CString::new(json.to_string()) {
+            Ok(s) => unsafe { (self.0)(s.as_ptr()) },
+            Err(e) => log::error!("Failed to notify history observer: {}", e),
+        }
+    }
+}
+
+/// Registers a callback to be notified about history changes made on any
+/// connection of the places API, including by syncing. Returns an ID for
+/// `places_unregister_history_observer`.
+#[no_mangle]
+pub extern "C" fn places_register_history_observer(
+    handle: u64,
+    callback: HistoryObserverCallback,
+    error: &mut ExternError,
+) -> u64 {
+    log::debug!("places_register_history_observer");
+    APIS.call_with_output(error, handle, |api| {
+        let id = api.register_history_observer(Arc::new(FfiHistoryObserver(callback)));
+        id.0 as u64
+    })
+}
+
+/// Unregisters a history observer. Returns 0 if the observer wasn't
+/// registered.
+#[no_mangle]
+pub extern "C" fn places_unregister_history_observer(
+    handle: u64,
+    observer_id: u64,
+    error: &mut ExternError,
+) -> u8 {
+    log::debug!("places_unregister_history_observer");
+    APIS.call_with_output(error, handle, |api| {
+        api.unregister_history_observer(ObserverId(observer_id as usize))
+    })
+}
+
 define_string_destructor!(places_destroy_string);
 define_bytebuffer_destructor!(places_destroy_bytebuffer);
 define_handle_map_deleter!(APIS, places_api_destroy);
 
 define_handle_map_deleter!(CONNECTIONS, places_connection_destroy);
 define_box_destructor!(PlacesInterruptHandle, places_interrupt_handle_destroy);
+define_box_destructor!(CancellationToken, places_cancellation_token_destroy);
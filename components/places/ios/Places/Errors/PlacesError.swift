@@ -35,6 +35,10 @@ public enum PlacesError: Error {
     /// The requested operation failed because the store is corrupt
     case databaseCorrupt(message: String)
 
+    /// The old key passed when changing the encryption key is incorrect, or
+    /// the database is already open with a different key.
+    case wrongEncryptionKey(message: String)
+
     /// Thrown on insertions and updates that specify a parent which
     /// is not a folder
     case invalidParent(message: String)
@@ -100,6 +104,9 @@ public enum PlacesError: Error {
         case Places_Corrupt:
             return .databaseCorrupt(message: String(freeingPlacesString: message!))
 
+        case Places_WrongEncryptionKey:
+            return .wrongEncryptionKey(message: String(freeingPlacesString: message!))
+
         default:
             return .unexpected(message: String(freeingPlacesString: message!))
         }
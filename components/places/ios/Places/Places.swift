@@ -72,6 +72,12 @@ public class PlacesAPI {
             }
         }
 
+        // Close the database now, instead of when the last reference to it
+        // goes away, so that it can be opened again right away.
+        PlacesError.unwrapOrLog { error in
+            places_api_close(self.handle, error)
+        }
+
         PlacesError.unwrapOrLog { error in
             places_api_destroy(self.handle, error)
         }
@@ -103,6 +109,48 @@ public class PlacesAPI {
             self.writeConn
         }
     }
+
+    /**
+     * Change the key used to encrypt the database.
+     *
+     * Reader connections should be closed first, since they can't read the
+     * database once its key changes. The writer returned by `getWriter`
+     * stays usable.
+     *
+     * - Parameter oldKey: The current key, or nil if the database isn't encrypted.
+     * - Parameter newKey: The new key, or nil to decrypt the database.
+     *
+     * - Throws:
+     *     - `PlacesError.wrongEncryptionKey`: If `oldKey` isn't the current key.
+     *     - `PlacesError.databaseBusy`: If the database is in use by another connection.
+     */
+    open func rekey(oldKey: String?, newKey: String?) throws {
+        try queue.sync {
+            try self.writeConn.queue.sync {
+                // The write connection has to be returned to change the key,
+                // so we give the writer a new one afterwards.
+                let writeHandle = self.writeConn.takeHandle()
+                var rekeyError: Error?
+                do {
+                    try PlacesError.unwrap { error in
+                        places_api_return_write_conn(self.handle, writeHandle, error)
+                    }
+                    try PlacesError.unwrap { error in
+                        places_api_rekey(self.handle, oldKey, newKey, error)
+                    }
+                } catch let e {
+                    rekeyError = e
+                }
+                let newHandle = try PlacesError.unwrap { error in
+                    places_connection_new(self.handle, Int32(PlacesConn_ReadWrite), error)
+                }
+                try self.writeConn.setHandle(newHandle)
+                if let e = rekeyError {
+                    throw e
+                }
+            }
+        }
+    }
 }
 
 /**
@@ -112,7 +160,7 @@ public class PlacesReadConnection {
     fileprivate let queue = DispatchQueue(label: "com.mozilla.places.conn")
     fileprivate var handle: ConnectionHandle;
     fileprivate weak var api: PlacesAPI?
-    fileprivate let interruptHandle: InterruptHandle
+    fileprivate var interruptHandle: InterruptHandle
 
     fileprivate init(handle: ConnectionHandle, api: PlacesAPI? = nil) throws {
         self.handle = handle
@@ -136,6 +184,15 @@ public class PlacesReadConnection {
         return handle
     }
 
+    // Note: caller synchronizes! Only used for the writer, which gets a new
+    // handle when the encryption key changes.
+    fileprivate func setHandle(_ handle: ConnectionHandle) throws {
+        self.handle = handle
+        self.interruptHandle = InterruptHandle(ptr: try PlacesError.unwrap { error in
+            places_new_interrupt_handle(handle, error)
+        })
+    }
+
     deinit {
         // Note: don't need to queue.sync in deinit -- no more references exist to us.
         let handle = self.takeHandle()
@@ -16,6 +16,7 @@ typedef enum PlacesErrorCode {
     Places_DatabaseBusy = 3,
     Places_DatabaseInterrupted = 4,
     Places_Corrupt = 5,
+    Places_WrongEncryptionKey = 6,
 
     Places_InvalidPlace_InvalidParent = 64 + 0,
     Places_InvalidPlace_NoSuchItem = 64 + 1,
@@ -55,6 +56,11 @@ PlacesConnectionHandle places_connection_new(PlacesAPIHandle handle,
                                              int32_t type,
                                              PlacesRustError *_Nonnull out_err);
 
+void places_api_rekey(PlacesAPIHandle handle,
+                      const char *_Nullable old_key,
+                      const char *_Nullable new_key,
+                      PlacesRustError *_Nonnull out_err);
+
 // MARK: History APIs
 
 void places_note_observation(PlacesConnectionHandle handle,
@@ -178,5 +184,8 @@ void places_interrupt_handle_destroy(RawPlacesInterruptHandle *_Nonnull handle);
 void places_connection_destroy(PlacesConnectionHandle conn,
                                PlacesRustError *_Nonnull out_err);
 
+void places_api_close(PlacesAPIHandle api,
+                      PlacesRustError *_Nonnull out_err);
+
 void places_api_destroy(PlacesAPIHandle api,
                         PlacesRustError *_Nonnull out_err);
@@ -0,0 +1,50 @@
+-- This Source Code Form is subject to the terms of the Mozilla Public
+-- License, v. 2.0. If a copy of the MPL was not distributed with this
+-- file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+-- This file creates the optional full-text index over the titles and URLs in
+-- moz_places. It's only created when the index is enabled (see
+-- `PlacesDb::set_use_fts_index`), and searches use it whenever it exists.
+
+-- An external content table, so it doesn't store a second copy of the titles
+-- or URLs. unicode61 folds case and removes diacritics from both the indexed
+-- text and the terms we look up, so "cafe" finds "Café", as it does with
+-- AUTOCOMPLETE_MATCH.
+CREATE VIRTUAL TABLE moz_places_fts USING fts5(
+    title,
+    url,
+    content = 'moz_places',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 1'
+);
+
+-- These triggers keep the index in sync with moz_places. Unlike the temp
+-- triggers in create_shared_triggers.sql, they're part of the schema, so they
+-- also maintain the index for connections opened before it was created. Since
+-- moz_places_fts is an external content table, removing a row from the index
+-- needs the values it was indexed with, so updates delete and reinsert.
+CREATE TRIGGER moz_places_afterinsert_trigger_fts
+AFTER INSERT ON moz_places FOR EACH ROW
+BEGIN
+    INSERT INTO moz_places_fts(rowid, title, url)
+    VALUES (NEW.id, NEW.title, NEW.url);
+END;
+
+CREATE TRIGGER moz_places_afterupdate_trigger_fts
+AFTER UPDATE OF title, url ON moz_places FOR EACH ROW
+BEGIN
+    INSERT INTO moz_places_fts(moz_places_fts, rowid, title, url)
+    VALUES ('delete', OLD.id, OLD.title, OLD.url);
+    INSERT INTO moz_places_fts(rowid, title, url)
+    VALUES (NEW.id, NEW.title, NEW.url);
+END;
+
+CREATE TRIGGER moz_places_afterdelete_trigger_fts
+AFTER DELETE ON moz_places FOR EACH ROW
+BEGIN
+    INSERT INTO moz_places_fts(moz_places_fts, rowid, title, url)
+    VALUES ('delete', OLD.id, OLD.title, OLD.url);
+END;
+
+-- Index everything that's already in moz_places.
+INSERT INTO moz_places_fts(moz_places_fts) VALUES('rebuild');
@@ -42,6 +42,11 @@ CREATE INDEX IF NOT EXISTS lastvisitdatelocalindex ON moz_places(last_visit_date
 CREATE INDEX IF NOT EXISTS lastvisitdateremoteindex ON moz_places(last_visit_date_remote);
 CREATE UNIQUE INDEX IF NOT EXISTS guid_uniqueindex ON moz_places(guid);
 CREATE INDEX IF NOT EXISTS originidindex ON moz_places(origin_id);
+-- Used by expiration to find the least recently visited, least frecent pages.
+CREATE INDEX IF NOT EXISTS lastvisitdateindex ON moz_places(
+    MAX(last_visit_date_local, last_visit_date_remote),
+    frecency
+);
 
 
 CREATE TABLE IF NOT EXISTS moz_places_tombstones (
@@ -131,7 +136,15 @@ CREATE TABLE IF NOT EXISTS moz_bookmarks_deleted (
     dateRemoved INTEGER NOT NULL
 ) WITHOUT ROWID;
 
--- Note: desktop has/had a 'keywords' table, but we intentionally do not.
+-- Keywords for URLs. Like desktop, a URL can only have one keyword, and a
+-- keyword can only be used for one URL. Unlike desktop, we don't support POST
+-- data for keywords.
+CREATE TABLE IF NOT EXISTS moz_keywords(
+    id INTEGER PRIMARY KEY,
+    keyword TEXT UNIQUE NOT NULL,
+    place_id INTEGER NOT NULL UNIQUE REFERENCES moz_places(id)
+                                     ON DELETE CASCADE
+);
 
 
 CREATE TABLE IF NOT EXISTS moz_origins (
@@ -157,6 +157,28 @@ BEGIN
     SELECT tagId, OLD.newPlaceId
     FROM moz_bookmarks_synced_tag_relation
     WHERE itemId = OLD.remoteId;
+
+    -- Flag other bookmarks for URLs whose keywords are about to change for
+    -- upload, so that they don't reupload stale keywords later.
+    UPDATE moz_bookmarks SET
+        syncChangeCounter = syncChangeCounter + 1
+    WHERE id IS NOT OLD.localId AND
+          fk IN (SELECT place_id FROM moz_keywords
+                 WHERE (place_id IN (OLD.oldPlaceId, OLD.newPlaceId) AND
+                        keyword IS NOT OLD.newKeyword) OR
+                       (keyword = OLD.newKeyword AND
+                        place_id <> OLD.newPlaceId));
+
+    -- Remove the keywords for the old and new URLs, and the new keyword
+    -- from any other URL, then add the new keyword for the new URL.
+    DELETE FROM moz_keywords
+    WHERE place_id IN (OLD.oldPlaceId, OLD.newPlaceId) OR
+          keyword = OLD.newKeyword;
+
+    INSERT INTO moz_keywords(keyword, place_id)
+    SELECT OLD.newKeyword, OLD.newPlaceId
+    WHERE OLD.newKeyword NOT NULL AND
+          OLD.newPlaceId NOT NULL;
 END;
 
 -- Updates all parents and positions to reflect the merged tree.
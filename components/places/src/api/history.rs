@@ -5,8 +5,17 @@
 use super::apply_observation;
 use crate::db::PlacesDb;
 use crate::error::*;
+use crate::msg_types::{
+    HistoryVisitGroup, HistoryVisitGroups, HistoryVisitInfo, HistoryVisitInfosWithBound,
+    TopFrecentSiteInfo, TopFrecentSiteInfos,
+};
 use crate::observation::VisitObservation;
 use crate::types::*;
+use rusqlite::types::ToSql;
+use sql_support::ConnExt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
 use url::Url;
 // This module can become, roughly: PlacesUtils.history()
 
@@ -54,6 +63,167 @@ pub fn insert(conn: &mut PlacesDb, place: AddablePlaceInfo) -> Result<()> {
     Ok(())
 }
 
+/// Returns up to `limit` "top sites", for example, for a new tab page. Pages
+/// are grouped by origin, so "http://example.com" and "https://example.com"
+/// are different sites, and ranked by their total frecency, which must be at
+/// least `frecency_threshold`. Each site uses the title of its most frecent
+/// page.
+///
+/// Only http and https pages are included, and, like Desktop, hidden pages
+/// (like redirect sources and frames) aren't counted. A page's frecency only
+/// counts in proportion to its visits that weren't redirects, embeds, frames
+/// or downloads, so pages only visited that way are skipped. Visits that
+/// failed to load never change a page's frecency, so pages that were only
+/// visited with errors are skipped, too.
+///
+/// Sites whose host, or the host with a leading "www.", is in
+/// `blocked_hosts` are skipped.
+pub fn get_top_frecent_site_infos(
+    db: &PlacesDb,
+    limit: u32,
+    frecency_threshold: i64,
+    blocked_hosts: &[String],
+) -> Result<TopFrecentSiteInfos> {
+    if limit == 0 {
+        return Ok(TopFrecentSiteInfos { infos: Vec::new() });
+    }
+    let mut blocked = Vec::with_capacity(blocked_hosts.len() * 2);
+    for host in blocked_hosts {
+        let host = host.trim().to_lowercase();
+        blocked.push(format!("www.{}", host));
+        blocked.push(host);
+    }
+    let blocked_names = (0..blocked.len())
+        .map(|i| format!(":blocked{}", i))
+        .collect::<Vec<_>>();
+    let mut params: Vec<(&str, &dyn ToSql)> = vec![
+        (":frecency_threshold", &frecency_threshold),
+        (":limit", &limit),
+    ];
+    for (name, host) in blocked_names.iter().zip(&blocked) {
+        params.push((name.as_str(), host));
+    }
+    // SQLite returns bare columns from the row with the `MAX()`, so the
+    // title is that of the most frecent page.
+    let sql = format!(
+        "WITH pages(origin_id, title, frecency) AS (
+             SELECT h.origin_id, h.title,
+                    h.frecency *
+                    (SELECT COUNT(*) FROM moz_historyvisits v
+                     WHERE v.place_id = h.id AND
+                           v.visit_type NOT IN ({excluded})) /
+                    (SELECT COUNT(*) FROM moz_historyvisits v
+                     WHERE v.place_id = h.id)
+             FROM moz_places h
+             WHERE NOT h.hidden AND
+                   h.frecency > 0
+         )
+         SELECT o.prefix, o.host, p.title, MAX(p.frecency), SUM(p.frecency) AS frecency
+         FROM pages p
+         JOIN moz_origins o ON o.id = p.origin_id
+         WHERE o.prefix IN ('http://', 'https://') AND
+               p.frecency > 0
+               {blocked}
+         GROUP BY o.id
+         HAVING SUM(p.frecency) >= :frecency_threshold
+         ORDER BY frecency DESC, o.id
+         LIMIT :limit",
+        excluded = [
+            VisitTransition::Embed,
+            VisitTransition::RedirectPermanent,
+            VisitTransition::RedirectTemporary,
+            VisitTransition::Download,
+            VisitTransition::FramedLink,
+        ]
+        .iter()
+        .map(|t| (*t as u8).to_string())
+        .collect::<Vec<_>>()
+        .join(", "),
+        blocked = if blocked_names.is_empty() {
+            String::new()
+        } else {
+            format!("AND o.host NOT IN ({})", blocked_names.join(", "))
+        },
+    );
+    let infos = db.query_rows_and_then_named_cached(&sql, &params, |row| -> Result<_> {
+        let prefix: String = row.get_checked(0)?;
+        let host: String = row.get_checked(1)?;
+        Ok(TopFrecentSiteInfo {
+            url: format!("{}{}/", prefix, host),
+            title: row.get_checked(2)?,
+            frecency: row.get_checked(4)?,
+        })
+    })?;
+    Ok(TopFrecentSiteInfos { infos })
+}
+
+const MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Groups a page of visits from `storage::history::get_visit_page` by the
+/// day they happened on, newest first. Days start at midnight in the time
+/// zone `utc_offset_minutes` ahead of UTC, and each group's `day` is the
+/// time of that midnight.
+///
+/// Since the visits in a page are newest first, the first group of a page
+/// might continue the last group of the previous page.
+pub fn group_visits_by_day(
+    page: HistoryVisitInfosWithBound,
+    utc_offset_minutes: i32,
+) -> HistoryVisitGroups {
+    let offset = i64::from(utc_offset_minutes) * 60 * 1000;
+    let mut groups: Vec<HistoryVisitGroup> = Vec::new();
+    for info in page.infos {
+        let day = (info.timestamp + offset) / MS_PER_DAY * MS_PER_DAY - offset;
+        match groups.last_mut() {
+            Some(group) if group.day == Some(day) => group.infos.push(info),
+            _ => groups.push(HistoryVisitGroup {
+                day: Some(day),
+                host: None,
+                infos: vec![info],
+            }),
+        }
+    }
+    HistoryVisitGroups {
+        groups,
+        bound: page.bound,
+        offset: page.offset,
+    }
+}
+
+/// Groups a page of visits from `storage::history::get_visit_page` by host.
+/// Groups are ordered by their most recent visit, and visits without a host
+/// are grouped under an empty host.
+pub fn group_visits_by_host(page: HistoryVisitInfosWithBound) -> HistoryVisitGroups {
+    let mut groups: Vec<HistoryVisitGroup> = Vec::new();
+    let mut indices: HashMap<String, usize> = HashMap::new();
+    for info in page.infos {
+        let host = host_for_visit(&info);
+        match indices.get(&host) {
+            Some(&index) => groups[index].infos.push(info),
+            None => {
+                indices.insert(host.clone(), groups.len());
+                groups.push(HistoryVisitGroup {
+                    day: None,
+                    host: Some(host),
+                    infos: vec![info],
+                });
+            }
+        }
+    }
+    HistoryVisitGroups {
+        groups,
+        bound: page.bound,
+        offset: page.offset,
+    }
+}
+
+fn host_for_visit(info: &HistoryVisitInfo) -> String {
+    Url::parse(&info.url)
+        .ok()
+        .and_then(|url| url.host_str().map(ToString::to_string))
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,20 +281,238 @@ mod tests {
         );
         // XXX - check more.
     }
+
+    #[test]
+    fn test_get_top_frecent_site_infos() {
+        let _ = env_logger::try_init();
+        let mut conn = new_mem_connection();
+        let visits = [
+            (
+                "https://www.example.com/",
+                VisitTransition::Typed,
+                Some("Example"),
+            ),
+            ("https://www.example.com/a", VisitTransition::Link, None),
+            ("http://www.example.com/b", VisitTransition::Link, None),
+            (
+                "https://mozilla.org/",
+                VisitTransition::Link,
+                Some("Mozilla"),
+            ),
+            ("https://blocked.com/", VisitTransition::Typed, None),
+            ("https://www.blocked.org/", VisitTransition::Typed, None),
+            ("https://embedded.com/", VisitTransition::Embed, None),
+            (
+                "https://redirect.com/",
+                VisitTransition::RedirectTemporary,
+                None,
+            ),
+            ("ftp://files.example.com/", VisitTransition::Typed, None),
+        ];
+        for (url, visit_type, title) in &visits {
+            apply_observation(
+                &mut conn,
+                VisitObservation::new(Url::parse(url).unwrap())
+                    .with_visit_type(*visit_type)
+                    .with_title(title.map(String::from))
+                    .with_at(Timestamp::now()),
+            )
+            .expect("Should apply visit");
+        }
+        let blocked = vec!["Blocked.com".to_string(), "blocked.org".to_string()];
+
+        let infos = get_top_frecent_site_infos(&conn, 10, 0, &blocked)
+            .expect("Should fetch top sites")
+            .infos;
+        let urls = infos
+            .iter()
+            .map(|info| info.url.as_str())
+            .collect::<Vec<_>>();
+        // Different schemes are different sites, even with the same host.
+        assert_eq!(
+            urls,
+            vec![
+                "https://www.example.com/",
+                "http://www.example.com/",
+                "https://mozilla.org/"
+            ]
+        );
+        assert_eq!(infos[0].title, Some("Example".to_string()));
+        assert_eq!(infos[2].title, Some("Mozilla".to_string()));
+        assert!(infos[0].frecency > infos[2].frecency);
+
+        let infos = get_top_frecent_site_infos(&conn, 1, 0, &blocked)
+            .expect("Should fetch top sites")
+            .infos;
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].url, "https://www.example.com/");
+
+        // Only sites over the threshold are included.
+        let threshold = infos[0].frecency;
+        let infos = get_top_frecent_site_infos(&conn, 10, threshold, &blocked)
+            .expect("Should fetch top sites")
+            .infos;
+        assert_eq!(infos.len(), 1);
+
+        assert!(get_top_frecent_site_infos(&conn, 10, 0, &[])
+            .expect("Should fetch top sites")
+            .infos
+            .iter()
+            .any(|info| info.url == "https://blocked.com/"));
+    }
+
+    fn visit_info(url: &str, timestamp: i64) -> HistoryVisitInfo {
+        HistoryVisitInfo {
+            url: url.to_string(),
+            title: None,
+            timestamp,
+            visit_type: VisitTransition::Link as i32,
+        }
+    }
+
+    #[test]
+    fn test_group_visits() {
+        // 2019-01-02T01:00:00Z, 2019-01-01T23:00:00Z, 2019-01-01T01:00:00Z.
+        let page = HistoryVisitInfosWithBound {
+            infos: vec![
+                visit_info("https://example.com/1", 1_546_390_800_000),
+                visit_info("https://mozilla.org/", 1_546_383_600_000),
+                visit_info("https://example.com/2", 1_546_304_400_000),
+            ],
+            bound: 1_546_304_400_000,
+            offset: 1,
+        };
+
+        let by_day = group_visits_by_day(page.clone(), 0);
+        assert_eq!(by_day.bound, page.bound);
+        assert_eq!(by_day.offset, page.offset);
+        let days = by_day
+            .groups
+            .iter()
+            .map(|group| (group.day, group.infos.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            days,
+            vec![(Some(1_546_387_200_000), 1), (Some(1_546_300_800_000), 2),]
+        );
+
+        // Two hours behind UTC, the first two visits are on the same day.
+        let days = group_visits_by_day(page.clone(), -120)
+            .groups
+            .iter()
+            .map(|group| (group.day, group.infos.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            days,
+            vec![(Some(1_546_308_000_000), 2), (Some(1_546_221_600_000), 1),]
+        );
+
+        let hosts = group_visits_by_host(page)
+            .groups
+            .iter()
+            .map(|group| (group.host.clone().unwrap(), group.infos.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hosts,
+            vec![
+                ("example.com".to_string(), 2),
+                ("mozilla.org".to_string(), 1)
+            ]
+        );
+    }
 }
 
 /////////////////////////////////////////////
 // Stuff to reimplement nsHistory::VisitUri()
-fn is_recently_visited(_url: &Url) -> Result<bool> {
-    // History.cpp keeps an in-memory hashtable of urls visited in the last
-    // 6 minutes to avoid pages which self-refresh from getting many entries.
-    // ie, there's no DB query done here.
-    // TODO: implement this.
-    Ok(false)
+
+/// How long a url is considered "recently visited". This is the same 6
+/// minutes History.cpp uses.
+pub const DEFAULT_RECENT_VISIT_WINDOW: Duration = Duration::from_secs(6 * 60);
+
+/// History.cpp keeps an in-memory hashtable of urls visited in the last
+/// 6 minutes to avoid pages which self-refresh from getting many entries.
+/// ie, there's no DB query done here.
+///
+/// Only the single read-write connection records local visits, so each
+/// `PlacesApi` shares one of these with its read-write connection. Use
+/// `PlacesApi::set_recent_visit_window` to change the window.
+#[derive(Debug)]
+pub struct RecentVisits {
+    state: Mutex<RecentVisitsState>,
 }
 
-fn add_recently_visited(_url: &Url) -> Result<()> {
-    Ok(())
+#[derive(Debug)]
+struct RecentVisitsState {
+    window: Duration,
+    visits: HashMap<String, Timestamp>,
+}
+
+impl RecentVisits {
+    pub fn new(window: Duration) -> Self {
+        RecentVisits {
+            state: Mutex::new(RecentVisitsState {
+                window,
+                visits: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.state.lock().unwrap().window
+    }
+
+    /// Change how long urls are remembered for. A window of zero means
+    /// nothing is ever considered recent.
+    pub fn set_window(&self, window: Duration) {
+        self.state.lock().unwrap().window = window;
+    }
+
+    /// Was `url` visited within the window around `at`?
+    pub fn is_recent(&self, url: &Url, at: Timestamp) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.visits.get(url.as_str()) {
+            Some(&last) => elapsed_between(last, at) < state.window,
+            None => false,
+        }
+    }
+
+    /// Note that `url` was visited at `at`.
+    pub fn add(&self, url: &Url, at: Timestamp) {
+        let mut state = self.state.lock().unwrap();
+        // Expire anything that fell out of the window so we don't grow forever.
+        let window = state.window;
+        state
+            .visits
+            .retain(|_, &mut last| elapsed_between(last, at) < window);
+        if window > Duration::from_secs(0) {
+            let last = state.visits.entry(url.as_str().to_string()).or_insert(at);
+            *last = (*last).max(at);
+        }
+    }
+
+    /// Forget `url`. Used when its history is deleted, so that visiting it
+    /// again straight afterwards is recorded.
+    pub fn remove(&self, url: &str) {
+        self.state.lock().unwrap().visits.remove(url);
+    }
+
+    /// Forget everything. Used when all history is deleted.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().visits.clear();
+    }
+}
+
+impl Default for RecentVisits {
+    fn default() -> Self {
+        RecentVisits::new(DEFAULT_RECENT_VISIT_WINDOW)
+    }
+}
+
+// Visits can be observed out of order, so we don't care which came first.
+fn elapsed_between(a: Timestamp, b: Timestamp) -> Duration {
+    b.duration_since(a)
+        .or_else(|| a.duration_since(b))
+        .unwrap_or_default()
 }
 
 // Other "recent" flags:
@@ -169,19 +557,9 @@ pub fn visit_uri(
     };
     // Do not save a reloaded uri if we have visited the same URI recently.
     // (Note that desktop implies `reload` based of the "is it the same as last
-    // and is it recent" check below) - but here we are asking for the
-    // VisitTransition to be passed in, which explicity has a value for reload.
-    // Note clear if we should try and unify these.
-    // (and note that if we can, we can drop the recently_visited cache)
-    if let Some(ref last) = last_url {
-        if url == last && is_recently_visited(url)? {
-            // it's a reload we don't want to record, although we do want to
-            // update it as being recent.
-            add_recently_visited(url)?;
-            return Ok(());
-        };
-    }
-    // So add it.
+    // and is it recent" check - `apply_observation` does the same when the
+    // referrer is the page itself, in addition to honoring an explicit
+    // `VisitTransition::Reload`, so we just need to pass `last_url` along).
 
     // XXX - translate the flags passed to this function, along with the
     // RECENT_* cache above to create the correct Transition type.
@@ -199,6 +577,7 @@ pub fn visit_uri(
     let obs = VisitObservation::new(url.clone())
         .with_is_error(is_error_page)
         .with_visit_type(transition)
+        .with_referrer(last_url)
         .with_is_redirect_source(redirect_source.map(|_r| true))
         .with_is_permanent_redirect_source(
             redirect_source.map(|r| r == RedirectSourceType::Permanent),
@@ -0,0 +1,12 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Keywords are shortcuts for URLs, like Desktop's smart keywords. Typing a
+//! keyword in autocomplete, followed by some search terms, matches the
+//! keyword's URL with `%s` replaced by the terms (see `matcher::search_frecent`).
+//! Keywords are synced as part of the bookmark records for their URLs.
+
+pub use crate::storage::keywords::{
+    get_keyword_for_url, get_url_for_keyword, normalize_keyword, remove_keyword, set_keyword,
+};
@@ -2,9 +2,12 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use crate::db::PlacesDb;
-use crate::error::Result;
+use crate::db::{CancellationToken, PlacesDb};
+use crate::error::{Error, ErrorKind, Result};
+use crate::match_impl::{decode_punycode_host, fts_query, search_tokens, FTS_CANDIDATES_SQL};
 pub use crate::match_impl::{MatchBehavior, SearchBehavior};
+use crate::storage::keywords::normalize_keyword;
+use rusqlite::types::ToSql;
 use serde_derive::*;
 use sql_support::ConnExt;
 use url::Url;
@@ -15,50 +18,159 @@ pub struct SearchParams {
     pub limit: u32,
 }
 
+/// Restriction tokens, which limit the results of a search to a particular
+/// kind of match instead of being searched for. These are the same as the
+/// defaults used by desktop's `UrlbarTokenizer`, except for `%` (open pages),
+/// since we don't track which pages are open.
+const RESTRICTION_TOKENS: &[(&str, SearchBehavior)] = &[
+    ("^", SearchBehavior::HISTORY),
+    ("*", SearchBehavior::BOOKMARK),
+    ("+", SearchBehavior::TAG),
+    ("#", SearchBehavior::TITLE),
+    ("$", SearchBehavior::URL),
+];
+
+/// A search string with its restriction tokens removed, and turned into the
+/// `SearchBehavior` they imply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenizedSearch {
+    /// The string to match against. Quoted phrases are kept, so this should
+    /// be split using `match_impl::search_tokens`.
+    pub search_string: String,
+    pub search_behavior: SearchBehavior,
+    /// True if the search contained any restriction tokens.
+    pub is_restricted: bool,
+}
+
+impl TokenizedSearch {
+    pub fn new(search_string: &str) -> Self {
+        let mut sources = SearchBehavior::empty();
+        let mut fields = SearchBehavior::empty();
+        let mut terms = Vec::new();
+        for token in search_tokens(search_string) {
+            let restriction = if token.quoted {
+                // Quoting a restriction character searches for it instead.
+                None
+            } else {
+                RESTRICTION_TOKENS
+                    .iter()
+                    .find(|(restrict, _)| *restrict == token.text)
+                    .map(|&(_, behavior)| behavior)
+            };
+            match restriction {
+                Some(behavior)
+                    if behavior.intersects(SearchBehavior::TITLE | SearchBehavior::URL) =>
+                {
+                    fields |= behavior
+                }
+                Some(behavior) => sources |= behavior,
+                None if token.quoted => terms.push(format!("\"{}\"", token.text)),
+                None => terms.push(token.text.to_string()),
+            }
+        }
+        let is_restricted = !(sources | fields).is_empty();
+        // Restricting to sources means results must match all of them, while
+        // restricting to fields just changes what we search through.
+        let search_behavior = if sources.is_empty() {
+            SearchBehavior::default()
+        } else {
+            sources | SearchBehavior::RESTRICT
+        } | fields;
+        TokenizedSearch {
+            search_string: if is_restricted {
+                terms.join(" ")
+            } else {
+                search_string.to_string()
+            },
+            search_behavior,
+            is_restricted,
+        }
+    }
+}
+
 /// Synchronously queries all providers for autocomplete matches, then filters
-/// the matches. This isn't cancelable yet; once a search is started, it can't
-/// be interrupted, even if the user moves on (see
-/// https://github.com/mozilla/application-services/issues/265).
+/// the matches. The search can only be stopped by interrupting the
+/// connection; use `search_frecent_cancellable` to stop just this search, for
+/// example, when the user types another character.
 ///
 /// A provider can be anything that returns URL suggestions: Places history
 /// and bookmarks, synced tabs, search engine suggestions, and search keywords.
+///
+/// The search string is split into tokens (see `TokenizedSearch`), each of
+/// which must match a result, in any order.
 pub fn search_frecent(conn: &PlacesDb, params: SearchParams) -> Result<Vec<SearchResult>> {
-    // TODO: Tokenize the query.
+    Ok(search_frecent_impl(conn, params, None)?.results)
+}
+
+/// The results of a search that can be cancelled.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CancellableSearchResults {
+    pub results: Vec<SearchResult>,
+    /// True if the search was cancelled before all providers were queried,
+    /// so `results` only contains matches from the ones that finished.
+    pub partial: bool,
+}
+
+/// Like `search_frecent`, but can be stopped by cancelling `token`, without
+/// interrupting anything else on the connection. A cancelled search isn't an
+/// error; it returns the matches found before it was cancelled, marked as
+/// partial.
+pub fn search_frecent_cancellable(
+    conn: &PlacesDb,
+    params: SearchParams,
+    token: &CancellationToken,
+) -> Result<CancellableSearchResults> {
+    search_frecent_impl(conn, params, Some(token))
+}
+
+fn search_frecent_impl(
+    conn: &PlacesDb,
+    params: SearchParams,
+    token: Option<&CancellationToken>,
+) -> Result<CancellableSearchResults> {
+    let tokenized = TokenizedSearch::new(&params.search_string);
 
     // Try to find the first heuristic result. Desktop tries extensions,
     // search engine aliases, origins, URLs, search engine domains, and
     // preloaded sites, before trying to fall back to fixing up the URL,
     // and a search if all else fails. We only try origins and URLs for
-    // heuristic matches, since that's all we support.
-
-    let matches = match_with_limit(
-        conn,
-        &[
-            // Try to match on the origin, or the full URL.
-            &OriginOrUrl::new(&params.search_string),
-            // query adaptive matches and suggestions, matching Anywhere.
-            &Adaptive::with_behavior(
-                &params.search_string,
-                MatchBehavior::Anywhere,
-                SearchBehavior::default(),
-            ),
-            &Suggestions::with_behavior(
-                &params.search_string,
-                MatchBehavior::Anywhere,
-                SearchBehavior::default(),
-            ),
-        ],
-        params.limit,
-    )?;
+    // heuristic matches, since that's all we support. Neither makes sense
+    // for a restricted search, so we skip them in that case.
+
+    // Try to match a keyword, like "w rust" for a bookmark with the keyword
+    // "w", and then on the origin, or the full URL.
+    let keyword = Keyword::new(&params.search_string);
+    let origin_or_url = OriginOrUrl::new(&params.search_string);
+    // query adaptive matches and suggestions, matching Anywhere.
+    let adaptive = Adaptive::with_behavior(
+        &tokenized.search_string,
+        MatchBehavior::Anywhere,
+        tokenized.search_behavior,
+    );
+    let suggestions = Suggestions::with_behavior(
+        &tokenized.search_string,
+        MatchBehavior::Anywhere,
+        tokenized.search_behavior,
+    );
+
+    let mut matchers: Vec<&dyn Matcher> = Vec::with_capacity(4);
+    if !tokenized.is_restricted {
+        matchers.push(&keyword);
+        matchers.push(&origin_or_url);
+    }
+    matchers.push(&adaptive);
+    matchers.push(&suggestions);
 
-    Ok(matches)
+    match_with_limit(conn, &matchers, params.limit, token)
 }
 
 pub fn match_url(conn: &PlacesDb, query: impl AsRef<str>) -> Result<Option<String>> {
     let scope = conn.begin_interrupt_scope();
-    let matcher = OriginOrUrl::new(query.as_ref());
-    // Note: The matcher ignores the limit argument (it's a trait method)
-    let results = matcher.search(conn, 1)?;
+    // Note: These matchers ignore the limit argument (it's a trait method)
+    let mut results = Keyword::new(query.as_ref()).search(conn, 1)?;
+    if results.is_empty() {
+        results = OriginOrUrl::new(query.as_ref()).search(conn, 1)?;
+    }
     scope.err_if_interrupted()?;
     // Doing it like this lets us move the result, avoiding a copy (which almost
     // certainly doesn't matter but whatever)
@@ -73,20 +185,45 @@ fn match_with_limit(
     conn: &PlacesDb,
     matchers: &[&dyn Matcher],
     max_results: u32,
-) -> Result<(Vec<SearchResult>)> {
+    token: Option<&CancellationToken>,
+) -> Result<CancellableSearchResults> {
     let mut results = Vec::new();
     let mut rem_results = max_results;
     let scope = conn.begin_interrupt_scope();
+    let _cancellation_scope = token.map(|token| token.begin_scope(conn));
+    let is_cancelled = || token.map_or(false, CancellationToken::is_cancelled);
+    let mut partial = false;
     for m in matchers {
         if rem_results == 0 {
             break;
         }
         scope.err_if_interrupted()?;
-        let matches = m.search(conn, rem_results)?;
-        results.extend(matches);
+        if is_cancelled() {
+            partial = true;
+            break;
+        }
+        match m.search(conn, rem_results) {
+            Ok(matches) => results.extend(matches),
+            // If the token interrupted the statement, return what we have.
+            Err(ref e) if is_cancelled() && is_interrupted_error(e) => {
+                partial = true;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
         rem_results = rem_results.saturating_sub(results.len() as u32);
     }
-    Ok(results)
+    Ok(CancellableSearchResults { results, partial })
+}
+
+fn is_interrupted_error(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::InterruptedError => true,
+        ErrorKind::SqlError(rusqlite::Error::SqliteFailure(err, _)) => {
+            err.code == rusqlite::ErrorCode::OperationInterrupted
+        }
+        _ => false,
+    }
 }
 
 /// Records an accepted autocomplete match, recording the query string,
@@ -244,6 +381,32 @@ impl SearchResult {
         })
     }
 
+    pub fn from_keyword_row(
+        row: &rusqlite::Row,
+        search_string: &str,
+        params: &str,
+    ) -> rusqlite::Result<Option<Self>> {
+        let href = row.get_checked::<_, String>("url")?;
+        let title = row.get_checked::<_, Option<String>>("title")?;
+        let frecency = row.get_checked::<_, i64>("frecency")?;
+
+        let url = match expand_keyword_url(&href, params).and_then(|href| Url::parse(&href).ok()) {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            search_string: search_string.into(),
+            title: title
+                .filter(|title| !title.is_empty())
+                .unwrap_or_else(|| url.to_string()),
+            url,
+            icon_url: None,
+            frecency,
+            reasons: vec![MatchReason::Keyword],
+        }))
+    }
+
     pub fn from_origin_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
         let search_string = row.get_checked::<_, String>("searchString")?;
         let url = row.get_checked::<_, String>("url")?;
@@ -255,7 +418,8 @@ impl SearchResult {
         Ok(Self {
             search_string,
             url,
-            title: display_url,
+            // Show IDN hosts as the user would type them.
+            title: decode_punycode_host(&display_url).into_owned(),
             icon_url: None,
             frecency,
             reasons: vec![MatchReason::Origin],
@@ -297,7 +461,7 @@ impl SearchResult {
         Ok(Self {
             search_string,
             url,
-            title: display_url,
+            title: decode_punycode_host(&display_url).into_owned(),
             icon_url: None,
             frecency,
             reasons,
@@ -309,6 +473,60 @@ trait Matcher {
     fn search(&self, conn: &PlacesDb, max_results: u32) -> Result<Vec<SearchResult>>;
 }
 
+/// Substitutes the search terms after a keyword into its URL, like Desktop:
+/// `%s` is replaced with the form-encoded terms, and `%S` with the terms as
+/// typed. URLs without either only match if there aren't any terms.
+fn expand_keyword_url(href: &str, params: &str) -> Option<String> {
+    if href.contains("%s") || href.contains("%S") {
+        let encoded = url::form_urlencoded::byte_serialize(params.as_bytes()).collect::<String>();
+        Some(href.replace("%s", &encoded).replace("%S", params))
+    } else if params.is_empty() {
+        Some(href.into())
+    } else {
+        None
+    }
+}
+
+struct Keyword<'query> {
+    query: &'query str,
+}
+
+impl<'query> Keyword<'query> {
+    pub fn new(query: &'query str) -> Keyword<'query> {
+        Keyword { query }
+    }
+}
+
+impl<'query> Matcher for Keyword<'query> {
+    fn search(&self, conn: &PlacesDb, _: u32) -> Result<Vec<SearchResult>> {
+        let query = self.query.trim();
+        let (keyword, params) = match query.find(char::is_whitespace) {
+            Some(index) => (&query[..index], query[index..].trim_start()),
+            None => (query, ""),
+        };
+        let keyword = match normalize_keyword(keyword) {
+            Some(keyword) => keyword,
+            None => return Ok(vec![]),
+        };
+        let result = conn.try_query_row(
+            "SELECT h.url,
+                    IFNULL((SELECT title FROM moz_bookmarks
+                            WHERE fk = h.id AND
+                                  title NOT NULL
+                            ORDER BY lastModified DESC
+                            LIMIT 1), h.title) AS title,
+                    h.frecency
+             FROM moz_keywords k
+             JOIN moz_places h ON h.id = k.place_id
+             WHERE k.keyword = :keyword",
+            &[(":keyword", &keyword)],
+            |row| SearchResult::from_keyword_row(row, self.query, params),
+            true,
+        )?;
+        Ok(result.and_then(|result| result).into_iter().collect())
+    }
+}
+
 struct OriginOrUrl<'query> {
     query: &'query str,
 }
@@ -453,7 +671,10 @@ impl<'query> Matcher for Adaptive<'query> {
                           title NOT NULL
                     ORDER BY lastModified DESC
                     LIMIT 1) AS btitle,
-                   NULL AS tags,
+                   (SELECT GROUP_CONCAT(t.tag, ', ')
+                    FROM moz_tags t
+                    JOIN moz_tags_relation r ON r.tag_id = t.id
+                    WHERE r.place_id = h.id) AS tags,
                    h.visit_count_local + h.visit_count_remote AS visit_count,
                    h.typed as typed,
                    h.id as id,
@@ -507,47 +728,81 @@ impl<'query> Suggestions<'query> {
 
 impl<'query> Matcher for Suggestions<'query> {
     fn search(&self, conn: &PlacesDb, max_results: u32) -> Result<Vec<SearchResult>> {
+        let fts_query = if conn.use_fts_index()? {
+            fts_query(self.query)
+        } else {
+            None
+        };
+        let mut params: Vec<(&str, &dyn ToSql)> = vec![
+            (":searchString", &self.query),
+            (":matchBehavior", &self.match_behavior),
+            (":searchBehavior", &self.search_behavior),
+            (":maxResults", &max_results),
+        ];
+        let sql = match &fts_query {
+            Some(fts_query) => {
+                params.push((":ftsQuery", fts_query));
+                &*SUGGESTIONS_FTS_QUERY
+            }
+            None => &*SUGGESTIONS_QUERY,
+        };
         Ok(conn.query_rows_and_then_named_cached(
-            "
-            SELECT h.url, h.title,
-                   EXISTS(SELECT 1 FROM moz_bookmarks
-                          WHERE fk = h.id) AS bookmarked,
-                   (SELECT title FROM moz_bookmarks
-                    WHERE fk = h.id AND
-                          title NOT NULL
-                    ORDER BY lastModified DESC
-                    LIMIT 1) AS btitle,
-                   NULL AS tags,
-                   h.visit_count_local + h.visit_count_remote AS visit_count,
-                   h.typed as typed,
-                   h.id as id,
-                   NULL AS open_count, h.frecency, :searchString AS searchString
-            FROM moz_places h
-            WHERE h.frecency > 0
-              AND AUTOCOMPLETE_MATCH(:searchString, h.url,
-                                     IFNULL(btitle, h.title), tags,
-                                     visit_count, h.typed,
-                                     bookmarked, NULL,
-                                     :matchBehavior, :searchBehavior)
-              AND (+h.visit_count_local > 0 OR +h.visit_count_remote > 0)
-            ORDER BY h.frecency DESC, h.id DESC
-            LIMIT :maxResults",
-            &[
-                (":searchString", &self.query),
-                (":matchBehavior", &self.match_behavior),
-                (":searchBehavior", &self.search_behavior),
-                (":maxResults", &max_results),
-            ],
+            sql,
+            &params,
             SearchResult::from_suggestion_row,
         )?)
     }
 }
 
+fn suggestions_query(candidates_filter: &str) -> String {
+    format!(
+        "
+        SELECT h.url, h.title,
+               EXISTS(SELECT 1 FROM moz_bookmarks
+                      WHERE fk = h.id) AS bookmarked,
+               (SELECT title FROM moz_bookmarks
+                WHERE fk = h.id AND
+                      title NOT NULL
+                ORDER BY lastModified DESC
+                LIMIT 1) AS btitle,
+               (SELECT GROUP_CONCAT(t.tag, ', ')
+                FROM moz_tags t
+                JOIN moz_tags_relation r ON r.tag_id = t.id
+                WHERE r.place_id = h.id) AS tags,
+               h.visit_count_local + h.visit_count_remote AS visit_count,
+               h.typed as typed,
+               h.id as id,
+               NULL AS open_count, h.frecency, :searchString AS searchString
+        FROM moz_places h
+        WHERE h.frecency > 0
+          {candidates_filter}
+          AND AUTOCOMPLETE_MATCH(:searchString, h.url,
+                                 IFNULL(btitle, h.title), tags,
+                                 visit_count, h.typed,
+                                 bookmarked, NULL,
+                                 :matchBehavior, :searchBehavior)
+          AND (+h.visit_count_local > 0 OR +h.visit_count_remote > 0)
+        ORDER BY h.frecency DESC, h.id DESC
+        LIMIT :maxResults",
+        candidates_filter = candidates_filter,
+    )
+}
+
+lazy_static::lazy_static! {
+    static ref SUGGESTIONS_QUERY: String = suggestions_query("");
+    // Only used when the full-text index is enabled.
+    static ref SUGGESTIONS_FTS_QUERY: String =
+        suggestions_query(&format!("AND h.id IN ({})", FTS_CANDIDATES_SQL));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::api::places_api::test::new_mem_connection;
     use crate::observation::VisitObservation;
+    use crate::storage::bookmarks::{
+        insert_bookmark, BookmarkPosition, BookmarkRootGuid, InsertableBookmark,
+    };
     use crate::storage::history::apply_observation;
     use crate::types::{Timestamp, VisitTransition};
 
@@ -680,6 +935,332 @@ mod tests {
             }]
         );
     }
+
+    #[test]
+    fn search_cancellable() {
+        let conn = new_mem_connection();
+        let visit = VisitObservation::new(Url::parse("http://example.com/1").unwrap())
+            .with_title("The Rust docs".to_string())
+            .with_visit_type(VisitTransition::Link)
+            .with_at(Timestamp::now());
+        apply_observation(&conn, visit).expect("Should apply visit");
+        let params = SearchParams {
+            search_string: "rust".into(),
+            limit: 10,
+        };
+
+        let token = CancellationToken::new();
+        let complete = search_frecent_cancellable(&conn, params.clone(), &token)
+            .expect("Should search without cancelling");
+        assert!(!complete.partial);
+        assert_eq!(
+            complete.results,
+            search_frecent(&conn, params.clone()).expect("Should search")
+        );
+
+        // A cancelled search should return partial results, without
+        // affecting other searches on the connection.
+        token.cancel();
+        let cancelled = search_frecent_cancellable(&conn, params.clone(), &token)
+            .expect("Cancelled search shouldn't fail");
+        assert!(cancelled.partial);
+        assert!(cancelled.results.is_empty());
+        let next = search_frecent_cancellable(&conn, params, &CancellationToken::new())
+            .expect("Should search with a new token");
+        assert!(!next.partial);
+        assert_eq!(next.results, complete.results);
+    }
+
+    #[test]
+    fn tokenize() {
+        let plain = TokenizedSearch::new("rust docs");
+        assert_eq!(plain.search_string, "rust docs");
+        assert_eq!(plain.search_behavior, SearchBehavior::default());
+        assert!(!plain.is_restricted);
+
+        let bookmarks = TokenizedSearch::new("* rust \"the docs\"");
+        assert_eq!(bookmarks.search_string, "rust \"the docs\"");
+        assert_eq!(
+            bookmarks.search_behavior,
+            SearchBehavior::BOOKMARK | SearchBehavior::RESTRICT
+        );
+        assert!(bookmarks.is_restricted);
+
+        let history_titles = TokenizedSearch::new("rust ^ #");
+        assert_eq!(history_titles.search_string, "rust");
+        assert_eq!(
+            history_titles.search_behavior,
+            SearchBehavior::HISTORY | SearchBehavior::RESTRICT | SearchBehavior::TITLE
+        );
+
+        let urls = TokenizedSearch::new("$ rust");
+        assert_eq!(
+            urls.search_behavior,
+            SearchBehavior::default() | SearchBehavior::URL
+        );
+
+        // Restriction characters which are quoted, or part of a word, are
+        // searched for.
+        let quoted = TokenizedSearch::new("\"+\" c++");
+        assert_eq!(quoted.search_string, "\"+\" c++");
+        assert!(!quoted.is_restricted);
+
+        // `%` isn't a restriction token, so it's searched for too.
+        let percent = TokenizedSearch::new("% off");
+        assert_eq!(percent.search_string, "% off");
+        assert_eq!(percent.search_behavior, SearchBehavior::default());
+        assert!(!percent.is_restricted);
+    }
+
+    #[test]
+    fn search_tokenized() {
+        let conn = new_mem_connection();
+        let pages = [
+            ("http://example.com/1", "The Rust docs"),
+            ("http://example.com/2", "Docs for the Rust language"),
+            ("http://example.com/3", "The Rust book"),
+        ];
+        for &(url, title) in &pages {
+            let visit = VisitObservation::new(Url::parse(url).unwrap())
+                .with_title(title.to_string())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(Timestamp::now());
+            apply_observation(&conn, visit).expect("Should apply visit");
+        }
+        insert_bookmark(
+            &conn,
+            &InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Unfiled.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url: Url::parse("http://example.com/2").unwrap(),
+                title: None,
+            }
+            .into(),
+        )
+        .expect("Should insert bookmark");
+
+        let search = |search_string: &str| -> Vec<String> {
+            let mut urls = search_frecent(
+                &conn,
+                SearchParams {
+                    search_string: search_string.into(),
+                    limit: 10,
+                },
+            )
+            .expect("Should search")
+            .into_iter()
+            .map(|result| result.url.into_string())
+            .collect::<Vec<_>>();
+            urls.sort();
+            urls
+        };
+
+        // Words can match in any order.
+        assert_eq!(
+            search("rust docs"),
+            vec!["http://example.com/1", "http://example.com/2"]
+        );
+        assert_eq!(
+            search("docs rust"),
+            vec!["http://example.com/1", "http://example.com/2"]
+        );
+        // But phrases must match exactly.
+        assert_eq!(search("\"rust docs\""), vec!["http://example.com/1"]);
+        // Restricted to bookmarks.
+        assert_eq!(search("* rust"), vec!["http://example.com/2"]);
+        assert_eq!(search("rust *"), vec!["http://example.com/2"]);
+        // Restricted to history.
+        assert_eq!(search("^ rust").len(), 3);
+        // We don't have any tagged pages.
+        assert!(search("+ rust").is_empty());
+    }
+
+    #[test]
+    fn search_keywords() {
+        let conn = new_mem_connection();
+        let url = Url::parse("https://en.wikipedia.org/w/index.php?search=%s").unwrap();
+        insert_bookmark(
+            &conn,
+            &InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Unfiled.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url: url.clone(),
+                title: Some("Wikipedia".into()),
+            }
+            .into(),
+        )
+        .expect("Should insert bookmark");
+        crate::api::keywords::set_keyword(&conn, &url, "w").expect("Should set keyword");
+
+        let results = search_frecent(
+            &conn,
+            SearchParams {
+                search_string: "W rust lang".into(),
+                limit: 10,
+            },
+        )
+        .expect("Should search by keyword");
+        assert_eq!(
+            results[0].url.as_str(),
+            "https://en.wikipedia.org/w/index.php?search=rust+lang"
+        );
+        assert_eq!(results[0].title, "Wikipedia");
+        assert_eq!(results[0].reasons, vec![MatchReason::Keyword]);
+
+        assert_eq!(
+            match_url(&conn, "w rust").expect("Should match keyword"),
+            Some("https://en.wikipedia.org/w/index.php?search=rust".to_string())
+        );
+
+        // Keywords must match exactly.
+        let results = search_frecent(
+            &conn,
+            SearchParams {
+                search_string: "wi rust".into(),
+                limit: 10,
+            },
+        )
+        .expect("Should search");
+        assert!(results
+            .iter()
+            .all(|result| !result.reasons.contains(&MatchReason::Keyword)));
+    }
+
+    #[test]
+    fn keyword_urls() {
+        assert_eq!(
+            expand_keyword_url("http://example.com/?q=%s", "a b&c"),
+            Some("http://example.com/?q=a+b%26c".to_string())
+        );
+        assert_eq!(
+            expand_keyword_url("http://example.com/%S", "a/b"),
+            Some("http://example.com/a/b".to_string())
+        );
+        assert_eq!(
+            expand_keyword_url("http://example.com/", ""),
+            Some("http://example.com/".to_string())
+        );
+        assert_eq!(expand_keyword_url("http://example.com/", "rust"), None);
+    }
+
+    #[test]
+    fn search_tags() {
+        let conn = new_mem_connection();
+        for &(url, title) in &[
+            ("http://example.com/1", "Pancakes"),
+            ("http://example.com/2", "Waffles"),
+        ] {
+            let visit = VisitObservation::new(Url::parse(url).unwrap())
+                .with_title(title.to_string())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(Timestamp::now());
+            apply_observation(&conn, visit).expect("Should apply visit");
+        }
+        let tagged = Url::parse("http://example.com/1").unwrap();
+        crate::api::tags::tag_url(&conn, &tagged, "recipes").expect("Should tag url");
+        crate::api::tags::tag_url(&conn, &tagged, "breakfast").expect("Should tag url");
+
+        let search = |search_string: &str| {
+            search_frecent(
+                &conn,
+                SearchParams {
+                    search_string: search_string.into(),
+                    limit: 10,
+                },
+            )
+            .expect("Should search")
+        };
+
+        let by_tag = search("recip");
+        assert_eq!(by_tag.len(), 1);
+        assert_eq!(by_tag[0].url, tagged);
+        assert!(by_tag[0].reasons.iter().any(|reason| match reason {
+            MatchReason::Tags(tags) => tags.contains("recipes") && tags.contains("breakfast"),
+            _ => false,
+        }));
+
+        // Restricted to tagged pages.
+        let restricted = search("+ example");
+        assert_eq!(restricted.len(), 1);
+        assert_eq!(restricted[0].url, tagged);
+
+        // Untagged pages don't have the reason.
+        let untagged = search("waffles");
+        assert_eq!(untagged.len(), 1);
+        assert!(untagged[0].reasons.iter().all(|reason| match reason {
+            MatchReason::Tags(_) => false,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn search_fts() {
+        let conn = new_mem_connection();
+        let pages = [
+            ("http://example.com/1", "The Rust docs"),
+            ("http://example.com/2", "Mozilla Firefox"),
+            ("http://example.com/3", "Café Tortoni"),
+            ("http://xn--bcher-kva.de/", "Books"),
+        ];
+        for &(url, title) in &pages {
+            let visit = VisitObservation::new(Url::parse(url).unwrap())
+                .with_title(title.to_string())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(Timestamp::now());
+            apply_observation(&conn, visit).expect("Should apply visit");
+        }
+        insert_bookmark(
+            &conn,
+            &InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Unfiled.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url: Url::parse("http://example.com/2").unwrap(),
+                title: Some("Browser".into()),
+            }
+            .into(),
+        )
+        .expect("Should insert bookmark");
+
+        let search = |search_string: &str| -> Vec<String> {
+            let mut urls = search_frecent(
+                &conn,
+                SearchParams {
+                    search_string: search_string.into(),
+                    limit: 10,
+                },
+            )
+            .expect("Should search")
+            .into_iter()
+            .map(|result| result.url.into_string())
+            .collect::<Vec<_>>();
+            urls.sort();
+            urls
+        };
+
+        assert_eq!(search("ust"), vec!["http://example.com/1"]);
+
+        conn.set_use_fts_index(true)
+            .expect("should enable the full-text index");
+        assert_eq!(search("rust"), vec!["http://example.com/1"]);
+        assert_eq!(search("\"rust docs\""), vec!["http://example.com/1"]);
+        // Bookmark titles aren't indexed, but are still searched.
+        assert_eq!(search("brow"), vec!["http://example.com/2"]);
+        // The index only matches at the start of words.
+        assert!(search("ust").is_empty());
+        // Diacritics and punycoded hosts match like they do without it.
+        assert_eq!(search("cafe"), vec!["http://example.com/3"]);
+        assert_eq!(search("bücher"), vec!["http://xn--bcher-kva.de/"]);
+    }
+
     #[test]
     fn search_unicode() {
         let conn = new_mem_connection();
@@ -702,8 +1283,7 @@ mod tests {
         .expect("Should search by URL without path");
         assert!(by_url_without_path
             .iter()
-            // Should we consider un-punycoding the title? (firefox desktop doesn't...)
-            .any(|result| result.title == "xn--exmple-cua.com/"
+            .any(|result| result.title == "exämple.com/"
                 && result.url.as_str() == "http://xn--exmple-cua.com/"
                 && result.reasons == [MatchReason::Url]));
 
@@ -718,13 +1298,35 @@ mod tests {
         assert!(
             by_url_with_path
                 .iter()
-                .any(|result| result.title == "xn--exmple-cua.com/123"
+                .any(|result| result.title == "exämple.com/123"
                     && result.url.as_str() == "http://xn--exmple-cua.com/123"
                     && result.reasons == [MatchReason::Url]),
             "{:?}",
             by_url_with_path
         );
 
+        // Typing the unicode form of an IDN host finds it.
+        let visit = VisitObservation::new(Url::parse("http://xn--bcher-kva.de/").unwrap())
+            .with_title("Books".to_string())
+            .with_visit_type(VisitTransition::Link)
+            .with_at(Timestamp::now());
+        apply_observation(&conn, visit).expect("Should apply visit");
+        let by_unicode_host = search_frecent(
+            &conn,
+            SearchParams {
+                search_string: "bücher".into(),
+                limit: 10,
+            },
+        )
+        .expect("Should search by unicode host");
+        assert_eq!(
+            by_unicode_host
+                .iter()
+                .map(|result| result.url.as_str())
+                .collect::<Vec<_>>(),
+            vec!["http://xn--bcher-kva.de/"]
+        );
+
         // The "ball of yarn" emoji is not currently accepted as valid
         // in URLs, but we should just return an empty result set.
         let ball_of_yarn_about_blank = "about:blank🧶";
@@ -3,8 +3,10 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 pub mod history;
+pub mod keywords;
 pub mod matcher;
 pub mod places_api;
+pub mod tags;
 use crate::db::PlacesDb;
 use crate::error::Result;
 use crate::observation::VisitObservation;
@@ -2,21 +2,31 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use crate::api::history::RecentVisits;
+use crate::bookmark_sync::store::BookmarksStore;
 use crate::db::db::PlacesDb;
+use crate::db::recovery::{self, RecoveryReport};
+use crate::db::{PlacesInterruptHandle, PooledReader, ReaderPool, DEFAULT_READER_POOL_SIZE};
 use crate::error::*;
+use crate::frecency::FrecencySettings;
 use crate::history_sync::store::HistoryStore;
-use crate::util::normalize_path;
+use crate::observers::{BookmarkObserver, HistoryObserver, ObserverId, Observers};
+use crate::storage::history::{get_frecency_settings, set_frecency_settings};
+use crate::storage::{get_meta, put_meta};
+use crate::util::{normalize_path, path_with_suffix, remove_file_if_exists};
 use lazy_static::lazy_static;
 use rusqlite::OpenFlags;
 use std::cell::Cell;
 use std::collections::HashMap;
+use std::fs;
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicBool, AtomicUsize, Ordering},
-    Arc, Mutex, Weak,
+    Arc, Mutex, RwLock, Weak,
 };
-use sync15::{telemetry, ClientInfo};
+use std::time::Duration;
+use sync15::{sync_multiple, telemetry, ClientInfo, Store};
 
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -50,15 +60,20 @@ impl ConnectionType {
     }
 }
 
-// We only allow a single PlacesApi per filename.
-// XXX - probably need encryption key here too so we can do something sane
-// if we attempt to open the same file with different keys.
+// We only allow a single PlacesApi per filename. Opening a file that's already
+// open returns the existing PlacesApi, but only if the encryption key matches,
+// and closing a PlacesApi removes it, so the file can be opened again.
 lazy_static! {
     static ref APIS: Mutex<HashMap<PathBuf, Weak<PlacesApi>>> = Mutex::new(HashMap::new());
 }
 
 static ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+// The global state is shared between all of our stores. Note that this key
+// predates bookmark sync, which is why it mentions history - we keep it
+// so existing history-only users don't lose their state.
+pub const GLOBAL_STATE_META_KEY: &str = "history_global_state";
+
 struct SyncState {
     client_info: Cell<Option<ClientInfo>>,
 }
@@ -68,20 +83,70 @@ struct SyncState {
 /// can exist to the database at once.
 pub struct PlacesApi {
     db_name: PathBuf,
-    encryption_key: Option<String>,
+    encryption_key: Mutex<Option<String>>,
     write_connection: Mutex<Option<PlacesDb>>,
     sync_state: Mutex<Option<SyncState>>,
     coop_tx_lock: Arc<Mutex<()>>,
     sync_conn_active: AtomicBool,
+    observers: Arc<Observers>,
+    // Shared with the read-write connection, so the window can be changed
+    // while it's checked out.
+    recent_visits: Arc<RecentVisits>,
+    // Shared with every connection we open, so that changing the settings
+    // applies to connections that are already open.
+    frecency_settings: Arc<RwLock<FrecencySettings>>,
+    // The number of connections we've opened that are still open, including
+    // pooled readers.
+    open_connections: Arc<AtomicUsize>,
+    recovery_report: Option<RecoveryReport>,
+    reader_pool: Arc<ReaderPool>,
+    closed: AtomicBool,
     id: usize,
 }
 impl PlacesApi {
     /// Create a new, or fetch an already open, PlacesApi backed by a file on disk.
+    /// Fetching an already open PlacesApi fails with `EncryptionKeyMismatch`
+    /// if it was opened with a different key.
+    ///
+    /// If SQLite reports that the database is corrupt when it's opened, it's
+    /// moved aside, and as much history and as many bookmarks as possible are
+    /// salvaged into a new database. See `recovery_report` for the outcome.
     pub fn new(db_name: impl AsRef<Path>, encryption_key: Option<&str>) -> Result<Arc<Self>> {
         let db_name = normalize_path(db_name)?;
         Self::new_or_existing(db_name, encryption_key)
     }
 
+    /// Like `new`, but also checks the integrity of the database with
+    /// `PRAGMA quick_check` before opening it, and recovers it if the check
+    /// finds any problems. This reads the whole database, so it's meant for
+    /// occasional use, like after a crash. The check is skipped if the
+    /// PlacesApi is already open.
+    pub fn new_with_integrity_check(
+        db_name: impl AsRef<Path>,
+        encryption_key: Option<&str>,
+    ) -> Result<Arc<Self>> {
+        let db_name = normalize_path(db_name)?;
+        Self::new_or_existing_with_options(db_name, encryption_key, None, true)
+    }
+
+    /// Create a new, or fetch an already open, PlacesApi backed by a file on
+    /// disk, using custom settings to calculate frecencies. The settings are
+    /// stored in the database, so later calls to `new` use them, too. If the
+    /// settings changed, the frecencies of all pages are marked as stale, and
+    /// recalculated by `storage::history::recalculate_stale_frecencies`.
+    ///
+    /// Changing the settings of an already open PlacesApi applies to all of
+    /// its connections, including ones that are already open, but fails with
+    /// `ConnectionAlreadyOpen` if its write connection is in use.
+    pub fn new_with_frecency_settings(
+        db_name: impl AsRef<Path>,
+        encryption_key: Option<&str>,
+        frecency_settings: &FrecencySettings,
+    ) -> Result<Arc<Self>> {
+        let db_name = normalize_path(db_name)?;
+        Self::new_or_existing_with_options(db_name, encryption_key, Some(frecency_settings), false)
+    }
+
     /// Create a new, or fetch an already open, memory-based PlacesApi. You must
     /// provide a name, but you are still able to have a single writer and many
     ///  reader connections to the same memory DB open.
@@ -91,32 +156,100 @@ impl PlacesApi {
     }
 
     fn new_or_existing(db_name: PathBuf, encryption_key: Option<&str>) -> Result<Arc<Self>> {
-        // XXX - we should check encryption_key via the HashMap here too. Also, we'd
-        // rather not keep the key in memory forever, and instead it would be better to
-        // require it in open_connection.
+        Self::new_or_existing_with_options(db_name, encryption_key, None, false)
+    }
+
+    fn new_or_existing_with_options(
+        db_name: PathBuf,
+        encryption_key: Option<&str>,
+        frecency_settings: Option<&FrecencySettings>,
+        check_integrity: bool,
+    ) -> Result<Arc<Self>> {
+        // XXX - we'd rather not keep the key in memory forever, and instead it would
+        // be better to require it in open_connection.
         // (Or maybe given these issues (and the surprising performance hit), we shouldn't
         // support encrypted places databases...)
         let mut guard = APIS.lock().unwrap();
         let id = ID_COUNTER.fetch_add(1, Ordering::SeqCst);
-        match guard.get(&db_name).and_then(Weak::upgrade) {
-            Some(existing) => Ok(existing.clone()),
+        // An API that's being closed is still in the map, but shouldn't be
+        // reused.
+        let existing = guard
+            .get(&db_name)
+            .and_then(Weak::upgrade)
+            .filter(|api| !api.closed.load(Ordering::SeqCst));
+        match existing {
+            Some(existing) => {
+                if !existing.has_encryption_key(encryption_key) {
+                    return Err(ErrorKind::EncryptionKeyMismatch(db_name).into());
+                }
+                if let Some(settings) = frecency_settings {
+                    existing.set_frecency_settings(settings)?;
+                }
+                Ok(existing.clone())
+            }
             None => {
+                let mut corrupt_path = if check_integrity
+                    && db_name.exists()
+                    && !recovery::check_integrity(&db_name, encryption_key, id)?
+                {
+                    Some(recovery::move_aside(&db_name)?)
+                } else {
+                    None
+                };
                 // We always create a new read-write connection for an initial open so
                 // we can create the schema and/or do version upgrades.
                 let coop_tx_lock = Arc::new(Mutex::new(()));
-                let connection = PlacesDb::open(
-                    &db_name,
-                    encryption_key,
-                    ConnectionType::ReadWrite,
-                    id,
-                    coop_tx_lock.clone(),
-                )?;
+                let observers = Arc::new(Observers::default());
+                let recent_visits = Arc::new(RecentVisits::default());
+                let open_writer = || {
+                    PlacesDb::open(
+                        &db_name,
+                        encryption_key,
+                        ConnectionType::ReadWrite,
+                        id,
+                        coop_tx_lock.clone(),
+                    )
+                };
+                let mut connection = match open_writer() {
+                    Ok(connection) => connection,
+                    // Move a corrupt database aside, so that it doesn't fail
+                    // every time it's opened.
+                    Err(e)
+                        if corrupt_path.is_none()
+                            && db_name.exists()
+                            && recovery::is_corrupt_open_error(&e, encryption_key.is_some()) =>
+                    {
+                        log::warn!("Failed to open corrupt database: {}", e);
+                        corrupt_path = Some(recovery::move_aside(&db_name)?);
+                        open_writer()?
+                    }
+                    Err(e) => return Err(e),
+                };
+                let open_connections = Arc::new(AtomicUsize::new(0));
+                connection.set_observers(observers.clone());
+                connection.set_recent_visits(recent_visits.clone());
+                connection.set_open_connections(open_connections.clone());
+                let recovery_report = match corrupt_path {
+                    Some(path) => Some(recovery::salvage(&connection, &path)?),
+                    None => None,
+                };
+                if let Some(settings) = frecency_settings {
+                    set_frecency_settings(&connection, settings)?;
+                }
+                let frecency_settings = connection.shared_frecency_settings();
                 let new = PlacesApi {
                     db_name: db_name.clone(),
-                    encryption_key: encryption_key.map(ToString::to_string),
+                    encryption_key: Mutex::new(encryption_key.map(ToString::to_string)),
                     write_connection: Mutex::new(Some(connection)),
                     sync_state: Mutex::new(None),
                     sync_conn_active: AtomicBool::new(false),
+                    observers,
+                    recent_visits,
+                    frecency_settings,
+                    open_connections,
+                    recovery_report,
+                    reader_pool: Arc::new(ReaderPool::new(DEFAULT_READER_POOL_SIZE)),
+                    closed: AtomicBool::new(false),
                     id,
                     coop_tx_lock,
                 };
@@ -127,19 +260,43 @@ impl PlacesApi {
         }
     }
 
+    fn set_frecency_settings(&self, settings: &FrecencySettings) -> Result<()> {
+        let guard = self.write_connection.lock().unwrap();
+        match &*guard {
+            Some(db) => set_frecency_settings(db, settings),
+            None => {
+                // The write connection is in use, so we can only succeed if
+                // the settings haven't changed.
+                let reader = self.open_connection(ConnectionType::ReadOnly)?;
+                if get_frecency_settings(&reader)? == *settings {
+                    Ok(())
+                } else {
+                    Err(ErrorKind::ConnectionAlreadyOpen.into())
+                }
+            }
+        }
+    }
+
     /// Open a connection to the database.
     pub fn open_connection(&self, conn_type: ConnectionType) -> Result<PlacesDb> {
-        let ec = self.encryption_key.as_ref().map(String::as_str);
+        // `close` holds the key lock while it checks for open connections,
+        // so check that we're not closed while holding it, too.
+        let encryption_key = self.encryption_key.lock().unwrap();
+        self.check_not_closed()?;
+        let ec = encryption_key.as_ref().map(String::as_str);
         match conn_type {
             ConnectionType::ReadOnly => {
                 // make a new one - we can have as many of these as we want.
-                PlacesDb::open(
+                let mut db = PlacesDb::open(
                     self.db_name.clone(),
                     ec,
                     ConnectionType::ReadOnly,
                     self.id,
                     self.coop_tx_lock.clone(),
-                )
+                )?;
+                db.set_shared_frecency_settings(self.frecency_settings.clone());
+                db.set_open_connections(self.open_connections.clone());
+                Ok(db)
             }
             ConnectionType::ReadWrite => {
                 // We only allow one of these.
@@ -155,21 +312,80 @@ impl PlacesApi {
         }
     }
 
+    /// Check out a read-only connection from this API's pool, opening a new
+    /// one if none are idle. The connection is returned to the pool when the
+    /// `PooledReader` is dropped. If the pool's maximum number of readers
+    /// are already checked out, this blocks until one is returned, and fails
+    /// with `ReaderCheckoutTimeout` if none are returned within the pool's
+    /// checkout timeout.
+    pub fn checkout_reader(&self) -> Result<PooledReader> {
+        self.check_not_closed()?;
+        ReaderPool::checkout(&self.reader_pool, || {
+            self.open_connection(ConnectionType::ReadOnly)
+        })
+    }
+
+    /// Creates or drops the full-text index using the read-write connection.
+    /// Since the index is part of the database, this applies to every
+    /// connection, including pooled readers and ones that are already open.
+    /// Fails with `ConnectionAlreadyOpen` if the read-write connection is
+    /// checked out; use `PlacesDb::set_use_fts_index` on it instead.
+    pub fn set_use_fts_index(&self, enabled: bool) -> Result<()> {
+        self.check_not_closed()?;
+        let guard = self.write_connection.lock().unwrap();
+        match &*guard {
+            Some(db) => db.set_use_fts_index(enabled),
+            None => Err(ErrorKind::ConnectionAlreadyOpen.into()),
+        }
+    }
+
+    /// Changes how long the read-write connection remembers visited urls, to
+    /// ignore reloads. The default is `DEFAULT_RECENT_VISIT_WINDOW`, and a
+    /// window of zero records every visit. This applies even if the
+    /// connection is currently checked out.
+    pub fn set_recent_visit_window(&self, window: Duration) {
+        self.recent_visits.set_window(window)
+    }
+
+    /// Changes the maximum number of readers that can be checked out from
+    /// the pool at once. The default is `DEFAULT_READER_POOL_SIZE`.
+    pub fn set_reader_pool_size(&self, size: usize) {
+        self.reader_pool.set_max_size(size)
+    }
+
+    /// Changes how long `checkout_reader` waits for a reader to be returned
+    /// to the pool. The default is `DEFAULT_READER_CHECKOUT_TIMEOUT`.
+    pub fn set_reader_checkout_timeout(&self, timeout: Duration) {
+        self.reader_pool.set_checkout_timeout(timeout)
+    }
+
+    /// Returns a handle that interrupts every reader that's checked out from
+    /// the pool when `interrupt` is called. Readers checked out after that
+    /// aren't affected.
+    pub fn new_reader_interrupt_handle(&self) -> PlacesInterruptHandle {
+        PlacesInterruptHandle::for_reader_pool(&self.reader_pool)
+    }
+
     pub fn open_sync_connection(&self) -> Result<SyncConn> {
+        self.check_not_closed()?;
         let prev_value = self
             .sync_conn_active
             .compare_and_swap(false, true, Ordering::SeqCst);
         if prev_value {
             Err(ErrorKind::ConnectionAlreadyOpen.into())
         } else {
-            let ec = self.encryption_key.as_ref().map(String::as_str);
-            let db = PlacesDb::open(
+            let encryption_key = self.encryption_key.lock().unwrap();
+            let ec = encryption_key.as_ref().map(String::as_str);
+            let mut db = PlacesDb::open(
                 self.db_name.clone(),
                 ec,
                 ConnectionType::Sync,
                 self.id,
                 self.coop_tx_lock.clone(),
             )?;
+            db.set_observers(self.observers.clone());
+            db.set_shared_frecency_settings(self.frecency_settings.clone());
+            db.set_open_connections(self.open_connections.clone());
             Ok(SyncConn {
                 db,
                 flag: &self.sync_conn_active,
@@ -192,6 +408,219 @@ impl PlacesApi {
         Ok(())
     }
 
+    /// Changes the encryption key of the database from `old_key` to
+    /// `new_key`. Passing `None` for `old_key` encrypts an unencrypted
+    /// database, and `None` for `new_key` decrypts an encrypted one.
+    ///
+    /// This fails with `ConnectionAlreadyOpen` if the write or sync
+    /// connection is in use, or any read-only connections are open, since
+    /// they can't read the database after its key changes. Idle pooled
+    /// readers are closed first. If changing the key fails, the database and
+    /// the write connection are left as they were.
+    pub fn rekey(&self, old_key: Option<&str>, new_key: Option<&str>) -> Result<()> {
+        self.check_not_closed()?;
+        let mut encryption_key = self.encryption_key.lock().unwrap();
+        if encryption_key.as_ref().map(String::as_str) != old_key {
+            return Err(ErrorKind::WrongEncryptionKey.into());
+        }
+        if old_key == new_key {
+            return Ok(());
+        }
+        // Claim the sync connection, so that we don't start syncing while
+        // we're changing the key.
+        if self
+            .sync_conn_active
+            .compare_and_swap(false, true, Ordering::SeqCst)
+        {
+            return Err(ErrorKind::ConnectionAlreadyOpen.into());
+        }
+        self.reader_pool.clear();
+        let result = self.rekey_write_connection(old_key, new_key);
+        self.sync_conn_active.store(false, Ordering::SeqCst);
+        result?;
+        *encryption_key = new_key.map(ToString::to_string);
+        Ok(())
+    }
+
+    fn rekey_write_connection(&self, old_key: Option<&str>, new_key: Option<&str>) -> Result<()> {
+        let mut guard = self.write_connection.lock().unwrap();
+        let db = match mem::replace(&mut *guard, None) {
+            Some(db) => db,
+            None => return Err(ErrorKind::ConnectionAlreadyOpen.into()),
+        };
+        // The write connection should be the only one left.
+        if self.open_connections.load(Ordering::SeqCst) > 1 {
+            *guard = Some(db);
+            return Err(ErrorKind::ConnectionAlreadyOpen.into());
+        }
+        if let (Some(_), Some(new_key)) = (old_key, new_key) {
+            let result = db.rekey(new_key);
+            *guard = Some(db);
+            return result;
+        }
+
+        // SQLCipher can only encrypt or decrypt a database by exporting it
+        // to a new one, which then replaces the old database.
+        if !self.db_name.exists() {
+            *guard = Some(db);
+            return Err(ErrorKind::CannotEncryptMemoryDatabase.into());
+        }
+        let export_path = path_with_suffix(&self.db_name, ".rekey");
+        let exported =
+            remove_file_if_exists(&export_path).and_then(|()| db.export_to(&export_path, new_key));
+        if let Err(e) = exported {
+            *guard = Some(db);
+            let _ = fs::remove_file(&export_path);
+            return Err(e);
+        }
+        // Close the write connection before replacing the database, so that
+        // its WAL is checkpointed and removed, and can't be mistaken for the
+        // new database's WAL. If we can't switch to the new database, we
+        // reopen the old one.
+        drop(db);
+        let (db, result) = match self.replace_with_export(&export_path, new_key) {
+            Ok(db) => (db, Ok(())),
+            Err(e) => (self.open_write_connection(old_key)?, Err(e)),
+        };
+        *guard = Some(db);
+        result
+    }
+
+    /// Replaces the database with the one at `export_path`, and opens a new
+    /// write connection to it. The original database is only removed once
+    /// the new connection is open, and is put back on failure.
+    fn replace_with_export(&self, export_path: &Path, new_key: Option<&str>) -> Result<PlacesDb> {
+        let backup_path = path_with_suffix(&self.db_name, ".rekey-old");
+        let moved_aside = remove_file_if_exists(&backup_path)
+            .and_then(|()| fs::rename(&self.db_name, &backup_path).map_err(Error::from));
+        if let Err(e) = moved_aside {
+            let _ = fs::remove_file(export_path);
+            return Err(e);
+        }
+        let replaced = fs::rename(export_path, &self.db_name)
+            .map_err(Error::from)
+            .and_then(|()| self.open_write_connection(new_key));
+        match replaced {
+            Ok(db) => {
+                let _ = fs::remove_file(&backup_path);
+                Ok(db)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(export_path);
+                fs::rename(&backup_path, &self.db_name)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Opens a new write connection, which shares our state like the one we
+    /// opened initially.
+    fn open_write_connection(&self, encryption_key: Option<&str>) -> Result<PlacesDb> {
+        let mut db = PlacesDb::open(
+            &self.db_name,
+            encryption_key,
+            ConnectionType::ReadWrite,
+            self.id,
+            self.coop_tx_lock.clone(),
+        )?;
+        db.set_observers(self.observers.clone());
+        db.set_recent_visits(self.recent_visits.clone());
+        db.set_shared_frecency_settings(self.frecency_settings.clone());
+        db.set_open_connections(self.open_connections.clone());
+        Ok(db)
+    }
+
+    /// Closes the write connection, and removes this PlacesApi from the list
+    /// of open databases, so that the next `PlacesApi::new` for the same
+    /// file opens it again, possibly with a different key. Once closed, this
+    /// PlacesApi can't open any more connections.
+    ///
+    /// This fails with `ConnectionAlreadyOpen` if the write or sync
+    /// connection is in use, or any read-only connections, including
+    /// checked out pooled readers, are still open, and the PlacesApi stays
+    /// open. Idle pooled readers are closed either way.
+    pub fn close(&self) -> Result<()> {
+        let _encryption_key = self.encryption_key.lock().unwrap();
+        self.check_not_closed()?;
+        // Claim the sync connection, so that we can't start syncing while
+        // we're closing, and leave it claimed.
+        if self
+            .sync_conn_active
+            .compare_and_swap(false, true, Ordering::SeqCst)
+        {
+            return Err(ErrorKind::ConnectionAlreadyOpen.into());
+        }
+        let mut write_connection = self.write_connection.lock().unwrap();
+        if write_connection.is_none() {
+            self.sync_conn_active.store(false, Ordering::SeqCst);
+            return Err(ErrorKind::ConnectionAlreadyOpen.into());
+        }
+        // Stop new readers from being checked out, and close the idle ones,
+        // so that only the write connection should be left.
+        self.closed.store(true, Ordering::SeqCst);
+        self.reader_pool.clear();
+        if self.open_connections.load(Ordering::SeqCst) > 1 {
+            self.closed.store(false, Ordering::SeqCst);
+            self.sync_conn_active.store(false, Ordering::SeqCst);
+            return Err(ErrorKind::ConnectionAlreadyOpen.into());
+        }
+        drop(write_connection.take());
+        *self.sync_state.lock().unwrap() = None;
+
+        let mut guard = APIS.lock().unwrap();
+        let is_registered = match guard.get(&self.db_name).and_then(Weak::upgrade) {
+            Some(existing) => existing.id == self.id,
+            None => true,
+        };
+        if is_registered {
+            guard.remove(&self.db_name);
+        }
+        Ok(())
+    }
+
+    fn has_encryption_key(&self, encryption_key: Option<&str>) -> bool {
+        let guard = self.encryption_key.lock().unwrap();
+        guard.as_ref().map(String::as_str) == encryption_key
+    }
+
+    fn check_not_closed(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(ErrorKind::ApiClosed.into());
+        }
+        Ok(())
+    }
+
+    /// If the database was corrupt when this API was created, describes what
+    /// was recovered from it.
+    pub fn recovery_report(&self) -> Option<&RecoveryReport> {
+        self.recovery_report.as_ref()
+    }
+
+    /// Register an observer to be notified about bookmark changes, made
+    /// either on the read-write connection or by syncing. See the
+    /// `observers` module for details.
+    pub fn register_bookmark_observer(&self, observer: Arc<dyn BookmarkObserver>) -> ObserverId {
+        self.observers.add_bookmark_observer(observer)
+    }
+
+    /// Unregister a bookmark observer. Returns false if the observer wasn't
+    /// registered.
+    pub fn unregister_bookmark_observer(&self, id: ObserverId) -> bool {
+        self.observers.remove_bookmark_observer(id)
+    }
+
+    /// Register an observer to be notified about history changes, made
+    /// either on the read-write connection or by syncing.
+    pub fn register_history_observer(&self, observer: Arc<dyn HistoryObserver>) -> ObserverId {
+        self.observers.add_history_observer(observer)
+    }
+
+    /// Unregister a history observer. Returns false if the observer wasn't
+    /// registered.
+    pub fn unregister_history_observer(&self, id: ObserverId) -> bool {
+        self.observers.remove_history_observer(id)
+    }
+
     // TODO: We need a better result here so we can return telemetry.
     // We possibly want more than just a `SyncTelemetryPing` so we can
     // return additional "custom" telemetry if the app wants it.
@@ -200,6 +629,69 @@ impl PlacesApi {
         client_init: &sync15::Sync15StorageClientInit,
         key_bundle: &sync15::KeyBundle,
     ) -> Result<telemetry::SyncTelemetryPing> {
+        self.with_sync_state(|conn, client_info| {
+            let store = HistoryStore::new(conn, client_info);
+            let mut sync_ping = telemetry::SyncTelemetryPing::new();
+            sync_stores(
+                conn,
+                client_info,
+                &[&store],
+                client_init,
+                key_bundle,
+                &mut sync_ping,
+            )?;
+            Ok(sync_ping)
+        })
+    }
+
+    /// Sync only bookmarks. See `sync` for notes about the telemetry returned.
+    pub fn sync_bookmarks(
+        &self,
+        client_init: &sync15::Sync15StorageClientInit,
+        key_bundle: &sync15::KeyBundle,
+    ) -> Result<telemetry::SyncTelemetryPing> {
+        self.with_sync_state(|conn, client_info| {
+            let store = BookmarksStore::new(conn, client_info);
+            let mut sync_ping = telemetry::SyncTelemetryPing::new();
+            sync_stores(
+                conn,
+                client_info,
+                &[&store],
+                client_init,
+                key_bundle,
+                &mut sync_ping,
+            )?;
+            Ok(sync_ping)
+        })
+    }
+
+    /// Sync both history and bookmarks in a single `sync_multiple` call, so
+    /// the token server and `meta/global` are only visited once.
+    pub fn sync_all(
+        &self,
+        client_init: &sync15::Sync15StorageClientInit,
+        key_bundle: &sync15::KeyBundle,
+    ) -> Result<telemetry::SyncTelemetryPing> {
+        self.with_sync_state(|conn, client_info| {
+            let history = HistoryStore::new(conn, client_info);
+            let bookmarks = BookmarksStore::new(conn, client_info);
+            let mut sync_ping = telemetry::SyncTelemetryPing::new();
+            sync_stores(
+                conn,
+                client_info,
+                &[&history, &bookmarks],
+                client_init,
+                key_bundle,
+                &mut sync_ping,
+            )?;
+            Ok(sync_ping)
+        })
+    }
+
+    fn with_sync_state<T>(
+        &self,
+        f: impl FnOnce(&PlacesDb, &Cell<Option<ClientInfo>>) -> Result<T>,
+    ) -> Result<T> {
         let mut guard = self.sync_state.lock().unwrap();
         let conn = self.open_sync_connection()?;
         if guard.is_none() {
@@ -208,11 +700,85 @@ impl PlacesApi {
             });
         }
         let sync_state = guard.as_ref().unwrap();
-        let store = HistoryStore::new(&conn, &sync_state.client_info);
-        let mut sync_ping = telemetry::SyncTelemetryPing::new();
-        store.sync(&client_init, &key_bundle, &mut sync_ping)?;
-        Ok(sync_ping)
+        f(&*conn, &sync_state.client_info)
+    }
+}
+
+/// Syncs `stores` together with `sync_multiple`, and persists the global
+/// state they share. This is used by `PlacesApi` and `HistoryStore::sync`.
+pub(crate) fn sync_stores(
+    conn: &PlacesDb,
+    client_info: &Cell<Option<ClientInfo>>,
+    stores: &[&dyn Store],
+    client_init: &sync15::Sync15StorageClientInit,
+    key_bundle: &sync15::KeyBundle,
+    sync_ping: &mut telemetry::SyncTelemetryPing,
+) -> Result<()> {
+    let global_state: Cell<Option<String>> =
+        Cell::new(get_meta::<String>(conn, GLOBAL_STATE_META_KEY)?);
+    let result = sync_multiple(
+        stores,
+        &global_state,
+        client_info,
+        client_init,
+        key_bundle,
+        sync_ping,
+    );
+    finish_sync(
+        conn,
+        global_state.replace(None),
+        stores.iter().map(|store| store.collection_name()),
+        result,
+    )
+}
+
+// Even on failure, the global state should be persisted.
+fn finish_sync<'a>(
+    conn: &PlacesDb,
+    global_state: Option<String>,
+    names: impl IntoIterator<Item = &'a str>,
+    result: sync15::Result<HashMap<String, sync15::Error>>,
+) -> Result<()> {
+    put_meta(
+        conn,
+        GLOBAL_STATE_META_KEY,
+        &global_state.unwrap_or_default(),
+    )?;
+    let failures = result?;
+    match first_failure(names, failures) {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+// Each store has been given a chance to sync, but we only have a single error
+// to return. `sync_multiple` returns the failures in a `HashMap`, so, to make
+// the error we return predictable, we report the failure for the first store
+// in the order they were synced, and log the rest.
+fn first_failure<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    mut failures: HashMap<String, sync15::Error>,
+) -> Option<sync15::Error> {
+    let mut first_err = None;
+    for name in names {
+        if let Some(err) = failures.remove(name) {
+            log::warn!("Sync of {} failed: {}", name, err);
+            if first_err.is_none() {
+                first_err = Some(err);
+            }
+        }
+    }
+    // `sync_multiple` only reports failures for the stores we passed, but
+    // don't drop any if that changes.
+    let mut rest: Vec<_> = failures.into_iter().collect();
+    rest.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (name, err) in rest {
+        log::warn!("Sync of {} failed: {}", name, err);
+        if first_err.is_none() {
+            first_err = Some(err);
+        }
     }
+    first_err
 }
 
 /// Wrapper around PlacesDb that automatically sets a flag (`sync_conn_active`)
@@ -338,6 +904,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_frecency_settings() {
+        use crate::frecency::DEFAULT_FRECENCY_SETTINGS;
+        use crate::storage::history::{frecency_stale_at, recalculate_stale_frecencies};
+        use crate::types::Timestamp;
+        use url::Url;
+
+        let name = PathBuf::from("file:test-frecency-settings?mode=memory&cache=shared");
+        let api = PlacesApi::new_or_existing(name.clone(), None).expect("should get an API");
+        let writer = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get writer");
+        assert_eq!(*writer.frecency_settings(), DEFAULT_FRECENCY_SETTINGS);
+        let url = Url::parse("https://www.example.com/").unwrap();
+        crate::storage::history::apply_observation(
+            &writer,
+            crate::VisitObservation::new(url.clone())
+                .with_visit_type(crate::types::VisitTransition::Typed)
+                .with_at(Timestamp::now()),
+        )
+        .expect("should apply visit");
+        let get_frecency = |db: &PlacesDb| -> i64 {
+            db.query_one("SELECT frecency FROM moz_places")
+                .expect("should get frecency")
+        };
+        let old_frecency = get_frecency(&writer);
+
+        let settings = FrecencySettings {
+            typed_visit_bonus: 4000,
+            ..FrecencySettings::default()
+        };
+
+        // The write connection is in use, so we can't change the settings...
+        PlacesApi::new_or_existing_with_frecency_settings(name.clone(), None, Some(&settings))
+            .expect_err("should fail to change settings while the writer is in use");
+        // ...but we can use the same ones.
+        PlacesApi::new_or_existing_with_frecency_settings(
+            name.clone(),
+            None,
+            Some(&DEFAULT_FRECENCY_SETTINGS),
+        )
+        .expect("should allow the same settings while the writer is in use");
+
+        api.close_connection(writer)
+            .expect("should be able to close");
+        let reader = api
+            .open_connection(ConnectionType::ReadOnly)
+            .expect("should get reader");
+        let sync_conn = api
+            .open_sync_connection()
+            .expect("should get sync connection");
+        PlacesApi::new_or_existing_with_frecency_settings(name.clone(), None, Some(&settings))
+            .expect("should change settings");
+        let writer = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get writer");
+        assert_eq!(*writer.frecency_settings(), settings);
+        assert!(frecency_stale_at(&writer, &url)
+            .expect("should check stale frecency")
+            .is_some());
+
+        // Connections that were already open should use the new settings...
+        assert_eq!(*reader.frecency_settings(), settings);
+        assert_eq!(*sync_conn.frecency_settings(), settings);
+        drop(sync_conn);
+
+        // ...as should new ones.
+        let sync_conn = api
+            .open_sync_connection()
+            .expect("should get sync connection");
+        assert_eq!(*sync_conn.frecency_settings(), settings);
+        drop(sync_conn);
+
+        let far_future = Timestamp(Timestamp::now().as_millis() + 60_000);
+        assert_eq!(
+            recalculate_stale_frecencies(&writer, 10, far_future).expect("should recalculate"),
+            0
+        );
+        assert!(get_frecency(&writer) > old_frecency);
+    }
+
     #[test]
     fn test_valid_writer_close() {
         let api = new_mem_api();
@@ -351,4 +998,322 @@ mod tests {
         // Make sure we can open it again.
         assert!(api.open_connection(ConnectionType::ReadWrite).is_ok());
     }
+
+    #[test]
+    fn test_conflicting_keys() {
+        let api = PlacesApi::new_memory("test-conflicting-keys", None).expect("should get an API");
+        let same =
+            PlacesApi::new_memory("test-conflicting-keys", None).expect("should get the same API");
+        assert!(Arc::ptr_eq(&api, &same));
+
+        match PlacesApi::new_memory("test-conflicting-keys", Some("key")) {
+            Err(e) => match e.kind() {
+                ErrorKind::EncryptionKeyMismatch(_) => {}
+                e => panic!("Wrong error: {:?}", e),
+            },
+            Ok(_) => panic!("should fail to open with a different key"),
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let api = PlacesApi::new_memory("test-close", None).expect("should get an API");
+        let writer = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get writer");
+        match api.close().unwrap_err().kind() {
+            ErrorKind::ConnectionAlreadyOpen => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        api.close_connection(writer)
+            .expect("should be able to close");
+
+        let sync_conn = api
+            .open_sync_connection()
+            .expect("should get sync connection");
+        match api.close().unwrap_err().kind() {
+            ErrorKind::ConnectionAlreadyOpen => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        drop(sync_conn);
+
+        // Open read-only connections, including pooled readers, should stop
+        // the API from closing, and shouldn't close it halfway.
+        let reader = api
+            .open_connection(ConnectionType::ReadOnly)
+            .expect("should get reader");
+        match api.close().unwrap_err().kind() {
+            ErrorKind::ConnectionAlreadyOpen => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        drop(reader);
+        let pooled = api.checkout_reader().expect("should check out reader");
+        match api.close().unwrap_err().kind() {
+            ErrorKind::ConnectionAlreadyOpen => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        let writer = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should still get writer");
+        api.close_connection(writer)
+            .expect("should be able to close");
+        drop(pooled);
+
+        // Idle pooled readers are closed along with the API.
+        drop(api.checkout_reader().expect("should check out reader"));
+        api.close().expect("should close");
+        match api.checkout_reader() {
+            Err(e) => match e.kind() {
+                ErrorKind::ApiClosed => {}
+                e => panic!("Wrong error: {:?}", e),
+            },
+            Ok(_) => panic!("shouldn't check out a reader after closing"),
+        }
+        match api
+            .open_connection(ConnectionType::ReadOnly)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::ApiClosed => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        assert!(api.open_sync_connection().is_err());
+        assert!(api.close().is_err());
+
+        // Opening the same database again should make a new API.
+        let new_api = PlacesApi::new_memory("test-close", None).expect("should reopen");
+        assert!(!Arc::ptr_eq(&api, &new_api));
+        new_api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get writer from the new API");
+    }
+
+    #[test]
+    fn test_rekey() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("places.sqlite");
+        let read_value = |api: &PlacesApi| -> Option<String> {
+            let reader = api
+                .open_connection(ConnectionType::ReadOnly)
+                .expect("should get reader");
+            get_meta(&reader, "test_value").expect("should read value")
+        };
+
+        {
+            let api = PlacesApi::new(&path, None).expect("should open unencrypted");
+            let writer = api
+                .open_connection(ConnectionType::ReadWrite)
+                .expect("should get writer");
+            put_meta(&writer, "test_value", &"hello").expect("should write value");
+            api.close_connection(writer)
+                .expect("should be able to close");
+
+            match api.rekey(Some("wrong"), Some("key1")).unwrap_err().kind() {
+                ErrorKind::WrongEncryptionKey => {}
+                e => panic!("Wrong error: {:?}", e),
+            }
+
+            // Encrypting fails while a reader is open, and leaves the
+            // database as it was.
+            let reader = api
+                .open_connection(ConnectionType::ReadOnly)
+                .expect("should get reader");
+            match api.rekey(None, Some("key1")).unwrap_err().kind() {
+                ErrorKind::ConnectionAlreadyOpen => {}
+                e => panic!("Wrong error: {:?}", e),
+            }
+            drop(reader);
+            assert_eq!(read_value(&api), Some("hello".to_string()));
+
+            // ...But idle pooled readers are closed first.
+            let pooled = api.checkout_reader().expect("should check out reader");
+            assert_eq!(
+                get_meta::<String>(&pooled, "test_value").expect("should read value"),
+                Some("hello".to_string())
+            );
+            drop(pooled);
+
+            api.rekey(None, Some("key1")).expect("should encrypt");
+            assert_eq!(read_value(&api), Some("hello".to_string()));
+
+            // Changing the key also fails while a reader is checked out.
+            let pooled = api.checkout_reader().expect("should check out reader");
+            match api.rekey(Some("key1"), Some("key2")).unwrap_err().kind() {
+                ErrorKind::ConnectionAlreadyOpen => {}
+                e => panic!("Wrong error: {:?}", e),
+            }
+            drop(pooled);
+
+            api.rekey(Some("key1"), Some("key2"))
+                .expect("should change key");
+            assert_eq!(read_value(&api), Some("hello".to_string()));
+        }
+
+        assert!(
+            PlacesApi::new(&path, Some("key1")).is_err(),
+            "should fail to open with the old key"
+        );
+        {
+            let api = PlacesApi::new(&path, Some("key2")).expect("should open with the new key");
+            assert_eq!(read_value(&api), Some("hello".to_string()));
+            api.rekey(Some("key2"), None).expect("should decrypt");
+            assert_eq!(read_value(&api), Some("hello".to_string()));
+        }
+
+        let api = PlacesApi::new(&path, None).expect("should open unencrypted");
+        assert_eq!(read_value(&api), Some("hello".to_string()));
+        assert!(api.recovery_report().is_none());
+    }
+
+    #[test]
+    fn test_set_recent_visit_window() {
+        use crate::api::history::DEFAULT_RECENT_VISIT_WINDOW;
+
+        let api = new_mem_api();
+        let writer = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get writer");
+        assert_eq!(writer.recent_visits().window(), DEFAULT_RECENT_VISIT_WINDOW);
+
+        // Changing the window while the writer is checked out should
+        // apply to it right away...
+        api.set_recent_visit_window(Duration::from_secs(0));
+        assert_eq!(writer.recent_visits().window(), Duration::from_secs(0));
+
+        // ...and stick after it's returned.
+        api.close_connection(writer)
+            .expect("should be able to close");
+        api.set_recent_visit_window(Duration::from_secs(60));
+        let writer = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get writer again");
+        assert_eq!(writer.recent_visits().window(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_sync_while_syncing() {
+        let api = new_mem_api();
+        let client_init = sync15::Sync15StorageClientInit {
+            key_id: "key-id".to_string(),
+            access_token: "access-token".to_string(),
+            tokenserver_url: url::Url::parse("https://example.com/").unwrap(),
+        };
+        let key_bundle = sync15::KeyBundle::new_random().unwrap();
+
+        // Only one sync can run at a time, so these should fail before
+        // making any requests.
+        let _sync_conn = api
+            .open_sync_connection()
+            .expect("should get sync connection");
+        for result in &[
+            api.sync(&client_init, &key_bundle),
+            api.sync_bookmarks(&client_init, &key_bundle),
+            api.sync_all(&client_init, &key_bundle),
+        ] {
+            match result.as_ref().unwrap_err().kind() {
+                ErrorKind::ConnectionAlreadyOpen => {}
+                e => panic!("Wrong error: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_finish_sync() {
+        let conn = new_mem_connection();
+        let names = || vec!["history", "bookmarks"];
+        let global_state = || get_meta::<String>(&conn, GLOBAL_STATE_META_KEY).unwrap();
+        assert_eq!(global_state(), None);
+
+        // The global state should be persisted even if the sync failed
+        // before syncing any stores.
+        let err = finish_sync(
+            &conn,
+            None,
+            names(),
+            Err(sync15::ErrorKind::TokenserverHttpError(401).into()),
+        )
+        .expect_err("should fail");
+        match err.kind() {
+            ErrorKind::SyncAdapterError(_) => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        assert_eq!(global_state(), Some(String::new()));
+
+        let mut failures = HashMap::new();
+        failures.insert(
+            "bookmarks".to_string(),
+            sync15::ErrorKind::TokenserverHttpError(500).into(),
+        );
+        finish_sync(&conn, Some("state".into()), names(), Ok(failures))
+            .expect_err("should report the bookmarks failure");
+        assert_eq!(global_state(), Some("state".to_string()));
+
+        finish_sync(&conn, Some("new state".into()), names(), Ok(HashMap::new()))
+            .expect("should succeed");
+        assert_eq!(global_state(), Some("new state".to_string()));
+    }
+
+    #[test]
+    fn test_set_use_fts_index() {
+        let api = new_mem_api();
+        let reader = api
+            .open_connection(ConnectionType::ReadOnly)
+            .expect("should get reader");
+        assert!(!reader.use_fts_index().expect("should check for the index"));
+
+        api.set_use_fts_index(true)
+            .expect("should enable the index");
+        // Readers see the index, even if they were opened before it existed.
+        assert!(reader.use_fts_index().expect("should check for the index"));
+        let pooled = api.checkout_reader().expect("should check out reader");
+        assert!(pooled.use_fts_index().expect("should check for the index"));
+        drop(pooled);
+        match reader.set_use_fts_index(false).unwrap_err().kind() {
+            ErrorKind::InvalidConnectionType => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+
+        let writer = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get writer");
+        match api.set_use_fts_index(false).unwrap_err().kind() {
+            ErrorKind::ConnectionAlreadyOpen => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        writer
+            .set_use_fts_index(false)
+            .expect("should disable the index");
+        assert!(!reader.use_fts_index().expect("should check for the index"));
+    }
+
+    #[test]
+    fn test_first_failure() {
+        let failures = || {
+            let mut failures = HashMap::new();
+            failures.insert(
+                "bookmarks".to_string(),
+                sync15::ErrorKind::TokenserverHttpError(401).into(),
+            );
+            failures.insert(
+                "history".to_string(),
+                sync15::ErrorKind::TokenserverHttpError(500).into(),
+            );
+            failures
+        };
+        let code = |err: Option<sync15::Error>| match err.as_ref().map(sync15::Error::kind) {
+            Some(sync15::ErrorKind::TokenserverHttpError(code)) => *code,
+            e => panic!("Wrong error: {:?}", e),
+        };
+        // The error should follow the order of the stores, not the map.
+        assert_eq!(
+            code(first_failure(vec!["history", "bookmarks"], failures())),
+            500
+        );
+        assert_eq!(
+            code(first_failure(vec!["bookmarks", "history"], failures())),
+            401
+        );
+        assert_eq!(code(first_failure(vec!["history"], failures())), 500);
+        assert!(first_failure(vec!["history"], HashMap::new()).is_none());
+    }
 }
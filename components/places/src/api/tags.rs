@@ -0,0 +1,13 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Tags are stored per URL, rather than per bookmark, so a URL can be tagged
+//! whether or not it's bookmarked, as long as it's in Places. Tagged URLs
+//! show up in autocomplete results with `MatchReason::Tags`, and the `+`
+//! restriction token limits a search to them.
+
+pub use crate::storage::tags::{
+    get_tags_for_url, get_urls_with_tag, remove_all_tags_from_url, remove_tag, rename_tag, tag_url,
+    untag_url, validate_tag, ValidatedTag,
+};
@@ -0,0 +1,431 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Dated backups of the bookmarks tree, like desktop's automatic bookmark
+//! backups.
+//!
+//! Each backup is a JSON object with the serialization of `fetch_tree` for
+//! the bookmarks root, and the tags and keywords for each bookmarked URL. It's
+//! compressed with gzip, and named after the UTC date it was made and the
+//! number of items it contains, like `bookmarks-2019-05-01_42.json.gz`. We
+//! keep at most one backup per day, and only the newest backups up to a
+//! limit.
+
+use crate::bookmark_sync::store::reset_in_tx;
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::observers::ChangeSource;
+use crate::storage::bookmarks::{
+    add_subtree_infos, delete_bookmark_in_tx, fetch_tree, insert_bookmark_in_tx, BookmarkRootGuid,
+    BookmarkTreeNode, USER_CONTENT_ROOTS,
+};
+use crate::storage::{delete_pending_temp_tables, keywords, tags};
+use crate::types::{SyncGuid, Timestamp};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde_derive::*;
+use sql_support::ConnExt;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const BACKUP_PREFIX: &str = "bookmarks-";
+const BACKUP_SUFFIX: &str = ".json.gz";
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Describes a backup file.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    /// The UTC date the backup was made, like `2019-05-01`.
+    pub date: String,
+    /// The number of bookmarks, folders and separators in the backup, not
+    /// counting the roots.
+    pub item_count: u32,
+}
+
+/// The contents of a backup file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Backup {
+    root: BookmarkTreeNode,
+    /// The tags for each bookmarked URL.
+    #[serde(default)]
+    tags: BTreeMap<String, Vec<String>>,
+    /// The keyword for each bookmarked URL.
+    #[serde(default)]
+    keywords: BTreeMap<String, String>,
+}
+
+impl BackupInfo {
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let (date, item_count) = {
+            let name = path.file_name()?.to_str()?;
+            if !name.starts_with(BACKUP_PREFIX) || !name.ends_with(BACKUP_SUFFIX) {
+                return None;
+            }
+            let stem = &name[BACKUP_PREFIX.len()..name.len() - BACKUP_SUFFIX.len()];
+            let mut parts = stem.splitn(2, '_');
+            let date = parts.next()?;
+            if date.len() != 10 {
+                return None;
+            }
+            (date.to_string(), parts.next()?.parse().ok()?)
+        };
+        Some(BackupInfo {
+            path,
+            date,
+            item_count,
+        })
+    }
+}
+
+/// Writes a backup of all bookmarks to `dir`, creating it if it doesn't
+/// exist. This replaces any other backup made on the same day, and then
+/// removes the oldest backups so that at most `max_backups` are left. The new
+/// backup is always kept, even if `max_backups` is 0.
+pub fn create_backup(db: &PlacesDb, dir: &Path, max_backups: usize) -> Result<BackupInfo> {
+    let tree = fetch_tree(db, BookmarkRootGuid::Root.guid())?
+        .ok_or_else(|| Corruption::InvalidLocalRoots)?;
+    let item_count = match &tree {
+        BookmarkTreeNode::Folder(root) => root.children.iter().map(count_items).sum(),
+        _ => return Err(Corruption::InvalidLocalRoots.into()),
+    };
+    let mut backup = Backup {
+        root: tree,
+        tags: BTreeMap::new(),
+        keywords: BTreeMap::new(),
+    };
+    let tag_rows = db.query_rows_and_then_named(
+        "SELECT h.url, t.tag
+         FROM moz_tags t
+         JOIN moz_tags_relation r ON r.tag_id = t.id
+         JOIN moz_places h ON h.id = r.place_id
+         WHERE EXISTS(SELECT 1 FROM moz_bookmarks b WHERE b.fk = h.id)
+         ORDER BY t.tag",
+        &[],
+        |row| -> Result<(String, String)> { Ok((row.get_checked(0)?, row.get_checked(1)?)) },
+    )?;
+    for (url, tag) in tag_rows {
+        backup.tags.entry(url).or_default().push(tag);
+    }
+    backup.keywords = db
+        .query_rows_and_then_named(
+            "SELECT h.url, k.keyword
+             FROM moz_keywords k
+             JOIN moz_places h ON h.id = k.place_id
+             WHERE EXISTS(SELECT 1 FROM moz_bookmarks b WHERE b.fk = h.id)",
+            &[],
+            |row| -> Result<(String, String)> { Ok((row.get_checked(0)?, row.get_checked(1)?)) },
+        )?
+        .into_iter()
+        .collect();
+    let date = format_date(Timestamp::now());
+    fs::create_dir_all(dir)?;
+    let info = BackupInfo {
+        path: dir.join(format!(
+            "{}{}_{}{}",
+            BACKUP_PREFIX, date, item_count, BACKUP_SUFFIX
+        )),
+        date,
+        item_count,
+    };
+
+    // Write to a temporary file first, so that we never leave a truncated
+    // backup behind.
+    let temp_path = info.path.with_extension("tmp");
+    {
+        let file = File::create(&temp_path)?;
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+        serde_json::to_writer(&mut encoder, &backup)?;
+        encoder.finish()?.flush()?;
+    }
+    fs::rename(&temp_path, &info.path)?;
+
+    let mut kept = 1;
+    for backup in list_backups(dir)? {
+        if backup.path == info.path {
+            continue;
+        }
+        if backup.date == info.date || kept >= max_backups {
+            log::debug!("Removing old bookmark backup {:?}", backup.path);
+            fs::remove_file(&backup.path)?;
+        } else {
+            kept += 1;
+        }
+    }
+    Ok(info)
+}
+
+/// Lists the backups in `dir`, newest first. Returns an empty list if `dir`
+/// doesn't exist.
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        if let Some(info) = BackupInfo::from_path(entry?.path()) {
+            backups.push(info);
+        }
+    }
+    backups.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| b.path.cmp(&a.path)));
+    Ok(backups)
+}
+
+/// Replaces all bookmarks, and the tags and keywords for bookmarked URLs,
+/// with the contents of the backup at `path`. This also resets the bookmark
+/// sync state, so that the next sync merges the restored tree with the server
+/// as if it was the first sync. Synced items that aren't in the backup are
+/// still deleted from the server, since we keep their tombstones.
+pub fn restore_backup(db: &PlacesDb, path: &Path) -> Result<()> {
+    let file = File::open(path)?;
+    let backup: Backup = serde_json::from_reader(GzDecoder::new(BufReader::new(file)))?;
+    let root = match &backup.root {
+        BookmarkTreeNode::Folder(root)
+            if root.guid.as_ref() == Some(BookmarkRootGuid::Root.guid()) =>
+        {
+            root
+        }
+        _ => return Err(ErrorKind::InvalidBookmarkBackup.into()),
+    };
+
+    let mut insert_infos = Vec::new();
+    for child in &root.children {
+        match child {
+            BookmarkTreeNode::Folder(folder) if is_user_content_root(&folder.guid) => {
+                add_subtree_infos(child.guid(), folder, &mut insert_infos)
+            }
+            _ => log::warn!("Ignoring unexpected item in the bookmark backup root"),
+        }
+    }
+
+    let tx = db.coop_transaction()?;
+    db.execute_batch(
+        "DELETE FROM moz_tags_relation
+         WHERE place_id IN (SELECT fk FROM moz_bookmarks);
+
+         DELETE FROM moz_keywords
+         WHERE place_id IN (SELECT fk FROM moz_bookmarks);",
+    )?;
+    let mut events = Vec::new();
+    for user_root in USER_CONTENT_ROOTS {
+        // Remove the last child first, so that the positions in the events
+        // match the positions before the restore.
+        let guids = db.query_rows_and_then_named(
+            "SELECT b.guid FROM moz_bookmarks b
+             JOIN moz_bookmarks p ON p.id = b.parent
+             WHERE p.guid = :guid
+             ORDER BY b.position DESC",
+            &[(":guid", user_root.guid())],
+            |row| -> Result<_> { Ok(row.get_checked::<_, SyncGuid>(0)?) },
+        )?;
+        for guid in guids {
+            events.extend(delete_bookmark_in_tx(db, &guid)?);
+        }
+    }
+    for insertable in insert_infos {
+        events.push(insert_bookmark_in_tx(db, &insertable)?);
+    }
+    for (url, url_tags) in &backup.tags {
+        let url = Url::parse(url)?;
+        for tag in url_tags {
+            tags::tag_url_in_tx(db, &url, tag)?;
+        }
+    }
+    for (url, keyword) in &backup.keywords {
+        keywords::set_keyword_in_tx(db, &Url::parse(url)?, keyword)?;
+    }
+    // Resetting the sync state also removes all tombstones, including the
+    // ones for synced items that we just deleted, so we put them back.
+    // Otherwise, the first sync would download those items again.
+    let tombstones = db.query_rows_and_then_named(
+        "SELECT guid, dateRemoved FROM moz_bookmarks_deleted",
+        &[],
+        |row| -> Result<(SyncGuid, Timestamp)> { Ok((row.get_checked(0)?, row.get_checked(1)?)) },
+    )?;
+    reset_in_tx(db)?;
+    for (guid, date_removed) in tombstones {
+        db.execute_named_cached(
+            "INSERT INTO moz_bookmarks_deleted(guid, dateRemoved)
+             VALUES(:guid, :date_removed)",
+            &[(":guid", &guid), (":date_removed", &date_removed)],
+        )?;
+    }
+    delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    db.observers()
+        .notify_bookmark_observers(ChangeSource::Local, &events);
+    Ok(())
+}
+
+fn is_user_content_root(guid: &Option<SyncGuid>) -> bool {
+    guid.as_ref()
+        .and_then(BookmarkRootGuid::from_guid)
+        .map_or(false, |root| USER_CONTENT_ROOTS.contains(&root))
+}
+
+fn count_items(node: &BookmarkTreeNode) -> u32 {
+    match node {
+        BookmarkTreeNode::Folder(folder) => folder
+            .children
+            .iter()
+            .map(|child| 1 + count_items(child))
+            .sum(),
+        _ => 0,
+    }
+}
+
+// Formats a timestamp as a UTC date, like `2019-05-01`. This is the
+// `civil_from_days` algorithm from
+// http://howardhinnant.github.io/date_algorithms.html, simplified for dates
+// after the epoch.
+fn format_date(when: Timestamp) -> String {
+    let z = when.as_millis() / MS_PER_DAY + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use crate::types::SyncStatus;
+    use rusqlite::NO_PARAMS;
+    use serde_json::json;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(Timestamp(0)), "1970-01-01");
+        assert_eq!(format_date(Timestamp(1_556_668_800_000)), "2019-05-01");
+        assert_eq!(format_date(Timestamp(951_782_400_000)), "2000-02-29");
+        assert_eq!(format_date(Timestamp(1_709_164_799_999)), "2024-02-28");
+    }
+
+    #[test]
+    fn test_backup_and_restore() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = new_mem_connection();
+        let dir = tempfile::tempdir()?;
+        let unfiled = BookmarkRootGuid::Unfiled.as_guid();
+        let tree = json!({
+            "guid": &unfiled,
+            "children": [
+                {
+                    "guid": "bookmark1___",
+                    "title": "the bookmark",
+                    "url": "https://www.example.com/",
+                },
+                {
+                    "guid": "folder1_____",
+                    "title": "the folder",
+                    "children": [
+                        {
+                            "guid": "bookmark2___",
+                            "url": "https://www.example2.com/",
+                        },
+                    ]
+                },
+            ]
+        });
+        insert_json_tree(&conn, tree.clone());
+        let url1 = Url::parse("https://www.example.com/")?;
+        let url2 = Url::parse("https://www.example2.com/")?;
+        tags::tag_url(&conn, &url1, "one")?;
+        tags::tag_url(&conn, &url1, "two")?;
+        keywords::set_keyword(&conn, &url2, "ex")?;
+
+        // Write a stale backup from another day, and a file that isn't a
+        // backup, to make sure they're handled correctly.
+        fs::write(dir.path().join("bookmarks-2019-05-01_3.json.gz"), b"")?;
+        fs::write(dir.path().join("notes.txt"), b"")?;
+
+        let info = create_backup(&conn, dir.path(), 5)?;
+        assert_eq!(info.item_count, 3);
+        assert_eq!(info.date, format_date(Timestamp::now()));
+        let backups = list_backups(dir.path())?;
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0], info);
+        assert_eq!(backups[1].date, "2019-05-01");
+
+        // A second backup on the same day replaces the first, and rotation
+        // removes the older backup.
+        let info = create_backup(&conn, dir.path(), 1)?;
+        assert_eq!(list_backups(dir.path())?, vec![info.clone()]);
+        assert!(dir.path().join("notes.txt").exists());
+
+        // Mess up the tree, tags and keywords, pretend we've synced, then
+        // restore.
+        tags::untag_url(&conn, &url1, "one")?;
+        tags::tag_url(&conn, &url1, "three")?;
+        keywords::set_keyword(&conn, &url1, "ex")?;
+        crate::storage::bookmarks::delete_bookmark(&conn, &"folder1_____".into())?;
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": &unfiled,
+                "children": [
+                    {
+                        "guid": "bookmark3___",
+                        "url": "https://www.example3.com/",
+                    },
+                ]
+            }),
+        );
+        conn.execute(
+            &format!(
+                "UPDATE moz_bookmarks SET syncStatus = {}",
+                SyncStatus::Normal as u8
+            ),
+            NO_PARAMS,
+        )?;
+        crate::storage::bookmarks::delete_bookmark(&conn, &"bookmark1___".into())?;
+        assert_eq!(
+            conn.query_one::<i64>("SELECT COUNT(*) FROM moz_bookmarks_deleted")?,
+            1
+        );
+
+        restore_backup(&conn, &info.path)?;
+        assert_json_tree(&conn, &unfiled, tree);
+        let mut url1_tags = tags::get_tags_for_url(&conn, &url1)?;
+        url1_tags.sort();
+        assert_eq!(url1_tags, vec!["one".to_string(), "two".to_string()]);
+        assert_eq!(keywords::get_keyword_for_url(&conn, &url1)?, None);
+        assert_eq!(
+            keywords::get_keyword_for_url(&conn, &url2)?,
+            Some("ex".to_string())
+        );
+        // The synced bookmark that isn't in the backup should have a
+        // tombstone, but the restored one shouldn't.
+        assert_eq!(
+            conn.query_rows_and_then_named(
+                "SELECT guid FROM moz_bookmarks_deleted",
+                &[],
+                |row| -> Result<SyncGuid> { Ok(row.get_checked(0)?) },
+            )?,
+            vec![SyncGuid::from("bookmark3___")]
+        );
+        assert_eq!(
+            conn.query_one::<i64>(&format!(
+                "SELECT COUNT(*) FROM moz_bookmarks WHERE syncStatus <> {}",
+                SyncStatus::New as u8
+            ))?,
+            0
+        );
+
+        // Restoring something that isn't a backup should fail.
+        let bad_path = dir.path().join("bookmarks-2019-05-02_0.json.gz");
+        fs::write(&bad_path, b"not a backup")?;
+        restore_backup(&conn, &bad_path).expect_err("should fail to restore");
+        Ok(())
+    }
+}
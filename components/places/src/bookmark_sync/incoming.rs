@@ -10,6 +10,7 @@ use super::{SyncedBookmarkKind, SyncedBookmarkValidity};
 use crate::error::*;
 use crate::storage::{
     bookmarks::maybe_truncate_title,
+    keywords::normalize_keyword,
     tags::{validate_tag, ValidatedTag},
     URL_LENGTH_MAX,
 };
@@ -66,16 +67,18 @@ impl<'a> IncomingApplicator<'a> {
             }
         };
         let tags = b.tags.iter().map(|t| validate_tag(t));
+        let keyword = b.keyword.as_ref().and_then(|k| normalize_keyword(k));
         let validity = if url.is_none() {
             // The bookmark has an invalid URL, so we can't apply it.
             SyncedBookmarkValidity::Replace
-        } else if tags.clone().all(|t| t.is_original()) {
-            // The bookmark has a valid URL and all original tags, so we can
-            // apply it as-is.
+        } else if tags.clone().all(|t| t.is_original()) && keyword == b.keyword {
+            // The bookmark has a valid URL, all original tags, and an original
+            // keyword, so we can apply it as-is.
             SyncedBookmarkValidity::Valid
         } else {
-            // The bookmark has a valid URL, but invalid or normalized tags. We
-            // can apply it, but should also reupload it with the new tags.
+            // The bookmark has a valid URL, but invalid or normalized tags or
+            // keyword. We can apply it, but should also reupload it with the
+            // new tags and keyword.
             SyncedBookmarkValidity::Reupload
         };
         self.db.execute_named_cached(
@@ -97,7 +100,7 @@ impl<'a> IncomingApplicator<'a> {
                 (":kind", &SyncedBookmarkKind::Bookmark),
                 (":dateAdded", &b.date_added),
                 (":title", &maybe_truncate_title(&b.title)),
-                (":keyword", &b.keyword),
+                (":keyword", &keyword),
                 (":validity", &validity),
                 (":url", &url),
             ],
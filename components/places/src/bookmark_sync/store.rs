@@ -12,6 +12,7 @@ use super::{SyncedBookmarkKind, SyncedBookmarkValidity};
 use crate::api::places_api::ConnectionType;
 use crate::db::{PlacesDb, TimeChunkedTransaction};
 use crate::error::*;
+use crate::observers::{BookmarkEvent, ChangeSource};
 use crate::storage::{bookmarks::BookmarkRootGuid, get_meta, put_meta};
 use crate::types::{BookmarkType, SyncGuid, SyncStatus, Timestamp};
 use dogear::{
@@ -20,13 +21,14 @@ use dogear::{
 use rusqlite::{Row, NO_PARAMS};
 use sql_support::{self, ConnExt};
 use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::result;
 use sync15::{
     telemetry, ClientInfo, CollectionRequest, IncomingChangeset, OutgoingChangeset, Payload,
     ServerTimestamp, Store,
 };
+use url::Url;
 static LAST_SYNC_META_KEY: &'static str = "bookmarks_last_sync_time";
 
 pub struct BookmarksStore<'a> {
@@ -101,12 +103,15 @@ impl<'a> BookmarksStore<'a> {
     /// Conceptually, we examine the merge state of each item, and either leave the
     /// item unchanged, upload the local side, apply the remote side, or apply and
     /// then reupload the remote side with a new structure.
+    ///
+    /// Returns the bookmark events for the changes, if any observers are
+    /// registered.
     fn update_local_items<'t>(
         &self,
         descendants: Vec<MergedDescendant<'t>>,
         deletions: Vec<Deletion>,
         _tx: &mut TimeChunkedTransaction,
-    ) -> Result<()> {
+    ) -> Result<Vec<BookmarkEvent>> {
         // First, insert rows for all merged descendants.
         sql_support::each_sized_chunk(
             &descendants,
@@ -164,6 +169,30 @@ impl<'a> BookmarksStore<'a> {
             Ok(())
         })?;
 
+        // If anyone's observing, note the state of the local items before we
+        // change them, so that we can report what the merge changed. The old
+        // states are keyed by their merged GUIDs, since deduping changes the
+        // local GUID.
+        let observing = self.db.observers().has_bookmark_observers();
+        let (old_items, removed_items) = if observing {
+            (
+                self.fetch_local_item_states(
+                    "r.mergedGuid",
+                    "mergedTree r
+                     JOIN moz_bookmarks b ON b.guid = r.localGuid",
+                    "",
+                )?,
+                self.fetch_local_item_states(
+                    "b.guid",
+                    "itemsToRemove d
+                     JOIN moz_bookmarks b ON b.guid = d.guid",
+                    "ORDER BY d.localLevel DESC",
+                )?,
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
         // `itemsToMerge` is a view, so "deleting" from it fires the
         // `insertNewLocalItems` and `updateExistingLocalItems`
         // triggers instead.
@@ -176,7 +205,41 @@ impl<'a> BookmarksStore<'a> {
         // Deleting from `itemsToRemove` fires the `removeLocalItems` trigger.
         self.db.execute_batch("DELETE FROM itemsToRemove")?;
 
-        Ok(())
+        if !observing {
+            return Ok(Vec::new());
+        }
+        let new_items = self.fetch_local_item_states(
+            "b.guid",
+            "mergedTree r
+             JOIN moz_bookmarks b ON b.guid = r.mergedGuid",
+            "ORDER BY r.level, r.position",
+        )?;
+        Ok(merge_events(old_items, removed_items, new_items))
+    }
+
+    /// Fetches the parents, positions, and properties of local items, for
+    /// figuring out which bookmark events to emit for a merge. `items` must
+    /// join `moz_bookmarks` as `b`. Roots aren't included, since they don't
+    /// have parents.
+    fn fetch_local_item_states(
+        &self,
+        guid_column: &str,
+        items: &str,
+        order_by: &str,
+    ) -> Result<Vec<LocalItemState>> {
+        let sql = format!(
+            "SELECT {guid_column} AS guid, p.guid AS parentGuid, b.position,
+                    b.type, NULLIF(b.title, '') AS title, h.url
+             FROM {items}
+             JOIN moz_bookmarks p ON p.id = b.parent
+             LEFT JOIN moz_places h ON h.id = b.fk
+             {order_by}",
+            guid_column = guid_column,
+            items = items,
+            order_by = order_by
+        );
+        self.db
+            .query_rows_and_then_named(&sql, &[], LocalItemState::from_row)
     }
 
     /// Stores a snapshot of all locally changed items in a temporary table for
@@ -210,11 +273,11 @@ impl<'a> BookmarksStore<'a> {
                                        kind, url, keyword, position)
              SELECT s.id, s.guid, s.syncChangeCounter, s.parentGuid,
                     s.parentTitle, s.dateAdded, s.title, s.placeId,
-                    {kind}, h.url, v.keyword, s.position
+                    {kind}, h.url, k.keyword, s.position
              FROM localItems s
              JOIN mergedTree r ON r.mergedGuid = s.guid
-             LEFT JOIN moz_bookmarks_synced v ON v.guid = r.remoteGuid
              LEFT JOIN moz_places h ON h.id = s.placeId
+             LEFT JOIN moz_keywords k ON k.place_id = s.placeId
              LEFT JOIN idsToWeaklyUpload w ON w.id = s.id
              WHERE s.guid <> '{root_guid}' AND
                    (s.syncChangeCounter > 0 OR w.id NOT NULL)",
@@ -471,18 +534,7 @@ impl<'a> Store for BookmarksStore<'a> {
     /// sync time.
     fn reset(&self) -> result::Result<(), failure::Error> {
         let tx = self.db.time_chunked_transaction()?;
-        self.db.execute_batch(&format!(
-            "DELETE FROM moz_bookmarks_synced;
-
-             DELETE FROM moz_bookmarks_deleted;
-
-             UPDATE moz_bookmarks
-             SET syncChangeCounter = 0,
-                 syncStatus = {}",
-            (SyncStatus::New as u8)
-        ))?;
-        create_synced_bookmark_roots(self.db)?;
-        put_meta(self.db, LAST_SYNC_META_KEY, &0)?;
+        reset_in_tx(self.db)?;
         tx.commit()?;
         Ok(())
     }
@@ -524,6 +576,25 @@ impl<'a> Store for BookmarksStore<'a> {
     }
 }
 
+/// Resets all bookmark sync state, so that the next sync is treated as the
+/// first. See `BookmarksStore::reset` for details. Assumes a transaction is
+/// already set up by the caller.
+pub(crate) fn reset_in_tx(db: &PlacesDb) -> Result<()> {
+    db.execute_batch(&format!(
+        "DELETE FROM moz_bookmarks_synced;
+
+         DELETE FROM moz_bookmarks_deleted;
+
+         UPDATE moz_bookmarks
+         SET syncChangeCounter = 0,
+             syncStatus = {}",
+        (SyncStatus::New as u8)
+    ))?;
+    create_synced_bookmark_roots(db)?;
+    put_meta(db, LAST_SYNC_META_KEY, &0)?;
+    Ok(())
+}
+
 struct Driver;
 
 impl dogear::Driver for Driver {
@@ -823,7 +894,8 @@ impl<'a> dogear::Store<Error> for Merger<'a> {
         let deletions = deletions.collect::<Vec<_>>();
 
         let mut tx = self.store.db.time_chunked_transaction()?;
-        self.store
+        let events = self
+            .store
             .update_local_items(descendants, deletions, &mut tx)?;
         self.store.stage_local_items_to_upload()?;
         self.store.db.execute_batch(
@@ -831,10 +903,105 @@ impl<'a> dogear::Store<Error> for Merger<'a> {
              DELETE FROM idsToWeaklyUpload;",
         )?;
         tx.commit()?;
+        self.store
+            .db
+            .observers()
+            .notify_bookmark_observers(ChangeSource::Sync, &events);
         Ok(())
     }
 }
 
+/// The state of a local item before or after a merge.
+struct LocalItemState {
+    guid: SyncGuid,
+    parent_guid: SyncGuid,
+    position: u32,
+    item_type: BookmarkType,
+    title: Option<String>,
+    url: Option<Url>,
+}
+
+impl LocalItemState {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(Self {
+            guid: row.get_checked("guid")?,
+            parent_guid: row.get_checked("parentGuid")?,
+            position: row.get_checked("position")?,
+            item_type: row.get_checked("type")?,
+            title: row.get_checked("title")?,
+            url: match row.get_checked::<_, Option<String>>("url")? {
+                Some(s) => Some(Url::parse(&s)?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Compares the states of local items before and after a merge, and returns
+/// the bookmark events for the differences. `new_items` should be ordered
+/// so that parents come before their children.
+fn merge_events(
+    old_items: Vec<LocalItemState>,
+    removed_items: Vec<LocalItemState>,
+    new_items: Vec<LocalItemState>,
+) -> Vec<BookmarkEvent> {
+    let mut old_items = old_items
+        .into_iter()
+        .map(|item| (item.guid.clone(), item))
+        .collect::<HashMap<_, _>>();
+    // Like local deletions, removing a folder only reports the folder, and
+    // not its descendants.
+    let removed_guids = removed_items
+        .iter()
+        .map(|item| item.guid.clone())
+        .collect::<HashSet<_>>();
+    let mut events = removed_items
+        .into_iter()
+        .filter(|item| !removed_guids.contains(&item.parent_guid))
+        .map(|item| BookmarkEvent::ItemRemoved {
+            guid: item.guid,
+            parent_guid: item.parent_guid,
+            position: item.position,
+            item_type: item.item_type,
+            url: item.url,
+        })
+        .collect::<Vec<_>>();
+    for item in new_items {
+        let old_item = match old_items.remove(&item.guid) {
+            Some(old_item) => old_item,
+            None => {
+                events.push(BookmarkEvent::ItemAdded {
+                    guid: item.guid,
+                    parent_guid: item.parent_guid,
+                    position: item.position,
+                    item_type: item.item_type,
+                    url: item.url,
+                    title: item.title,
+                });
+                continue;
+            }
+        };
+        if old_item.parent_guid != item.parent_guid || old_item.position != item.position {
+            events.push(BookmarkEvent::ItemMoved {
+                guid: item.guid.clone(),
+                old_parent_guid: old_item.parent_guid,
+                old_position: old_item.position,
+                new_parent_guid: item.parent_guid,
+                new_position: item.position,
+            });
+        }
+        if old_item.title != item.title || old_item.url != item.url {
+            events.push(BookmarkEvent::ItemChanged {
+                guid: item.guid,
+                item_type: item.item_type,
+                url: item.url,
+                title: item.title,
+            });
+        }
+    }
+    events
+}
+
 /// A helper that interpolates a named SQL common table expression (CTE) for
 /// local items. The CTE may be included in a `WITH RECURSIVE` clause.
 struct LocalItemsFragment<'a>(&'a str);
@@ -955,6 +1122,7 @@ mod tests {
     use crate::storage::{
         bookmarks::{get_raw_bookmark, update_bookmark, UpdatableBookmark, USER_CONTENT_ROOTS},
         history::frecency_stale_at,
+        keywords::{get_keyword_for_url, get_url_for_keyword, set_keyword},
         tags,
     };
     use crate::tests::{
@@ -1249,6 +1417,203 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_apply_observers() {
+        use crate::observers::BookmarkObserver;
+        use std::sync::{Arc, Mutex};
+
+        let api = new_mem_api();
+        let conn = api
+            .open_sync_connection()
+            .expect("should get a sync connection");
+        apply_incoming(
+            &conn,
+            json!([{
+                "id": "menu",
+                "type": "folder",
+                "parentid": "places",
+                "parentName": "",
+                "dateAdded": 0,
+                "title": "menu",
+                "children": ["folder1_____", "bookmark2___"],
+            }, {
+                "id": "folder1_____",
+                "type": "folder",
+                "parentid": "menu",
+                "parentName": "menu",
+                "dateAdded": 1_381_542_355_843u64,
+                "title": "A folder",
+                "children": ["bookmark1___"],
+            }, {
+                "id": "bookmark1___",
+                "type": "bookmark",
+                "parentid": "folder1_____",
+                "parentName": "A folder",
+                "dateAdded": 1_381_542_355_843u64,
+                "title": "1",
+                "bmkUri": "http://example.com/1",
+            }, {
+                "id": "bookmark2___",
+                "type": "bookmark",
+                "parentid": "menu",
+                "parentName": "menu",
+                "dateAdded": 1_381_542_355_843u64,
+                "title": "2",
+                "bmkUri": "http://example.com/2",
+            }]),
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observer: Arc<dyn BookmarkObserver> = {
+            let events = events.clone();
+            Arc::new(move |source: ChangeSource, new_events: &[BookmarkEvent]| {
+                assert_eq!(source, ChangeSource::Sync);
+                events.lock().unwrap().extend_from_slice(new_events);
+            })
+        };
+        api.register_bookmark_observer(observer);
+
+        // Delete the folder, move its bookmark into the menu with a new
+        // title, and add a new bookmark.
+        apply_incoming(
+            &conn,
+            json!([{
+                "id": "menu",
+                "type": "folder",
+                "parentid": "places",
+                "parentName": "",
+                "dateAdded": 0,
+                "title": "menu",
+                "children": ["bookmark2___", "bookmark1___", "bookmark3___"],
+            }, {
+                "id": "folder1_____",
+                "deleted": true,
+            }, {
+                "id": "bookmark1___",
+                "type": "bookmark",
+                "parentid": "menu",
+                "parentName": "menu",
+                "dateAdded": 1_381_542_355_843u64,
+                "title": "1-remote",
+                "bmkUri": "http://example.com/1",
+            }, {
+                "id": "bookmark3___",
+                "type": "bookmark",
+                "parentid": "menu",
+                "parentName": "menu",
+                "dateAdded": 1_381_542_355_843u64,
+                "title": "3",
+                "bmkUri": "http://example.com/3",
+            }]),
+        );
+
+        let menu = BookmarkRootGuid::Menu.as_guid();
+        let folder_guid = SyncGuid::from("folder1_____");
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                BookmarkEvent::ItemRemoved {
+                    guid: folder_guid.clone(),
+                    parent_guid: menu.clone(),
+                    position: 0,
+                    item_type: BookmarkType::Folder,
+                    url: None,
+                },
+                BookmarkEvent::ItemMoved {
+                    guid: "bookmark2___".into(),
+                    old_parent_guid: menu.clone(),
+                    old_position: 1,
+                    new_parent_guid: menu.clone(),
+                    new_position: 0,
+                },
+                BookmarkEvent::ItemMoved {
+                    guid: "bookmark1___".into(),
+                    old_parent_guid: folder_guid.clone(),
+                    old_position: 0,
+                    new_parent_guid: menu.clone(),
+                    new_position: 1,
+                },
+                BookmarkEvent::ItemChanged {
+                    guid: "bookmark1___".into(),
+                    item_type: BookmarkType::Bookmark,
+                    url: Some(Url::parse("http://example.com/1").unwrap()),
+                    title: Some("1-remote".into()),
+                },
+                BookmarkEvent::ItemAdded {
+                    guid: "bookmark3___".into(),
+                    parent_guid: menu.clone(),
+                    position: 2,
+                    item_type: BookmarkType::Bookmark,
+                    url: Some(Url::parse("http://example.com/3").unwrap()),
+                    title: Some("3".into()),
+                },
+            ]
+        );
+
+        // Add a folder with a child, then delete both. Only the folder
+        // should be reported as removed.
+        let menu_record = |children: &[&str]| {
+            json!({
+                "id": "menu",
+                "type": "folder",
+                "parentid": "places",
+                "parentName": "",
+                "dateAdded": 0,
+                "title": "menu",
+                "children": children,
+            })
+        };
+        apply_incoming(
+            &conn,
+            json!([
+                menu_record(&["bookmark2___", "bookmark1___", "bookmark3___", "folder2_____"]),
+                {
+                    "id": "folder2_____",
+                    "type": "folder",
+                    "parentid": "menu",
+                    "parentName": "menu",
+                    "dateAdded": 1_381_542_355_843u64,
+                    "title": "Another folder",
+                    "children": ["bookmark4___"],
+                },
+                {
+                    "id": "bookmark4___",
+                    "type": "bookmark",
+                    "parentid": "folder2_____",
+                    "parentName": "Another folder",
+                    "dateAdded": 1_381_542_355_843u64,
+                    "title": "4",
+                    "bmkUri": "http://example.com/4",
+                },
+            ]),
+        );
+        events.lock().unwrap().clear();
+        apply_incoming(
+            &conn,
+            json!([
+                menu_record(&["bookmark2___", "bookmark1___", "bookmark3___"]),
+                {
+                    "id": "folder2_____",
+                    "deleted": true,
+                },
+                {
+                    "id": "bookmark4___",
+                    "deleted": true,
+                },
+            ]),
+        );
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![BookmarkEvent::ItemRemoved {
+                guid: "folder2_____".into(),
+                parent_guid: menu.clone(),
+                position: 3,
+                item_type: BookmarkType::Folder,
+                url: None,
+            }]
+        );
+    }
+
     #[test]
     fn test_apply_query() {
         // should we add some more query variations here?
@@ -1533,6 +1898,97 @@ mod tests {
         assert_eq!(outgoing.changes[0].id, "bookmarkAAAA");
         assert_eq!(outgoing.changes[0].data["keyword"], "a");
 
+        store
+            .sync_finished(ServerTimestamp(1.0), vec!["bookmarkAAAA".into()])
+            .expect("Should push synced changes back to the store");
+
+        // Changing the keyword locally should reupload the bookmark.
+        let url = Url::parse("http://example.com/a")?;
+        assert_eq!(get_keyword_for_url(&writer, &url)?, Some("a".to_string()));
+        set_keyword(&writer, &url, "B")?;
+
+        let outgoing = store
+            .apply_incoming(
+                IncomingChangeset::new(store.collection_name().to_string(), ServerTimestamp(2.0)),
+                &mut telemetry::EngineIncoming::new(),
+            )
+            .expect("Should fetch outgoing records after changing the keyword");
+        assert_eq!(outgoing.changes.len(), 1);
+        assert_eq!(outgoing.changes[0].id, "bookmarkAAAA");
+        assert_eq!(outgoing.changes[0].data["keyword"], "b");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incoming_keywords() -> Result<()> {
+        let api = new_mem_api();
+        let writer = api.open_connection(ConnectionType::ReadWrite)?;
+        let syncer = api.open_sync_connection()?;
+
+        let records = vec![
+            json!({
+                "id": "toolbar",
+                "type": "folder",
+                "parentid": "places",
+                "parentName": "",
+                "dateAdded": 0,
+                "title": "toolbar",
+                "children": ["bookmarkAAAA", "bookmarkBBBB"],
+            }),
+            json!({
+                "id": "bookmarkAAAA",
+                "type": "bookmark",
+                "parentid": "toolbar",
+                "parentName": "toolbar",
+                "dateAdded": 1_552_183_116_885u64,
+                "title": "A",
+                "bmkUri": "http://example.com/a?q=%s",
+                "keyword": "A",
+            }),
+            json!({
+                "id": "bookmarkBBBB",
+                "type": "bookmark",
+                "parentid": "toolbar",
+                "parentName": "toolbar",
+                "dateAdded": 1_552_183_116_885u64,
+                "title": "B",
+                "bmkUri": "http://example.com/b",
+                "keyword": "b",
+            }),
+        ];
+
+        let client_info = Cell::new(None);
+        let store = BookmarksStore::new(&syncer, &client_info);
+
+        let mut incoming =
+            IncomingChangeset::new(store.collection_name().to_string(), ServerTimestamp(0.0));
+        for record in records {
+            let payload = Payload::from_json(record).unwrap();
+            incoming.changes.push((payload, ServerTimestamp(0.0)));
+        }
+
+        let outgoing = store
+            .apply_incoming(incoming, &mut telemetry::EngineIncoming::new())
+            .expect("Should apply incoming records");
+
+        // The keyword for A isn't normalized, so we should reupload it.
+        let reuploaded = outgoing
+            .changes
+            .iter()
+            .find(|p| p.id == "bookmarkAAAA")
+            .expect("Should reupload A");
+        assert_eq!(reuploaded.data["keyword"], "a");
+
+        assert_eq!(
+            get_url_for_keyword(&writer, "a")?,
+            Some(Url::parse("http://example.com/a?q=%s")?)
+        );
+        assert_eq!(
+            get_url_for_keyword(&writer, "b")?,
+            Some(Url::parse("http://example.com/b")?)
+        );
+
         Ok(())
     }
 
@@ -48,14 +48,12 @@ use std::time::{Duration, Instant};
 
 impl PlacesDb {
     /// Begin a TimeChunkedTransaction. Must be called from the
-    /// sync connection, see module doc for details.
+    /// sync connection, see module doc for details. Fails with
+    /// `InvalidConnectionType` on any other connection.
     pub fn time_chunked_transaction(&self) -> Result<TimeChunkedTransaction> {
-        if !cfg!(test) || !self.is_in_memory() {
-            assert_eq!(
-                self.conn_type(),
-                ConnectionType::Sync,
-                "time_chunked_transaction must only be called by the Sync connection"
-            );
+        // Tests use in-memory connections for Sync, like `coop_transaction`.
+        if (!cfg!(test) || !self.is_in_memory()) && self.conn_type() != ConnectionType::Sync {
+            return Err(ErrorKind::InvalidConnectionType.into());
         }
         // Note that we don't allow commit_after as a param because it
         // is closely related to the timeouts configured on the database
@@ -68,6 +66,30 @@ impl PlacesDb {
         )?)
     }
 
+    /// Begin a ChunkedCoopTransaction, which commits and starts a new
+    /// transaction after every `rows_per_chunk` calls to `maybe_commit`. This
+    /// is for long-running work on the ReadWrite connection, like imports, so
+    /// that the Sync connection can write between chunks, and interrupted work
+    /// keeps the chunks it already committed. Fails with
+    /// `InvalidConnectionType` on any other connection.
+    pub fn chunked_coop_transaction(
+        &self,
+        rows_per_chunk: usize,
+    ) -> Result<ChunkedCoopTransaction> {
+        // This can be reached from the FFI with any connection, so we return
+        // an error instead of asserting.
+        if self.conn_type() != ConnectionType::ReadWrite {
+            return Err(ErrorKind::InvalidConnectionType.into());
+        }
+        let _lock = self.coop_tx_lock.lock().unwrap();
+        Ok(ChunkedCoopTransaction {
+            tx: get_tx_with_retry_on_locked(self.conn())?,
+            rows_per_chunk: rows_per_chunk.max(1),
+            rows: 0,
+            coop: &self.coop_tx_lock,
+        })
+    }
+
     /// Begin a "coop" transaction. Must be called from the sync connection, see
     /// module doc for details.
     pub fn coop_transaction(&self) -> Result<UncheckedTransaction> {
@@ -145,21 +167,7 @@ impl<'conn> TimeChunkedTransaction<'conn> {
 
     #[inline(never)]
     fn commit_and_start_new_tx(&mut self) -> Result<()> {
-        // We can't call self.tx.commit() here as it wants to consume
-        // self.tx, and we can't set up the new self.tx first as then
-        // we'll be trying to start a new transaction while the current
-        // one is in progress. So explicitly set the finished flag on it.
-        self.tx.finished = true;
-        self.tx.execute_batch("COMMIT")?;
-        // acquire a lock on our cooperator - if our only other writer
-        // thread holds a write lock we'll block until it is released.
-        // Note however that sqlite might still return a locked error if the
-        // database is being checkpointed - so we still perform exactly 1 retry,
-        // which we do while we have the lock, because we don't want our other
-        // write connection to win this race either.
-        let _lock = self.coop.lock().unwrap();
-        self.tx = get_tx_with_retry_on_locked(self.tx.conn)?;
-        Ok(())
+        commit_and_start_new_tx(&mut self.tx, self.coop)
     }
 
     /// Consumes and commits a TimeChunkedTransaction transaction.
@@ -185,6 +193,56 @@ impl<'conn> ConnExt for TimeChunkedTransaction<'conn> {
     }
 }
 
+/// A transaction on the ReadWrite connection that's committed in chunks of
+/// rows, instead of after some amount of time. Call `.maybe_commit()` after
+/// each row, and `.commit()` at the end. Like `TimeChunkedTransaction`,
+/// there's no `.rollback()`; dropping it only rolls back the current chunk.
+pub struct ChunkedCoopTransaction<'conn> {
+    tx: UncheckedTransaction<'conn>,
+    rows_per_chunk: usize,
+    rows: usize,
+    coop: &'conn Mutex<()>,
+}
+
+impl<'conn> ChunkedCoopTransaction<'conn> {
+    /// Counts a row, and if that fills the current chunk, commits it and
+    /// starts another. Returns `true` if the chunk was committed.
+    #[inline]
+    pub fn maybe_commit(&mut self) -> Result<bool> {
+        self.rows += 1;
+        if self.rows < self.rows_per_chunk {
+            return Ok(false);
+        }
+        self.rows = 0;
+        commit_and_start_new_tx(&mut self.tx, self.coop)?;
+        Ok(true)
+    }
+
+    /// Consumes and commits a ChunkedCoopTransaction transaction.
+    pub fn commit(self) -> Result<()> {
+        self.tx.commit()?;
+        Ok(())
+    }
+}
+
+fn commit_and_start_new_tx(tx: &mut UncheckedTransaction, coop: &Mutex<()>) -> Result<()> {
+    // We can't call tx.commit() here as it wants to consume tx, and we can't
+    // set up the new tx first as then we'll be trying to start a new
+    // transaction while the current one is in progress. So explicitly set the
+    // finished flag on it.
+    tx.finished = true;
+    tx.execute_batch("COMMIT")?;
+    // acquire a lock on our cooperator - if our only other writer
+    // thread holds a write lock we'll block until it is released.
+    // Note however that sqlite might still return a locked error if the
+    // database is being checkpointed - so we still perform exactly 1 retry,
+    // which we do while we have the lock, because we don't want our other
+    // write connection to win this race either.
+    let _lock = coop.lock().unwrap();
+    *tx = get_tx_with_retry_on_locked(tx.conn)?;
+    Ok(())
+}
+
 // A helper that attempts to get an Immediate lock on the DB. If it fails with
 // a "busy" or "locked" error, it does exactly 1 retry.
 fn get_tx_with_retry_on_locked(conn: &Connection) -> Result<UncheckedTransaction> {
@@ -4,17 +4,37 @@
 
 use super::interrupt::{InterruptScope, PlacesInterruptHandle};
 use super::schema;
+use crate::api::history::RecentVisits;
 use crate::api::places_api::ConnectionType;
 use crate::error::*;
+use crate::frecency::FrecencySettings;
+use crate::observers::Observers;
+use crate::storage::history::get_frecency_settings;
 use rusqlite::Connection;
 use sql_support::ConnExt;
 use std::ops::Deref;
 use std::path::Path;
 
-use std::sync::{atomic::AtomicUsize, Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, RwLock, RwLockReadGuard,
+};
 
 pub const MAX_VARIABLE_NUMBER: usize = 999;
 
+// See the comment about `encryption_pragmas` in `with_connection`.
+const PAGE_SIZE: u32 = 32768;
+
+// Returns the pragma, without `PRAGMA`, that sets the page size for an
+// encrypted or unencrypted database.
+fn page_size_pragma(encrypted: bool) -> String {
+    if encrypted {
+        format!("cipher_page_size = {}", PAGE_SIZE)
+    } else {
+        format!("page_size = {}", PAGE_SIZE)
+    }
+}
+
 #[derive(Debug)]
 pub struct PlacesDb {
     pub db: Connection,
@@ -23,6 +43,30 @@ pub struct PlacesDb {
     api_id: usize,
     in_memory: bool,
     pub(super) coop_tx_lock: Arc<Mutex<()>>,
+    recent_visits: Arc<RecentVisits>,
+    frecency_settings: Arc<RwLock<FrecencySettings>>,
+    observers: Arc<Observers>,
+    // Declared after `db`, so that the connection is closed before it's no
+    // longer counted.
+    open_connections: Option<OpenConnectionGuard>,
+}
+
+/// Counts a connection as open until it's dropped. `PlacesApi` uses this to
+/// tell whether any of the connections it opened are still open.
+#[derive(Debug)]
+struct OpenConnectionGuard(Arc<AtomicUsize>);
+
+impl OpenConnectionGuard {
+    fn new(open_connections: Arc<AtomicUsize>) -> Self {
+        open_connections.fetch_add(1, Ordering::SeqCst);
+        OpenConnectionGuard(open_connections)
+    }
+}
+
+impl Drop for OpenConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl PlacesDb {
@@ -34,8 +78,6 @@ impl PlacesDb {
         coop_tx_lock: Arc<Mutex<()>>,
         in_memory: bool,
     ) -> Result<Self> {
-        const PAGE_SIZE: u32 = 32768;
-
         // `encryption_pragmas` is both for `PRAGMA key` and for `PRAGMA page_size` / `PRAGMA
         // cipher_page_size` (Even though nominally page_size has nothing to do with encryption, we
         // need to set `PRAGMA cipher_page_size` for encrypted databases, and `PRAGMA page_size` for
@@ -52,18 +94,18 @@ impl PlacesDb {
         let encryption_pragmas = if let Some(key) = encryption_key {
             format!(
                 "PRAGMA key = '{key}';
-                 PRAGMA cipher_page_size = {page_size};",
+                 PRAGMA {page_size};",
                 key = sql_support::escape_string_for_pragma(key),
-                page_size = PAGE_SIZE,
+                page_size = page_size_pragma(true),
             )
         } else {
             format!(
-                "PRAGMA page_size = {};
+                "PRAGMA {};
                  -- Disable calling mlock/munlock for every malloc/free.
                  -- In practice this results in a massive speedup, especially
                  -- for insert-heavy workloads.
                  PRAGMA cipher_memory_security = false;",
-                PAGE_SIZE
+                page_size_pragma(false)
             )
         };
 
@@ -104,6 +146,15 @@ impl PlacesDb {
             interrupt_counter: Arc::new(AtomicUsize::new(0)),
             coop_tx_lock,
             in_memory,
+            // The API shares its cache with the read-write connection.
+            recent_visits: Arc::new(RecentVisits::default()),
+            // The API shares its settings with all of its connections, so
+            // that changing them applies to connections that are already open.
+            frecency_settings: Arc::new(RwLock::new(FrecencySettings::default())),
+            // The API sets this to share its observers with the connection.
+            observers: Arc::new(Observers::default()),
+            // The API sets this to count its open connections.
+            open_connections: None,
         };
         match res.conn_type() {
             // For read-only connections, we can avoid opening a transaction,
@@ -115,6 +166,7 @@ impl PlacesDb {
                 let tx = res.unchecked_transaction()?;
                 schema::init(&res)?;
                 tx.commit()?;
+                res.replace_frecency_settings(get_frecency_settings(&res)?);
             }
         }
 
@@ -152,11 +204,38 @@ impl PlacesDb {
         )?)
     }
 
+    /// Changes the key of an encrypted database. This can't encrypt or
+    /// decrypt a database; use `export_to` for that.
+    pub(crate) fn rekey(&self, new_key: &str) -> Result<()> {
+        self.execute_batch(&format!(
+            "PRAGMA rekey = '{}';",
+            sql_support::escape_string_for_pragma(new_key)
+        ))?;
+        Ok(())
+    }
+
+    /// Copies this database into a new database at `path`, which is
+    /// encrypted with `encryption_key`, or unencrypted if it's `None`.
+    pub(crate) fn export_to(&self, path: &Path, encryption_key: Option<&str>) -> Result<()> {
+        // Like `with_connection`, we need to set the right page size before
+        // the new database is written.
+        let page_size = page_size_pragma(encryption_key.is_some());
+        sql_support::export_database(&self.db, path, encryption_key, &[&page_size])?;
+        Ok(())
+    }
+
     pub fn new_interrupt_handle(&self) -> PlacesInterruptHandle {
-        PlacesInterruptHandle {
-            db_handle: self.db.get_interrupt_handle(),
-            interrupt_counter: self.interrupt_counter.clone(),
-        }
+        PlacesInterruptHandle::for_connection(
+            self.db.get_interrupt_handle(),
+            self.interrupt_counter.clone(),
+        )
+    }
+
+    /// Stops existing interrupt handles from interrupting scopes that begin
+    /// after this. Statements can still be interrupted, since those handles
+    /// share the same SQLite connection.
+    pub(crate) fn reset_interrupt_counter(&mut self) {
+        self.interrupt_counter = Arc::new(AtomicUsize::new(0));
     }
 
     #[inline]
@@ -178,6 +257,91 @@ impl PlacesDb {
     pub fn is_in_memory(&self) -> bool {
         self.in_memory
     }
+
+    /// The cache of recently visited urls used to ignore reloads. See
+    /// `RecentVisits` for details.
+    #[inline]
+    pub fn recent_visits(&self) -> &RecentVisits {
+        &self.recent_visits
+    }
+
+    /// Whether searches look up candidates in the full-text index, instead
+    /// of matching against every page. This is much faster for large
+    /// histories, but the index only matches at the start of words in page
+    /// titles and URLs, so, for example, "zilla" won't find "mozilla.org"
+    /// with the index enabled.
+    ///
+    /// The index is part of the database, so this is the same for every
+    /// connection to it.
+    pub fn use_fts_index(&self) -> Result<bool> {
+        schema::has_fts_index(self)
+    }
+
+    /// Creates or drops the full-text index. Creating it indexes every page
+    /// that's already in the database, which can take a while for large
+    /// histories. Only the read-write connection can do this.
+    pub fn set_use_fts_index(&self, enabled: bool) -> Result<()> {
+        if self.conn_type() != ConnectionType::ReadWrite {
+            return Err(ErrorKind::InvalidConnectionType.into());
+        }
+        let tx = self.coop_transaction()?;
+        if enabled {
+            schema::create_fts_index(self)?;
+        } else {
+            schema::drop_fts_index(self)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The settings used to calculate frecencies on this connection. These
+    /// are loaded from the database when the connection is opened, are
+    /// shared by all connections opened through the same `PlacesApi`, and are
+    /// changed via `PlacesApi::new_with_frecency_settings`.
+    #[inline]
+    pub fn frecency_settings(&self) -> RwLockReadGuard<FrecencySettings> {
+        self.frecency_settings.read().unwrap()
+    }
+
+    #[inline]
+    pub(crate) fn replace_frecency_settings(&self, settings: FrecencySettings) {
+        *self.frecency_settings.write().unwrap() = settings;
+    }
+
+    #[inline]
+    pub(crate) fn shared_frecency_settings(&self) -> Arc<RwLock<FrecencySettings>> {
+        self.frecency_settings.clone()
+    }
+
+    #[inline]
+    pub(crate) fn set_shared_frecency_settings(
+        &mut self,
+        frecency_settings: Arc<RwLock<FrecencySettings>>,
+    ) {
+        self.frecency_settings = frecency_settings;
+    }
+
+    /// The observers to notify about changes made on this connection.
+    #[inline]
+    pub(crate) fn observers(&self) -> &Observers {
+        &self.observers
+    }
+
+    #[inline]
+    pub(crate) fn set_observers(&mut self, observers: Arc<Observers>) {
+        self.observers = observers;
+    }
+
+    #[inline]
+    pub(crate) fn set_recent_visits(&mut self, recent_visits: Arc<RecentVisits>) {
+        self.recent_visits = recent_visits;
+    }
+
+    /// Counts this connection in `open_connections` until it's dropped.
+    #[inline]
+    pub(crate) fn set_open_connections(&mut self, open_connections: Arc<AtomicUsize>) {
+        self.open_connections = Some(OpenConnectionGuard::new(open_connections));
+    }
 }
 
 impl Drop for PlacesDb {
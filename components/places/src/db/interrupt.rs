@@ -2,11 +2,12 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use super::{PlacesDb, ReaderPool};
 use crate::error::*;
 use rusqlite::InterruptHandle;
 use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex, Weak,
 };
 
 // SeqCst is overkill for much of this, but whatever.
@@ -14,15 +15,99 @@ use std::sync::{
 /// A Sync+Send type which can be used allow someone to interrupt an
 /// operation, even if it happens while rust code (and not SQL) is
 /// executing.
-pub struct PlacesInterruptHandle {
-    pub(crate) db_handle: InterruptHandle,
-    pub(crate) interrupt_counter: Arc<AtomicUsize>,
+pub struct PlacesInterruptHandle(InterruptTarget);
+
+enum InterruptTarget {
+    Connection {
+        db_handle: InterruptHandle,
+        interrupt_counter: Arc<AtomicUsize>,
+    },
+    // Interrupts whichever readers are checked out from the pool.
+    ReaderPool(Weak<ReaderPool>),
 }
 
 impl PlacesInterruptHandle {
+    pub(crate) fn for_connection(
+        db_handle: InterruptHandle,
+        interrupt_counter: Arc<AtomicUsize>,
+    ) -> Self {
+        PlacesInterruptHandle(InterruptTarget::Connection {
+            db_handle,
+            interrupt_counter,
+        })
+    }
+
+    pub(crate) fn for_reader_pool(pool: &Arc<ReaderPool>) -> Self {
+        PlacesInterruptHandle(InterruptTarget::ReaderPool(Arc::downgrade(pool)))
+    }
+
     pub fn interrupt(&self) {
-        self.interrupt_counter.fetch_add(1, Ordering::SeqCst);
-        self.db_handle.interrupt();
+        match &self.0 {
+            InterruptTarget::Connection {
+                db_handle,
+                interrupt_counter,
+            } => {
+                interrupt_counter.fetch_add(1, Ordering::SeqCst);
+                db_handle.interrupt();
+            }
+            InterruptTarget::ReaderPool(pool) => {
+                if let Some(pool) = pool.upgrade() {
+                    pool.interrupt();
+                }
+            }
+        }
+    }
+}
+
+/// A Sync+Send token used to cancel a single query, like an autocomplete
+/// search, without interrupting anything else running on the same
+/// connection. Clones share the same state, so one can be passed to the query,
+/// and another kept to cancel it. Each query should get its own token.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<CancellationState>);
+
+#[derive(Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    // The interrupt handle for the connection running the query, if it's
+    // running.
+    db_handle: Mutex<Option<InterruptHandle>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the query. If it's running SQL, the statement is interrupted.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        if let Some(handle) = &*self.0.db_handle.lock().unwrap() {
+            handle.interrupt();
+        }
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Lets `cancel` interrupt statements on `conn` until the returned scope
+    /// is dropped.
+    pub(crate) fn begin_scope(&self, conn: &PlacesDb) -> CancellationScope<'_> {
+        *self.0.db_handle.lock().unwrap() = Some(conn.db.get_interrupt_handle());
+        CancellationScope(self)
+    }
+}
+
+/// Returned by `CancellationToken::begin_scope`. Once this is dropped,
+/// cancelling the token no longer interrupts the connection, so it can be
+/// reused for other queries.
+pub(crate) struct CancellationScope<'a>(&'a CancellationToken);
+
+impl<'a> Drop for CancellationScope<'a> {
+    fn drop(&mut self) {
+        *(self.0).0.db_handle.lock().unwrap() = None;
     }
 }
 
@@ -73,5 +158,7 @@ mod test {
         // Make sure this compiles
         is_sync::<PlacesInterruptHandle>();
         is_send::<PlacesInterruptHandle>();
+        is_sync::<CancellationToken>();
+        is_send::<CancellationToken>();
     }
 }
@@ -7,8 +7,15 @@ mod coop_transaction;
 #[allow(clippy::module_inception)] // FIXME
 pub mod db;
 mod interrupt;
+mod pool;
+pub(crate) mod recovery;
 mod schema;
 pub(crate) use self::interrupt::InterruptScope;
-pub use self::interrupt::PlacesInterruptHandle;
+pub use self::interrupt::{CancellationToken, PlacesInterruptHandle};
+pub(crate) use self::pool::ReaderPool;
 pub use crate::db::coop_transaction::TimeChunkedTransaction;
 pub use crate::db::db::PlacesDb;
+pub use crate::db::pool::{
+    PooledReader, DEFAULT_READER_CHECKOUT_TIMEOUT, DEFAULT_READER_POOL_SIZE,
+};
+pub use crate::db::recovery::RecoveryReport;
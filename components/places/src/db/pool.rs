@@ -0,0 +1,347 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A bounded pool of read-only connections, used by
+//! `PlacesApi::checkout_reader`.
+//!
+//! Since the database uses WAL mode, readers don't block the writer, or each
+//! other, so running concurrent queries (like autocomplete searches, where
+//! every keystroke starts a new query) on separate readers lets them run in
+//! parallel. However, a reader that's in the middle of a transaction keeps
+//! reading from the snapshot it started with, and stops the WAL from being
+//! checkpointed past that snapshot. To avoid this, readers returned to the
+//! pool with an open transaction are closed instead of reused.
+
+use super::{PlacesDb, PlacesInterruptHandle};
+use crate::error::*;
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The default maximum number of readers in the pool.
+pub const DEFAULT_READER_POOL_SIZE: usize = 4;
+
+/// The default time to wait for a reader to be returned, if the maximum
+/// number of readers are already checked out.
+pub const DEFAULT_READER_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct ReaderPool {
+    state: Mutex<PoolState>,
+    // Signaled when a reader is returned, or the pool grows.
+    available: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<PlacesDb>,
+    checked_out: usize,
+    // Interrupt handles for the checked out readers, keyed by their
+    // checkout id.
+    interrupt_handles: HashMap<usize, PlacesInterruptHandle>,
+    next_checkout_id: usize,
+    max_size: usize,
+    checkout_timeout: Duration,
+    // Incremented by `clear`, so that readers checked out before then are
+    // closed when they're returned.
+    generation: usize,
+}
+
+impl ReaderPool {
+    pub(crate) fn new(max_size: usize) -> Self {
+        ReaderPool {
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                checked_out: 0,
+                interrupt_handles: HashMap::new(),
+                next_checkout_id: 0,
+                max_size: max_size.max(1),
+                checkout_timeout: DEFAULT_READER_CHECKOUT_TIMEOUT,
+                generation: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Checks out an idle reader, or calls `open` to open a new one if there
+    /// aren't any. If the maximum number of readers are already checked out,
+    /// this waits for one to be returned, and fails with
+    /// `ReaderCheckoutTimeout` if none are returned in time.
+    pub(crate) fn checkout(
+        pool: &Arc<ReaderPool>,
+        open: impl FnOnce() -> Result<PlacesDb>,
+    ) -> Result<PooledReader> {
+        let mut state = pool.state.lock().unwrap();
+        let deadline = Instant::now() + state.checkout_timeout;
+        while state.idle.is_empty() && state.checked_out >= state.max_size {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ErrorKind::ReaderCheckoutTimeout.into());
+            }
+            state = pool
+                .available
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+        state.checked_out += 1;
+        let generation = state.generation;
+        let idle = state.idle.pop();
+        // Don't hold the lock while we open the connection, so that other
+        // threads can still return readers.
+        drop(state);
+        let db = match idle {
+            Some(db) => db,
+            None => match open() {
+                Ok(db) => db,
+                Err(e) => {
+                    pool.state.lock().unwrap().checked_out -= 1;
+                    pool.available.notify_one();
+                    return Err(e);
+                }
+            },
+        };
+        let mut state = pool.state.lock().unwrap();
+        let checkout_id = state.next_checkout_id;
+        state.next_checkout_id += 1;
+        state
+            .interrupt_handles
+            .insert(checkout_id, db.new_interrupt_handle());
+        Ok(PooledReader {
+            db: Some(db),
+            checkout_id,
+            generation,
+            pool: pool.clone(),
+        })
+    }
+
+    /// Changes how long `checkout` waits for a reader to be returned. The
+    /// default is `DEFAULT_READER_CHECKOUT_TIMEOUT`.
+    pub(crate) fn set_checkout_timeout(&self, timeout: Duration) {
+        self.state.lock().unwrap().checkout_timeout = timeout;
+    }
+
+    /// Interrupts the readers that are currently checked out. Readers that
+    /// are checked out later aren't affected.
+    pub(crate) fn interrupt(&self) {
+        let state = self.state.lock().unwrap();
+        for handle in state.interrupt_handles.values() {
+            handle.interrupt();
+        }
+    }
+
+    /// Changes the maximum number of readers, closing idle readers if there
+    /// are too many.
+    pub(crate) fn set_max_size(&self, max_size: usize) {
+        let excess = {
+            let mut state = self.state.lock().unwrap();
+            state.max_size = max_size.max(1);
+            let keep = state.max_size.saturating_sub(state.checked_out);
+            if state.idle.len() > keep {
+                state.idle.split_off(keep)
+            } else {
+                Vec::new()
+            }
+        };
+        self.available.notify_all();
+        drop(excess);
+    }
+
+    /// Closes all idle readers. Readers that are checked out are closed when
+    /// they're returned. This is used when the database is closed, or its
+    /// key changes, since the readers can't be used after that.
+    pub(crate) fn clear(&self) {
+        let idle = {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            mem::replace(&mut state.idle, Vec::new())
+        };
+        drop(idle);
+    }
+
+    fn put_back(&self, mut db: PlacesDb, checkout_id: usize, generation: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.checked_out -= 1;
+        state.interrupt_handles.remove(&checkout_id);
+        let db = if generation == state.generation
+            && db.is_autocommit()
+            && state.idle.len() + state.checked_out < state.max_size
+        {
+            // Interrupt handles from the last checkout shouldn't stop the
+            // next one.
+            db.reset_interrupt_counter();
+            state.idle.push(db);
+            None
+        } else {
+            Some(db)
+        };
+        drop(state);
+        self.available.notify_one();
+        // Closing a connection runs `PRAGMA optimize`, so do it after
+        // releasing the lock.
+        drop(db);
+    }
+}
+
+/// A read-only connection checked out from a `PlacesApi`'s pool. This
+/// dereferences to a `PlacesDb`, and is returned to the pool when dropped.
+pub struct PooledReader {
+    db: Option<PlacesDb>,
+    checkout_id: usize,
+    generation: usize,
+    pool: Arc<ReaderPool>,
+}
+
+impl Deref for PooledReader {
+    type Target = PlacesDb;
+    #[inline]
+    fn deref(&self) -> &PlacesDb {
+        self.db.as_ref().expect("only taken when dropped")
+    }
+}
+
+impl Drop for PooledReader {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            self.pool.put_back(db, self.checkout_id, self.generation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_api;
+    use crate::api::places_api::ConnectionType;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn counts(pool: &ReaderPool) -> (usize, usize) {
+        let state = pool.state.lock().unwrap();
+        (state.idle.len(), state.checked_out)
+    }
+
+    #[test]
+    fn test_checkout_and_return() {
+        let api = new_mem_api();
+        let pool = Arc::new(ReaderPool::new(2));
+        let open = || api.open_connection(ConnectionType::ReadOnly);
+
+        let reader1 = ReaderPool::checkout(&pool, open).expect("should check out reader 1");
+        let reader2 = ReaderPool::checkout(&pool, open).expect("should check out reader 2");
+        assert_eq!(counts(&pool), (0, 2));
+        assert_eq!(reader1.conn_type(), ConnectionType::ReadOnly);
+        drop(reader1);
+        assert_eq!(counts(&pool), (1, 1));
+
+        // Checking out again should reuse the idle reader, without opening
+        // a new one.
+        let reader3 = ReaderPool::checkout(&pool, || -> Result<PlacesDb> {
+            panic!("shouldn't open a new reader")
+        })
+        .expect("should reuse idle reader");
+        assert_eq!(counts(&pool), (0, 2));
+
+        // A reader with an open transaction shouldn't be reused.
+        reader3
+            .execute_batch("BEGIN")
+            .expect("should begin transaction");
+        drop(reader3);
+        assert_eq!(counts(&pool), (0, 1));
+
+        drop(reader2);
+        assert_eq!(counts(&pool), (1, 0));
+        let reader4 = ReaderPool::checkout(&pool, open).expect("should check out reader 4");
+        let reader5 = ReaderPool::checkout(&pool, open).expect("should check out reader 5");
+        drop(reader4);
+        drop(reader5);
+        assert_eq!(counts(&pool), (2, 0));
+
+        // Shrinking the pool closes idle readers.
+        pool.set_max_size(1);
+        assert_eq!(counts(&pool), (1, 0));
+
+        // Readers checked out before the pool is cleared shouldn't be
+        // reused.
+        let reader6 = ReaderPool::checkout(&pool, open).expect("should check out reader 6");
+        pool.clear();
+        drop(reader6);
+        assert_eq!(counts(&pool), (0, 0));
+    }
+
+    #[test]
+    fn test_checkout_waits() {
+        let api = new_mem_api();
+        let pool = Arc::new(ReaderPool::new(1));
+        let reader = ReaderPool::checkout(&pool, || api.open_connection(ConnectionType::ReadOnly))
+            .expect("should check out reader");
+
+        let (tx, rx) = mpsc::channel();
+        let child = {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                let reader = ReaderPool::checkout(&pool, || -> Result<PlacesDb> {
+                    panic!("shouldn't open a new reader")
+                })
+                .expect("should check out returned reader");
+                tx.send(()).unwrap();
+                drop(reader);
+            })
+        };
+        // The pool is full, so the other thread should be waiting.
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(reader);
+        rx.recv().expect("should get returned reader");
+        child.join().unwrap();
+        assert_eq!(counts(&pool), (1, 0));
+    }
+
+    #[test]
+    fn test_checkout_timeout() {
+        let api = new_mem_api();
+        let pool = Arc::new(ReaderPool::new(1));
+        pool.set_checkout_timeout(Duration::from_millis(10));
+        let reader = ReaderPool::checkout(&pool, || api.open_connection(ConnectionType::ReadOnly))
+            .expect("should check out reader");
+        match ReaderPool::checkout(&pool, || -> Result<PlacesDb> {
+            panic!("shouldn't open a new reader")
+        }) {
+            Err(e) => match e.kind() {
+                ErrorKind::ReaderCheckoutTimeout => {}
+                e => panic!("Wrong error: {:?}", e),
+            },
+            Ok(_) => panic!("shouldn't check out a reader while the pool is full"),
+        }
+        drop(reader);
+        assert_eq!(counts(&pool), (1, 0));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let api = new_mem_api();
+        let pool = Arc::new(ReaderPool::new(1));
+        let pool_handle = PlacesInterruptHandle::for_reader_pool(&pool);
+
+        let reader = ReaderPool::checkout(&pool, || api.open_connection(ConnectionType::ReadOnly))
+            .expect("should check out reader");
+        let reader_handle = reader.new_interrupt_handle();
+        let scope = reader.begin_interrupt_scope();
+        pool_handle.interrupt();
+        assert!(scope.was_interrupted());
+        drop(scope);
+        drop(reader);
+
+        // The returned reader is reused, but interrupting the pool while it's
+        // idle, or with a handle from the last checkout, shouldn't affect the
+        // next checkout.
+        pool_handle.interrupt();
+        let reader = ReaderPool::checkout(&pool, || -> Result<PlacesDb> {
+            panic!("shouldn't open a new reader")
+        })
+        .expect("should reuse idle reader");
+        let scope = reader.begin_interrupt_scope();
+        reader_handle.interrupt();
+        assert!(!scope.was_interrupted());
+    }
+}
@@ -0,0 +1,486 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Detects and recovers from a corrupt database.
+//!
+//! If SQLite reports that the file is corrupt when `PlacesApi` opens it, or
+//! `PlacesApi::new_with_integrity_check` was used and `PRAGMA quick_check`
+//! finds a problem, the database and its WAL are moved aside, with a
+//! `.corrupt` suffix, and a new database is created in its place. We then attach the corrupt database,
+//! and copy every row we can still read from the history, bookmarks,
+//! keywords and tags tables into the new one.
+//!
+//! Recovered items are marked as new, and the sync metadata isn't copied, so
+//! the next sync is a first sync that merges the recovered data with the
+//! server. This also restores anything we couldn't salvage. Recovery runs
+//! while the database is being opened, so observers aren't notified.
+
+use super::PlacesDb;
+use crate::api::places_api::ConnectionType;
+use crate::bookmark_sync::store::reset_in_tx;
+use crate::error::*;
+use crate::maintenance::{check_and_repair_bookmarks, BookmarkRepairs};
+use crate::storage::bookmarks::BookmarkRootGuid;
+use crate::types::BookmarkType;
+use crate::util::path_with_suffix;
+use rusqlite::types::Value;
+use rusqlite::{ErrorCode, NO_PARAMS};
+use serde_derive::*;
+use sql_support::ConnExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Describes what was recovered from a corrupt database.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RecoveryReport {
+    /// Where the corrupt database was moved.
+    pub corrupt_path: PathBuf,
+    /// The number of pages, visits, bookmarks (not including the roots),
+    /// keywords and tags in the recovered database.
+    pub places: u32,
+    pub visits: u32,
+    pub bookmarks: u32,
+    pub keywords: u32,
+    pub tags: u32,
+    /// Repairs made to the recovered bookmarks tree.
+    pub bookmark_repairs: BookmarkRepairs,
+    /// True if every row was salvaged, false if some were unreadable or
+    /// referenced rows that were, and are lost.
+    pub complete: bool,
+}
+
+/// Returns true if the SQLite error means the database is corrupt.
+/// `SQLITE_NOTADB` is also what SQLCipher returns for the wrong key, so we
+/// only treat it as corruption for unencrypted databases.
+fn is_corrupt_error(error: &rusqlite::Error, encrypted: bool) -> bool {
+    match error {
+        rusqlite::Error::SqliteFailure(e, _) => match e.code {
+            ErrorCode::DatabaseCorrupt => true,
+            ErrorCode::NotADatabase => !encrypted,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns true if opening the database failed because it's corrupt.
+pub(crate) fn is_corrupt_open_error(error: &Error, encrypted: bool) -> bool {
+    match error.kind() {
+        ErrorKind::SqlError(inner) => is_corrupt_error(inner, encrypted),
+        _ => false,
+    }
+}
+
+/// Checks the integrity of the database at `path`, returning false if it's
+/// corrupt. Other errors, like a wrong encryption key, are returned as-is.
+pub(crate) fn check_integrity(
+    path: &Path,
+    encryption_key: Option<&str>,
+    api_id: usize,
+) -> Result<bool> {
+    let encrypted = encryption_key.is_some();
+    let db = match PlacesDb::open(
+        path,
+        encryption_key,
+        ConnectionType::ReadOnly,
+        api_id,
+        Arc::new(Mutex::new(())),
+    ) {
+        Ok(db) => db,
+        Err(e) => {
+            if !is_corrupt_open_error(&e, encrypted) {
+                return Err(e);
+            }
+            log::warn!("Failed to open corrupt database: {}", e);
+            return Ok(false);
+        }
+    };
+    let problems =
+        match db.query_rows_into::<Vec<String>, _, _, _>("PRAGMA quick_check", &[], |row| {
+            row.get_checked(0)
+        }) {
+            Ok(problems) => problems,
+            Err(ref e) if is_corrupt_error(e, encrypted) => {
+                log::warn!("Integrity check failed: {}", e);
+                return Ok(false);
+            }
+            Err(e) => return Err(e.into()),
+        };
+    if problems.len() == 1 && problems[0] == "ok" {
+        return Ok(true);
+    }
+    for problem in &problems {
+        log::warn!("Integrity check found a problem: {}", problem);
+    }
+    Ok(false)
+}
+
+/// Moves the database at `path`, and its WAL and shared memory files, aside.
+/// If an earlier corrupt database was already moved aside, this one gets a
+/// numbered suffix, like `.corrupt-1`, so that we don't lose it. Returns the
+/// new path.
+pub(crate) fn move_aside(path: &Path) -> Result<PathBuf> {
+    const SUFFIXES: [&str; 3] = ["", "-wal", "-shm"];
+    let mut attempt = 0;
+    let corrupt_path = loop {
+        let candidate = if attempt == 0 {
+            path_with_suffix(path, ".corrupt")
+        } else {
+            path_with_suffix(path, &format!(".corrupt-{}", attempt))
+        };
+        if SUFFIXES
+            .iter()
+            .all(|suffix| !path_with_suffix(&candidate, suffix).exists())
+        {
+            break candidate;
+        }
+        attempt += 1;
+    };
+    for suffix in &SUFFIXES {
+        let from = path_with_suffix(path, suffix);
+        if from.exists() {
+            fs::rename(&from, path_with_suffix(&corrupt_path, suffix))?;
+        }
+    }
+    Ok(corrupt_path)
+}
+
+/// Copies what it can from the corrupt database at `corrupt_path` into `db`,
+/// which should be a new read-write connection.
+pub(crate) fn salvage(db: &PlacesDb, corrupt_path: &Path) -> Result<RecoveryReport> {
+    let mut report = RecoveryReport {
+        corrupt_path: corrupt_path.to_owned(),
+        ..RecoveryReport::default()
+    };
+    let path = corrupt_path.to_string_lossy().into_owned();
+    if let Err(e) = db.execute_named("ATTACH DATABASE :path AS corrupt", &[(":path", &path)]) {
+        log::warn!("Can't attach corrupt database; nothing salvaged: {}", e);
+        return Ok(report);
+    }
+    // Rows are copied in the order we read them, so references between rows
+    // aren't checked until we remove the dangling ones below.
+    db.execute_batch("PRAGMA foreign_keys = OFF")?;
+    let result = salvage_tables(db);
+    db.execute_batch(
+        "PRAGMA foreign_keys = ON;
+         DETACH DATABASE corrupt;",
+    )?;
+    report.complete = result?;
+
+    report.bookmark_repairs = check_and_repair_bookmarks(db)?;
+    report.places = db.query_one("SELECT COUNT(*) FROM moz_places")?;
+    report.visits = db.query_one("SELECT COUNT(*) FROM moz_historyvisits")?;
+    // The roots are the root, and its children.
+    report.bookmarks = db.query_row_and_then_named(
+        "SELECT COUNT(*) FROM moz_bookmarks
+         WHERE parent <> (SELECT id FROM moz_bookmarks WHERE guid = :root_guid)",
+        &[(":root_guid", BookmarkRootGuid::Root.guid())],
+        |row| row.get_checked(0),
+        false,
+    )?;
+    report.keywords = db.query_one("SELECT COUNT(*) FROM moz_keywords")?;
+    report.tags = db.query_one("SELECT COUNT(*) FROM moz_tags")?;
+    log::info!("Recovered corrupt database: {:?}", report);
+    Ok(report)
+}
+
+// Returns true if every row was salvaged.
+fn salvage_tables(db: &PlacesDb) -> Result<bool> {
+    let tx = db.unchecked_transaction()?;
+    let mut complete = true;
+
+    // The triggers on the main connection recalculate origins, visit counts
+    // and foreign counts as we go, so we don't copy those.
+    complete &= salvage_rows(
+        db,
+        "SELECT id, url, title, hidden, typed, frecency, guid, url_hash,
+                description, preview_image_url
+         FROM corrupt.moz_places",
+        "INSERT INTO moz_places(id, url, title, hidden, typed, frecency, guid,
+                                url_hash, description, preview_image_url)
+         VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )?;
+    complete &= salvage_rows(
+        db,
+        "SELECT id, is_local, from_visit, place_id, visit_date, visit_type
+         FROM corrupt.moz_historyvisits",
+        "INSERT INTO moz_historyvisits(id, is_local, from_visit, place_id,
+                                       visit_date, visit_type)
+         VALUES(?, ?, ?, ?, ?, ?)",
+    )?;
+    // The new database already has the roots, so we skip them, and look up
+    // their new IDs for their children.
+    complete &= salvage_rows(
+        db,
+        "SELECT b.id, b.fk, b.type,
+                IFNULL((SELECT m.id FROM main.moz_bookmarks m
+                        JOIN corrupt.moz_bookmarks p ON p.guid = m.guid
+                        WHERE p.id = b.parent), b.parent),
+                b.position, b.title, b.dateAdded, b.lastModified, b.guid
+         FROM corrupt.moz_bookmarks b
+         WHERE NOT EXISTS(SELECT 1 FROM main.moz_bookmarks
+                          WHERE guid = b.guid)",
+        "INSERT INTO moz_bookmarks(id, fk, type, parent, position, title,
+                                   dateAdded, lastModified, guid)
+         VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )?;
+    complete &= salvage_rows(
+        db,
+        "SELECT id, keyword, place_id FROM corrupt.moz_keywords",
+        "INSERT INTO moz_keywords(id, keyword, place_id) VALUES(?, ?, ?)",
+    )?;
+    complete &= salvage_rows(
+        db,
+        "SELECT id, tag, lastModified FROM corrupt.moz_tags",
+        "INSERT INTO moz_tags(id, tag, lastModified) VALUES(?, ?, ?)",
+    )?;
+    complete &= salvage_rows(
+        db,
+        "SELECT tag_id, place_id FROM corrupt.moz_tags_relation",
+        "INSERT INTO moz_tags_relation(tag_id, place_id) VALUES(?, ?)",
+    )?;
+
+    // Remove rows that reference rows we couldn't salvage. Bookmarks whose
+    // parents are missing are moved to unfiled by `check_and_repair_bookmarks`
+    // instead.
+    let delete_dangling_bookmarks = format!(
+        "DELETE FROM moz_bookmarks
+         WHERE type = {} AND fk NOT IN (SELECT id FROM moz_places)",
+        BookmarkType::Bookmark as u8
+    );
+    let dangling: [&str; 5] = [
+        "DELETE FROM moz_historyvisits
+         WHERE place_id NOT IN (SELECT id FROM moz_places)",
+        "UPDATE moz_historyvisits SET from_visit = NULL
+         WHERE from_visit NOT IN (SELECT id FROM moz_historyvisits)",
+        &delete_dangling_bookmarks,
+        "DELETE FROM moz_keywords
+         WHERE place_id NOT IN (SELECT id FROM moz_places)",
+        "DELETE FROM moz_tags_relation
+         WHERE tag_id NOT IN (SELECT id FROM moz_tags) OR
+               place_id NOT IN (SELECT id FROM moz_places)",
+    ];
+    for sql in &dangling {
+        if db.execute(sql, NO_PARAMS)? > 0 {
+            complete = false;
+        }
+    }
+
+    // Everything we salvaged is new to Sync.
+    reset_in_tx(db)?;
+    tx.commit()?;
+    Ok(complete)
+}
+
+// Reads all the rows it can with `select_sql`, and inserts them with
+// `insert_sql`. Returns false if reading stopped early, or if any rows
+// couldn't be inserted.
+fn salvage_rows(db: &PlacesDb, select_sql: &str, insert_sql: &str) -> Result<bool> {
+    let mut complete = true;
+    let mut rows = Vec::new();
+    if let Err(e) = read_rows(db, select_sql, &mut rows) {
+        log::warn!("Stopped salvaging after {} rows: {}", rows.len(), e);
+        complete = false;
+    }
+    let mut stmt = db.prepare(insert_sql)?;
+    for row in &rows {
+        match stmt.execute(row) {
+            Ok(_) => {}
+            Err(rusqlite::Error::SqliteFailure(ref e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                complete = false;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(complete)
+}
+
+fn read_rows(db: &PlacesDb, sql: &str, rows: &mut Vec<Vec<Value>>) -> rusqlite::Result<()> {
+    let mut stmt = db.prepare(sql)?;
+    let column_count = stmt.column_count();
+    for result in stmt.query_and_then(NO_PARAMS, |row| -> rusqlite::Result<Vec<Value>> {
+        (0..column_count).map(|i| row.get_checked(i)).collect()
+    })? {
+        rows.push(result?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::PlacesApi;
+    use crate::observation::VisitObservation;
+    use crate::storage::history::apply_observation;
+    use crate::storage::{keywords, tags};
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use crate::types::VisitTransition;
+    use serde_json::json;
+    use std::io::Write;
+    use url::Url;
+
+    fn populate(db: &PlacesDb) -> Result<()> {
+        let url = Url::parse("https://www.example.com/")?;
+        apply_observation(
+            db,
+            VisitObservation::new(url.clone()).with_visit_type(VisitTransition::Link),
+        )?;
+        insert_json_tree(
+            db,
+            json!({
+                "guid": BookmarkRootGuid::Unfiled.as_guid(),
+                "children": [
+                    {
+                        "guid": "folder1_____",
+                        "title": "A folder",
+                        "children": [
+                            {
+                                "guid": "bookmark1___",
+                                "url": "https://www.example.com/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        keywords::set_keyword(db, &url, "ex")?;
+        tags::tag_url(db, &url, "example")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_salvage() -> Result<()> {
+        let _ = env_logger::try_init();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("places.sqlite");
+        {
+            let api = PlacesApi::new(&path, None)?;
+            let conn = api.open_connection(ConnectionType::ReadWrite)?;
+            populate(&conn)?;
+        }
+        assert!(check_integrity(&path, None, 0)?);
+
+        // Salvaging a healthy database should copy everything.
+        let corrupt_path = move_aside(&path)?;
+        assert!(!path.exists());
+        let db = PlacesDb::open(
+            &path,
+            None,
+            ConnectionType::ReadWrite,
+            0,
+            Arc::new(Mutex::new(())),
+        )?;
+        let report = salvage(&db, &corrupt_path)?;
+        assert_eq!(
+            report,
+            RecoveryReport {
+                corrupt_path,
+                places: 1,
+                visits: 1,
+                bookmarks: 2,
+                keywords: 1,
+                tags: 1,
+                bookmark_repairs: BookmarkRepairs::default(),
+                complete: true,
+            }
+        );
+        assert_json_tree(
+            &db,
+            &BookmarkRootGuid::Unfiled.as_guid(),
+            json!({
+                "guid": BookmarkRootGuid::Unfiled.as_guid(),
+                "children": [
+                    {
+                        "guid": "folder1_____",
+                        "title": "A folder",
+                        "children": [
+                            {
+                                "guid": "bookmark1___",
+                                "url": "https://www.example.com/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        let (visit_count, foreign_count): (u32, u32) = db.query_row_and_then_named(
+            "SELECT visit_count_local, foreign_count FROM moz_places",
+            &[],
+            |row| -> rusqlite::Result<_> { Ok((row.get_checked(0)?, row.get_checked(1)?)) },
+            false,
+        )?;
+        // One bookmark, one keyword and one tag.
+        assert_eq!((visit_count, foreign_count), (1, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_on_open() -> Result<()> {
+        let _ = env_logger::try_init();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("places.sqlite");
+        {
+            let api = PlacesApi::new(&path, None)?;
+            assert_eq!(api.recovery_report(), None);
+            let conn = api.open_connection(ConnectionType::ReadWrite)?;
+            populate(&conn)?;
+        }
+
+        // Clobber the header, so that SQLite can't read anything.
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .write_all(&[0; 100])?;
+        assert!(!check_integrity(&path, None, 0)?);
+
+        let api = PlacesApi::new(&path, None)?;
+        let report = api.recovery_report().expect("should have recovered");
+        assert_eq!(report.corrupt_path, path_with_suffix(&path, ".corrupt"));
+        assert!(report.corrupt_path.exists());
+        assert_eq!(report.places, 0);
+        assert_eq!(report.bookmarks, 0);
+        assert!(!report.complete);
+
+        // The new database should work.
+        let conn = api.open_connection(ConnectionType::ReadWrite)?;
+        populate(&conn)?;
+        let first_corrupt_path = report.corrupt_path.clone();
+        drop(conn);
+        drop(api);
+
+        // Corrupting it again shouldn't replace the first corrupt database.
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .write_all(&[0; 100])?;
+        let api = PlacesApi::new(&path, None)?;
+        let second = api.recovery_report().expect("should have recovered");
+        assert_eq!(second.corrupt_path, path_with_suffix(&path, ".corrupt-1"));
+        assert!(first_corrupt_path.exists());
+        assert!(second.corrupt_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_integrity_check_on_request() -> Result<()> {
+        let _ = env_logger::try_init();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("places.sqlite");
+        {
+            let api = PlacesApi::new(&path, None)?;
+            let conn = api.open_connection(ConnectionType::ReadWrite)?;
+            populate(&conn)?;
+        }
+
+        // A healthy database should pass the check, and be opened as-is.
+        let api = PlacesApi::new_with_integrity_check(&path, None)?;
+        assert_eq!(api.recovery_report(), None);
+        let conn = api.open_connection(ConnectionType::ReadOnly)?;
+        assert_eq!(conn.query_one::<i64>("SELECT COUNT(*) FROM moz_places")?, 1);
+        Ok(())
+    }
+}
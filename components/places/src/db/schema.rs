@@ -15,7 +15,7 @@ use crate::storage::bookmarks::create_bookmark_roots;
 use rusqlite::NO_PARAMS;
 use sql_support::ConnExt;
 
-const VERSION: i64 = 7;
+const VERSION: i64 = 10;
 
 // Shared schema and temp tables for the read-write and Sync connections.
 const CREATE_SHARED_SCHEMA_SQL: &str = include_str!("../../sql/create_shared_schema.sql");
@@ -28,6 +28,15 @@ const CREATE_SYNC_TRIGGERS_SQL: &str = include_str!("../../sql/create_sync_trigg
 // Triggers for the main read-write connection only.
 const CREATE_MAIN_TRIGGERS_SQL: &str = include_str!("../../sql/create_main_triggers.sql");
 
+// The optional full-text index, and its triggers.
+const CREATE_FTS_INDEX_SQL: &str = include_str!("../../sql/create_fts_index.sql");
+const DROP_FTS_INDEX_SQL: &str = "
+    DROP TRIGGER IF EXISTS moz_places_afterinsert_trigger_fts;
+    DROP TRIGGER IF EXISTS moz_places_afterupdate_trigger_fts;
+    DROP TRIGGER IF EXISTS moz_places_afterdelete_trigger_fts;
+    DROP TABLE IF EXISTS moz_places_fts;
+";
+
 lazy_static::lazy_static! {
     // Triggers for the read-write and Sync connections.
     static ref CREATE_SHARED_TRIGGERS_SQL: String = {
@@ -185,7 +194,31 @@ fn upgrade(db: &PlacesDb, from: i64) -> Result<()> {
     migration(db, 4, 5, &[CREATE_SHARED_SCHEMA_SQL], || Ok(()))?;
     migration(db, 5, 6, &[CREATE_SHARED_SCHEMA_SQL], || Ok(()))?; // new tags tables.
     migration(db, 6, 7, &[CREATE_SHARED_SCHEMA_SQL], || Ok(()))?; // bookmark syncing.
-                                                                  // Add more migrations here...
+    migration(db, 7, 8, &[CREATE_SHARED_SCHEMA_SQL], || Ok(()))?; // optional full-text index.
+    migration(
+        db,
+        8,
+        9,
+        &[
+            CREATE_SHARED_SCHEMA_SQL,
+            // We used to only store keywords in the synced bookmarks table, so
+            // keep any that we've already synced.
+            "INSERT OR IGNORE INTO moz_keywords(keyword, place_id)
+             SELECT lower(trim(keyword)), placeId
+             FROM moz_bookmarks_synced
+             WHERE NOT isDeleted AND
+                   trim(IFNULL(keyword, '')) <> '' AND
+                   placeId NOT NULL",
+            // Temp triggers aren't set up yet, so bump the foreign count
+            // ourselves.
+            "UPDATE moz_places SET
+                 foreign_count = foreign_count + 1
+             WHERE id IN (SELECT place_id FROM moz_keywords)",
+        ],
+        || Ok(()),
+    )?; // keywords.
+    migration(db, 9, 10, &[CREATE_SHARED_SCHEMA_SQL], || Ok(()))?; // expiration index.
+        // Add more migrations here...
 
     if get_current_schema_version(db)? == VERSION {
         return Ok(());
@@ -207,6 +240,30 @@ pub fn create(db: &PlacesDb) -> Result<()> {
     Ok(())
 }
 
+/// Whether the database has the full-text index over page titles and URLs.
+pub fn has_fts_index(db: &PlacesDb) -> Result<bool> {
+    Ok(db.query_one::<bool>(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master
+                       WHERE type = 'table' AND name = 'moz_places_fts')",
+    )?)
+}
+
+/// Creates the full-text index, and indexes every page that's already in
+/// `moz_places`. Does nothing if the index already exists.
+pub fn create_fts_index(db: &PlacesDb) -> Result<()> {
+    if !has_fts_index(db)? {
+        log::debug!("Creating full-text index");
+        db.execute_batch(CREATE_FTS_INDEX_SQL)?;
+    }
+    Ok(())
+}
+
+pub fn drop_fts_index(db: &PlacesDb) -> Result<()> {
+    log::debug!("Dropping full-text index");
+    db.execute_batch(DROP_FTS_INDEX_SQL)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -668,4 +725,88 @@ mod tests {
         )
         .expect_err("changing the guid should fail");
     }
+
+    fn fts_matches(conn: &PlacesDb, query: &str) -> Vec<i64> {
+        conn.query_rows_and_then_named(
+            "SELECT rowid FROM moz_places_fts WHERE moz_places_fts MATCH :query
+             ORDER BY rowid",
+            &[(":query", &query)],
+            |row| row.get_checked::<_, i64>(0),
+        )
+        .expect("should query the index")
+    }
+
+    #[test]
+    fn test_places_fts_triggers() {
+        let conn = PlacesDb::open_in_memory(None).expect("no memory db");
+        assert!(!has_fts_index(&conn).expect("should check for the index"));
+
+        conn.execute_named_cached(
+            "INSERT INTO moz_places (guid, url, url_hash, title)
+             VALUES (:guid, :url, hash(:url), 'Example')",
+            &[
+                (":guid", &SyncGuid::new()),
+                (":url", &"https://example.com/"),
+            ],
+        )
+        .expect("should insert place");
+        let existing_id = conn.last_insert_rowid();
+
+        // Creating the index should index existing pages.
+        create_fts_index(&conn).expect("should create the index");
+        assert!(has_fts_index(&conn).expect("should check for the index"));
+        assert_eq!(fts_matches(&conn, "example"), vec![existing_id]);
+        // Creating it again is a no-op.
+        create_fts_index(&conn).expect("should create the index again");
+        assert_eq!(fts_matches(&conn, "example"), vec![existing_id]);
+
+        conn.execute_named_cached(
+            "INSERT INTO moz_places (guid, url, url_hash, title)
+             VALUES (:guid, :url, hash(:url), 'Mozilla')",
+            &[
+                (":guid", &SyncGuid::new()),
+                (":url", &"https://www.mozilla.org/firefox/"),
+            ],
+        )
+        .expect("should insert place");
+        let place_id = conn.last_insert_rowid();
+        assert_eq!(fts_matches(&conn, "mozilla"), vec![place_id]);
+        assert_eq!(fts_matches(&conn, "firef*"), vec![place_id]);
+
+        conn.execute_named_cached(
+            "UPDATE moz_places SET title = 'Rust' WHERE id = :id",
+            &[(":id", &place_id)],
+        )
+        .expect("should update title");
+        assert_eq!(fts_matches(&conn, "rust"), vec![place_id]);
+        // Still indexed by the url, but not the old title.
+        assert_eq!(fts_matches(&conn, "mozilla"), vec![place_id]);
+        assert!(fts_matches(&conn, "title:mozilla").is_empty());
+
+        conn.execute_named_cached(
+            "DELETE FROM moz_places WHERE id = :id",
+            &[(":id", &place_id)],
+        )
+        .expect("should delete place");
+        assert!(fts_matches(&conn, "rust").is_empty());
+        assert!(fts_matches(&conn, "mozilla").is_empty());
+
+        // Diacritics are folded in both the index and the query.
+        conn.execute_named_cached(
+            "UPDATE moz_places SET title = 'Café' WHERE id = :id",
+            &[(":id", &existing_id)],
+        )
+        .expect("should update title");
+        assert_eq!(fts_matches(&conn, "cafe"), vec![existing_id]);
+        assert_eq!(fts_matches(&conn, "CAFÉ"), vec![existing_id]);
+
+        drop_fts_index(&conn).expect("should drop the index");
+        assert!(!has_fts_index(&conn).expect("should check for the index"));
+        // Writes still work without the index and its triggers.
+        conn.execute_named_cached(
+            "DELETE FROM moz_places WHERE id = :id",
+            &[(":id", &existing_id)],
+        )
+        .expect("should delete place");
+    }
 }
@@ -119,6 +119,39 @@ pub enum ErrorKind {
 
     #[fail(display = "Protobuf decode error: {}", _0)]
     ProtobufDecodeError(#[fail(cause)] prost::DecodeError),
+
+    // Returned when importing from a places database that's missing tables
+    // or columns we need, usually because it's too old.
+    #[fail(display = "Can't import from an unsupported places database")]
+    UnsupportedImportDatabase,
+
+    // Returned when restoring a bookmark backup that doesn't contain the
+    // bookmarks root.
+    #[fail(display = "Invalid bookmark backup")]
+    InvalidBookmarkBackup,
+
+    // Returned when changing the encryption key, if the old key is wrong.
+    #[fail(display = "The encryption key is incorrect")]
+    WrongEncryptionKey,
+
+    // Encrypting or decrypting a database copies it into a new file, which
+    // we can't do for memory databases.
+    #[fail(display = "Can't encrypt or decrypt an in-memory database")]
+    CannotEncryptMemoryDatabase,
+
+    // Returned when opening a database that's already open with a different
+    // encryption key.
+    #[fail(display = "Database {:?} is already open with a different key", _0)]
+    EncryptionKeyMismatch(std::path::PathBuf),
+
+    // Returned when using a PlacesApi after it's been closed.
+    #[fail(display = "This PlacesApi has been closed")]
+    ApiClosed,
+
+    // Returned when every pooled reader stays checked out for longer than
+    // the pool's checkout timeout.
+    #[fail(display = "Timed out waiting for a reader to be returned to the pool")]
+    ReaderCheckoutTimeout,
 }
 
 macro_rules! impl_from_error {
@@ -184,6 +217,9 @@ pub enum InvalidPlaceInfo {
     // Like Urls, a tag is considered private info, so the value isn't in the error.
     #[fail(display = "The tag value is invalid")]
     InvalidTag,
+    // Keywords are also private info.
+    #[fail(display = "The keyword value is invalid")]
+    InvalidKeyword,
     #[fail(
         display = "Cannot change the '{}' property of a bookmark of type {:?}",
         _0, _1
@@ -192,6 +228,10 @@ pub enum InvalidPlaceInfo {
 
     #[fail(display = "Cannot update the bookmark root {:?}", _0)]
     CannotUpdateRoot(BookmarkRootGuid),
+
+    // Returned when restoring an item that already exists.
+    #[fail(display = "Item already exists: {}", _0)]
+    GuidAlreadyExists(String),
 }
 
 // Error types used when we can't continue due to corruption.
@@ -5,7 +5,7 @@
 // This module implement the traits that make the FFI code easier to manage.
 
 use crate::api::matcher::SearchResult;
-use crate::db::PlacesInterruptHandle;
+use crate::db::{CancellationToken, PlacesInterruptHandle};
 use crate::error::{Error, ErrorKind, InvalidPlaceInfo};
 use crate::msg_types;
 use ffi_support::{
@@ -24,7 +24,8 @@ pub mod error_codes {
     pub const URL_PARSE_ERROR: i32 = 2;
 
     /// The requested operation failed because the database was busy
-    /// performing operations on a separate connection to the same DB.
+    /// performing operations on a separate connection to the same DB, or
+    /// because no pooled reader was returned in time.
     pub const DATABASE_BUSY: i32 = 3;
 
     /// The requested operation failed because it was interrupted
@@ -33,6 +34,10 @@ pub mod error_codes {
     /// The requested operation failed because the store is corrupt
     pub const DATABASE_CORRUPT: i32 = 5;
 
+    /// The encryption key provided when changing keys is incorrect, or
+    /// doesn't match the key of an already open database.
+    pub const WRONG_ENCRYPTION_KEY: i32 = 6;
+
     // Skip a bunch of spaces to make it clear these are part of a group,
     // even as more and more errors get added. We're only exposing the
     // InvalidPlaceInfo items that can actually be triggered, the others
@@ -97,6 +102,10 @@ fn get_code(err: &Error) -> ErrorCode {
             log::info!("Operation interrupted");
             ErrorCode::new(error_codes::DATABASE_INTERRUPTED)
         }
+        ErrorKind::ReaderCheckoutTimeout => {
+            log::error!("Timed out waiting for a pooled reader");
+            ErrorCode::new(error_codes::DATABASE_BUSY)
+        }
         ErrorKind::InterruptedError => {
             // Can't unify with the above ... :(
             log::info!("Operation interrupted");
@@ -106,6 +115,14 @@ fn get_code(err: &Error) -> ErrorCode {
             log::info!("The store is corrupt: {}", e);
             ErrorCode::new(error_codes::DATABASE_CORRUPT)
         }
+        ErrorKind::WrongEncryptionKey => {
+            log::error!("Wrong encryption key");
+            ErrorCode::new(error_codes::WRONG_ENCRYPTION_KEY)
+        }
+        ErrorKind::EncryptionKeyMismatch(path) => {
+            log::error!("Database {:?} is already open with a different key", path);
+            ErrorCode::new(error_codes::WRONG_ENCRYPTION_KEY)
+        }
 
         err => {
             log::error!("Unexpected error: {:?}", err);
@@ -122,7 +139,11 @@ impl From<Error> for ExternError {
 
 implement_into_ffi_by_json!(SearchResult);
 implement_into_ffi_by_pointer!(PlacesInterruptHandle);
+implement_into_ffi_by_pointer!(CancellationToken);
 implement_into_ffi_by_protobuf!(msg_types::HistoryVisitInfos);
+implement_into_ffi_by_protobuf!(msg_types::HistoryVisitInfosWithBound);
+implement_into_ffi_by_protobuf!(msg_types::HistoryVisitGroups);
+implement_into_ffi_by_protobuf!(msg_types::TopFrecentSiteInfos);
 implement_into_ffi_by_protobuf!(msg_types::BookmarkNode);
 implement_into_ffi_by_protobuf!(msg_types::BookmarkNodeList);
 implement_into_ffi_by_delegation!(
@@ -5,6 +5,7 @@
 use crate::error::*;
 use crate::types::VisitTransition;
 use rusqlite::Connection;
+use serde_derive::*;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum RedirectBonus {
@@ -13,7 +14,11 @@ enum RedirectBonus {
     Normal,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// The weights and bonuses used to calculate frecency scores. These can be
+/// customized when creating a `PlacesApi`; any settings missing when
+/// deserializing use their default values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FrecencySettings {
     // TODO: These probably should not all be i32s...
     pub num_visits: i32,                     // from "places.frecency.numVisits"
@@ -7,6 +7,7 @@ use super::{HISTORY_TTL, MAX_OUTGOING_PLACES, MAX_VISITS};
 use crate::api::history::can_add_url;
 use crate::db::PlacesDb;
 use crate::error::*;
+use crate::observers::ChangeSource;
 use crate::storage::history::history_sync::{
     apply_synced_deletion, apply_synced_reconciliation, apply_synced_visits, fetch_outgoing,
     fetch_visits, finish_incoming, finish_outgoing, FetchedVisit, FetchedVisitPage, OutgoingInfo,
@@ -194,6 +195,7 @@ pub fn apply_plan(
     let mut tx = db.time_chunked_transaction()?;
 
     let mut outgoing = OutgoingChangeset::new("history".into(), inbound.timestamp);
+    let mut events = Vec::new();
     for (guid, plan) in plans {
         tx.maybe_commit()?;
         match &plan {
@@ -215,7 +217,7 @@ pub fn apply_plan(
             }
             IncomingPlan::Delete => {
                 log::trace!("incoming: deleting {:?}", guid);
-                apply_synced_deletion(&db, &guid)?;
+                events.extend(apply_synced_deletion(&db, &guid)?);
                 telem.applied(1);
             }
             IncomingPlan::Apply {
@@ -230,7 +232,7 @@ pub fn apply_plan(
                     new_title,
                     visits
                 );
-                apply_synced_visits(&db, &guid, &url, new_title, visits)?;
+                events.extend(apply_synced_visits(&db, &guid, &url, new_title, visits)?);
                 telem.applied(1);
             }
             IncomingPlan::Reconciled => {
@@ -242,6 +244,8 @@ pub fn apply_plan(
     }
     finish_incoming(&db)?;
     tx.commit()?;
+    db.observers()
+        .notify_history_observers(ChangeSource::Sync, &events);
     // It might make sense for fetch_outgoing to manage its own
     // time_chunked_transaction - even though doesn't seem a large bottleneck
     // at this time, the fact we hold a single transaction for the entire call
@@ -749,6 +753,92 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_apply_plan_observers() -> Result<()> {
+        use crate::observers::{HistoryEvent, HistoryObserver};
+        use std::sync::{Arc, Mutex};
+
+        let _ = env_logger::try_init();
+        let db = PlacesDb::open_in_memory(None)?;
+        let url = Url::parse("http://example.com")?;
+        apply_observation(
+            &db,
+            VisitObservation::new(Url::parse("http://example.com/local")?)
+                .with_visit_type(VisitTransition::Link),
+        )?;
+        let local_guid = get_existing_guid(&db, &Url::parse("http://example.com/local")?);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observer: Arc<dyn HistoryObserver> = {
+            let events = events.clone();
+            Arc::new(move |source: ChangeSource, new_events: &[HistoryEvent]| {
+                assert_eq!(source, ChangeSource::Sync);
+                events.lock().unwrap().extend_from_slice(new_events);
+            })
+        };
+        db.observers().add_history_observer(observer);
+
+        let now: Timestamp = SystemTime::now().into();
+        let mut incoming = IncomingChangeset::new("history".to_string(), ServerTimestamp(0f64));
+        for json in vec![
+            json!({
+                "id": "aaaaaaaaaaaa",
+                "title": "title",
+                "histUri": url.as_str(),
+                "visits": [ {"date": ServerVisitTimestamp::from(now), "type": 1}]
+            }),
+            json!({
+                "id": local_guid,
+                "deleted": true,
+            }),
+        ] {
+            let payload = Payload::from_json(json).unwrap();
+            incoming.changes.push((payload, ServerTimestamp(0f64)));
+        }
+        apply_plan(&db, incoming, &mut telemetry::EngineIncoming::new())?;
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[0],
+            HistoryEvent::Visited {
+                guid: "aaaaaaaaaaaa".into(),
+                url: url.clone(),
+                visit_date: now,
+                visit_type: VisitTransition::Link,
+                is_local: false,
+            }
+        );
+        assert_eq!(
+            events[1],
+            HistoryEvent::TitleChanged {
+                guid: "aaaaaaaaaaaa".into(),
+                url: url.clone(),
+                title: "title".into(),
+            }
+        );
+        match &events[2] {
+            HistoryEvent::FrecencyChanged {
+                guid,
+                url: changed_url,
+                frecency,
+            } => {
+                assert_eq!(guid, &SyncGuid::from("aaaaaaaaaaaa"));
+                assert_eq!(changed_url, &url);
+                assert!(*frecency > 0, "should have frecency");
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        assert_eq!(
+            events[3],
+            HistoryEvent::Deleted {
+                guid: local_guid,
+                url: Url::parse("http://example.com/local")?,
+            }
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_apply_plan_outgoing_new() -> Result<()> {
         let _ = env_logger::try_init();
@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use crate::api::places_api::ConnectionType;
+use crate::api::places_api::{sync_stores, ConnectionType};
 use crate::db::PlacesDb;
 use crate::error::*;
 use crate::storage::history::history_sync::reset_storage;
@@ -14,15 +14,14 @@ use std::result;
 use sync15::telemetry;
 use sync15::CollectionRequest;
 use sync15::{
-    sync_multiple, ClientInfo, IncomingChangeset, KeyBundle, OutgoingChangeset, ServerTimestamp,
-    Store, Sync15StorageClientInit,
+    ClientInfo, IncomingChangeset, KeyBundle, OutgoingChangeset, ServerTimestamp, Store,
+    Sync15StorageClientInit,
 };
 
 use super::plan::{apply_plan, finish_plan};
 use super::MAX_INCOMING_PLACES;
 
 const LAST_SYNC_META_KEY: &str = "history_last_sync_time";
-const GLOBAL_STATE_META_KEY: &str = "history_global_state";
 
 // A HistoryStore is short-lived and constructed each sync by something which
 // owns the connection and ClientInfo.
@@ -82,44 +81,23 @@ impl<'a> HistoryStore<'a> {
         Ok(())
     }
 
-    fn set_global_state(&self, global_state: Option<String>) -> Result<()> {
-        let to_write = match global_state {
-            Some(ref s) => s,
-            None => "",
-        };
-        self.put_meta(GLOBAL_STATE_META_KEY, &to_write)
-    }
-
-    fn get_global_state(&self) -> Result<Option<String>> {
-        self.get_meta::<String>(GLOBAL_STATE_META_KEY)
-    }
-
-    /// A convenience wrapper around sync_multiple.
+    /// A convenience wrapper around sync_multiple, for syncing history on
+    /// its own. `PlacesApi::sync` does the same thing, and also makes sure
+    /// that only one sync runs at a time.
     pub fn sync(
         &self,
         storage_init: &Sync15StorageClientInit,
         root_sync_key: &KeyBundle,
         sync_ping: &mut telemetry::SyncTelemetryPing,
     ) -> Result<()> {
-        let global_state: Cell<Option<String>> = Cell::new(self.get_global_state()?);
-        let result = sync_multiple(
+        sync_stores(
+            self.db,
+            self.client_info,
             &[self],
-            &global_state,
-            &self.client_info,
             storage_init,
             root_sync_key,
             sync_ping,
-        );
-        self.set_global_state(global_state.replace(None))?;
-        let failures = result?;
-        if failures.is_empty() {
-            Ok(())
-        } else {
-            assert_eq!(failures.len(), 1);
-            let (name, err) = failures.into_iter().next().unwrap();
-            assert_eq!(name, "history");
-            Err(err.into())
-        }
+        )
     }
 }
 
@@ -0,0 +1,830 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Imports history and bookmarks from a desktop Firefox `places.sqlite`, for
+//! migrating existing profiles.
+//!
+//! The foreign database is opened read-only, and its rows are mapped into our
+//! schema one table at a time. Rows we can't represent, like pages with
+//! invalid URLs, visits with unknown transition types, or keywords with POST
+//! data, are skipped and counted in the `ImportMetrics`. Pages that already
+//! exist locally are merged, and foreign GUIDs are kept unless they're
+//! invalid or already used by a different local item, in which case a new
+//! GUID is generated.
+//!
+//! Like `bookmarks::html::import_html`, importing the same database twice
+//! duplicates its bookmarks, but not its history.
+
+use crate::api::places_api::ConnectionType;
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::storage::bookmarks::{
+    insert_tree, BookmarkNode, BookmarkRootGuid, BookmarkTreeNode, FolderNode, SeparatorNode,
+    USER_CONTENT_ROOTS,
+};
+use crate::storage::history::{add_visit, update_frecency};
+use crate::storage::{keywords, new_page_info, tags, RowId, URL_LENGTH_MAX};
+use crate::types::{SyncGuid, Timestamp, VisitTransition};
+use crate::valid_guid::is_valid_places_guid;
+use rusqlite::{Connection, OpenFlags, Row, NO_PARAMS};
+use serde_derive::*;
+use sql_support::ConnExt;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use url::Url;
+
+/// The item annotation desktop uses for bookmark descriptions. We don't have
+/// per-bookmark descriptions, so these become the page's description instead.
+const DESCRIPTION_ANNO: &str = "bookmarkProperties/description";
+
+/// The number of pages, visits or annotations to import in each transaction.
+const IMPORT_CHUNK_SIZE: usize = 500;
+
+/// Desktop's tags are folders under this root, containing a bookmark for
+/// each tagged URL.
+const DESKTOP_TAGS_ROOT_GUID: &str = "tags________";
+
+/// The number of rows of one kind that were imported and skipped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportCounts {
+    pub imported: u32,
+    pub skipped: u32,
+}
+
+/// What a call to `import_desktop_places` did.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportMetrics {
+    /// Pages with visits or bookmarks. Pages that already existed locally
+    /// are counted as imported.
+    pub places: ImportCounts,
+    pub visits: ImportCounts,
+    /// Bookmarks, folders and separators, not including the roots.
+    pub bookmarks: ImportCounts,
+    pub tags: ImportCounts,
+    pub keywords: ImportCounts,
+    /// Page and item annotations. Only bookmark descriptions are imported.
+    pub annotations: ImportCounts,
+}
+
+/// Imports history, bookmarks, tags, keywords and bookmark descriptions from
+/// the desktop `places.sqlite` at `path`. This must be called on a read-write
+/// connection, and can be interrupted. History is imported in chunked
+/// transactions, so an interrupted or failed import keeps the history that
+/// was imported before it stopped. Observers aren't notified about imported
+/// pages or bookmarks.
+pub fn import_desktop_places(db: &PlacesDb, path: impl AsRef<Path>) -> Result<ImportMetrics> {
+    if db.conn_type() != ConnectionType::ReadWrite {
+        return Err(ErrorKind::InvalidConnectionType.into());
+    }
+    let foreign = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let required = [
+        ("moz_places", "guid"),
+        ("moz_historyvisits", "visit_date"),
+        ("moz_bookmarks", "guid"),
+        ("moz_keywords", "place_id"),
+    ];
+    for (table, column) in &required {
+        if !has_column(&foreign, table, column)? {
+            log::warn!("Can't import places without {}.{}", table, column);
+            return Err(ErrorKind::UnsupportedImportDatabase.into());
+        }
+    }
+
+    let mut metrics = ImportMetrics::default();
+    let place_ids = import_places(db, &foreign, &mut metrics)?;
+    import_visits(db, &foreign, &place_ids, &mut metrics)?;
+    import_bookmarks(db, &foreign, &mut metrics)?;
+    import_keywords(db, &foreign, &mut metrics)?;
+    import_annotations(db, &foreign, &mut metrics)?;
+    log::info!("Imported desktop places: {:?}", metrics);
+    Ok(metrics)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    Ok(conn.query_row_and_then_named(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(:table) WHERE name = :column)",
+        &[(":table", &table), (":column", &column)],
+        |row| -> rusqlite::Result<bool> { row.get_checked(0) },
+        false,
+    )?)
+}
+
+fn parse_foreign_url(url: &str) -> Option<Url> {
+    if url.len() > URL_LENGTH_MAX {
+        return None;
+    }
+    Url::parse(url).ok()
+}
+
+/// Desktop stores times in microseconds, and we store them in milliseconds.
+fn foreign_timestamp(micros: i64) -> Option<Timestamp> {
+    if micros > 0 {
+        Some(Timestamp(micros as u64 / 1000))
+    } else {
+        None
+    }
+}
+
+/// Returns the foreign GUID if we can use it for a new row in `table`.
+fn unused_guid(db: &PlacesDb, table: &str, guid: &str) -> Result<Option<SyncGuid>> {
+    if !is_valid_places_guid(guid) {
+        return Ok(None);
+    }
+    let sql = format!("SELECT EXISTS(SELECT 1 FROM {} WHERE guid = :guid)", table);
+    let exists = db.query_row_and_then_named(
+        &sql,
+        &[(":guid", &guid)],
+        |row| -> rusqlite::Result<bool> { row.get_checked(0) },
+        true,
+    )?;
+    Ok(if exists { None } else { Some(guid.into()) })
+}
+
+#[derive(Debug)]
+struct ForeignPlace {
+    id: i64,
+    url: Option<String>,
+    title: Option<String>,
+    hidden: bool,
+    typed: u32,
+    guid: Option<String>,
+    description: Option<String>,
+    preview_image_url: Option<String>,
+}
+
+impl ForeignPlace {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get_checked("id")?,
+            url: row.get_checked("url")?,
+            title: row.get_checked("title")?,
+            hidden: row.get_checked("hidden")?,
+            typed: row.get_checked("typed")?,
+            guid: row.get_checked("guid")?,
+            description: row.get_checked("description")?,
+            preview_image_url: row.get_checked("preview_image_url")?,
+        })
+    }
+}
+
+/// Imports or merges every page with visits or bookmarks, and returns a map
+/// of foreign place IDs to local ones.
+fn import_places(
+    db: &PlacesDb,
+    foreign: &Connection,
+    metrics: &mut ImportMetrics,
+) -> Result<HashMap<i64, RowId>> {
+    // Desktop only added these columns fairly recently.
+    let optional_column = |column: &str| -> Result<String> {
+        Ok(if has_column(foreign, "moz_places", column)? {
+            format!("h.{}", column)
+        } else {
+            "NULL".into()
+        })
+    };
+    let sql = format!(
+        "SELECT h.id, h.url, h.title, h.hidden, h.typed, h.guid,
+                {} AS description, {} AS preview_image_url
+         FROM moz_places h
+         WHERE EXISTS(SELECT 1 FROM moz_historyvisits WHERE place_id = h.id) OR
+               EXISTS(SELECT 1 FROM moz_bookmarks WHERE fk = h.id)",
+        optional_column("description")?,
+        optional_column("preview_image_url")?,
+    );
+    let scope = db.begin_interrupt_scope();
+    let mut place_ids = HashMap::new();
+    let mut tx = db.chunked_coop_transaction(IMPORT_CHUNK_SIZE)?;
+    let mut stmt = foreign.prepare(&sql)?;
+    for result in stmt.query_and_then(NO_PARAMS, ForeignPlace::from_row)? {
+        let place = result?;
+        scope.err_if_interrupted()?;
+        let url = match place.url.as_ref().and_then(|url| parse_foreign_url(url)) {
+            Some(url) => url,
+            None => {
+                metrics.places.skipped += 1;
+                continue;
+            }
+        };
+        let existing = db.try_query_one::<RowId>(
+            "SELECT id FROM moz_places WHERE url_hash = hash(:url) AND url = :url",
+            &[(":url", &url.as_str())],
+            true,
+        )?;
+        let row_id = match existing {
+            Some(row_id) => row_id,
+            None => {
+                let guid = match &place.guid {
+                    Some(guid) => unused_guid(db, "moz_places", guid)?,
+                    None => None,
+                };
+                new_page_info(db, &url, guid)?.row_id
+            }
+        };
+        // Existing pages keep their titles and descriptions, but are shown
+        // if either copy is.
+        db.execute_named_cached(
+            "UPDATE moz_places SET
+               title = CASE WHEN IFNULL(title, '') = '' THEN :title ELSE title END,
+               hidden = hidden AND :hidden,
+               typed = MAX(typed, :typed),
+               description = IFNULL(description, :description),
+               preview_image_url = IFNULL(preview_image_url, :preview_image_url)
+             WHERE id = :id",
+            &[
+                (":title", &place.title),
+                (":hidden", &place.hidden),
+                (":typed", &place.typed),
+                (":description", &place.description),
+                (":preview_image_url", &place.preview_image_url),
+                (":id", &row_id),
+            ],
+        )?;
+        place_ids.insert(place.id, row_id);
+        metrics.places.imported += 1;
+        tx.maybe_commit()?;
+    }
+    tx.commit()?;
+    Ok(place_ids)
+}
+
+#[derive(Debug)]
+struct ForeignVisit {
+    id: i64,
+    from_visit: Option<i64>,
+    place_id: i64,
+    visit_date: i64,
+    visit_type: i64,
+}
+
+impl ForeignVisit {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get_checked("id")?,
+            from_visit: row.get_checked("from_visit")?,
+            place_id: row.get_checked("place_id")?,
+            visit_date: row.get_checked("visit_date")?,
+            visit_type: row.get_checked("visit_type")?,
+        })
+    }
+}
+
+/// Imports visits for the pages in `place_ids`, skipping visits we already
+/// have, and then recalculates frecencies for the pages that were visited.
+fn import_visits(
+    db: &PlacesDb,
+    foreign: &Connection,
+    place_ids: &HashMap<i64, RowId>,
+    metrics: &mut ImportMetrics,
+) -> Result<()> {
+    let scope = db.begin_interrupt_scope();
+    let mut visit_ids: HashMap<i64, RowId> = HashMap::new();
+    let mut visited: BTreeSet<RowId> = BTreeSet::new();
+    let mut tx = db.chunked_coop_transaction(IMPORT_CHUNK_SIZE)?;
+    let mut stmt = foreign.prepare(
+        "SELECT id, from_visit, place_id, visit_date, visit_type
+         FROM moz_historyvisits
+         ORDER BY id",
+    )?;
+    for result in stmt.query_and_then(NO_PARAMS, ForeignVisit::from_row)? {
+        let visit = result?;
+        scope.err_if_interrupted()?;
+        let place_id = place_ids.get(&visit.place_id).cloned();
+        let visit_date = foreign_timestamp(visit.visit_date);
+        let visit_type = if visit.visit_type > 0 && visit.visit_type <= 255 {
+            VisitTransition::from_primitive(visit.visit_type as u8)
+        } else {
+            None
+        };
+        let (place_id, visit_date, visit_type) = match (place_id, visit_date, visit_type) {
+            (Some(place_id), Some(visit_date), Some(visit_type)) => {
+                (place_id, visit_date, visit_type)
+            }
+            _ => {
+                metrics.visits.skipped += 1;
+                continue;
+            }
+        };
+        let exists = db.query_row_and_then_named(
+            "SELECT EXISTS(SELECT 1 FROM moz_historyvisits
+                           WHERE place_id = :place_id AND visit_date = :visit_date)",
+            &[(":place_id", &place_id), (":visit_date", &visit_date)],
+            |row| -> rusqlite::Result<bool> { row.get_checked(0) },
+            true,
+        )?;
+        if exists {
+            metrics.visits.skipped += 1;
+            continue;
+        }
+        let from_visit = visit
+            .from_visit
+            .and_then(|from_visit| visit_ids.get(&from_visit).cloned());
+        let row_id = add_visit(db, place_id, from_visit, visit_date, visit_type, true)?;
+        visit_ids.insert(visit.id, row_id);
+        visited.insert(place_id);
+        metrics.visits.imported += 1;
+        tx.maybe_commit()?;
+    }
+    for place_id in visited {
+        scope.err_if_interrupted()?;
+        db.execute_named_cached(
+            "UPDATE moz_places SET sync_change_counter = sync_change_counter + 1
+             WHERE id = :id",
+            &[(":id", &place_id)],
+        )?;
+        update_frecency(db, place_id, None)?;
+        tx.maybe_commit()?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct ForeignBookmark {
+    id: i64,
+    guid: String,
+    bookmark_type: i64,
+    parent: Option<i64>,
+    title: Option<String>,
+    date_added: i64,
+    last_modified: i64,
+    url: Option<String>,
+}
+
+impl ForeignBookmark {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get_checked("id")?,
+            guid: row.get_checked("guid")?,
+            bookmark_type: row.get_checked("type")?,
+            parent: row.get_checked("parent")?,
+            title: row.get_checked("title")?,
+            date_added: row.get_checked("dateAdded")?,
+            last_modified: row.get_checked("lastModified")?,
+            url: row.get_checked("url")?,
+        })
+    }
+}
+
+/// Imports the children of each desktop root into the matching local root,
+/// and turns desktop's tag folders into tags.
+fn import_bookmarks(
+    db: &PlacesDb,
+    foreign: &Connection,
+    metrics: &mut ImportMetrics,
+) -> Result<()> {
+    let mut stmt = foreign.prepare(
+        "SELECT b.id, b.guid, b.type, b.parent, b.title,
+                IFNULL(b.dateAdded, 0) AS dateAdded,
+                IFNULL(b.lastModified, 0) AS lastModified,
+                h.url
+         FROM moz_bookmarks b
+         LEFT JOIN moz_places h ON h.id = b.fk
+         ORDER BY b.parent, b.position",
+    )?;
+    let mut total = 0u32;
+    let mut roots: HashMap<String, i64> = HashMap::new();
+    let mut children: HashMap<i64, Vec<ForeignBookmark>> = HashMap::new();
+    for result in stmt.query_and_then(NO_PARAMS, ForeignBookmark::from_row)? {
+        let bookmark = result?;
+        if BookmarkRootGuid::well_known(&bookmark.guid).is_some()
+            || bookmark.guid == DESKTOP_TAGS_ROOT_GUID
+        {
+            roots.insert(bookmark.guid.clone(), bookmark.id);
+            continue;
+        }
+        total += 1;
+        if let Some(parent) = bookmark.parent {
+            children.entry(parent).or_default().push(bookmark);
+        }
+    }
+
+    let mut imported_tags = Vec::new();
+    let mut tag_rows = 0u32;
+    if let Some(tags_root) = roots.get(DESKTOP_TAGS_ROOT_GUID) {
+        for tag_folder in children.remove(tags_root).unwrap_or_default() {
+            tag_rows += 1;
+            let tag = tag_folder.title.unwrap_or_default();
+            for entry in children.remove(&tag_folder.id).unwrap_or_default() {
+                tag_rows += 1;
+                match entry.url.as_ref().and_then(|url| parse_foreign_url(url)) {
+                    Some(url) => imported_tags.push((url, tag.clone())),
+                    None => metrics.tags.skipped += 1,
+                }
+            }
+        }
+    }
+
+    let mut imported = 0u32;
+    for &root in USER_CONTENT_ROOTS {
+        let foreign_id = match roots.get(root.as_str()) {
+            Some(id) => *id,
+            None => continue,
+        };
+        let tree = FolderNode {
+            guid: Some(root.into()),
+            children: convert_children(db, &mut children, foreign_id, &mut imported)?,
+            ..Default::default()
+        };
+        if !tree.children.is_empty() {
+            insert_tree(db, &tree)?;
+        }
+    }
+    metrics.bookmarks.imported += imported;
+    metrics.bookmarks.skipped += total.saturating_sub(tag_rows + imported);
+
+    for (url, tag) in imported_tags {
+        match tags::tag_url(db, &url, &tag) {
+            Ok(()) => metrics.tags.imported += 1,
+            Err(e) => {
+                log::warn!("Skipping imported tag: {}", e);
+                metrics.tags.skipped += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn convert_children(
+    db: &PlacesDb,
+    children: &mut HashMap<i64, Vec<ForeignBookmark>>,
+    parent: i64,
+    imported: &mut u32,
+) -> Result<Vec<BookmarkTreeNode>> {
+    let mut nodes = Vec::new();
+    for item in children.remove(&parent).unwrap_or_default() {
+        let guid = unused_guid(db, "moz_bookmarks", &item.guid)?;
+        let date_added = foreign_timestamp(item.date_added);
+        let last_modified = foreign_timestamp(item.last_modified);
+        let node: BookmarkTreeNode = match item.bookmark_type {
+            1 => match item.url.as_ref().and_then(|url| parse_foreign_url(url)) {
+                Some(url) => BookmarkNode {
+                    guid,
+                    date_added,
+                    last_modified,
+                    title: item.title,
+                    url,
+                }
+                .into(),
+                None => continue,
+            },
+            2 => FolderNode {
+                guid,
+                date_added,
+                last_modified,
+                title: item.title,
+                children: convert_children(db, children, item.id, imported)?,
+            }
+            .into(),
+            3 => SeparatorNode {
+                guid,
+                date_added,
+                last_modified,
+            }
+            .into(),
+            _ => continue,
+        };
+        *imported += 1;
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+/// Imports keywords, without replacing any local keywords.
+fn import_keywords(db: &PlacesDb, foreign: &Connection, metrics: &mut ImportMetrics) -> Result<()> {
+    let post_data = if has_column(foreign, "moz_keywords", "post_data")? {
+        "k.post_data"
+    } else {
+        "NULL"
+    };
+    let mut stmt = foreign.prepare(&format!(
+        "SELECT k.keyword, h.url, {} AS post_data
+         FROM moz_keywords k
+         JOIN moz_places h ON h.id = k.place_id",
+        post_data
+    ))?;
+    let rows = stmt.query_and_then(NO_PARAMS, |row| -> Result<_> {
+        Ok((
+            row.get_checked::<_, String>("keyword")?,
+            row.get_checked::<_, Option<String>>("url")?,
+            row.get_checked::<_, Option<String>>("post_data")?,
+        ))
+    })?;
+    for result in rows {
+        let (keyword, url, post_data) = result?;
+        // We don't support POST data, and a keyword without it would load
+        // the wrong page.
+        let url = match url.as_ref().and_then(|url| parse_foreign_url(url)) {
+            Some(url) if post_data.as_ref().map_or(true, String::is_empty) => url,
+            _ => {
+                metrics.keywords.skipped += 1;
+                continue;
+            }
+        };
+        if keywords::get_url_for_keyword(db, &keyword)?.is_some()
+            || keywords::get_keyword_for_url(db, &url)?.is_some()
+        {
+            metrics.keywords.skipped += 1;
+            continue;
+        }
+        match keywords::set_keyword(db, &url, &keyword) {
+            Ok(()) => metrics.keywords.imported += 1,
+            Err(e) => {
+                log::warn!("Skipping imported keyword: {}", e);
+                metrics.keywords.skipped += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Imports bookmark descriptions as page descriptions, if the page doesn't
+/// already have one. All other annotations are skipped.
+fn import_annotations(
+    db: &PlacesDb,
+    foreign: &Connection,
+    metrics: &mut ImportMetrics,
+) -> Result<()> {
+    if !has_column(foreign, "moz_anno_attributes", "name")? {
+        return Ok(());
+    }
+    if has_column(foreign, "moz_annos", "content")? {
+        let count: u32 = foreign.query_one("SELECT COUNT(*) FROM moz_annos")?;
+        metrics.annotations.skipped += count;
+    }
+    if !has_column(foreign, "moz_items_annos", "content")? {
+        return Ok(());
+    }
+    let scope = db.begin_interrupt_scope();
+    let mut tx = db.chunked_coop_transaction(IMPORT_CHUNK_SIZE)?;
+    let mut stmt = foreign.prepare(
+        "SELECT n.name, a.content, h.url
+         FROM moz_items_annos a
+         JOIN moz_anno_attributes n ON n.id = a.anno_attribute_id
+         LEFT JOIN moz_bookmarks b ON b.id = a.item_id
+         LEFT JOIN moz_places h ON h.id = b.fk",
+    )?;
+    let rows = stmt.query_and_then(NO_PARAMS, |row| -> Result<_> {
+        Ok((
+            row.get_checked::<_, String>("name")?,
+            row.get_checked::<_, Option<String>>("content")?,
+            row.get_checked::<_, Option<String>>("url")?,
+        ))
+    })?;
+    for result in rows {
+        let (name, content, url) = result?;
+        scope.err_if_interrupted()?;
+        let description = content.filter(|content| name == DESCRIPTION_ANNO && !content.is_empty());
+        let url = url.as_ref().and_then(|url| parse_foreign_url(url));
+        let changed = match (description, url) {
+            (Some(description), Some(url)) => db.execute_named_cached(
+                "UPDATE moz_places SET description = :description
+                 WHERE url_hash = hash(:url) AND url = :url AND
+                       description IS NULL",
+                &[(":description", &description), (":url", &url.as_str())],
+            )?,
+            _ => 0,
+        };
+        if changed > 0 {
+            metrics.annotations.imported += 1;
+        } else {
+            metrics.annotations.skipped += 1;
+        }
+        tx.maybe_commit()?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::{new_mem_api, new_mem_connection};
+    use crate::storage::bookmarks::fetch_tree;
+    use crate::storage::history::url_to_guid;
+    use pretty_assertions::assert_eq;
+
+    // Just enough of the desktop schema for the import.
+    const DESKTOP_SCHEMA_SQL: &str = "
+        CREATE TABLE moz_places (
+            id INTEGER PRIMARY KEY, url LONGVARCHAR, title LONGVARCHAR,
+            rev_host LONGVARCHAR, visit_count INTEGER DEFAULT 0,
+            hidden INTEGER DEFAULT 0 NOT NULL, typed INTEGER DEFAULT 0 NOT NULL,
+            frecency INTEGER DEFAULT -1 NOT NULL, last_visit_date INTEGER,
+            guid TEXT, foreign_count INTEGER DEFAULT 0 NOT NULL,
+            url_hash INTEGER DEFAULT 0 NOT NULL, description TEXT,
+            preview_image_url TEXT, origin_id INTEGER
+        );
+        CREATE TABLE moz_historyvisits (
+            id INTEGER PRIMARY KEY, from_visit INTEGER, place_id INTEGER,
+            visit_date INTEGER, visit_type INTEGER, session INTEGER
+        );
+        CREATE TABLE moz_bookmarks (
+            id INTEGER PRIMARY KEY, type INTEGER, fk INTEGER DEFAULT NULL,
+            parent INTEGER, position INTEGER, title LONGVARCHAR,
+            keyword_id INTEGER, folder_type TEXT, dateAdded INTEGER,
+            lastModified INTEGER, guid TEXT
+        );
+        CREATE TABLE moz_keywords (
+            id INTEGER PRIMARY KEY AUTOINCREMENT, keyword TEXT UNIQUE,
+            place_id INTEGER, post_data TEXT
+        );
+        CREATE TABLE moz_anno_attributes (
+            id INTEGER PRIMARY KEY, name VARCHAR(32) UNIQUE NOT NULL
+        );
+        CREATE TABLE moz_annos (
+            id INTEGER PRIMARY KEY, place_id INTEGER NOT NULL,
+            anno_attribute_id INTEGER, content LONGVARCHAR
+        );
+        CREATE TABLE moz_items_annos (
+            id INTEGER PRIMARY KEY, item_id INTEGER NOT NULL,
+            anno_attribute_id INTEGER, content LONGVARCHAR
+        );
+
+        INSERT INTO moz_places(id, url, title, guid, description) VALUES
+            (1, 'https://www.mozilla.org/', 'Mozilla', 'mozillaguid1',
+             'Internet for people'),
+            (2, 'https://example.com/', 'Example', 'exampleguid1', NULL),
+            (3, 'not a url', 'Broken', 'brokenguid12', NULL),
+            (4, 'https://bookmarked.example/', 'Bookmarked', 'bookmarked12', NULL);
+
+        INSERT INTO moz_historyvisits(id, from_visit, place_id, visit_date,
+                                      visit_type) VALUES
+            (1, NULL, 1, 1546300800000000, 1),
+            (2, 1, 2, 1546300860000000, 1),
+            (3, NULL, 3, 1546300920000000, 2),
+            (4, NULL, 1, 1546300980000000, 42);
+
+        INSERT INTO moz_bookmarks(id, type, fk, parent, position, title,
+                                  dateAdded, lastModified, guid) VALUES
+            (1, 2, NULL, NULL, 0, '', 0, 0, 'root________'),
+            (2, 2, NULL, 1, 0, 'menu', 0, 0, 'menu________'),
+            (3, 2, NULL, 1, 1, 'toolbar', 0, 0, 'toolbar_____'),
+            (4, 2, NULL, 1, 2, 'tags', 0, 0, 'tags________'),
+            (5, 2, NULL, 1, 3, 'unfiled', 0, 0, 'unfiled_____'),
+            (6, 2, NULL, 1, 4, 'mobile', 0, 0, 'mobile______'),
+            (7, 1, 4, 3, 0, 'Bookmarked', 1546300800000000, 1546300800000000,
+             'bookmarkgd01'),
+            (8, 3, NULL, 3, 1, NULL, 0, 0, 'separatorg01'),
+            (9, 2, NULL, 2, 0, 'Folder', 0, 0, 'foldergd0001'),
+            (10, 1, 1, 9, 0, 'Mozilla', 0, 0, 'mozillabkmk1'),
+            (11, 1, 99, 9, 1, 'Missing', 0, 0, 'missingplac1'),
+            (12, 2, NULL, 4, 0, 'cool', 0, 0, 'tagfoldergd1'),
+            (13, 1, 4, 12, 0, NULL, 0, 0, 'tagentrygd01');
+
+        INSERT INTO moz_keywords(keyword, place_id, post_data) VALUES
+            ('bm', 4, NULL),
+            ('post', 1, 'q=%s');
+
+        INSERT INTO moz_anno_attributes(id, name) VALUES
+            (1, 'bookmarkProperties/description'),
+            (2, 'URIProperties/characterSet');
+        INSERT INTO moz_items_annos(item_id, anno_attribute_id, content) VALUES
+            (7, 1, 'A bookmarked page');
+        INSERT INTO moz_annos(place_id, anno_attribute_id, content) VALUES
+            (1, 2, 'UTF-8');
+    ";
+
+    fn counts(imported: u32, skipped: u32) -> ImportCounts {
+        ImportCounts { imported, skipped }
+    }
+
+    #[test]
+    fn test_import_desktop_places() -> Result<()> {
+        let _ = env_logger::try_init();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("places.sqlite");
+        Connection::open(&path)?.execute_batch(DESKTOP_SCHEMA_SQL)?;
+
+        let conn = new_mem_connection();
+        let metrics = import_desktop_places(&conn, &path)?;
+        assert_eq!(
+            metrics,
+            ImportMetrics {
+                places: counts(3, 1),
+                visits: counts(2, 2),
+                bookmarks: counts(4, 1),
+                tags: counts(1, 0),
+                keywords: counts(1, 1),
+                annotations: counts(1, 1),
+            }
+        );
+
+        let mozilla = Url::parse("https://www.mozilla.org/")?;
+        let bookmarked = Url::parse("https://bookmarked.example/")?;
+        assert_eq!(
+            url_to_guid(&conn, &mozilla)?,
+            Some(SyncGuid::from("mozillaguid1"))
+        );
+        let linked: u32 = conn.query_one(
+            "SELECT COUNT(*) FROM moz_historyvisits
+             WHERE from_visit IS NOT NULL AND visit_date = 1546300860000",
+        )?;
+        assert_eq!(linked, 1);
+        let visited: u32 = conn.query_one(
+            "SELECT COUNT(*) FROM moz_places
+             WHERE visit_count_local > 0 AND frecency > 0",
+        )?;
+        assert_eq!(visited, 2);
+        let descriptions: Vec<Option<String>> = conn.query_rows_into(
+            "SELECT description FROM moz_places ORDER BY url",
+            &[],
+            |row| row.get_checked(0),
+        )?;
+        assert_eq!(
+            descriptions,
+            vec![
+                Some("A bookmarked page".to_string()),
+                None,
+                Some("Internet for people".to_string())
+            ]
+        );
+        assert_eq!(tags::get_tags_for_url(&conn, &bookmarked)?, vec!["cool"]);
+        assert_eq!(
+            keywords::get_keyword_for_url(&conn, &bookmarked)?,
+            Some("bm".to_string())
+        );
+        assert_eq!(keywords::get_keyword_for_url(&conn, &mozilla)?, None);
+
+        let toolbar = match fetch_tree(&conn, &BookmarkRootGuid::Toolbar.into())? {
+            Some(BookmarkTreeNode::Folder(f)) => f,
+            _ => panic!("should have a toolbar"),
+        };
+        assert_eq!(toolbar.children.len(), 2);
+        match &toolbar.children[0] {
+            BookmarkTreeNode::Bookmark(b) => {
+                assert_eq!(b.guid, Some(SyncGuid::from("bookmarkgd01")));
+                assert_eq!(b.url, bookmarked);
+                assert_eq!(b.date_added, Some(Timestamp(1_546_300_800_000)));
+            }
+            node => panic!("expected a bookmark, got {:?}", node),
+        }
+
+        // Importing again shouldn't duplicate history, keywords or
+        // descriptions, but will duplicate bookmarks, with new GUIDs.
+        let metrics = import_desktop_places(&conn, &path)?;
+        assert_eq!(
+            metrics,
+            ImportMetrics {
+                places: counts(3, 1),
+                visits: counts(0, 4),
+                bookmarks: counts(4, 1),
+                tags: counts(1, 0),
+                keywords: counts(0, 2),
+                annotations: counts(0, 2),
+            }
+        );
+        let visits: u32 = conn.query_one("SELECT COUNT(*) FROM moz_historyvisits")?;
+        assert_eq!(visits, 2);
+        let toolbar = match fetch_tree(&conn, &BookmarkRootGuid::Toolbar.into())? {
+            Some(BookmarkTreeNode::Folder(f)) => f,
+            _ => panic!("should have a toolbar"),
+        };
+        assert_eq!(toolbar.children.len(), 4);
+        match &toolbar.children[2] {
+            BookmarkTreeNode::Bookmark(b) => {
+                assert_eq!(b.url, bookmarked);
+                assert_ne!(b.guid, Some(SyncGuid::from("bookmarkgd01")));
+            }
+            node => panic!("expected a bookmark, got {:?}", node),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_unsupported() -> Result<()> {
+        let _ = env_logger::try_init();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("places.sqlite");
+        Connection::open(&path)?
+            .execute_batch("CREATE TABLE moz_places(id INTEGER PRIMARY KEY)")?;
+
+        let conn = new_mem_connection();
+        match import_desktop_places(&conn, &path)
+            .expect_err("should fail to import")
+            .kind()
+        {
+            ErrorKind::UnsupportedImportDatabase => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_read_only() -> Result<()> {
+        let _ = env_logger::try_init();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("places.sqlite");
+        Connection::open(&path)?.execute_batch(DESKTOP_SCHEMA_SQL)?;
+
+        let reader = new_mem_api().open_connection(ConnectionType::ReadOnly)?;
+        match import_desktop_places(&reader, &path)
+            .expect_err("should fail to import")
+            .kind()
+        {
+            ErrorKind::InvalidConnectionType => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+        Ok(())
+    }
+}
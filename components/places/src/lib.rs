@@ -5,6 +5,7 @@
 #![allow(unknown_lints)]
 
 pub mod api;
+pub mod backup;
 pub mod error;
 pub mod types;
 // Making these all pub for now while we flesh out the API.
@@ -14,9 +15,12 @@ pub mod ffi;
 pub mod frecency;
 pub mod hash;
 pub mod history_sync;
+pub mod import;
+pub mod maintenance;
 // match_impl is pub mostly for benchmarks (which have to run as a separate pseudo-crate).
 pub mod match_impl;
 pub mod observation;
+pub mod observers;
 pub mod storage;
 #[cfg(test)]
 mod tests;
@@ -32,7 +36,7 @@ pub use crate::api::apply_observation;
 pub use crate::api::places_api::test;
 pub use crate::api::places_api::{ConnectionType, PlacesApi};
 
-pub use crate::db::{PlacesDb, PlacesInterruptHandle};
+pub use crate::db::{CancellationToken, PlacesDb, PlacesInterruptHandle};
 pub use crate::error::*;
 pub use crate::observation::VisitObservation;
 pub use crate::storage::PageInfo;
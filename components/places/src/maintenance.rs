@@ -0,0 +1,300 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checks for, and repairs, corruption in the bookmarks tree.
+//!
+//! The schema and our APIs should prevent these problems, but we've seen
+//! trees with gaps or duplicates in child positions, items whose parent
+//! doesn't exist (which can happen if foreign keys were ever turned off), and
+//! items whose parent is a bookmark or separator instead of a folder. Like
+//! desktop's `PlacesDBUtils`, we move misparented items to the unfiled root,
+//! and renumber the children of folders with invalid positions.
+//!
+//! Repaired items have their change counters bumped, so the fixed tree is
+//! uploaded on the next sync. Observers aren't notified about repairs.
+
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::storage::bookmarks::BookmarkRootGuid;
+use crate::storage::RowId;
+use crate::types::BookmarkType;
+use rusqlite::Result as RusqliteResult;
+use serde_derive::*;
+use sql_support::ConnExt;
+
+/// Describes what `check_and_repair_bookmarks` fixed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BookmarkRepairs {
+    /// The number of items whose parent didn't exist, which were moved to
+    /// the unfiled root.
+    pub orphans_moved: u32,
+    /// The number of items whose parent wasn't a folder, which were moved to
+    /// the unfiled root.
+    pub invalid_parents_fixed: u32,
+    /// The number of folders whose children had gaps or duplicates in their
+    /// positions, and were renumbered.
+    pub folders_renumbered: u32,
+}
+
+impl BookmarkRepairs {
+    /// Returns true if nothing needed fixing.
+    pub fn is_empty(&self) -> bool {
+        *self == BookmarkRepairs::default()
+    }
+}
+
+/// Checks the bookmarks tree for corruption, fixes anything it finds, and
+/// returns what it fixed.
+pub fn check_and_repair_bookmarks(db: &PlacesDb) -> Result<BookmarkRepairs> {
+    let tx = db.coop_transaction()?;
+    let mut repairs = BookmarkRepairs::default();
+
+    let unfiled_id = db
+        .try_query_row(
+            "SELECT id FROM moz_bookmarks WHERE guid = :guid",
+            &[(":guid", BookmarkRootGuid::Unfiled.guid())],
+            |row| row.get_checked::<_, RowId>(0),
+            true,
+        )?
+        .ok_or_else(|| Corruption::InvalidLocalRoots)?;
+
+    let orphans = db.query_rows_into::<Vec<RowId>, _, _, _>(
+        "SELECT b.id FROM moz_bookmarks b
+         LEFT JOIN moz_bookmarks p ON p.id = b.parent
+         WHERE p.id IS NULL AND
+               b.guid <> :root_guid
+         ORDER BY b.id",
+        &[(":root_guid", BookmarkRootGuid::Root.guid())],
+        |row| row.get_checked(0),
+    )?;
+    for id in &orphans {
+        move_to_unfiled(db, *id, unfiled_id)?;
+    }
+    repairs.orphans_moved = orphans.len() as u32;
+
+    let invalid_parents = db.query_rows_into::<Vec<RowId>, _, _, _>(
+        "SELECT b.id FROM moz_bookmarks b
+         JOIN moz_bookmarks p ON p.id = b.parent
+         WHERE p.type <> :folder_type
+         ORDER BY p.id, b.position",
+        &[(":folder_type", &(BookmarkType::Folder as u8))],
+        |row| row.get_checked(0),
+    )?;
+    for id in &invalid_parents {
+        move_to_unfiled(db, *id, unfiled_id)?;
+    }
+    repairs.invalid_parents_fixed = invalid_parents.len() as u32;
+
+    // A folder's children are valid if their positions are exactly
+    // 0..count, with no gaps or duplicates.
+    let bad_folders = db.query_rows_into::<Vec<RowId>, _, _, _>(
+        "SELECT parent FROM moz_bookmarks
+         WHERE parent NOT NULL
+         GROUP BY parent
+         HAVING MIN(position) <> 0 OR
+                MAX(position) <> COUNT(*) - 1 OR
+                COUNT(DISTINCT position) <> COUNT(*)",
+        &[],
+        |row| row.get_checked(0),
+    )?;
+    for folder_id in &bad_folders {
+        let children = db.query_rows_into_cached::<Vec<(RowId, u32)>, _, _, _>(
+            "SELECT id, position FROM moz_bookmarks
+             WHERE parent = :parent
+             ORDER BY position, id",
+            &[(":parent", folder_id)],
+            |row| -> RusqliteResult<_> { Ok((row.get_checked(0)?, row.get_checked(1)?)) },
+        )?;
+        for (position, (child_id, old_position)) in children.into_iter().enumerate() {
+            if position as u32 != old_position {
+                db.execute_named_cached(
+                    "UPDATE moz_bookmarks SET
+                         position = :position,
+                         syncChangeCounter = syncChangeCounter + 1
+                     WHERE id = :id",
+                    &[(":position", &(position as u32)), (":id", &child_id)],
+                )?;
+            }
+        }
+        db.execute_named_cached(
+            "UPDATE moz_bookmarks SET syncChangeCounter = syncChangeCounter + 1
+             WHERE id = :id",
+            &[(":id", folder_id)],
+        )?;
+    }
+    repairs.folders_renumbered = bad_folders.len() as u32;
+
+    tx.commit()?;
+    Ok(repairs)
+}
+
+// Appends an item to the unfiled root. This leaves a gap in the positions of
+// the old parent's children, if it exists, which the caller must fix.
+fn move_to_unfiled(db: &PlacesDb, id: RowId, unfiled_id: RowId) -> Result<()> {
+    db.execute_named_cached(
+        "UPDATE moz_bookmarks SET
+             parent = :unfiled_id,
+             position = (SELECT IFNULL(MAX(position) + 1, 0) FROM moz_bookmarks
+                         WHERE parent = :unfiled_id),
+             syncChangeCounter = syncChangeCounter + 1
+         WHERE id = :id",
+        &[(":unfiled_id", &unfiled_id), (":id", &id)],
+    )?;
+    db.execute_named_cached(
+        "UPDATE moz_bookmarks SET syncChangeCounter = syncChangeCounter + 1
+         WHERE id = :unfiled_id",
+        &[(":unfiled_id", &unfiled_id)],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::bookmarks::get_raw_bookmark;
+    use crate::storage::run_maintenance;
+    use crate::tests::{check_positions, insert_json_tree};
+    use crate::types::SyncGuid;
+    use serde_json::json;
+
+    #[test]
+    fn test_check_and_repair_bookmarks() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = new_mem_connection();
+        assert!(check_and_repair_bookmarks(&conn)?.is_empty());
+
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": BookmarkRootGuid::Menu.as_guid(),
+                "children": [
+                    {
+                        "guid": "bookmark1___",
+                        "url": "https://www.example.com/1",
+                    },
+                    {
+                        "guid": "bookmark2___",
+                        "url": "https://www.example.com/2",
+                    },
+                    {
+                        "guid": "folder1_____",
+                        "children": [
+                            {
+                                "guid": "bookmark3___",
+                                "url": "https://www.example.com/3",
+                            },
+                        ],
+                    },
+                    {
+                        "guid": "bookmark4___",
+                        "url": "https://www.example.com/4",
+                    },
+                ],
+            }),
+        );
+
+        // Make a gap in the menu's positions, parent an item to a bookmark,
+        // and orphan another.
+        conn.execute_batch(
+            "UPDATE moz_bookmarks SET position = 5 WHERE guid = 'bookmark4___';
+
+             UPDATE moz_bookmarks SET
+                 parent = (SELECT id FROM moz_bookmarks WHERE guid = 'bookmark1___'),
+                 position = 0
+             WHERE guid = 'bookmark2___';
+
+             PRAGMA foreign_keys = OFF;
+             UPDATE moz_bookmarks SET parent = 9999 WHERE guid = 'bookmark3___';
+             PRAGMA foreign_keys = ON;
+
+             UPDATE moz_bookmarks SET syncChangeCounter = 0;",
+        )?;
+
+        let repairs = check_and_repair_bookmarks(&conn)?;
+        assert_eq!(
+            repairs,
+            BookmarkRepairs {
+                orphans_moved: 1,
+                invalid_parents_fixed: 1,
+                folders_renumbered: 1,
+            }
+        );
+        check_positions(&conn);
+
+        let get_location = |guid: &str| -> (SyncGuid, u32) {
+            let raw = get_raw_bookmark(&conn, &guid.into())
+                .expect("should work")
+                .expect("should exist");
+            (raw.parent_guid.expect("should have a parent"), raw.position)
+        };
+        let menu = BookmarkRootGuid::Menu.as_guid();
+        let unfiled = BookmarkRootGuid::Unfiled.as_guid();
+        assert_eq!(get_location("bookmark1___"), (menu.clone(), 0));
+        assert_eq!(get_location("folder1_____"), (menu.clone(), 1));
+        assert_eq!(get_location("bookmark4___"), (menu, 2));
+        assert_eq!(get_location("bookmark3___"), (unfiled.clone(), 0));
+        assert_eq!(get_location("bookmark2___"), (unfiled, 1));
+
+        // The repaired items and their parents should be uploaded on the next
+        // sync.
+        let changed = conn.query_rows_and_then_named(
+            "SELECT guid FROM moz_bookmarks
+             WHERE syncChangeCounter > 0
+             ORDER BY guid",
+            &[],
+            |row| row.get_checked::<_, String>(0),
+        )?;
+        assert_eq!(
+            changed,
+            vec![
+                "bookmark2___",
+                "bookmark3___",
+                "bookmark4___",
+                "folder1_____",
+                "menu________",
+                "unfiled_____",
+            ]
+        );
+
+        // And everything should be fine now.
+        assert!(check_and_repair_bookmarks(&conn)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_maintenance_returns_repairs() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = new_mem_connection();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": BookmarkRootGuid::Menu.as_guid(),
+                "children": [
+                    {
+                        "guid": "bookmark1___",
+                        "url": "https://www.example.com/1",
+                    },
+                ],
+            }),
+        );
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             UPDATE moz_bookmarks SET parent = 9999 WHERE guid = 'bookmark1___';
+             PRAGMA foreign_keys = ON;",
+        )?;
+
+        let repairs = run_maintenance(&conn)?;
+        assert_eq!(
+            serde_json::to_value(&repairs)?,
+            json!({
+                "orphans_moved": 1,
+                "invalid_parents_fixed": 0,
+                "folders_renumbered": 0,
+            })
+        );
+        assert!(run_maintenance(&conn)?.is_empty());
+        Ok(())
+    }
+}
@@ -10,6 +10,7 @@ use rusqlite::{
     types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef},
 };
 use std::borrow::Cow;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 use url::percent_encoding;
 
 const MAX_CHARS_TO_SEARCH_THROUGH: usize = 255;
@@ -198,26 +199,96 @@ fn is_ascii_lower_alpha(c: u8) -> bool {
     c.wrapping_sub(b'a') <= (b'z' - b'a')
 }
 
+/// Returns true for characters in scripts which aren't written with spaces
+/// between words (Chinese, Japanese, Thai, and friends). We can't find word
+/// boundaries in these without a dictionary, so treat every character as
+/// its own word.
+#[inline]
+fn is_unspaced_script(c: char) -> bool {
+    match c as u32 {
+        0x0E00..=0x0EFF // Thai, Lao
+        | 0x1000..=0x109F // Myanmar
+        | 0x1780..=0x17FF // Khmer
+        | 0x3040..=0x30FF // Hiragana, Katakana
+        | 0x3400..=0x4DBF // CJK Unified Ideographs Extension A
+        | 0x4E00..=0x9FFF // CJK Unified Ideographs
+        | 0xF900..=0xFAFF // CJK Compatibility Ideographs
+        | 0x20000..=0x2FA1F => true, // CJK Unified Ideographs Extension B onwards
+        _ => false,
+    }
+}
+
+/// Whether `c` can continue a word started by `prev`. This is the unicode
+/// version of the check in `is_on_boundary`: a lower case (or caseless)
+/// letter following any letter.
+#[inline]
+fn continues_word(prev: char, c: char) -> bool {
+    c.is_alphabetic()
+        && !c.is_uppercase()
+        && prev.is_alphabetic()
+        && !is_unspaced_script(c)
+        && !is_unspaced_script(prev)
+}
+
 /// port of isOnBoundary from gecko places.
 ///
 /// > Check whether a character position is on a word boundary of a UTF-8 string
 /// > (rather than within a word).  We define "within word" to be any position
 /// > between [a-zA-Z] and [a-z] -- this lets us match CamelCase words.
-/// > TODO: support non-latin alphabets.
+///
+/// Unlike places, we also handle non-latin alphabets, using the same rule
+/// for unicode letters (see `continues_word`).
 #[inline(always)]
 fn is_on_boundary(text: &str, index: usize) -> bool {
     if index == 0 {
         return true;
     }
     let bytes = text.as_bytes();
-    if is_ascii_lower_alpha(bytes[index]) {
-        let prev_lower = dubious_to_ascii_lower(bytes[index - 1]);
-        !is_ascii_lower_alpha(prev_lower)
-    } else {
-        true
+    let (cur, prev) = (bytes[index], bytes[index - 1]);
+    if cur.is_ascii() && prev.is_ascii() {
+        return if is_ascii_lower_alpha(cur) {
+            let prev_lower = dubious_to_ascii_lower(prev);
+            !is_ascii_lower_alpha(prev_lower)
+        } else {
+            true
+        };
+    }
+    match (
+        text[..index].chars().next_back(),
+        text[index..].chars().next(),
+    ) {
+        (Some(p), Some(c)) => !continues_word(p, c),
+        _ => true,
     }
 }
 
+/// Folds `s` into the form we match against: diacritics (combining marks,
+/// after decomposing) are removed, and lower case letters are fully case
+/// folded, so that "É" matches "e", "ß" matches "ss" and "ς" matches "σ".
+/// The dotless "ı" is treated as "i" too, so Turkish text matches however
+/// "I" was typed.
+///
+/// Upper case letters are kept as they are, since `is_on_boundary` needs
+/// them to find the start of CamelCase words, and the search functions
+/// ignore case anyway. ASCII text is already in this form, so it's returned
+/// unchanged without allocating.
+pub fn fold_for_matching(s: &str) -> Cow<'_, str> {
+    if s.is_ascii() {
+        return Cow::Borrowed(s);
+    }
+    let mut folded = String::with_capacity(s.len());
+    for c in s.nfd().filter(|&c| !is_combining_mark(c)) {
+        if c == '\u{131}' {
+            folded.push('i');
+        } else if c.is_uppercase() {
+            folded.push(c);
+        } else {
+            folded.extend(std::iter::once(c).default_case_fold());
+        }
+    }
+    Cow::Owned(folded)
+}
+
 /// Returns true if `source` starts with `token` ignoring case.
 ///
 /// Loose port of stringMatch from places, which we've modified to perform more correct case
@@ -306,6 +377,168 @@ pub fn find_in_string(token: &str, src: &str, only_boundary: bool) -> bool {
     false
 }
 
+/// A single token from a search string. See `search_tokens`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchToken<'a> {
+    /// The text to search for, without any surrounding quotes.
+    pub text: &'a str,
+    /// True if this token was a double-quoted phrase.
+    pub quoted: bool,
+}
+
+/// Splits a search string into tokens, which are each matched separately.
+/// Tokens are separated by whitespace, except that text in double quotes
+/// is a single token (so `"rust docs"` only matches the phrase, where
+/// `rust docs` matches both words in any order). An unterminated quote runs
+/// to the end of the string.
+#[inline]
+pub fn search_tokens(search_str: &str) -> SearchTokens<'_> {
+    SearchTokens {
+        remaining: search_str,
+    }
+}
+
+pub struct SearchTokens<'a> {
+    remaining: &'a str,
+}
+
+#[inline(always)]
+fn is_token_separator(c: char) -> bool {
+    c.is_ascii_whitespace()
+}
+
+impl<'a> Iterator for SearchTokens<'a> {
+    type Item = SearchToken<'a>;
+
+    fn next(&mut self) -> Option<SearchToken<'a>> {
+        loop {
+            let s = self.remaining.trim_start_matches(is_token_separator);
+            if s.is_empty() {
+                self.remaining = s;
+                return None;
+            }
+            if s.starts_with('"') {
+                let phrase = &s[1..];
+                let end = phrase.find('"').unwrap_or_else(|| phrase.len());
+                self.remaining = if end < phrase.len() {
+                    &phrase[end + 1..]
+                } else {
+                    ""
+                };
+                let text = phrase[..end].trim_matches(is_token_separator);
+                // Skip empty phrases, they'd match anything.
+                if text.is_empty() {
+                    continue;
+                }
+                return Some(SearchToken { text, quoted: true });
+            }
+            let end = s.find(is_token_separator).unwrap_or_else(|| s.len());
+            self.remaining = &s[end..];
+            return Some(SearchToken {
+                text: &s[..end],
+                quoted: false,
+            });
+        }
+    }
+}
+
+/// Converts a search string into a query for the `moz_places_fts` full-text
+/// index, which matches pages with a word starting with each token, or,
+/// for tokens that the index splits into several words, like quoted phrases
+/// or "example.com", with those words in order.
+///
+/// The index folds case and diacritics the same way `AUTOCOMPLETE_MATCH` does,
+/// but stores punycoded hosts as they are, so tokens with non-ASCII
+/// characters also look up their punycoded form. This way, "bücher" finds
+/// "xn--bcher-kva.de", like it does without the index.
+///
+/// Tokens without any letters or numbers can't be looked up in the index, so
+/// they're left out. Returns `None` if that leaves nothing to look up.
+pub fn fts_query(search_str: &str) -> Option<String> {
+    let phrases = search_tokens(search_str)
+        .filter(|token| token.text.chars().any(char::is_alphanumeric))
+        .map(|token| {
+            let phrase = fts_phrase(token.text);
+            if token.text.is_ascii() {
+                return phrase;
+            }
+            match idna::domain_to_ascii(token.text) {
+                Ok(ref ascii) if ascii.contains("xn--") => {
+                    format!("({} OR {})", phrase, fts_phrase(ascii))
+                }
+                _ => phrase,
+            }
+        })
+        .collect::<Vec<_>>();
+    if phrases.is_empty() {
+        None
+    } else {
+        Some(phrases.join(" "))
+    }
+}
+
+fn fts_phrase(text: &str) -> String {
+    format!("\"{}\"*", text.replace('"', "\"\""))
+}
+
+/// Selects the ids of places that might match the `fts_query` bound to
+/// `:ftsQuery`. We only index page titles, so bookmarks with their own titles
+/// and tagged pages are always included, and left for `AUTOCOMPLETE_MATCH`
+/// to check.
+pub const FTS_CANDIDATES_SQL: &str = "
+    SELECT rowid FROM moz_places_fts
+    WHERE moz_places_fts MATCH :ftsQuery
+    UNION
+    SELECT b.fk FROM moz_bookmarks b
+    JOIN moz_places p ON p.id = b.fk
+    WHERE b.title NOT NULL AND b.title <> IFNULL(p.title, '')
+    UNION
+    SELECT place_id FROM moz_tags_relation";
+
+fn percent_decode_str(s: &str) -> Cow<'_, str> {
+    // Bail out early if we don't need to percent decode. It's a
+    // little weird that it's measurably faster to check this
+    // separately, but whatever.
+    if memchr::memchr(b'%', s.as_bytes()).is_none() {
+        return Cow::Borrowed(s);
+    }
+    match percent_encoding::percent_decode(s.as_bytes()).decode_utf8() {
+        Err(_) => Cow::Borrowed(s),
+        Ok(decoded) => decoded,
+    }
+}
+
+/// Decodes the punycoded (IDN) labels in the host at the start of `href`,
+/// which shouldn't include the scheme. For example, `xn--bcher-kva.de/path`
+/// becomes `bücher.de/path`. Returns `href` unchanged if the host isn't
+/// punycoded, or isn't valid.
+pub fn decode_punycode_host(href: &str) -> Cow<'_, str> {
+    let bytes = href.as_bytes();
+    let host_end = memchr::memchr3(b'/', b'?', b'#', bytes).unwrap_or_else(|| href.len());
+    let host_start = memchr::memrchr(b'@', &bytes[..host_end])
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let host_and_port = &href[host_start..host_end];
+    let host = match memchr::memchr(b':', host_and_port.as_bytes()) {
+        Some(port_start) => &host_and_port[..port_start],
+        None => host_and_port,
+    };
+    if !host.contains("xn--") {
+        return Cow::Borrowed(href);
+    }
+    match idna::domain_to_unicode(host) {
+        (decoded, Ok(())) => Cow::Owned(
+            [
+                &href[..host_start],
+                decoded.as_str(),
+                &href[host_start + host.len()..],
+            ]
+            .concat(),
+        ),
+        (_, Err(_)) => Cow::Borrowed(href),
+    }
+}
+
 // Search functions used as function pointers by AutocompleteMatch::Invoke
 
 fn find_anywhere(token: &str, source: &str) -> bool {
@@ -353,26 +586,38 @@ impl<'search, 'url, 'title, 'tags> AutocompleteMatch<'search, 'url, 'title, 'tag
     }
 
     fn fixup_url_str<'a>(&self, mut s: &'a str) -> Cow<'a, str> {
+        let mut stripped_scheme = false;
         if self.match_behavior != MatchBehavior::AnywhereUnmodified {
             if s.starts_with("http://") {
                 s = &s[7..];
+                stripped_scheme = true;
             } else if s.starts_with("https://") {
                 s = &s[8..];
+                stripped_scheme = true;
             } else if s.starts_with("ftp://") {
                 s = &s[6..];
+                stripped_scheme = true;
             }
         }
-        // Bail out early if we don't need to percent decode. It's a
-        // little weird that it's measurably faster to check this
-        // separately, but whatever.
-        if memchr::memchr(b'%', s.as_bytes()).is_none() {
-            return Cow::Borrowed(s);
+        // Match against the unicode form of IDN hosts, since that's what
+        // people type. We only know where the host is if we stripped the
+        // scheme.
+        if stripped_scheme {
+            if let Cow::Owned(decoded) = decode_punycode_host(s) {
+                return Cow::Owned(percent_decode_str(&decoded).into_owned());
+            }
         }
-        // TODO: would be nice to decode punycode here too, but for now
-        // this is probably fine.
-        match percent_encoding::percent_decode(s.as_bytes()).decode_utf8() {
-            Err(_) => Cow::Borrowed(s),
-            Ok(decoded) => decoded,
+        percent_decode_str(s)
+    }
+
+    /// Diacritic and case folding (see `fold_for_matching`), for the
+    /// behaviors that want it.
+    fn fold<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        match self.match_behavior {
+            MatchBehavior::AnywhereUnmodified | MatchBehavior::BeginningCaseSensitive => {
+                Cow::Borrowed(s)
+            }
+            _ => fold_for_matching(s),
         }
     }
 
@@ -410,23 +655,40 @@ impl<'search, 'url, 'title, 'tags> AutocompleteMatch<'search, 'url, 'title, 'tag
         let fixed_url = self.fixup_url_str(self.url_str);
         let search_fn = self.get_search_fn();
 
-        let trimmed_url = util::slice_up_to(fixed_url.as_ref(), MAX_CHARS_TO_SEARCH_THROUGH);
-        let trimmed_title = util::slice_up_to(self.title_str, MAX_CHARS_TO_SEARCH_THROUGH);
-        for token in self.search_str.split_ascii_whitespace() {
+        let trimmed_url = self.fold(util::slice_up_to(
+            fixed_url.as_ref(),
+            MAX_CHARS_TO_SEARCH_THROUGH,
+        ));
+        let trimmed_url = trimmed_url.as_ref();
+        let trimmed_title = self.fold(util::slice_up_to(
+            self.title_str,
+            MAX_CHARS_TO_SEARCH_THROUGH,
+        ));
+        let trimmed_title = trimmed_title.as_ref();
+        let tags = self.fold(self.tags);
+        let tags = tags.as_ref();
+        for token in search_tokens(self.search_str) {
+            let token = self.fold(token.text);
+            let token = token.as_ref();
+            // Folding can remove everything (eg, a lone combining mark), in
+            // which case there's nothing left to look for.
+            if token.is_empty() {
+                continue;
+            }
             let matches = match (
                 self.has_behavior(SearchBehavior::TITLE),
                 self.has_behavior(SearchBehavior::URL),
             ) {
                 (true, true) => {
-                    (search_fn(token, trimmed_title) || search_fn(token, self.tags))
+                    (search_fn(token, trimmed_title) || search_fn(token, tags))
                         && search_fn(token, trimmed_url)
                 }
-                (true, false) => search_fn(token, trimmed_title) || search_fn(token, self.tags),
+                (true, false) => search_fn(token, trimmed_title) || search_fn(token, tags),
                 (false, true) => search_fn(token, trimmed_url),
                 (false, false) => {
                     search_fn(token, trimmed_url)
                         || search_fn(token, trimmed_title)
-                        || search_fn(token, self.tags)
+                        || search_fn(token, tags)
                 }
             };
             if !matches {
@@ -454,6 +716,200 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_search_tokens() {
+        fn tokens(s: &str) -> Vec<(&str, bool)> {
+            search_tokens(s).map(|t| (t.text, t.quoted)).collect()
+        }
+        assert!(tokens("").is_empty());
+        assert!(tokens("   ").is_empty());
+        assert_eq!(tokens("rust"), vec![("rust", false)]);
+        assert_eq!(
+            tokens("  rust \t docs "),
+            vec![("rust", false), ("docs", false)]
+        );
+        assert_eq!(
+            tokens("\"rust docs\" book"),
+            vec![("rust docs", true), ("book", false)]
+        );
+        assert_eq!(
+            tokens("book \" rust  docs \""),
+            vec![("book", false), ("rust  docs", true)]
+        );
+        // Unterminated quotes run to the end, and empty ones are ignored.
+        assert_eq!(
+            tokens("\"\" a \"rust docs"),
+            vec![("a", false), ("rust docs", true)]
+        );
+        // Quotes within a word aren't special.
+        assert_eq!(tokens("it\"s"), vec![("it\"s", false)]);
+    }
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(fts_query(""), None);
+        assert_eq!(fts_query("  - | "), None);
+        assert_eq!(fts_query("rust"), Some("\"rust\"*".to_string()));
+        assert_eq!(
+            fts_query("\"rust docs\" - example.com"),
+            Some("\"rust docs\"* \"example.com\"*".to_string())
+        );
+        assert_eq!(fts_query("it\"s"), Some("\"it\"\"s\"*".to_string()));
+        assert_eq!(
+            fts_query("bücher.de rust"),
+            Some("(\"bücher.de\"* OR \"xn--bcher-kva.de\"*) \"rust\"*".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invoke_tokens() {
+        let matches = |search_str: &str, title_str: &str| {
+            AutocompleteMatch {
+                search_str,
+                url_str: "http://example.com/",
+                title_str,
+                tags: "",
+                visit_count: 1,
+                typed: false,
+                bookmarked: false,
+                open_page_count: 0,
+                match_behavior: MatchBehavior::Anywhere,
+                search_behavior: SearchBehavior::default(),
+            }
+            .invoke()
+        };
+        assert!(matches("rust docs", "The Rust docs"));
+        assert!(matches("rust docs", "docs for Rust"));
+        assert!(!matches("rust docs", "The Rust book"));
+        assert!(matches("\"rust docs\"", "The Rust docs"));
+        assert!(!matches("\"rust docs\"", "docs for Rust"));
+    }
+
+    #[test]
+    fn test_fold_for_matching() {
+        assert_eq!(fold_for_matching("Example"), "Example");
+        assert_eq!(fold_for_matching("Straße"), "Strasse");
+        assert_eq!(fold_for_matching("Crème Brûlée"), "Creme Brulee");
+        assert_eq!(fold_for_matching("ΟΔΥΣΣΕΎΣ"), "ΟΔΥΣΣΕΥΣ");
+        assert_eq!(fold_for_matching("Οδυσσεύς"), "Οδυσσευσ");
+        assert_eq!(fold_for_matching("İstanbul"), "Istanbul");
+        assert_eq!(fold_for_matching("IŞIK ılık"), "ISIK ilik");
+        assert_eq!(fold_for_matching("GitHub – Home"), "GitHub – Home");
+    }
+
+    #[test]
+    fn test_is_on_boundary() {
+        fn boundary_at(text: &str, needle: &str) -> bool {
+            is_on_boundary(text, text.find(needle).unwrap())
+        }
+        // The original ASCII behavior.
+        assert!(boundary_at("foo bar", "bar"));
+        assert!(!boundary_at("foobar", "bar"));
+        assert!(boundary_at("fooBar", "Bar"));
+        assert!(boundary_at("foo123", "123"));
+        // Other alphabets work the same way.
+        assert!(boundary_at("привет мир", "мир"));
+        assert!(!boundary_at("приветмир", "мир"));
+        assert!(boundary_at("αλφαΒήτα", "Βήτα"));
+        assert!(!boundary_at("αλφαβήτα", "βήτα"));
+        assert!(boundary_at("café-bar", "bar"));
+        assert!(!boundary_at("caféine", "ine"));
+        // Every character is a word in scripts without spaces.
+        assert!(boundary_at("你好世界", "世界"));
+        assert!(boundary_at("ありがとう", "がとう"));
+    }
+
+    #[test]
+    fn test_decode_punycode_host() {
+        assert_eq!(decode_punycode_host("example.com/"), "example.com/");
+        assert_eq!(
+            decode_punycode_host("xn--bcher-kva.de/xn--bcher-kva"),
+            "bücher.de/xn--bcher-kva"
+        );
+        assert_eq!(
+            decode_punycode_host("user@www.xn--bcher-kva.de:8080/?q=1"),
+            "user@www.bücher.de:8080/?q=1"
+        );
+        assert_eq!(decode_punycode_host("xn--exmple-cua.com"), "exämple.com");
+    }
+
+    #[test]
+    fn test_invoke_punycode() {
+        let matches = |search_str: &str, url_str: &str, match_behavior: MatchBehavior| {
+            AutocompleteMatch {
+                search_str,
+                url_str,
+                title_str: "",
+                tags: "",
+                visit_count: 1,
+                typed: false,
+                bookmarked: false,
+                open_page_count: 0,
+                match_behavior,
+                search_behavior: SearchBehavior::default(),
+            }
+            .invoke()
+        };
+        let url = "https://xn--bcher-kva.de/%C3%BCber";
+        assert!(matches("bücher", url, MatchBehavior::Anywhere));
+        assert!(matches("bücher.de/über", url, MatchBehavior::Beginning));
+        assert!(!matches("bücher", url, MatchBehavior::AnywhereUnmodified));
+    }
+
+    #[test]
+    fn test_invoke_unicode() {
+        let matches = |search_str: &str, title_str: &str, match_behavior: MatchBehavior| {
+            AutocompleteMatch {
+                search_str,
+                url_str: "http://example.com/",
+                title_str,
+                tags: "",
+                visit_count: 1,
+                typed: false,
+                bookmarked: false,
+                open_page_count: 0,
+                match_behavior,
+                search_behavior: SearchBehavior::default(),
+            }
+            .invoke()
+        };
+        use crate::match_impl::MatchBehavior::*;
+        assert!(matches("strasse", "Hauptstraße 1", Anywhere));
+        assert!(matches("STRASSE", "Hauptstraße 1", Anywhere));
+        assert!(matches("straße", "Hauptstrasse 1", Anywhere));
+        assert!(matches("istanbul", "İstanbul'da hava", Boundary));
+        assert!(matches("ılık", "ILIK bir gün", Boundary));
+        assert!(matches("ΟΔΥΣΣΕΥΣ", "Ο Οδυσσεύς", Boundary));
+        assert!(!matches("οδυσσευς", "Η Οδύσσεια του Οδυσσέα", Anywhere));
+        assert!(matches("οδυσσεια", "Η Οδύσσεια του Οδυσσέα", Boundary));
+        assert!(matches("creme", "Crème brûlée recipes", Boundary));
+        assert!(matches("brulee creme", "Crème brûlée recipes", Boundary));
+        assert!(!matches("rulee", "Crème brûlée recipes", Boundary));
+        assert!(matches("rulee", "Crème brûlée recipes", Anywhere));
+        assert!(matches("мир", "Привет, Мир", Boundary));
+        assert!(!matches("ир", "Привет, Мир", Boundary));
+        assert!(matches("世界", "你好世界", Boundary));
+        // CamelCase words start on a boundary, even if the text has other
+        // non-ASCII characters.
+        assert!(matches("hub", "GitHub - Home", Boundary));
+        assert!(matches("hub", "GitHub – Home", Boundary));
+        assert!(matches("hub", "GitHub – Home", BoundaryAnywhere));
+        assert!(!matches("ithub", "GitHub – Home", Boundary));
+        assert!(matches("normale", "ÉcoleNormale", Boundary));
+        assert!(matches("strasse", "HauptStraße", Boundary));
+        // Case sensitive matching is left alone.
+        assert!(!matches(
+            "creme",
+            "Crème brûlée recipes",
+            BeginningCaseSensitive
+        ));
+        assert!(matches(
+            "Crème",
+            "Crème brûlée recipes",
+            BeginningCaseSensitive
+        ));
+    }
+
     // Test the various dubious things this code assumes about unicode / ascii text
     // in the name of performance. This is mostly a port of the test_casing gtests in places
     #[test]
@@ -0,0 +1,283 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Observers are notified about changes to bookmarks and history, so that
+//! apps don't need to re-fetch everything to find out what changed. They're
+//! registered on the `PlacesApi`, and notified about changes made on both
+//! the read-write connection and the sync connection, after the transaction
+//! that made the changes commits.
+//!
+//! Note that observers are called synchronously, on the thread that made the
+//! change. They must not block, and must not use the connection that made
+//! the change.
+
+use crate::types::{BookmarkType, SyncGuid, Timestamp, VisitTransition};
+use serde_derive::*;
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use url::Url;
+
+/// Where a change came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeSource {
+    /// The change was made by the app, on the read-write connection.
+    Local,
+    /// The change was made by applying incoming records during a sync.
+    Sync,
+}
+
+/// A change to a bookmark, folder or separator.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BookmarkEvent {
+    ItemAdded {
+        guid: SyncGuid,
+        parent_guid: SyncGuid,
+        position: u32,
+        #[serde(rename = "type")]
+        item_type: BookmarkType,
+        #[serde(with = "url_serde")]
+        url: Option<Url>,
+        title: Option<String>,
+    },
+    ItemMoved {
+        guid: SyncGuid,
+        old_parent_guid: SyncGuid,
+        old_position: u32,
+        new_parent_guid: SyncGuid,
+        new_position: u32,
+    },
+    /// The title or URL of an item changed. Both hold the new values.
+    ItemChanged {
+        guid: SyncGuid,
+        #[serde(rename = "type")]
+        item_type: BookmarkType,
+        #[serde(with = "url_serde")]
+        url: Option<Url>,
+        title: Option<String>,
+    },
+    /// An item was removed. Removing a folder also removes its descendants,
+    /// which don't get their own events.
+    ItemRemoved {
+        guid: SyncGuid,
+        parent_guid: SyncGuid,
+        position: u32,
+        #[serde(rename = "type")]
+        item_type: BookmarkType,
+        #[serde(with = "url_serde")]
+        url: Option<Url>,
+    },
+}
+
+impl BookmarkEvent {
+    /// The GUID of the changed item.
+    pub fn guid(&self) -> &SyncGuid {
+        match self {
+            BookmarkEvent::ItemAdded { guid, .. }
+            | BookmarkEvent::ItemMoved { guid, .. }
+            | BookmarkEvent::ItemChanged { guid, .. }
+            | BookmarkEvent::ItemRemoved { guid, .. } => guid,
+        }
+    }
+}
+
+/// Observes changes to bookmarks. Tags and keywords aren't part of the tree,
+/// so changing them isn't reported. Bulk changes that the app starts itself,
+/// like `import_desktop_places`, `check_and_repair_bookmarks`, and recovering
+/// a corrupt database, aren't reported either; observers should re-fetch the
+/// tree after them.
+pub trait BookmarkObserver: Send + Sync {
+    /// Called with all events for a single transaction, in the order the
+    /// changes were made.
+    fn on_bookmark_events(&self, source: ChangeSource, events: &[BookmarkEvent]);
+}
+
+impl<F> BookmarkObserver for F
+where
+    F: Fn(ChangeSource, &[BookmarkEvent]) + Send + Sync,
+{
+    fn on_bookmark_events(&self, source: ChangeSource, events: &[BookmarkEvent]) {
+        self(source, events)
+    }
+}
+
+/// A change to a page in history.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HistoryEvent {
+    /// A visit was added to a page.
+    Visited {
+        guid: SyncGuid,
+        #[serde(with = "url_serde")]
+        url: Url,
+        visit_date: Timestamp,
+        visit_type: VisitTransition,
+        is_local: bool,
+    },
+    TitleChanged {
+        guid: SyncGuid,
+        #[serde(with = "url_serde")]
+        url: Url,
+        title: String,
+    },
+    FrecencyChanged {
+        guid: SyncGuid,
+        #[serde(with = "url_serde")]
+        url: Url,
+        frecency: i32,
+    },
+    /// A page was removed from history, either because it was deleted, or
+    /// because all its visits were deleted and it isn't bookmarked.
+    Deleted {
+        guid: SyncGuid,
+        #[serde(with = "url_serde")]
+        url: Url,
+    },
+}
+
+impl HistoryEvent {
+    /// The GUID of the changed page.
+    pub fn guid(&self) -> &SyncGuid {
+        match self {
+            HistoryEvent::Visited { guid, .. }
+            | HistoryEvent::TitleChanged { guid, .. }
+            | HistoryEvent::FrecencyChanged { guid, .. }
+            | HistoryEvent::Deleted { guid, .. } => guid,
+        }
+    }
+}
+
+/// Observes changes to history. Bulk removals that the app starts itself,
+/// like `wipe_local`, `delete_everything` and `expire_history`, aren't
+/// reported.
+pub trait HistoryObserver: Send + Sync {
+    /// Called with all events for a single transaction, in the order the
+    /// changes were made.
+    fn on_history_events(&self, source: ChangeSource, events: &[HistoryEvent]);
+}
+
+impl<F> HistoryObserver for F
+where
+    F: Fn(ChangeSource, &[HistoryEvent]) + Send + Sync,
+{
+    fn on_history_events(&self, source: ChangeSource, events: &[HistoryEvent]) {
+        self(source, events)
+    }
+}
+
+/// Identifies a registered observer, so that it can be unregistered later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(pub usize);
+
+struct ObserverList<O: ?Sized>(Mutex<Vec<(ObserverId, Arc<O>)>>);
+
+impl<O: ?Sized> ObserverList<O> {
+    fn add(&self, id: ObserverId, observer: Arc<O>) {
+        self.0.lock().unwrap().push((id, observer));
+    }
+
+    fn remove(&self, id: ObserverId) -> bool {
+        let mut observers = self.0.lock().unwrap();
+        let len = observers.len();
+        observers.retain(|(observer_id, _)| *observer_id != id);
+        observers.len() != len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    // Returns a copy of the list, so that observers can register or
+    // unregister other observers without deadlocking.
+    fn snapshot(&self) -> Vec<Arc<O>> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, observer)| observer.clone())
+            .collect()
+    }
+}
+
+impl<O: ?Sized> Default for ObserverList<O> {
+    fn default() -> Self {
+        ObserverList(Mutex::new(Vec::new()))
+    }
+}
+
+/// The observers registered on a `PlacesApi`, which are shared with all of
+/// its write connections.
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: AtomicUsize,
+    bookmarks: ObserverList<dyn BookmarkObserver>,
+    history: ObserverList<dyn HistoryObserver>,
+}
+
+impl Observers {
+    fn next_id(&self) -> ObserverId {
+        ObserverId(self.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+
+    pub fn add_bookmark_observer(&self, observer: Arc<dyn BookmarkObserver>) -> ObserverId {
+        let id = self.next_id();
+        self.bookmarks.add(id, observer);
+        id
+    }
+
+    pub fn remove_bookmark_observer(&self, id: ObserverId) -> bool {
+        self.bookmarks.remove(id)
+    }
+
+    /// Whether any bookmark observers are registered. Callers can use this
+    /// to avoid the work of building events that no one will see.
+    pub fn has_bookmark_observers(&self) -> bool {
+        !self.bookmarks.is_empty()
+    }
+
+    pub fn notify_bookmark_observers(&self, source: ChangeSource, events: &[BookmarkEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        for observer in self.bookmarks.snapshot() {
+            observer.on_bookmark_events(source, events);
+        }
+    }
+
+    pub fn add_history_observer(&self, observer: Arc<dyn HistoryObserver>) -> ObserverId {
+        let id = self.next_id();
+        self.history.add(id, observer);
+        id
+    }
+
+    pub fn remove_history_observer(&self, id: ObserverId) -> bool {
+        self.history.remove(id)
+    }
+
+    pub fn notify_history_observers(&self, source: ChangeSource, events: &[HistoryEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        for observer in self.history.snapshot() {
+            observer.on_history_events(source, events);
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("bookmarks", &self.bookmarks.len())
+            .field("history", &self.history.len())
+            .finish()
+    }
+}
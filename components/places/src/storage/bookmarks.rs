@@ -6,6 +6,7 @@ use super::RowId;
 use super::{fetch_page_info, new_page_info};
 use crate::db::PlacesDb;
 use crate::error::*;
+use crate::observers::{BookmarkEvent, ChangeSource};
 use crate::types::{BookmarkType, SyncGuid, SyncStatus, Timestamp};
 use rusqlite::types::ToSql;
 use rusqlite::{Connection, Row};
@@ -25,8 +26,10 @@ pub use public_node::PublicNode;
 pub use root_guid::{BookmarkRootGuid, USER_CONTENT_ROOTS};
 
 mod conversions;
+pub mod html;
 pub mod public_node;
 mod root_guid;
+pub mod undo;
 
 fn create_root(
     db: &Connection,
@@ -240,10 +243,18 @@ pub fn insert_bookmark(db: &PlacesDb, bm: &InsertableItem) -> Result<SyncGuid> {
     let result = insert_bookmark_in_tx(db, bm);
     super::delete_pending_temp_tables(db)?;
     match result {
-        Ok(_) => tx.commit()?,
-        Err(_) => tx.rollback()?,
+        Ok(event) => {
+            tx.commit()?;
+            let guid = event.guid().clone();
+            db.observers()
+                .notify_bookmark_observers(ChangeSource::Local, &[event]);
+            Ok(guid)
+        }
+        Err(e) => {
+            tx.rollback()?;
+            Err(e)
+        }
     }
-    result
 }
 
 pub fn maybe_truncate_title(t: &Option<String>) -> Option<&str> {
@@ -252,7 +263,7 @@ pub fn maybe_truncate_title(t: &Option<String>) -> Option<&str> {
     t.as_ref().map(|title| slice_up_to(title, TITLE_LENGTH_MAX))
 }
 
-fn insert_bookmark_in_tx(db: &PlacesDb, bm: &InsertableItem) -> Result<SyncGuid> {
+pub(crate) fn insert_bookmark_in_tx(db: &PlacesDb, bm: &InsertableItem) -> Result<BookmarkEvent> {
     // find the row ID of the parent.
     if bm.parent_guid() == BookmarkRootGuid::Root {
         return Err(InvalidPlaceInfo::CannotUpdateRoot(BookmarkRootGuid::Root).into());
@@ -353,7 +364,19 @@ fn insert_bookmark_in_tx(db: &PlacesDb, bm: &InsertableItem) -> Result<SyncGuid>
         WHERE id = :parent_id";
     db.execute_named_cached(sql_counter, &[(":parent_id", &parent.row_id)])?;
 
-    Ok(guid)
+    let (url, title) = match bm {
+        InsertableItem::Bookmark(b) => (Some(b.url.clone()), b.title.clone()),
+        InsertableItem::Separator(_) => (None, None),
+        InsertableItem::Folder(f) => (None, f.title.clone()),
+    };
+    Ok(BookmarkEvent::ItemAdded {
+        guid,
+        parent_guid: parent_guid.clone(),
+        position,
+        item_type: bookmark_type,
+        url,
+        title: maybe_truncate_title(&title).map(ToString::to_string),
+    })
 }
 
 /// Delete the specified bookmark. Returns true if a bookmark with the guid
@@ -362,13 +385,27 @@ pub fn delete_bookmark(db: &PlacesDb, guid: &SyncGuid) -> Result<bool> {
     let tx = db.coop_transaction()?;
     let result = delete_bookmark_in_tx(db, guid);
     match result {
-        Ok(_) => tx.commit()?,
-        Err(_) => tx.rollback()?,
+        Ok(event) => {
+            tx.commit()?;
+            let did_delete = event.is_some();
+            if let Some(event) = event {
+                db.observers()
+                    .notify_bookmark_observers(ChangeSource::Local, &[event]);
+            }
+            Ok(did_delete)
+        }
+        Err(e) => {
+            tx.rollback()?;
+            Err(e)
+        }
     }
-    result
 }
 
-fn delete_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid) -> Result<bool> {
+/// Returns the event for the deleted item, or None if the item didn't exist.
+pub(crate) fn delete_bookmark_in_tx(
+    db: &PlacesDb,
+    guid: &SyncGuid,
+) -> Result<Option<BookmarkEvent>> {
     // Can't delete a root.
     if let Some(root) = guid.as_root() {
         return Err(InvalidPlaceInfo::CannotUpdateRoot(root).into());
@@ -377,7 +414,7 @@ fn delete_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid) -> Result<bool> {
         Some(r) => r,
         None => {
             log::debug!("Can't delete bookmark '{:?}' as it doesn't exist", guid);
-            return Ok(false);
+            return Ok(None);
         }
     };
     // There's an argument to be made here that we should still honor the
@@ -394,7 +431,15 @@ fn delete_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid) -> Result<bool> {
         &[(":id", &record.row_id)],
     )?;
     super::delete_pending_temp_tables(db)?;
-    Ok(true)
+    Ok(Some(BookmarkEvent::ItemRemoved {
+        parent_guid: record
+            .parent_guid
+            .ok_or_else(|| Corruption::NonRootWithoutParent(guid.to_string()))?,
+        position: record.position,
+        item_type: record.bookmark_type,
+        url: record.url,
+        guid: record.guid,
+    }))
 }
 
 /// Support for modifying bookmarks, including changing the location in
@@ -484,10 +529,17 @@ pub fn update_bookmark(db: &PlacesDb, guid: &SyncGuid, item: &UpdatableItem) ->
     let result = update_bookmark_in_tx(db, guid, item);
     // Note: `tx` automatically rolls back on drop if we don't commit
     tx.commit()?;
-    result
+    db.observers()
+        .notify_bookmark_observers(ChangeSource::Local, &result?);
+    Ok(())
 }
 
-fn update_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid, item: &UpdatableItem) -> Result<()> {
+/// Returns the events for the update, which are empty if nothing changed.
+fn update_bookmark_in_tx(
+    db: &PlacesDb,
+    guid: &SyncGuid,
+    item: &UpdatableItem,
+) -> Result<Vec<BookmarkEvent>> {
     if guid.is_root() {
         return Err(InvalidPlaceInfo::CannotUpdateRoot(BookmarkRootGuid::Root).into());
     }
@@ -515,16 +567,19 @@ fn update_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid, item: &UpdatableItem) -
     // to make our life easier we update every field, using existing when
     // no value is specified.
     let parent_id;
+    let parent_guid;
     let position;
     match item.location() {
         UpdateTreeLocation::None => {
             parent_id = existing_parent_id;
+            parent_guid = existing_parent_guid;
             position = existing.position;
             update_old_parent_status = false;
             update_new_parent_status = false;
         }
         UpdateTreeLocation::Position(pos) => {
             parent_id = existing_parent_id;
+            parent_guid = existing_parent_guid;
             update_old_parent_status = true;
             update_new_parent_status = false;
             let parent = get_raw_bookmark(db, existing_parent_guid)?.ok_or_else(|| {
@@ -542,6 +597,7 @@ fn update_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid, item: &UpdatableItem) -
                 return Err(InvalidPlaceInfo::InvalidParent(new_parent_guid.to_string()).into());
             }
             parent_id = new_parent.row_id;
+            parent_guid = new_parent_guid;
             update_old_parent_status = true;
             update_new_parent_status = true;
             let existing_parent = get_raw_bookmark(db, existing_parent_guid)?.ok_or_else(|| {
@@ -551,6 +607,10 @@ fn update_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid, item: &UpdatableItem) -
             position = resolve_pos_for_insert(db, *pos, &new_parent)?;
         }
     };
+    let url = match item {
+        UpdatableItem::Bookmark(UpdatableBookmark { url: Some(url), .. }) => Some(url.clone()),
+        _ => existing.url.clone(),
+    };
     let place_id = match item {
         UpdatableItem::Bookmark(b) => match &b.url {
             None => existing.place_id,
@@ -634,7 +694,26 @@ fn update_bookmark_in_tx(db: &PlacesDb, guid: &SyncGuid, item: &UpdatableItem) -
         set_ancestors_last_modified(db, parent_id, now)?;
         db.execute_named_cached(sql_counter, &[(":parent_id", &parent_id)])?;
     }
-    Ok(())
+
+    let mut events = Vec::new();
+    if parent_id != existing_parent_id || position != existing.position {
+        events.push(BookmarkEvent::ItemMoved {
+            guid: guid.clone(),
+            old_parent_guid: existing_parent_guid.clone(),
+            old_position: existing.position,
+            new_parent_guid: parent_guid.clone(),
+            new_position: position,
+        });
+    }
+    if change_incr {
+        events.push(BookmarkEvent::ItemChanged {
+            guid: guid.clone(),
+            item_type: existing.bookmark_type,
+            url,
+            title: maybe_truncate_title(&title).map(ToString::to_string),
+        });
+    }
+    Ok(events)
 }
 
 fn set_ancestors_last_modified(db: &PlacesDb, parent_id: RowId, time: Timestamp) -> Result<()> {
@@ -990,7 +1069,11 @@ mod test_serialize {
 
 }
 
-fn add_subtree_infos(parent: &SyncGuid, tree: &FolderNode, insert_infos: &mut Vec<InsertableItem>) {
+pub(crate) fn add_subtree_infos(
+    parent: &SyncGuid,
+    tree: &FolderNode,
+    insert_infos: &mut Vec<InsertableItem>,
+) {
     // TODO: track last modified? Like desktop, we should probably have
     // the default values passed in so the entire tree has consistent
     // timestamps.
@@ -1041,6 +1124,18 @@ fn add_subtree_infos(parent: &SyncGuid, tree: &FolderNode, insert_infos: &mut Ve
 }
 
 pub fn insert_tree(db: &PlacesDb, tree: &FolderNode) -> Result<()> {
+    let tx = db.coop_transaction()?;
+    let events = insert_tree_in_tx(db, tree)?;
+    super::delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    db.observers()
+        .notify_bookmark_observers(ChangeSource::Local, &events);
+    Ok(())
+}
+
+/// Inserts a tree inside an existing transaction, returning the events to
+/// notify once it's committed.
+pub(crate) fn insert_tree_in_tx(db: &PlacesDb, tree: &FolderNode) -> Result<Vec<BookmarkEvent>> {
     let parent_guid = match &tree.guid {
         Some(guid) => guid,
         None => return Err(InvalidPlaceInfo::InvalidParent("<no guid>".into()).into()),
@@ -1049,14 +1144,12 @@ pub fn insert_tree(db: &PlacesDb, tree: &FolderNode) -> Result<()> {
     let mut insert_infos: Vec<InsertableItem> = Vec::new();
     add_subtree_infos(&parent_guid, tree, &mut insert_infos);
     log::info!("insert_tree inserting {} records", insert_infos.len());
-    let tx = db.coop_transaction()?;
 
+    let mut events = Vec::with_capacity(insert_infos.len());
     for insertable in insert_infos {
-        insert_bookmark_in_tx(db, &insertable)?;
+        events.push(insert_bookmark_in_tx(db, &insertable)?);
     }
-    super::delete_pending_temp_tables(db)?;
-    tx.commit()?;
-    Ok(())
+    Ok(events)
 }
 
 #[derive(Debug)]
@@ -2161,4 +2254,155 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_observers() -> Result<()> {
+        use crate::api::places_api::{test::new_mem_api, ConnectionType};
+        use crate::observers::BookmarkObserver;
+        use std::sync::{Arc, Mutex};
+
+        let _ = env_logger::try_init();
+        let api = new_mem_api();
+        let conn = api.open_connection(ConnectionType::ReadWrite)?;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observer: Arc<dyn BookmarkObserver> = {
+            let events = events.clone();
+            Arc::new(move |source: ChangeSource, new_events: &[BookmarkEvent]| {
+                assert_eq!(source, ChangeSource::Local);
+                events.lock().unwrap().extend_from_slice(new_events);
+            })
+        };
+        let id = api.register_bookmark_observer(observer);
+
+        let unfiled = BookmarkRootGuid::Unfiled.as_guid();
+        let folder_guid = SyncGuid::from("folder1_____");
+        let bookmark_guid = SyncGuid::from("bookmark1___");
+        let url = Url::parse("https://www.example.com/")?;
+        insert_bookmark(
+            &conn,
+            &InsertableFolder {
+                parent_guid: unfiled.clone(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: Some(folder_guid.clone()),
+                title: Some("the folder".into()),
+            }
+            .into(),
+        )?;
+        insert_bookmark(
+            &conn,
+            &InsertableBookmark {
+                parent_guid: unfiled.clone(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: Some(bookmark_guid.clone()),
+                url: url.clone(),
+                title: Some("the title".into()),
+            }
+            .into(),
+        )?;
+        update_bookmark(
+            &conn,
+            &bookmark_guid,
+            &UpdatableBookmark {
+                location: UpdateTreeLocation::Parent(folder_guid.clone(), BookmarkPosition::Append),
+                title: Some("the new title".into()),
+                ..Default::default()
+            }
+            .into(),
+        )?;
+        // Updating with the same values doesn't change anything.
+        update_bookmark(
+            &conn,
+            &bookmark_guid,
+            &UpdatableBookmark {
+                title: Some("the new title".into()),
+                ..Default::default()
+            }
+            .into(),
+        )?;
+        assert!(delete_bookmark(&conn, &folder_guid)?);
+        // Failed changes don't notify.
+        assert!(!delete_bookmark(&conn, &folder_guid)?);
+        insert_bookmark(
+            &conn,
+            &InsertableBookmark {
+                parent_guid: folder_guid.clone(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url: url.clone(),
+                title: None,
+            }
+            .into(),
+        )
+        .expect_err("should fail to insert into a deleted folder");
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                BookmarkEvent::ItemAdded {
+                    guid: folder_guid.clone(),
+                    parent_guid: unfiled.clone(),
+                    position: 0,
+                    item_type: BookmarkType::Folder,
+                    url: None,
+                    title: Some("the folder".into()),
+                },
+                BookmarkEvent::ItemAdded {
+                    guid: bookmark_guid.clone(),
+                    parent_guid: unfiled.clone(),
+                    position: 1,
+                    item_type: BookmarkType::Bookmark,
+                    url: Some(url.clone()),
+                    title: Some("the title".into()),
+                },
+                BookmarkEvent::ItemMoved {
+                    guid: bookmark_guid.clone(),
+                    old_parent_guid: unfiled.clone(),
+                    old_position: 1,
+                    new_parent_guid: folder_guid.clone(),
+                    new_position: 0,
+                },
+                BookmarkEvent::ItemChanged {
+                    guid: bookmark_guid.clone(),
+                    item_type: BookmarkType::Bookmark,
+                    url: Some(url.clone()),
+                    title: Some("the new title".into()),
+                },
+                BookmarkEvent::ItemRemoved {
+                    guid: folder_guid.clone(),
+                    parent_guid: unfiled.clone(),
+                    position: 0,
+                    item_type: BookmarkType::Folder,
+                    url: None,
+                },
+            ]
+        );
+
+        // Unregistered observers aren't notified.
+        assert!(api.unregister_bookmark_observer(id));
+        assert!(!api.unregister_bookmark_observer(id));
+        events.lock().unwrap().clear();
+        insert_bookmark(
+            &conn,
+            &InsertableBookmark {
+                parent_guid: unfiled.clone(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url,
+                title: None,
+            }
+            .into(),
+        )?;
+        assert!(events.lock().unwrap().is_empty());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,1001 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Import and export of bookmarks in the Netscape bookmark file format, better
+//! known as `bookmarks.html`, which almost every browser can read and write.
+//!
+//! The format is a loose dialect of HTML, where each folder is an `<H3>`
+//! followed by a `<DL>` of its children, bookmarks are `<A>` elements and
+//! separators are `<HR>`s. We only look at the tags and attributes we
+//! understand, and don't validate anything else, so that files written by
+//! other browsers can still be imported.
+
+use super::{
+    fetch_tree, insert_tree_in_tx, BookmarkNode, BookmarkRootGuid, BookmarkTreeNode, FolderNode,
+    SeparatorNode,
+};
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::observers::ChangeSource;
+use crate::storage::{delete_pending_temp_tables, keywords, tags, URL_LENGTH_MAX};
+use crate::types::Timestamp;
+use sql_support::ConnExt;
+use std::collections::HashMap;
+use std::fmt::Write;
+use url::Url;
+
+/// The contents of a parsed `bookmarks.html` file.
+#[derive(Debug, Default)]
+pub struct HtmlBookmarks {
+    /// The trees to insert. Each of these has the guid of the root it should
+    /// be inserted into, and is suitable for passing to `insert_tree`.
+    pub roots: Vec<FolderNode>,
+    /// The tags to add to each URL. A URL may appear more than once.
+    pub tags: Vec<(Url, Vec<String>)>,
+    /// The keyword for each URL.
+    pub keywords: Vec<(Url, String)>,
+}
+
+impl HtmlBookmarks {
+    fn root_mut(&mut self, root: BookmarkRootGuid) -> &mut FolderNode {
+        let index = match self
+            .roots
+            .iter()
+            .position(|f| f.guid.as_ref() == Some(root.guid()))
+        {
+            Some(index) => index,
+            None => {
+                self.roots.push(FolderNode {
+                    guid: Some(root.into()),
+                    ..Default::default()
+                });
+                self.roots.len() - 1
+            }
+        };
+        &mut self.roots[index]
+    }
+}
+
+/// Imports a `bookmarks.html` file into the bookmarks tree. Top-level items
+/// are added to the end of the menu, and the toolbar and unfiled folders are
+/// merged into the toolbar and unfiled roots. Bookmarks with invalid URLs,
+/// tags or keywords are skipped when parsing; everything else is imported
+/// in a single transaction, so a failure leaves the tree unchanged.
+pub fn import_html(db: &PlacesDb, html: &str) -> Result<()> {
+    let parsed = parse_html(html);
+    let tx = db.coop_transaction()?;
+    let mut events = Vec::new();
+    for root in &parsed.roots {
+        log::debug!("importing html bookmarks into {:?}", root.guid);
+        events.extend(insert_tree_in_tx(db, root)?);
+    }
+    for (url, url_tags) in &parsed.tags {
+        for tag in url_tags {
+            tags::tag_url_in_tx(db, url, tag)?;
+        }
+    }
+    for (url, keyword) in &parsed.keywords {
+        keywords::set_keyword_in_tx(db, url, keyword)?;
+    }
+    delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    db.observers()
+        .notify_bookmark_observers(ChangeSource::Local, &events);
+    Ok(())
+}
+
+/// Parses the contents of a `bookmarks.html` file. This never fails - we
+/// keep whatever we manage to understand, and skip everything else.
+pub fn parse_html(html: &str) -> HtmlBookmarks {
+    let mut parser = Parser::default();
+    for token in Tokenizer::new(html) {
+        parser.handle_token(token);
+    }
+    parser.finish()
+}
+
+/// Exports all bookmarks, with their tags and keywords, as a `bookmarks.html`
+/// file. Like desktop, the menu's children are written at the top level, and
+/// the other roots are written as folders.
+pub fn export_html(db: &PlacesDb) -> Result<String> {
+    let root = match fetch_tree(db, &BookmarkRootGuid::Root.into())? {
+        Some(BookmarkTreeNode::Folder(f)) => f,
+        _ => return Err(Corruption::InvalidLocalRoots.into()),
+    };
+    // Fetch all keywords and tags up front, instead of querying for each
+    // bookmark.
+    let keywords: HashMap<String, String> = db
+        .query_rows_and_then_named_cached(
+            "SELECT h.url, k.keyword
+             FROM moz_keywords k
+             JOIN moz_places h ON h.id = k.place_id",
+            &[],
+            |row| -> Result<_> { Ok((row.get_checked::<_, String>(0)?, row.get_checked(1)?)) },
+        )?
+        .into_iter()
+        .collect();
+    let mut url_tags: HashMap<String, Vec<String>> = HashMap::new();
+    let tag_rows = db.query_rows_and_then_named_cached(
+        "SELECT h.url, t.tag
+         FROM moz_tags t
+         JOIN moz_tags_relation r ON r.tag_id = t.id
+         JOIN moz_places h ON h.id = r.place_id
+         ORDER BY t.lastModified DESC",
+        &[],
+        |row| -> Result<_> { Ok((row.get_checked::<_, String>(0)?, row.get_checked(1)?)) },
+    )?;
+    for (url, tag) in tag_rows {
+        url_tags.entry(url).or_default().push(tag);
+    }
+    let mut writer = HtmlWriter {
+        keywords,
+        tags: url_tags,
+        out: String::new(),
+    };
+    writer.out.push_str(HTML_HEADER);
+    writer.out.push_str("<DL><p>\n");
+    for child in &root.children {
+        let folder = match child {
+            BookmarkTreeNode::Folder(f) => f,
+            _ => continue,
+        };
+        let root_guid = folder.guid.as_ref().and_then(BookmarkRootGuid::from_guid);
+        match root_guid {
+            Some(BookmarkRootGuid::Menu) => writer.write_children(folder, 1)?,
+            Some(BookmarkRootGuid::Toolbar) => {
+                writer.write_folder(folder, "Bookmarks Toolbar", TOOLBAR_ATTR, 1)?
+            }
+            Some(BookmarkRootGuid::Unfiled) => {
+                if !folder.children.is_empty() {
+                    writer.write_folder(folder, "Other Bookmarks", UNFILED_ATTR, 1)?
+                }
+            }
+            Some(BookmarkRootGuid::Mobile) => {
+                if !folder.children.is_empty() {
+                    writer.write_folder(folder, "Mobile Bookmarks", "", 1)?
+                }
+            }
+            _ => writer.write_folder(folder, "", "", 1)?,
+        }
+    }
+    writer.out.push_str("</DL>\n");
+    Ok(writer.out)
+}
+
+const HTML_HEADER: &str = "<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file.
+     It will be read and overwritten.
+     DO NOT EDIT! -->
+<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks Menu</H1>
+
+";
+
+const TOOLBAR_ATTR: &str = "PERSONAL_TOOLBAR_FOLDER";
+const UNFILED_ATTR: &str = "UNFILED_BOOKMARKS_FOLDER";
+
+struct HtmlWriter {
+    /// The keyword for each URL.
+    keywords: HashMap<String, String>,
+    /// The tags for each URL, most recently added first.
+    tags: HashMap<String, Vec<String>>,
+    out: String,
+}
+
+impl HtmlWriter {
+    fn indent(&mut self, depth: usize) {
+        for _ in 0..depth {
+            self.out.push_str("    ");
+        }
+    }
+
+    fn write_dates(&mut self, date_added: Option<Timestamp>, last_modified: Option<Timestamp>) {
+        if let Some(date_added) = date_added {
+            let _ = write!(self.out, " ADD_DATE=\"{}\"", date_added.as_millis() / 1000);
+        }
+        if let Some(last_modified) = last_modified {
+            let _ = write!(
+                self.out,
+                " LAST_MODIFIED=\"{}\"",
+                last_modified.as_millis() / 1000
+            );
+        }
+    }
+
+    fn write_folder(
+        &mut self,
+        folder: &FolderNode,
+        default_title: &str,
+        special_attr: &str,
+        depth: usize,
+    ) -> Result<()> {
+        self.indent(depth);
+        self.out.push_str("<DT><H3");
+        self.write_dates(folder.date_added, folder.last_modified);
+        if !special_attr.is_empty() {
+            let _ = write!(self.out, " {}=\"true\"", special_attr);
+        }
+        self.out.push('>');
+        let title = folder.title.as_ref().map_or(default_title, String::as_str);
+        self.out.push_str(&escape_html(title));
+        self.out.push_str("</H3>\n");
+        self.indent(depth);
+        self.out.push_str("<DL><p>\n");
+        self.write_children(folder, depth + 1)?;
+        self.indent(depth);
+        self.out.push_str("</DL><p>\n");
+        Ok(())
+    }
+
+    fn write_children(&mut self, folder: &FolderNode, depth: usize) -> Result<()> {
+        for child in &folder.children {
+            match child {
+                BookmarkTreeNode::Bookmark(b) => self.write_bookmark(b, depth)?,
+                BookmarkTreeNode::Separator(_) => {
+                    self.indent(depth);
+                    self.out.push_str("<HR>\n");
+                }
+                BookmarkTreeNode::Folder(f) => self.write_folder(f, "", "", depth)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_bookmark(&mut self, bookmark: &BookmarkNode, depth: usize) -> Result<()> {
+        self.indent(depth);
+        let _ = write!(
+            self.out,
+            "<DT><A HREF=\"{}\"",
+            escape_html(bookmark.url.as_str())
+        );
+        self.write_dates(bookmark.date_added, bookmark.last_modified);
+        if let Some(keyword) = self.keywords.get(bookmark.url.as_str()) {
+            let _ = write!(self.out, " SHORTCUTURL=\"{}\"", escape_html(keyword));
+        }
+        if let Some(url_tags) = self.tags.get(bookmark.url.as_str()) {
+            let _ = write!(self.out, " TAGS=\"{}\"", escape_html(&url_tags.join(",")));
+        }
+        self.out.push('>');
+        let title = bookmark.title.as_ref().map_or("", String::as_str);
+        self.out.push_str(&escape_html(title));
+        self.out.push_str("</A>\n");
+        Ok(())
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_html(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_owned();
+    }
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                }
+                _ if entity.starts_with('#') => {
+                    entity[1..].parse().ok().and_then(std::char::from_u32)
+                }
+                _ => None,
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Dates are usually in seconds, but some browsers have written them in
+/// milliseconds or microseconds, so we guess based on the magnitude.
+fn parse_date(value: &str) -> Option<Timestamp> {
+    let value: u64 = value.trim().parse().ok()?;
+    if value == 0 {
+        return None;
+    }
+    let millis = if value >= 100_000_000_000_000 {
+        value / 1000
+    } else if value >= 100_000_000_000 {
+        value
+    } else {
+        value * 1000
+    };
+    Some(Timestamp(millis))
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    StartTag {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    EndTag(String),
+    Text(String),
+}
+
+/// A very forgiving tokenizer, which splits the input into tags and text.
+/// Tag and attribute names are uppercased, and entities are decoded.
+/// Comments, doctypes and processing instructions are skipped.
+struct Tokenizer<'a> {
+    input: &'a str,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input }
+    }
+
+    fn skip_past(&mut self, terminator: &str) {
+        self.input = match self.input.find(terminator) {
+            Some(end) => &self.input[end + terminator.len()..],
+            None => "",
+        };
+    }
+
+    fn read_name(&mut self) -> String {
+        let end = self
+            .input
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or_else(|| self.input.len());
+        let name = self.input[..end].to_ascii_uppercase();
+        self.input = &self.input[end..];
+        name
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.input = self.input.trim_start();
+    }
+
+    fn read_attr_value(&mut self) -> String {
+        let raw = match self.input.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let rest = &self.input[1..];
+                let end = rest.find(quote).unwrap_or_else(|| rest.len());
+                let value = &rest[..end];
+                self.input = &rest[(end + 1).min(rest.len())..];
+                value
+            }
+            _ => {
+                let end = self
+                    .input
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or_else(|| self.input.len());
+                let value = &self.input[..end];
+                self.input = &self.input[end..];
+                value
+            }
+        };
+        unescape_html(raw)
+    }
+
+    fn read_start_tag(&mut self) -> Token {
+        let name = self.read_name();
+        let mut attrs = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.input.starts_with('>') {
+                self.input = &self.input[1..];
+                break;
+            }
+            if self.input.starts_with('/') {
+                self.input = &self.input[1..];
+                continue;
+            }
+            if self.input.is_empty() {
+                break;
+            }
+            let attr_name = self.read_name();
+            self.skip_whitespace();
+            let value = if self.input.starts_with('=') {
+                self.input = &self.input[1..];
+                self.skip_whitespace();
+                self.read_attr_value()
+            } else {
+                String::new()
+            };
+            if !attr_name.is_empty() {
+                attrs.push((attr_name, value));
+            }
+        }
+        Token::StartTag { name, attrs }
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if self.input.is_empty() {
+                return None;
+            }
+            if !self.input.starts_with('<') {
+                let end = self.input.find('<').unwrap_or_else(|| self.input.len());
+                let text = unescape_html(&self.input[..end]);
+                self.input = &self.input[end..];
+                return Some(Token::Text(text));
+            }
+            match self.input[1..].chars().next() {
+                Some('!') if self.input.starts_with("<!--") => self.skip_past("-->"),
+                Some('!') | Some('?') => self.skip_past(">"),
+                Some('/') => {
+                    self.input = &self.input[2..];
+                    let name = self.read_name();
+                    self.skip_past(">");
+                    return Some(Token::EndTag(name));
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.input = &self.input[1..];
+                    return Some(self.read_start_tag());
+                }
+                _ => {
+                    // A stray `<`, so treat it as text.
+                    self.input = &self.input[1..];
+                    return Some(Token::Text("<".into()));
+                }
+            }
+        }
+    }
+}
+
+enum FrameKind {
+    /// A folder inside another folder.
+    Folder,
+    /// A folder which should be merged into a root.
+    Root(BookmarkRootGuid),
+    /// A `<DL>` without a heading. We don't create a folder for these, but
+    /// add their children to the parent instead.
+    Anonymous,
+}
+
+struct Frame {
+    kind: FrameKind,
+    folder: FolderNode,
+}
+
+/// The element whose text we're currently collecting.
+enum Pending {
+    Folder {
+        folder: FolderNode,
+        root: Option<BookmarkRootGuid>,
+    },
+    Bookmark {
+        bookmark: BookmarkNode,
+        tags: Vec<String>,
+        keyword: Option<String>,
+    },
+}
+
+#[derive(Default)]
+struct Parser {
+    result: HtmlBookmarks,
+    stack: Vec<Frame>,
+    // The folder we've seen the heading for, but not yet its `<DL>`.
+    heading: Option<(FolderNode, Option<BookmarkRootGuid>)>,
+    pending: Option<Pending>,
+    text: String,
+}
+
+impl Parser {
+    fn handle_token(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                if self.pending.is_some() {
+                    self.text.push_str(&text);
+                }
+            }
+            Token::StartTag { name, attrs } => {
+                self.finish_pending();
+                match name.as_str() {
+                    "DL" => self.open_list(),
+                    "H3" => self.start_folder(attrs),
+                    "A" => self.start_bookmark(attrs),
+                    "HR" => {
+                        self.heading = None;
+                        self.current_folder().children.push(
+                            SeparatorNode {
+                                guid: None,
+                                date_added: None,
+                                last_modified: None,
+                            }
+                            .into(),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            Token::EndTag(name) => {
+                self.finish_pending();
+                if name == "DL" {
+                    self.close_list();
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> HtmlBookmarks {
+        self.finish_pending();
+        while !self.stack.is_empty() {
+            self.close_list();
+        }
+        // An empty folder still needs to be created, but an empty root is
+        // just noise.
+        self.result.roots.retain(|root| !root.children.is_empty());
+        self.result
+    }
+
+    fn current_folder(&mut self) -> &mut FolderNode {
+        if self.stack.is_empty() {
+            // Items outside of any list go into the menu.
+            self.stack.push(Frame {
+                kind: FrameKind::Root(BookmarkRootGuid::Menu),
+                folder: FolderNode::default(),
+            });
+        }
+        &mut self.stack.last_mut().unwrap().folder
+    }
+
+    fn open_list(&mut self) {
+        let frame = match self.heading.take() {
+            Some((folder, Some(root))) if self.stack.len() <= 1 => Frame {
+                kind: FrameKind::Root(root),
+                folder,
+            },
+            Some((folder, _)) => Frame {
+                kind: FrameKind::Folder,
+                folder,
+            },
+            None if self.stack.is_empty() => Frame {
+                kind: FrameKind::Root(BookmarkRootGuid::Menu),
+                folder: FolderNode::default(),
+            },
+            None => Frame {
+                kind: FrameKind::Anonymous,
+                folder: FolderNode::default(),
+            },
+        };
+        self.stack.push(frame);
+    }
+
+    fn close_list(&mut self) {
+        // A heading without a list is an empty folder.
+        if let Some((folder, _)) = self.heading.take() {
+            self.current_folder().children.push(folder.into());
+        }
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        match frame.kind {
+            FrameKind::Folder => self.current_folder().children.push(frame.folder.into()),
+            FrameKind::Anonymous => self.current_folder().children.extend(frame.folder.children),
+            FrameKind::Root(root) => self
+                .result
+                .root_mut(root)
+                .children
+                .extend(frame.folder.children),
+        }
+    }
+
+    fn start_folder(&mut self, attrs: Vec<(String, String)>) {
+        if let Some((folder, _)) = self.heading.take() {
+            self.current_folder().children.push(folder.into());
+        }
+        let mut folder = FolderNode::default();
+        let mut root = None;
+        for (name, value) in attrs {
+            match name.as_str() {
+                "ADD_DATE" => folder.date_added = parse_date(&value),
+                "LAST_MODIFIED" => folder.last_modified = parse_date(&value),
+                "PERSONAL_TOOLBAR_FOLDER" => root = Some(BookmarkRootGuid::Toolbar),
+                "UNFILED_BOOKMARKS_FOLDER" => root = Some(BookmarkRootGuid::Unfiled),
+                _ => {}
+            }
+        }
+        self.pending = Some(Pending::Folder { folder, root });
+    }
+
+    fn start_bookmark(&mut self, attrs: Vec<(String, String)>) {
+        if let Some((folder, _)) = self.heading.take() {
+            self.current_folder().children.push(folder.into());
+        }
+        let mut href = None;
+        let mut date_added = None;
+        let mut last_modified = None;
+        let mut tags = Vec::new();
+        let mut keyword = None;
+        for (name, value) in attrs {
+            match name.as_str() {
+                "HREF" => href = Some(value),
+                "ADD_DATE" => date_added = parse_date(&value),
+                "LAST_MODIFIED" => last_modified = parse_date(&value),
+                "SHORTCUTURL" if !value.trim().is_empty() => {
+                    keyword = keywords::normalize_keyword(&value);
+                    if keyword.is_none() {
+                        log::warn!("skipping invalid keyword for imported bookmark");
+                    }
+                }
+                "TAGS" => {
+                    tags = value
+                        .split(',')
+                        .filter(|tag| !tag.trim().is_empty())
+                        .filter_map(|tag| match tags::validate_tag(tag).ensure_valid() {
+                            Ok(tag) => Some(tag.to_owned()),
+                            Err(_) => {
+                                log::warn!("skipping invalid tag for imported bookmark");
+                                None
+                            }
+                        })
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        let href = match href {
+            Some(href) => href,
+            // Probably an anchor, so not a bookmark.
+            None => return,
+        };
+        if href.len() > URL_LENGTH_MAX {
+            log::warn!("skipping imported bookmark with a URL that's too long");
+            return;
+        }
+        let url = match Url::parse(href.trim()) {
+            Ok(url) => url,
+            Err(e) => {
+                log::warn!("skipping imported bookmark with an invalid URL: {}", e);
+                return;
+            }
+        };
+        self.pending = Some(Pending::Bookmark {
+            bookmark: BookmarkNode {
+                guid: None,
+                date_added,
+                last_modified,
+                title: None,
+                url,
+            },
+            tags,
+            keyword,
+        });
+    }
+
+    fn finish_pending(&mut self) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let title = self.text.trim();
+        let title = if title.is_empty() {
+            None
+        } else {
+            Some(title.to_owned())
+        };
+        self.text.clear();
+        match pending {
+            Pending::Folder { mut folder, root } => {
+                folder.title = title;
+                self.heading = Some((folder, root));
+            }
+            Pending::Bookmark {
+                mut bookmark,
+                tags,
+                keyword,
+            } => {
+                bookmark.title = title;
+                if !tags.is_empty() {
+                    self.result.tags.push((bookmark.url.clone(), tags));
+                }
+                if let Some(keyword) = keyword {
+                    self.result.keywords.push((bookmark.url.clone(), keyword));
+                }
+                self.current_folder().children.push(bookmark.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use serde_json::json;
+
+    const FIREFOX_HTML: &str = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file.
+     It will be read and overwritten.
+     DO NOT EDIT! -->
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks Menu</H1>
+
+<DL><p>
+    <DT><A HREF="https://www.mozilla.org/" ADD_DATE="1546300800" LAST_MODIFIED="1546387200" SHORTCUTURL="moz" TAGS="mozilla,browsers">Mozilla &amp; Friends</A>
+    <HR>
+    <DT><H3 ADD_DATE="1546300800">Reading</H3>
+    <DL><p>
+        <DT><A HREF="https://example.com/a?b=1&amp;c=2">Example</A>
+        <DT><H3>Empty</H3>
+        <DL><p>
+        </DL><p>
+    </DL><p>
+    <DT><A HREF="not a url">Broken</A>
+    <DT><H3 PERSONAL_TOOLBAR_FOLDER="true">Bookmarks Toolbar</H3>
+    <DL><p>
+        <DT><A HREF="https://example.org/" ADD_DATE="1546300800000">Toolbar item</A>
+    </DL><p>
+    <DT><H3 UNFILED_BOOKMARKS_FOLDER="true">Other Bookmarks</H3>
+    <DL><p>
+        <dt><a href='https://example.net/' add_date=1546300800000000>Unfiled &#x263A;</a>
+    </DL><p>
+</DL>
+"#;
+
+    #[test]
+    fn test_parse_html() {
+        let _ = env_logger::try_init();
+        let parsed = parse_html(FIREFOX_HTML);
+        assert_eq!(parsed.roots.len(), 3);
+        let find_root = |root: BookmarkRootGuid| {
+            parsed
+                .roots
+                .iter()
+                .find(|f| f.guid.as_ref() == Some(root.guid()))
+                .expect("should have root")
+        };
+
+        let menu = find_root(BookmarkRootGuid::Menu);
+        assert_eq!(menu.children.len(), 3);
+        match &menu.children[0] {
+            BookmarkTreeNode::Bookmark(b) => {
+                assert_eq!(b.url.as_str(), "https://www.mozilla.org/");
+                assert_eq!(b.title, Some("Mozilla & Friends".into()));
+                assert_eq!(b.date_added, Some(Timestamp(1_546_300_800_000)));
+                assert_eq!(b.last_modified, Some(Timestamp(1_546_387_200_000)));
+            }
+            node => panic!("expected a bookmark, got {:?}", node),
+        }
+        assert_eq!(
+            menu.children[1].node_type(),
+            crate::types::BookmarkType::Separator
+        );
+        match &menu.children[2] {
+            BookmarkTreeNode::Folder(f) => {
+                assert_eq!(f.title, Some("Reading".into()));
+                assert_eq!(f.date_added, Some(Timestamp(1_546_300_800_000)));
+                assert_eq!(f.children.len(), 2);
+            }
+            node => panic!("expected a folder, got {:?}", node),
+        }
+
+        // Millisecond and microsecond dates should be converted.
+        for &guid in &[BookmarkRootGuid::Toolbar, BookmarkRootGuid::Unfiled] {
+            match &find_root(guid).children[..] {
+                [BookmarkTreeNode::Bookmark(b)] => {
+                    assert_eq!(b.date_added, Some(Timestamp(1_546_300_800_000)))
+                }
+                children => panic!("unexpected children {:?}", children),
+            }
+        }
+
+        assert_eq!(
+            parsed.tags,
+            vec![(
+                Url::parse("https://www.mozilla.org/").unwrap(),
+                vec!["mozilla".to_string(), "browsers".to_string()]
+            )]
+        );
+        assert_eq!(
+            parsed.keywords,
+            vec![(
+                Url::parse("https://www.mozilla.org/").unwrap(),
+                "moz".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_html_invalid_tags_and_keywords() {
+        let _ = env_logger::try_init();
+        let parsed = parse_html(
+            r#"<DL><p>
+    <DT><A HREF="https://example.com/" SHORTCUTURL="two words" TAGS="ok, not ok ,,">Example</A>
+    <DT><A HREF="https://example.org/" SHORTCUTURL=" Ex ">Other</A>
+</DL>"#,
+        );
+        assert_eq!(
+            parsed.tags,
+            vec![(
+                Url::parse("https://example.com/").unwrap(),
+                vec!["ok".to_string()]
+            )]
+        );
+        assert_eq!(
+            parsed.keywords,
+            vec![(
+                Url::parse("https://example.org/").unwrap(),
+                "ex".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_import_html() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = new_mem_connection();
+        import_html(&conn, FIREFOX_HTML)?;
+
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Menu.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Menu.as_guid(),
+                "children": [
+                    {
+                        "title": "Mozilla & Friends",
+                        "url": "https://www.mozilla.org/",
+                        "date_added": 1_546_300_800_000u64,
+                    },
+                    {
+                        "type": 3,
+                    },
+                    {
+                        "title": "Reading",
+                        "children": [
+                            {
+                                "title": "Example",
+                                "url": "https://example.com/a?b=1&c=2",
+                            },
+                            {
+                                "title": "Empty",
+                                "children": [],
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Unfiled.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Unfiled.as_guid(),
+                "children": [
+                    {
+                        "title": "Unfiled \u{263A}",
+                        "url": "https://example.net/",
+                    },
+                ],
+            }),
+        );
+        let url = Url::parse("https://www.mozilla.org/")?;
+        let mut url_tags = tags::get_tags_for_url(&conn, &url)?;
+        url_tags.sort();
+        assert_eq!(
+            url_tags,
+            vec!["browsers".to_string(), "mozilla".to_string()]
+        );
+        assert_eq!(
+            keywords::get_keyword_for_url(&conn, &url)?,
+            Some("moz".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_html_roundtrip() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = new_mem_connection();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": &BookmarkRootGuid::Toolbar.as_guid(),
+                "children": [
+                    {
+                        "title": "<quotes> & \"things\"",
+                        "url": "https://example.com/?q=\"x\"&y=<z>",
+                        "date_added": 1_546_300_800_000u64,
+                    },
+                    {
+                        "type": 3,
+                    },
+                    {
+                        "title": "A folder",
+                        "children": [
+                            {
+                                "title": "Nested",
+                                "url": "https://example.org/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        let url = Url::parse("https://example.org/")?;
+        tags::tag_url(&conn, &url, "nested")?;
+        keywords::set_keyword(&conn, &url, "nest")?;
+
+        let html = export_html(&conn)?;
+        assert!(html.starts_with("<!DOCTYPE NETSCAPE-Bookmark-file-1>"));
+        assert!(html.contains("PERSONAL_TOOLBAR_FOLDER=\"true\""));
+        assert!(html.contains("ADD_DATE=\"1546300800\""));
+
+        let other = new_mem_connection();
+        import_html(&other, &html)?;
+        assert_json_tree(
+            &other,
+            &BookmarkRootGuid::Toolbar.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Toolbar.as_guid(),
+                "children": [
+                    {
+                        "title": "<quotes> & \"things\"",
+                        "url": "https://example.com/?q=%22x%22&y=%3Cz%3E",
+                        "date_added": 1_546_300_800_000u64,
+                    },
+                    {
+                        "type": 3,
+                    },
+                    {
+                        "title": "A folder",
+                        "children": [
+                            {
+                                "title": "Nested",
+                                "url": "https://example.org/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert_eq!(
+            tags::get_tags_for_url(&other, &url)?,
+            vec!["nested".to_string()]
+        );
+        assert_eq!(
+            keywords::get_keyword_for_url(&other, &url)?,
+            Some("nest".to_string())
+        );
+        Ok(())
+    }
+}
@@ -205,8 +205,10 @@ pub fn update_bookmark_from_message(db: &PlacesDb, msg: ProtoBookmark) -> Result
     )?;
     let (guid, updatable) = info.into_updatable(node_type)?;
 
-    update_bookmark_in_tx(db, &guid, &updatable)?;
+    let events = update_bookmark_in_tx(db, &guid, &updatable)?;
     tx.commit()?;
+    db.observers()
+        .notify_bookmark_observers(ChangeSource::Local, &events);
     Ok(())
 }
 
@@ -254,32 +256,42 @@ pub fn fetch_public_tree(db: &PlacesDb, item_guid: &SyncGuid) -> Result<Option<P
 
 pub fn search_bookmarks(db: &PlacesDb, search: &str, limit: u32) -> Result<Vec<PublicNode>> {
     let scope = db.begin_interrupt_scope();
-    Ok(db.query_rows_into_cached(
-        &SEARCH_QUERY,
-        &[(":search", &search), (":limit", &limit)],
-        |row| -> Result<_> {
-            scope.err_if_interrupted()?;
-            Ok(PublicNode {
-                node_type: BookmarkType::Bookmark,
-                guid: row.get_checked("guid")?,
-                parent_guid: row.get_checked("parentGuid")?,
-                position: row.get_checked("position")?,
-                date_added: row.get_checked("dateAdded")?,
-                last_modified: row.get_checked("lastModified")?,
-                title: row.get_checked("title")?,
-                url: row
-                    .get_checked::<_, Option<String>>("url")?
-                    .map(|href| url::Url::parse(&href))
-                    .transpose()?,
-                child_guids: None,
-                child_nodes: None,
-            })
-        },
-    )?)
+    let fts_query = if db.use_fts_index()? {
+        crate::match_impl::fts_query(search)
+    } else {
+        None
+    };
+    let mut params: Vec<(&str, &dyn rusqlite::types::ToSql)> =
+        vec![(":search", &search), (":limit", &limit)];
+    let sql = match &fts_query {
+        Some(fts_query) => {
+            params.push((":ftsQuery", fts_query));
+            &*SEARCH_FTS_QUERY
+        }
+        None => &*SEARCH_QUERY,
+    };
+    Ok(db.query_rows_into_cached(sql, &params, |row| -> Result<_> {
+        scope.err_if_interrupted()?;
+        Ok(PublicNode {
+            node_type: BookmarkType::Bookmark,
+            guid: row.get_checked("guid")?,
+            parent_guid: row.get_checked("parentGuid")?,
+            position: row.get_checked("position")?,
+            date_added: row.get_checked("dateAdded")?,
+            last_modified: row.get_checked("lastModified")?,
+            title: row.get_checked("title")?,
+            url: row
+                .get_checked::<_, Option<String>>("url")?
+                .map(|href| url::Url::parse(&href))
+                .transpose()?,
+            child_guids: None,
+            child_nodes: None,
+        })
+    })?)
 }
 
-lazy_static::lazy_static! {
-    pub static ref SEARCH_QUERY: String = format!(
+fn search_query(candidates_filter: &str) -> String {
+    format!(
         "SELECT
             b.guid,
             p.guid AS parentGuid,
@@ -293,6 +305,7 @@ lazy_static::lazy_static! {
         JOIN moz_bookmarks p ON p.id = b.parent
         JOIN moz_places h ON h.id = b.fk
         WHERE b.type = {bookmark_type}
+            {candidates_filter}
             AND AUTOCOMPLETE_MATCH(
                 :search, h.url, IFNULL(b.title, h.title),
                 NULL, -- tags
@@ -311,9 +324,19 @@ lazy_static::lazy_static! {
             )
         LIMIT :limit",
         bookmark_type = BookmarkType::Bookmark as u8,
+        candidates_filter = candidates_filter,
         match_bhvr = crate::match_impl::MatchBehavior::Anywhere as u32,
         search_bhvr = crate::match_impl::SearchBehavior::BOOKMARK.bits(),
-    );
+    )
+}
+
+lazy_static::lazy_static! {
+    pub static ref SEARCH_QUERY: String = search_query("");
+    // Only used when the full-text index is enabled.
+    pub static ref SEARCH_FTS_QUERY: String = search_query(&format!(
+        "AND h.id IN ({})",
+        crate::match_impl::FTS_CANDIDATES_SQL
+    ));
 }
 #[cfg(test)]
 mod test {
@@ -482,6 +505,50 @@ mod test {
         }
         Ok(())
     }
+    #[test]
+    fn test_search_fts() -> Result<()> {
+        let conn = new_mem_connection();
+        let _ = env_logger::try_init();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": String::from(BookmarkRootGuid::Unfiled.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmark1___",
+                        "url": "https://www.example1.com/",
+                        "title": "",
+                    },
+                    {
+                        "guid": "bookmark2___",
+                        "url": "https://www.mozilla.org/",
+                        "title": "my example bookmark",
+                    },
+                    {
+                        "guid": "bookmark3___",
+                        "url": "https://www.rust-lang.org/",
+                        "title": "",
+                    },
+                ]
+            }),
+        );
+        conn.set_use_fts_index(true)?;
+        let search = |query: &str| -> Result<Vec<String>> {
+            let mut guids = search_bookmarks(&conn, query, 10)?
+                .into_iter()
+                .map(|b| b.guid.0)
+                .collect::<Vec<_>>();
+            guids.sort();
+            Ok(guids)
+        };
+        assert_eq!(search("example")?, vec!["bookmark1___", "bookmark2___"]);
+        assert_eq!(search("rust lang")?, vec!["bookmark3___"]);
+        // Matches in the middle of words in URLs aren't found through the
+        // index, but bookmark titles are always searched.
+        assert_eq!(search("ample")?, vec!["bookmark2___"]);
+        Ok(())
+    }
+
     #[test]
     fn test_fetch_bookmark() -> Result<()> {
         let conn = new_mem_connection();
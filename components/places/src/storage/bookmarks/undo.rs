@@ -0,0 +1,398 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Support for undoing bookmark deletions and moves.
+//!
+//! Deleting or moving an item with these functions returns an `UndoSnapshot`
+//! which can later be passed to `restore`. Restoring a deletion re-inserts
+//! the item and all its descendants with their original GUIDs, positions and
+//! dates. The restored items are inserted as new items, so they'll be
+//! uploaded on the next sync, and any tombstones that the deletion added to
+//! `moz_bookmarks_deleted` are removed, so we never upload both a tombstone
+//! and a record for the same item.
+
+use super::{
+    add_subtree_infos, delete_bookmark_in_tx, fetch_tree, get_raw_bookmark, insert_bookmark_in_tx,
+    update_bookmark_in_tx, BookmarkNode, BookmarkPosition, BookmarkTreeNode, InsertableBookmark,
+    InsertableFolder, InsertableItem, InsertableSeparator, RawBookmark, SeparatorNode,
+    UpdatableBookmark, UpdatableFolder, UpdatableItem, UpdatableSeparator, UpdateTreeLocation,
+};
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::observers::ChangeSource;
+use crate::storage::delete_pending_temp_tables;
+use crate::types::{BookmarkType, SyncGuid};
+
+/// A change which can be undone with `restore`.
+#[derive(Debug)]
+pub enum UndoSnapshot {
+    /// An item was deleted. `item` holds the item and all its descendants,
+    /// as they were before the deletion.
+    Deletion {
+        parent_guid: SyncGuid,
+        position: u32,
+        item: BookmarkTreeNode,
+    },
+    /// An item was moved from `parent_guid` and `position`.
+    Move {
+        guid: SyncGuid,
+        parent_guid: SyncGuid,
+        position: u32,
+    },
+}
+
+/// Delete the specified item, like `delete_bookmark`, and return a snapshot
+/// that can restore it. Returns None if the item doesn't exist.
+pub fn delete_bookmark_with_undo(db: &PlacesDb, guid: &SyncGuid) -> Result<Option<UndoSnapshot>> {
+    if let Some(root) = guid.as_root() {
+        return Err(InvalidPlaceInfo::CannotUpdateRoot(root).into());
+    }
+    let tx = db.coop_transaction()?;
+    let raw = match get_raw_bookmark(db, guid)? {
+        Some(raw) => raw,
+        None => return Ok(None),
+    };
+    let snapshot = UndoSnapshot::Deletion {
+        parent_guid: raw
+            .parent_guid
+            .clone()
+            .ok_or_else(|| Corruption::NonRootWithoutParent(guid.to_string()))?,
+        position: raw.position,
+        item: snapshot_item(db, &raw)?,
+    };
+    let event = delete_bookmark_in_tx(db, guid)?;
+    tx.commit()?;
+    if let Some(event) = event {
+        db.observers()
+            .notify_bookmark_observers(ChangeSource::Local, &[event]);
+    }
+    Ok(Some(snapshot))
+}
+
+/// Move the specified item to a new parent and position, like
+/// `update_bookmark` with an `UpdateTreeLocation`, and return a snapshot
+/// that can move it back. Only the location is restored - other changes made
+/// to the item after the move are kept.
+pub fn move_bookmark_with_undo(
+    db: &PlacesDb,
+    guid: &SyncGuid,
+    location: UpdateTreeLocation,
+) -> Result<UndoSnapshot> {
+    if let Some(root) = guid.as_root() {
+        return Err(InvalidPlaceInfo::CannotUpdateRoot(root).into());
+    }
+    let tx = db.coop_transaction()?;
+    let raw = get_raw_bookmark(db, guid)?
+        .ok_or_else(|| InvalidPlaceInfo::NoSuchGuid(guid.to_string()))?;
+    let parent_guid = raw
+        .parent_guid
+        .clone()
+        .ok_or_else(|| Corruption::NonRootWithoutParent(guid.to_string()))?;
+    let events = update_bookmark_in_tx(db, guid, &location_update(&raw, location))?;
+    tx.commit()?;
+    db.observers()
+        .notify_bookmark_observers(ChangeSource::Local, &events);
+    Ok(UndoSnapshot::Move {
+        guid: guid.clone(),
+        parent_guid,
+        position: raw.position,
+    })
+}
+
+/// Undo the change captured by a snapshot. Fails if a deleted item has
+/// since been re-added, or if a moved item or the original parent no longer
+/// exist.
+pub fn restore(db: &PlacesDb, snapshot: &UndoSnapshot) -> Result<()> {
+    let tx = db.coop_transaction()?;
+    let events = match snapshot {
+        UndoSnapshot::Deletion {
+            parent_guid,
+            position,
+            item,
+        } => {
+            let guid = item.guid();
+            if get_raw_bookmark(db, guid)?.is_some() {
+                return Err(InvalidPlaceInfo::GuidAlreadyExists(guid.to_string()).into());
+            }
+            let mut insert_infos = vec![insertable_for_node(
+                item,
+                parent_guid,
+                BookmarkPosition::Specific(*position),
+            )];
+            if let BookmarkTreeNode::Folder(f) = item {
+                add_subtree_infos(guid, f, &mut insert_infos);
+            }
+            let mut events = Vec::with_capacity(insert_infos.len());
+            for insertable in insert_infos {
+                events.push(insert_bookmark_in_tx(db, &insertable)?);
+            }
+            delete_pending_temp_tables(db)?;
+            events
+        }
+        UndoSnapshot::Move {
+            guid,
+            parent_guid,
+            position,
+        } => {
+            let raw = get_raw_bookmark(db, guid)?
+                .ok_or_else(|| InvalidPlaceInfo::NoSuchGuid(guid.to_string()))?;
+            let location = if raw.parent_guid.as_ref() == Some(parent_guid) {
+                UpdateTreeLocation::Position(BookmarkPosition::Specific(*position))
+            } else {
+                UpdateTreeLocation::Parent(
+                    parent_guid.clone(),
+                    BookmarkPosition::Specific(*position),
+                )
+            };
+            update_bookmark_in_tx(db, guid, &location_update(&raw, location))?
+        }
+    };
+    tx.commit()?;
+    db.observers()
+        .notify_bookmark_observers(ChangeSource::Local, &events);
+    Ok(())
+}
+
+// `fetch_tree` always returns a folder for the requested item, so we only
+// use it for folders, and build the node for other items ourselves.
+fn snapshot_item(db: &PlacesDb, raw: &RawBookmark) -> Result<BookmarkTreeNode> {
+    Ok(match raw.bookmark_type {
+        BookmarkType::Bookmark => BookmarkNode {
+            guid: Some(raw.guid.clone()),
+            date_added: Some(raw.date_added),
+            last_modified: Some(raw.date_modified),
+            title: raw.title.clone(),
+            url: raw.url.clone().ok_or_else(|| InvalidPlaceInfo::NoUrl)?,
+        }
+        .into(),
+        BookmarkType::Separator => SeparatorNode {
+            guid: Some(raw.guid.clone()),
+            date_added: Some(raw.date_added),
+            last_modified: Some(raw.date_modified),
+        }
+        .into(),
+        BookmarkType::Folder => fetch_tree(db, &raw.guid)?
+            .ok_or_else(|| InvalidPlaceInfo::NoSuchGuid(raw.guid.to_string()))?,
+    })
+}
+
+fn insertable_for_node(
+    node: &BookmarkTreeNode,
+    parent_guid: &SyncGuid,
+    position: BookmarkPosition,
+) -> InsertableItem {
+    match node {
+        BookmarkTreeNode::Bookmark(b) => InsertableBookmark {
+            parent_guid: parent_guid.clone(),
+            position,
+            date_added: b.date_added,
+            last_modified: b.last_modified,
+            guid: b.guid.clone(),
+            url: b.url.clone(),
+            title: b.title.clone(),
+        }
+        .into(),
+        BookmarkTreeNode::Separator(s) => InsertableSeparator {
+            parent_guid: parent_guid.clone(),
+            position,
+            date_added: s.date_added,
+            last_modified: s.last_modified,
+            guid: s.guid.clone(),
+        }
+        .into(),
+        BookmarkTreeNode::Folder(f) => InsertableFolder {
+            parent_guid: parent_guid.clone(),
+            position,
+            date_added: f.date_added,
+            last_modified: f.last_modified,
+            guid: f.guid.clone(),
+            title: f.title.clone(),
+        }
+        .into(),
+    }
+}
+
+// Builds an update that only changes the location of an item.
+fn location_update(raw: &RawBookmark, location: UpdateTreeLocation) -> UpdatableItem {
+    match raw.bookmark_type {
+        BookmarkType::Bookmark => UpdatableBookmark {
+            location,
+            ..Default::default()
+        }
+        .into(),
+        BookmarkType::Separator => UpdatableSeparator { location }.into(),
+        BookmarkType::Folder => UpdatableFolder {
+            location,
+            ..Default::default()
+        }
+        .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::bookmarks::BookmarkRootGuid;
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use crate::types::Timestamp;
+    use rusqlite::NO_PARAMS;
+    use serde_json::json;
+    use sql_support::ConnExt;
+
+    #[test]
+    fn test_undo_delete() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = new_mem_connection();
+        let unfiled = BookmarkRootGuid::Unfiled.as_guid();
+        let tree = json!({
+            "guid": &unfiled,
+            "children": [
+                {
+                    "guid": "bookmark1___",
+                    "title": "the bookmark",
+                    "url": "https://www.example.com/",
+                    "date_added": Timestamp(1_000),
+                    "last_modified": Timestamp(2_000),
+                },
+                {
+                    "guid": "folder1_____",
+                    "title": "the folder",
+                    "date_added": Timestamp(1_000),
+                    "last_modified": Timestamp(2_000),
+                    "children": [
+                        {
+                            "guid": "bookmark2___",
+                            "title": "a child",
+                            "url": "https://www.example2.com/",
+                            "date_added": Timestamp(3_000),
+                            "last_modified": Timestamp(4_000),
+                        },
+                        {
+                            "guid": "separator1__",
+                            "type": BookmarkType::Separator as u8,
+                            "date_added": Timestamp(3_000),
+                            "last_modified": Timestamp(4_000),
+                        },
+                    ]
+                },
+                {
+                    "guid": "bookmark3___",
+                    "title": "the last bookmark",
+                    "url": "https://www.example3.com/",
+                    "date_added": Timestamp(1_000),
+                    "last_modified": Timestamp(2_000),
+                },
+            ]
+        });
+        insert_json_tree(&conn, tree.clone());
+        // Pretend everything has been synced, so deleting creates tombstones.
+        conn.execute("UPDATE moz_bookmarks SET syncStatus = 2", NO_PARAMS)?;
+
+        let snapshot =
+            delete_bookmark_with_undo(&conn, &"folder1_____".into())?.expect("should exist");
+        assert!(get_raw_bookmark(&conn, &"bookmark2___".into())?.is_none());
+        assert_eq!(
+            conn.query_one::<i64>("SELECT COUNT(*) FROM moz_bookmarks_deleted")?,
+            3
+        );
+
+        restore(&conn, &snapshot)?;
+        assert_json_tree(&conn, &unfiled, tree);
+        assert_eq!(
+            conn.query_one::<i64>("SELECT COUNT(*) FROM moz_bookmarks_deleted")?,
+            0
+        );
+
+        // Restoring again should fail, since the items exist.
+        restore(&conn, &snapshot).expect_err("should fail to restore twice");
+
+        // Bookmarks are snapshotted too.
+        let snapshot =
+            delete_bookmark_with_undo(&conn, &"bookmark1___".into())?.expect("should exist");
+        restore(&conn, &snapshot)?;
+        let raw = get_raw_bookmark(&conn, &"bookmark1___".into())?.expect("should exist");
+        assert_eq!(raw.position, 0);
+        assert_eq!(raw.date_added, Timestamp(1_000));
+        assert_eq!(raw.url, Some(url::Url::parse("https://www.example.com/")?));
+
+        assert!(delete_bookmark_with_undo(&conn, &"nonexistent_".into())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_move() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = new_mem_connection();
+        let unfiled = BookmarkRootGuid::Unfiled.as_guid();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": &unfiled,
+                "children": [
+                    {
+                        "guid": "bookmark1___",
+                        "url": "https://www.example.com/",
+                    },
+                    {
+                        "guid": "folder1_____",
+                        "children": [],
+                    },
+                    {
+                        "guid": "bookmark2___",
+                        "url": "https://www.example2.com/",
+                    },
+                ]
+            }),
+        );
+        let expected = json!({
+            "guid": &unfiled,
+            "children": [
+                {
+                    "guid": "bookmark1___",
+                    "url": "https://www.example.com/",
+                },
+                {
+                    "guid": "folder1_____",
+                    "children": [],
+                },
+                {
+                    "guid": "bookmark2___",
+                    "url": "https://www.example2.com/",
+                },
+            ]
+        });
+
+        // Move into another folder.
+        let snapshot = move_bookmark_with_undo(
+            &conn,
+            &"bookmark1___".into(),
+            UpdateTreeLocation::Parent("folder1_____".into(), BookmarkPosition::Append),
+        )?;
+        assert_eq!(
+            get_raw_bookmark(&conn, &"bookmark1___".into())?
+                .unwrap()
+                .parent_guid,
+            Some("folder1_____".into())
+        );
+        restore(&conn, &snapshot)?;
+        assert_json_tree(&conn, &unfiled, expected.clone());
+
+        // Move within the same folder.
+        let snapshot = move_bookmark_with_undo(
+            &conn,
+            &"bookmark2___".into(),
+            UpdateTreeLocation::Position(BookmarkPosition::Specific(0)),
+        )?;
+        assert_eq!(
+            get_raw_bookmark(&conn, &"bookmark2___".into())?
+                .unwrap()
+                .position,
+            0
+        );
+        restore(&conn, &snapshot)?;
+        assert_json_tree(&conn, &unfiled, expected);
+        Ok(())
+    }
+}
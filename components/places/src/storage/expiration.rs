@@ -0,0 +1,550 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! History expiration, which removes old history to keep the database within
+//! the limits of an `ExpirationPolicy`. Like Desktop, expiration removes the
+//! oldest, least frecent pages that aren't bookmarked, tagged, or have
+//! keywords, and then the oldest visits.
+//!
+//! Expired pages that have already been synced get tombstones, like pages
+//! removed any other way, so that the next sync doesn't download them again.
+//! Pages with visit tombstones that haven't been uploaded yet are kept until
+//! the next sync, because removing them would also remove the tombstones.
+
+use super::history::update_frecency;
+use super::{delete_pending_temp_tables, RowId};
+use crate::api::places_api::ConnectionType;
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::types::{SyncStatus, Timestamp};
+use rusqlite::Result as RusqliteResult;
+use serde_derive::*;
+use sql_support::{self, ConnExt};
+use std::time::{Duration, Instant};
+
+/// The number of pages or visits to expire at once, before checking the
+/// limits again.
+const EXPIRE_CHUNK_SIZE: u32 = 100;
+
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Limits on how much history to keep. A limit of 0 means there's no limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExpirationPolicy {
+    /// The maximum number of pages to keep.
+    pub max_pages: u32,
+    /// The maximum number of visits to keep.
+    pub max_visits: u32,
+    /// The maximum size of the data in the database, in bytes. This doesn't
+    /// include free pages, which are only returned to the file system by
+    /// `run_maintenance`.
+    pub max_size_bytes: u64,
+    /// History newer than this is never expired, even if it's over the
+    /// limits.
+    pub min_age_days: u32,
+}
+
+impl Default for ExpirationPolicy {
+    fn default() -> Self {
+        Self {
+            max_pages: 0,
+            max_visits: 0,
+            max_size_bytes: 0,
+            min_age_days: 4,
+        }
+    }
+}
+
+/// What a call to `expire_history` did.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExpirationResult {
+    /// The number of pages removed.
+    pub expired_pages: u32,
+    /// The number of visits removed, including the visits of removed pages.
+    pub expired_visits: u32,
+    /// False if expiration ran out of time, and should be run again.
+    pub finished: bool,
+}
+
+/// How far over the policy's limits the database is.
+#[derive(Debug, Default)]
+struct Excess {
+    pages: u32,
+    visits: u32,
+    size: bool,
+}
+
+impl Excess {
+    fn measure(db: &PlacesDb, policy: &ExpirationPolicy) -> Result<Self> {
+        let mut excess = Excess::default();
+        if policy.max_pages > 0 {
+            let pages = db.query_one::<i64>("SELECT COUNT(*) FROM moz_places")?;
+            excess.pages = (pages - i64::from(policy.max_pages)).max(0) as u32;
+        }
+        if policy.max_visits > 0 {
+            let visits = db.query_one::<i64>("SELECT COUNT(*) FROM moz_historyvisits")?;
+            excess.visits = (visits - i64::from(policy.max_visits)).max(0) as u32;
+        }
+        excess.size = is_over_size(db, policy)?;
+        Ok(excess)
+    }
+
+    /// Updates the excess after expiring a chunk. Counting pages and visits
+    /// is a full scan, so we only do that once, and subtract what we've
+    /// expired; checking the size is cheap, so we check it again.
+    fn expired(
+        &mut self,
+        db: &PlacesDb,
+        policy: &ExpirationPolicy,
+        (pages, visits): (u32, u32),
+    ) -> Result<()> {
+        self.pages = self.pages.saturating_sub(pages);
+        self.visits = self.visits.saturating_sub(visits);
+        self.size = is_over_size(db, policy)?;
+        Ok(())
+    }
+
+    fn is_over(&self) -> bool {
+        self.pages > 0 || self.visits > 0 || self.size
+    }
+}
+
+fn is_over_size(db: &PlacesDb, policy: &ExpirationPolicy) -> Result<bool> {
+    if policy.max_size_bytes == 0 {
+        return Ok(false);
+    }
+    let page_size = db.query_one::<i64>("PRAGMA page_size")?;
+    let used_pages =
+        db.query_one::<i64>("PRAGMA page_count")? - db.query_one::<i64>("PRAGMA freelist_count")?;
+    Ok((used_pages * page_size) as u64 > policy.max_size_bytes)
+}
+
+/// Expires history until the database is within the limits of the `policy`,
+/// or until `budget` runs out. This is meant to be called while the app is
+/// idle, and again later if the result isn't `finished`.
+///
+/// Each chunk is committed in its own transaction, so an interrupted or
+/// failed call only loses the chunk it was working on, and other writers
+/// aren't blocked for the whole call. Only the read-write connection can
+/// expire history.
+pub fn expire_history(
+    db: &PlacesDb,
+    policy: &ExpirationPolicy,
+    budget: Duration,
+) -> Result<ExpirationResult> {
+    // This can be reached from the FFI with any connection, and
+    // `coop_transaction` asserts, so we return an error instead.
+    if db.conn_type() != ConnectionType::ReadWrite {
+        return Err(ErrorKind::InvalidConnectionType.into());
+    }
+    let started_at = Instant::now();
+    let scope = db.begin_interrupt_scope();
+    let cutoff = Timestamp(
+        Timestamp::now()
+            .as_millis()
+            .saturating_sub(u64::from(policy.min_age_days) * MS_PER_DAY),
+    );
+    let mut result = ExpirationResult::default();
+    let mut excess = Excess::measure(db, policy)?;
+    loop {
+        if !excess.is_over() {
+            result.finished = true;
+            break;
+        }
+        if started_at.elapsed() >= budget {
+            break;
+        }
+        scope.err_if_interrupted()?;
+        let tx = db.coop_transaction()?;
+        let mut expired = (0, 0);
+        if excess.pages > 0 || excess.size {
+            let limit = if excess.pages > 0 {
+                excess.pages.min(EXPIRE_CHUNK_SIZE)
+            } else {
+                EXPIRE_CHUNK_SIZE
+            };
+            expired = expire_pages(db, limit, cutoff)?;
+        }
+        // Expire visits if we're only over the visit limit, or if there
+        // aren't any pages left that we can expire.
+        if expired == (0, 0) && (excess.visits > 0 || excess.size) {
+            let limit = if excess.visits > 0 {
+                excess.visits.min(EXPIRE_CHUNK_SIZE)
+            } else {
+                EXPIRE_CHUNK_SIZE
+            };
+            expired = expire_visits(db, limit, cutoff)?;
+        }
+        tx.commit()?;
+        if expired == (0, 0) {
+            // Everything that's left is either too new to expire, or
+            // bookmarked, so there's nothing more we can do.
+            log::info!("Can't expire any more history, but still over the limits");
+            result.finished = true;
+            break;
+        }
+        result.expired_pages += expired.0;
+        result.expired_visits += expired.1;
+        excess.expired(db, policy, expired)?;
+    }
+    log::debug!("Expired history: {:?}", result);
+    Ok(result)
+}
+
+/// Removes up to `limit` of the oldest, least frecent pages last visited
+/// before `cutoff`, along with their visits. Returns the number of pages and
+/// visits removed.
+fn expire_pages(db: &PlacesDb, limit: u32, cutoff: Timestamp) -> Result<(u32, u32)> {
+    let pages: Vec<(RowId, String)> = db.query_rows_into_cached(
+        "SELECT h.id, h.url FROM moz_places h
+         WHERE h.foreign_count = 0 AND
+               MAX(h.last_visit_date_local, h.last_visit_date_remote) < :cutoff AND
+               NOT EXISTS(SELECT 1 FROM moz_historyvisit_tombstones t
+                          WHERE t.place_id = h.id)
+         -- Uses `lastvisitdateindex`.
+         ORDER BY MAX(h.last_visit_date_local, h.last_visit_date_remote), h.frecency
+         LIMIT :limit",
+        &[(":cutoff", &cutoff), (":limit", &limit)],
+        |row| -> RusqliteResult<_> { Ok((row.get_checked(0)?, row.get_checked(1)?)) },
+    )?;
+    for (_, url) in &pages {
+        db.recent_visits().remove(url);
+    }
+    let page_ids: Vec<RowId> = pages.iter().map(|(id, _)| *id).collect();
+    let mut expired_visits = 0;
+    sql_support::each_chunk(&page_ids, |chunk, _| -> Result<()> {
+        let vars = sql_support::repeat_sql_vars(chunk.len());
+        db.conn().execute(
+            &format!(
+                "UPDATE moz_historyvisits SET from_visit = NULL
+                 WHERE from_visit IN (SELECT id FROM moz_historyvisits
+                                      WHERE place_id IN ({}))",
+                vars
+            ),
+            chunk,
+        )?;
+        expired_visits += db.conn().execute(
+            &format!("DELETE FROM moz_historyvisits WHERE place_id IN ({})", vars),
+            chunk,
+        )? as u32;
+        db.conn().execute(
+            &format!(
+                "DELETE FROM moz_places_stale_frecencies WHERE place_id IN ({})",
+                vars
+            ),
+            chunk,
+        )?;
+        db.conn().execute(
+            &format!(
+                "INSERT OR IGNORE INTO moz_places_tombstones(guid)
+                 SELECT guid FROM moz_places
+                 WHERE id IN ({}) AND sync_status = {}",
+                vars,
+                SyncStatus::Normal as u8
+            ),
+            chunk,
+        )?;
+        db.conn().execute(
+            &format!("DELETE FROM moz_places WHERE id IN ({})", vars),
+            chunk,
+        )?;
+        Ok(())
+    })?;
+    delete_pending_temp_tables(db)?;
+    Ok((page_ids.len() as u32, expired_visits))
+}
+
+/// Removes up to `limit` of the oldest visits before `cutoff`, and any pages
+/// that no longer have visits and can be expired. Returns the number of pages
+/// and visits removed.
+fn expire_visits(db: &PlacesDb, limit: u32, cutoff: Timestamp) -> Result<(u32, u32)> {
+    let visits: Vec<(RowId, RowId, String)> = db.query_rows_into_cached(
+        "SELECT v.id, v.place_id, h.url FROM moz_historyvisits v
+         JOIN moz_places h ON h.id = v.place_id
+         WHERE v.visit_date < :cutoff
+         ORDER BY v.visit_date
+         LIMIT :limit",
+        &[(":cutoff", &cutoff), (":limit", &limit)],
+        |row| -> RusqliteResult<_> {
+            Ok((
+                row.get_checked(0)?,
+                row.get_checked(1)?,
+                row.get_checked(2)?,
+            ))
+        },
+    )?;
+    for (_, _, url) in &visits {
+        db.recent_visits().remove(url);
+    }
+    sql_support::each_chunk_mapped(
+        &visits,
+        |(visit_id, _, _)| visit_id,
+        |chunk, _| -> Result<()> {
+            let vars = sql_support::repeat_sql_vars(chunk.len());
+            db.conn().execute(
+                &format!(
+                    "UPDATE moz_historyvisits SET from_visit = NULL
+                     WHERE from_visit IN ({})",
+                    vars
+                ),
+                chunk,
+            )?;
+            db.conn().execute(
+                &format!("DELETE FROM moz_historyvisits WHERE id IN ({})", vars),
+                chunk,
+            )?;
+            Ok(())
+        },
+    )?;
+
+    let mut page_ids: Vec<RowId> = visits.iter().map(|(_, place_id, _)| *place_id).collect();
+    page_ids.sort();
+    page_ids.dedup();
+    let mut expired_pages = 0;
+    sql_support::each_chunk(&page_ids, |chunk, _| -> Result<()> {
+        let vars = sql_support::repeat_sql_vars(chunk.len());
+        let mut stmt = db.conn().prepare(&format!(
+            "SELECT h.id FROM moz_places h
+             WHERE h.id IN ({}) AND
+                   h.foreign_count = 0 AND
+                   h.last_visit_date_local = 0 AND
+                   h.last_visit_date_remote = 0 AND
+                   NOT EXISTS(SELECT 1 FROM moz_historyvisit_tombstones t
+                              WHERE t.place_id = h.id)",
+            vars
+        ))?;
+        let orphans = stmt
+            .query_and_then(chunk, |row| row.get_checked(0))?
+            .collect::<RusqliteResult<Vec<RowId>>>()?;
+        for page_id in chunk {
+            if !orphans.contains(page_id) {
+                update_frecency(db, *page_id, None)?;
+            }
+        }
+        if orphans.is_empty() {
+            return Ok(());
+        }
+        let orphan_vars = sql_support::repeat_sql_vars(orphans.len());
+        db.conn().execute(
+            &format!(
+                "DELETE FROM moz_places_stale_frecencies WHERE place_id IN ({})",
+                orphan_vars
+            ),
+            &orphans,
+        )?;
+        db.conn().execute(
+            &format!(
+                "INSERT OR IGNORE INTO moz_places_tombstones(guid)
+                 SELECT guid FROM moz_places
+                 WHERE id IN ({}) AND sync_status = {}",
+                orphan_vars,
+                SyncStatus::Normal as u8
+            ),
+            &orphans,
+        )?;
+        expired_pages += db.conn().execute(
+            &format!("DELETE FROM moz_places WHERE id IN ({})", orphan_vars),
+            &orphans,
+        )? as u32;
+        Ok(())
+    })?;
+    delete_pending_temp_tables(db)?;
+    Ok((expired_pages, visits.len() as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_api;
+    use crate::observation::VisitObservation;
+    use crate::storage::bookmarks::{
+        insert_bookmark, BookmarkPosition, BookmarkRootGuid, InsertableBookmark,
+    };
+    use crate::storage::history::apply_observation;
+    use crate::types::{SyncStatus, VisitTransition};
+    use url::Url;
+
+    const BUDGET: Duration = Duration::from_secs(60);
+
+    fn add_visit(db: &PlacesDb, url: &str, days_ago: u64) {
+        apply_observation(
+            db,
+            VisitObservation::new(Url::parse(url).unwrap())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(Timestamp(
+                    Timestamp::now().as_millis() - days_ago * MS_PER_DAY,
+                )),
+        )
+        .expect("Should apply visit");
+    }
+
+    fn urls(db: &PlacesDb) -> Vec<String> {
+        db.query_rows_and_then_named("SELECT url FROM moz_places ORDER BY url", &[], |row| {
+            row.get_checked(0)
+        })
+        .expect("Should fetch urls")
+    }
+
+    #[test]
+    fn test_expire_pages() {
+        let _ = env_logger::try_init();
+        let conn = PlacesDb::open_in_memory(None).expect("no memory db");
+        for i in 0..5 {
+            add_visit(&conn, &format!("https://example.com/old{}", i), 100 - i);
+        }
+        add_visit(&conn, "https://example.com/new", 1);
+        add_visit(&conn, "https://example.com/bookmarked", 200);
+        insert_bookmark(
+            &conn,
+            &InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Unfiled.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url: Url::parse("https://example.com/bookmarked").unwrap(),
+                title: None,
+            }
+            .into(),
+        )
+        .expect("Should insert bookmark");
+        conn.execute_batch(&format!(
+            "UPDATE moz_places SET sync_status = {}",
+            SyncStatus::Normal as u8
+        ))
+        .expect("Should mark pages as synced");
+
+        // Running out of time shouldn't expire anything.
+        let result = expire_history(
+            &conn,
+            &ExpirationPolicy {
+                max_pages: 4,
+                ..ExpirationPolicy::default()
+            },
+            Duration::from_secs(0),
+        )
+        .expect("Should expire history");
+        assert_eq!(result, ExpirationResult::default());
+        assert_eq!(urls(&conn).len(), 7);
+
+        // The oldest pages should be expired first. They've been synced, so
+        // they should have tombstones.
+        let result = expire_history(
+            &conn,
+            &ExpirationPolicy {
+                max_pages: 4,
+                ..ExpirationPolicy::default()
+            },
+            BUDGET,
+        )
+        .expect("Should expire history");
+        assert_eq!(
+            result,
+            ExpirationResult {
+                expired_pages: 3,
+                expired_visits: 3,
+                finished: true,
+            }
+        );
+        assert_eq!(
+            urls(&conn),
+            vec![
+                "https://example.com/bookmarked",
+                "https://example.com/new",
+                "https://example.com/old3",
+                "https://example.com/old4",
+            ]
+        );
+        assert_eq!(
+            conn.query_one::<i64>("SELECT COUNT(*) FROM moz_places_tombstones")
+                .unwrap(),
+            3
+        );
+
+        // Bookmarked and new pages are never expired.
+        let result = expire_history(
+            &conn,
+            &ExpirationPolicy {
+                max_pages: 1,
+                ..ExpirationPolicy::default()
+            },
+            BUDGET,
+        )
+        .expect("Should expire history");
+        assert_eq!(result.expired_pages, 2);
+        assert!(result.finished);
+        assert_eq!(
+            urls(&conn),
+            vec!["https://example.com/bookmarked", "https://example.com/new"]
+        );
+    }
+
+    #[test]
+    fn test_expire_visits() {
+        let _ = env_logger::try_init();
+        let conn = PlacesDb::open_in_memory(None).expect("no memory db");
+        add_visit(&conn, "https://example.com/1", 30);
+        add_visit(&conn, "https://example.com/1", 20);
+        add_visit(&conn, "https://example.com/1", 1);
+        add_visit(&conn, "https://example.com/2", 25);
+        add_visit(&conn, "https://example.com/3", 40);
+        // Pages with unsynced visit tombstones shouldn't be expired.
+        conn.execute_batch(
+            "INSERT INTO moz_historyvisit_tombstones(place_id, visit_date)
+             SELECT id, 1 FROM moz_places WHERE url = 'https://example.com/3'",
+        )
+        .expect("Should insert tombstone");
+
+        let result = expire_history(
+            &conn,
+            &ExpirationPolicy {
+                max_visits: 2,
+                ..ExpirationPolicy::default()
+            },
+            BUDGET,
+        )
+        .expect("Should expire history");
+        assert_eq!(
+            result,
+            ExpirationResult {
+                expired_pages: 1,
+                expired_visits: 3,
+                finished: true,
+            }
+        );
+        // The visits for 3 and 2 were the oldest; 2 is now an orphan, but 3
+        // is kept because of its tombstone.
+        assert_eq!(
+            urls(&conn),
+            vec!["https://example.com/1", "https://example.com/3"]
+        );
+        assert_eq!(
+            conn.query_one::<i64>("SELECT COUNT(*) FROM moz_historyvisits")
+                .unwrap(),
+            2
+        );
+        // 2 was never synced, so it doesn't need a tombstone.
+        assert_eq!(
+            conn.query_one::<i64>("SELECT COUNT(*) FROM moz_places_tombstones")
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_expire_read_only() {
+        let api = new_mem_api();
+        let reader = api
+            .open_connection(ConnectionType::ReadOnly)
+            .expect("should get reader");
+        match expire_history(&reader, &ExpirationPolicy::default(), BUDGET)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::InvalidConnectionType => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+    }
+}
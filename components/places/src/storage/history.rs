@@ -5,12 +5,13 @@
 use super::{fetch_page_info, new_page_info, PageInfo, RowId};
 use crate::db::PlacesDb;
 use crate::error::Result;
-use crate::frecency;
+use crate::frecency::{self, FrecencySettings};
 use crate::hash;
-use crate::msg_types::{HistoryVisitInfo, HistoryVisitInfos};
+use crate::msg_types::{HistoryVisitInfo, HistoryVisitInfos, HistoryVisitInfosWithBound};
 use crate::observation::VisitObservation;
+use crate::observers::{ChangeSource, HistoryEvent};
 use crate::storage::{delete_pending_temp_tables, get_meta, put_meta};
-use crate::types::{SyncGuid, SyncStatus, Timestamp, VisitTransition};
+use crate::types::{SyncGuid, SyncStatus, Timestamp, VisitTransition, VisitTransitionSet};
 use rusqlite::types::ToSql;
 use rusqlite::Result as RusqliteResult;
 use rusqlite::{Row, NO_PARAMS};
@@ -28,20 +29,31 @@ static DELETION_HIGH_WATER_MARK_META_KEY: &str = "history_deleted_hwm";
 /// Returns the RowId of a new visit in moz_historyvisits, or None if no new visit was added.
 pub fn apply_observation(db: &PlacesDb, visit_ob: VisitObservation) -> Result<Option<RowId>> {
     let tx = db.coop_transaction()?;
-    let result = apply_observation_direct(db, visit_ob)?;
+    let (result, events) = apply_observation_in_tx(db, visit_ob)?;
     tx.commit()?;
+    db.observers()
+        .notify_history_observers(ChangeSource::Local, &events);
     Ok(result)
 }
 
 /// Returns the RowId of a new visit in moz_historyvisits, or None if no new visit was added.
+/// Unlike `apply_observation`, this doesn't notify observers, since the
+/// caller manages the transaction.
 pub fn apply_observation_direct(
     db: &PlacesDb,
     visit_ob: VisitObservation,
 ) -> Result<Option<RowId>> {
+    Ok(apply_observation_in_tx(db, visit_ob)?.0)
+}
+
+fn apply_observation_in_tx(
+    db: &PlacesDb,
+    visit_ob: VisitObservation,
+) -> Result<(Option<RowId>, Vec<HistoryEvent>)> {
     let url = Url::parse(&visit_ob.url)?;
     // Don't insert urls larger than our length max.
     if url.as_str().len() > super::URL_LENGTH_MAX {
-        return Ok(None);
+        return Ok((None, Vec::new()));
     }
     let mut page_info = match fetch_page_info(db, &url)? {
         Some(info) => info.page,
@@ -49,16 +61,38 @@ pub fn apply_observation_direct(
     };
     let mut update_change_counter = false;
     let mut update_frec = false;
+    let mut title_changed = false;
+    let mut events = Vec::new();
     let mut updates: Vec<(&str, &str, &ToSql)> = Vec::new();
 
     if let Some(ref title) = visit_ob.title {
-        page_info.title = crate::util::slice_up_to(title, super::TITLE_LENGTH_MAX).into();
+        let title: String = crate::util::slice_up_to(title, super::TITLE_LENGTH_MAX).into();
+        title_changed = title != page_info.title;
+        page_info.title = title;
         updates.push(("title", ":title", &page_info.title));
         update_change_counter = true;
     }
+    let at = visit_ob.at.unwrap_or_else(Timestamp::now);
+    let is_remote = visit_ob.is_remote.unwrap_or(false);
+    // Pages which refresh themselves would otherwise flood history with
+    // visits, so (like desktop) we ignore reloads of recently visited pages.
+    let visit_type = match visit_ob.visit_type {
+        Some(visit_type)
+            if !is_remote
+                && is_reload(&visit_ob, &url, visit_type)
+                && db.recent_visits().is_recent(&url, at) =>
+        {
+            log::debug!("Ignoring reload of a recently visited page");
+            None
+        }
+        visit_type => visit_type,
+    };
+    if visit_ob.visit_type.is_some() && !is_remote {
+        db.recent_visits().add(&url, at);
+    }
     // There's a new visit, so update everything that implies. To help with
     // testing we return the rowid of the visit we added.
-    let visit_row_id = match visit_ob.visit_type {
+    let visit_row_id = match visit_type {
         Some(visit_type) => {
             // A single non-hidden visit makes the place non-hidden.
             if !visit_ob.get_is_hidden() {
@@ -69,9 +103,14 @@ pub fn apply_observation_direct(
                 updates.push(("typed", ":typed", &page_info.typed));
             }
 
-            let at = visit_ob.at.unwrap_or_else(Timestamp::now);
-            let is_remote = visit_ob.is_remote.unwrap_or(false);
             let row_id = add_visit(db, page_info.row_id, None, at, visit_type, !is_remote)?;
+            events.push(HistoryEvent::Visited {
+                guid: page_info.guid.clone(),
+                url: url.clone(),
+                visit_date: at,
+                visit_type,
+                is_local: !is_remote,
+            });
             // a new visit implies new frecency except in error cases.
             if !visit_ob.is_error.unwrap_or(false) {
                 update_frec = true;
@@ -107,36 +146,179 @@ pub fn apply_observation_direct(
         );
         db.execute_named_cached(&sql, &params)?;
     }
+    if title_changed {
+        events.push(HistoryEvent::TitleChanged {
+            guid: page_info.guid.clone(),
+            url: url.clone(),
+            title: page_info.title.clone(),
+        });
+    }
     // This needs to happen after the other updates.
     if update_frec {
-        update_frecency(
+        let frecency = update_frecency(
             &db,
             page_info.row_id,
             Some(visit_ob.get_redirect_frecency_boost()),
         )?;
+        if let Some(frecency) = frecency {
+            events.push(HistoryEvent::FrecencyChanged {
+                guid: page_info.guid.clone(),
+                url,
+                frecency,
+            });
+        }
     }
-    Ok(visit_row_id)
+    Ok((visit_row_id, events))
 }
 
-pub fn update_frecency(db: &PlacesDb, id: RowId, redirect_boost: Option<bool>) -> Result<()> {
+// Either an explicit reload, or a page which refreshed itself by following a
+// link to itself. Other visits, like typing the url again or opening it from a
+// bookmark, are always recorded, even if the page is its own referrer.
+fn is_reload(visit_ob: &VisitObservation, url: &Url, visit_type: VisitTransition) -> bool {
+    match visit_type {
+        VisitTransition::Reload => true,
+        VisitTransition::Link => {
+            visit_ob.referrer.as_ref().map(String::as_str) == Some(url.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Recalculates the frecency of a page. Returns the new frecency if it
+/// changed.
+pub fn update_frecency(
+    db: &PlacesDb,
+    id: RowId,
+    redirect_boost: Option<bool>,
+) -> Result<Option<i32>> {
     let score = frecency::calculate_frecency(
         db.conn(),
-        &frecency::DEFAULT_FRECENCY_SETTINGS,
+        &db.frecency_settings(),
         id.0, // TODO: calculate_frecency should take a RowId here.
         redirect_boost,
     )?;
 
-    db.execute_named(
+    let changed = db.execute_named(
         "
         UPDATE moz_places
             SET frecency = :frecency
-        WHERE id = :page_id",
+        WHERE id = :page_id AND
+              frecency <> :frecency",
         &[(":frecency", &score), (":page_id", &id.0)],
     )?;
 
+    Ok(if changed > 0 { Some(score) } else { None })
+}
+
+const FRECENCY_SETTINGS_META_KEY: &str = "frecency_settings";
+
+/// Fetches the frecency settings stored in the database, or the default
+/// settings if none were stored.
+pub(crate) fn get_frecency_settings(db: &PlacesDb) -> Result<FrecencySettings> {
+    let json = match get_meta::<String>(db, FRECENCY_SETTINGS_META_KEY)? {
+        Some(json) => json,
+        None => return Ok(FrecencySettings::default()),
+    };
+    Ok(serde_json::from_str(&json).unwrap_or_else(|e| {
+        log::warn!(
+            "Failed to parse stored frecency settings, using defaults: {}",
+            e
+        );
+        FrecencySettings::default()
+    }))
+}
+
+/// Stores new frecency settings for the database, and marks the frecencies
+/// of all pages as stale if they changed. The stale frecencies are then
+/// recalculated by `recalculate_stale_frecencies`.
+pub(crate) fn set_frecency_settings(db: &PlacesDb, settings: &FrecencySettings) -> Result<()> {
+    if *db.frecency_settings() == *settings {
+        return Ok(());
+    }
+    let tx = db.coop_transaction()?;
+    put_meta(
+        db,
+        FRECENCY_SETTINGS_META_KEY,
+        &serde_json::to_string(settings)?,
+    )?;
+    db.execute_named_cached(
+        "INSERT OR IGNORE INTO moz_places_stale_frecencies(place_id, stale_at)
+         SELECT id, :now FROM moz_places
+         WHERE frecency <> 0",
+        &[(":now", &Timestamp::now())],
+    )?;
+    tx.commit()?;
+    db.replace_frecency_settings(settings.clone());
     Ok(())
 }
 
+/// The number of frecencies to recalculate in each transaction.
+const RECALCULATE_FRECENCIES_CHUNK_SIZE: usize = 100;
+
+/// Recalculates frecencies for up to `limit` pages whose frecencies were
+/// marked as stale, oldest first, stopping early once `deadline` passes.
+/// This is meant to be called repeatedly while the app is idle, and returns
+/// the number of stale pages that remain.
+///
+/// Progress is committed in chunks, so an interrupted or failed call only
+/// loses the work done since the last chunk. Observers are notified about
+/// each chunk as soon as it's committed.
+pub fn recalculate_stale_frecencies(db: &PlacesDb, limit: u32, deadline: Timestamp) -> Result<u32> {
+    let scope = db.begin_interrupt_scope();
+    let mut tx = db.chunked_coop_transaction(RECALCULATE_FRECENCIES_CHUNK_SIZE)?;
+    let stale: Vec<(RowId, Timestamp, SyncGuid, String)> = db.query_rows_into_cached(
+        "SELECT s.place_id, s.stale_at, h.guid, h.url
+         FROM moz_places_stale_frecencies s
+         JOIN moz_places h ON h.id = s.place_id
+         ORDER BY s.stale_at
+         LIMIT :limit",
+        &[(":limit", &limit)],
+        |row| -> RusqliteResult<_> {
+            Ok((
+                row.get_checked(0)?,
+                row.get_checked(1)?,
+                row.get_checked(2)?,
+                row.get_checked(3)?,
+            ))
+        },
+    )?;
+    let mut events = Vec::new();
+    for (place_id, stale_at, guid, url) in stale {
+        if Timestamp::now() >= deadline {
+            break;
+        }
+        scope.err_if_interrupted()?;
+        if let Some(frecency) = update_frecency(db, place_id, None)? {
+            events.push(HistoryEvent::FrecencyChanged {
+                guid,
+                url: Url::parse(&url)?,
+                frecency,
+            });
+        }
+        // Sync might have marked the frecency as stale again after we
+        // committed an earlier chunk, in which case we leave it for the next
+        // call.
+        db.execute_named_cached(
+            "DELETE FROM moz_places_stale_frecencies
+             WHERE place_id = :place_id AND
+                   stale_at <= :stale_at",
+            &[(":place_id", &place_id), (":stale_at", &stale_at)],
+        )?;
+        // Report each chunk as soon as it's committed, so that observers
+        // still hear about them if we're interrupted later.
+        if tx.maybe_commit()? {
+            db.observers()
+                .notify_history_observers(ChangeSource::Local, &events);
+            events.clear();
+        }
+    }
+    tx.commit()?;
+    db.observers()
+        .notify_history_observers(ChangeSource::Local, &events);
+    let remaining = db.query_one::<i64>("SELECT COUNT(*) FROM moz_places_stale_frecencies")?;
+    Ok(remaining as u32)
+}
+
 /// Indicates if and when a URL's frecency was marked as stale.
 pub fn frecency_stale_at(db: &PlacesDb, url: &Url) -> Result<Option<Timestamp>> {
     let result = db.try_query_row(
@@ -154,7 +336,7 @@ pub fn frecency_stale_at(db: &PlacesDb, url: &Url) -> Result<Option<Timestamp>>
 // Add a single visit - you must know the page rowid. Does not update the
 // page info - if you are calling this, you will also need to update the
 // parent page with an updated change counter etc.
-fn add_visit(
+pub(crate) fn add_visit(
     db: &PlacesDb,
     page_id: RowId,
     from_visit: Option<RowId>,
@@ -201,8 +383,15 @@ pub fn url_to_guid(db: &PlacesDb, url: &Url) -> Result<Option<SyncGuid>> {
 }
 
 /// Internal function for deleting a place, creating a tombstone if necessary.
-/// Assumes a transaction is already set up by the caller.
-fn do_delete_place_by_guid(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
+/// Assumes a transaction is already set up by the caller. Returns an event
+/// for the deleted place, or None if it didn't exist.
+fn do_delete_place_by_guid(db: &PlacesDb, guid: &SyncGuid) -> Result<Option<HistoryEvent>> {
+    let url = db.try_query_row(
+        "SELECT url FROM moz_places WHERE guid = :guid",
+        &[(":guid", guid)],
+        |row| -> Result<_> { Ok(Url::parse(&row.get_checked::<_, String>(0)?)?) },
+        true,
+    )?;
     // We only create tombstones for history which exists and with sync_status
     // == SyncStatus::Normal
     let sql = "INSERT OR IGNORE INTO moz_places_tombstones (guid)
@@ -213,7 +402,13 @@ fn do_delete_place_by_guid(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
     let delete_sql = "DELETE FROM moz_places WHERE guid = :guid";
     db.execute_named_cached(delete_sql, &[(":guid", guid)])?;
     delete_pending_temp_tables(db)?;
-    Ok(())
+    if let Some(url) = &url {
+        db.recent_visits().remove(url.as_str());
+    }
+    Ok(url.map(|url| HistoryEvent::Deleted {
+        guid: guid.clone(),
+        url,
+    }))
 }
 
 /// Delete a place given its guid, creating a tombstone if necessary.
@@ -221,21 +416,29 @@ pub fn delete_place_by_guid(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
     let tx = db.coop_transaction()?;
     let result = do_delete_place_by_guid(db, guid);
     tx.commit()?;
-    result
+    if let Some(event) = result? {
+        db.observers()
+            .notify_history_observers(ChangeSource::Local, &[event]);
+    }
+    Ok(())
 }
 
 /// Delete all visits in a date range.
 pub fn delete_visits_between(db: &PlacesDb, start: Timestamp, end: Timestamp) -> Result<()> {
     let tx = db.coop_transaction()?;
-    delete_visits_between_in_tx(db, start, end)?;
+    let events = delete_visits_between_in_tx(db, start, end)?;
     tx.commit()?;
+    db.observers()
+        .notify_history_observers(ChangeSource::Local, &events);
     Ok(())
 }
 
 pub fn delete_place_visit_at_time(db: &PlacesDb, place: &Url, visit: Timestamp) -> Result<()> {
     let tx = db.coop_transaction()?;
-    delete_place_visit_at_time_in_tx(db, place.as_str(), visit)?;
+    let events = delete_place_visit_at_time_in_tx(db, place.as_str(), visit)?;
     tx.commit()?;
+    db.observers()
+        .notify_history_observers(ChangeSource::Local, &events);
     Ok(())
 }
 
@@ -251,7 +454,7 @@ pub fn wipe_local(db: &PlacesDb) -> Result<()> {
 }
 
 fn wipe_local_in_tx(db: &PlacesDb, tx: sql_support::UncheckedTransaction) -> Result<()> {
-    use crate::frecency::DEFAULT_FRECENCY_SETTINGS;
+    db.recent_visits().clear();
     db.execute_all(&[
         "DELETE FROM moz_places WHERE foreign_count == 0",
         "DELETE FROM moz_historyvisits",
@@ -264,7 +467,7 @@ fn wipe_local_in_tx(db: &PlacesDb, tx: sql_support::UncheckedTransaction) -> Res
             "UPDATE moz_places SET
                 frecency = {unvisited_bookmark_frec},
                 sync_change_counter = 0",
-            unvisited_bookmark_frec = DEFAULT_FRECENCY_SETTINGS.unvisited_bookmark_bonus
+            unvisited_bookmark_frec = db.frecency_settings().unvisited_bookmark_bonus
         ),
     ])?;
 
@@ -306,7 +509,11 @@ pub fn delete_everything(db: &PlacesDb) -> Result<()> {
     Ok(())
 }
 
-fn delete_place_visit_at_time_in_tx(db: &PlacesDb, url: &str, visit_date: Timestamp) -> Result<()> {
+fn delete_place_visit_at_time_in_tx(
+    db: &PlacesDb,
+    url: &str,
+    visit_date: Timestamp,
+) -> Result<Vec<HistoryEvent>> {
     let place = db.conn().try_query_row(
         "SELECT h.id
          FROM moz_places h
@@ -325,8 +532,9 @@ fn delete_place_visit_at_time_in_tx(db: &PlacesDb, url: &str, visit_date: Timest
         id
     } else {
         // No such visit, nothing to do.
-        return Ok(());
+        return Ok(Vec::new());
     };
+    db.recent_visits().remove(url);
 
     db.conn().execute_named_cached(
         "INSERT OR IGNORE INTO moz_historyvisit_tombstones(place_id, visit_date)
@@ -343,7 +551,7 @@ fn delete_place_visit_at_time_in_tx(db: &PlacesDb, url: &str, visit_date: Timest
 
     let to_clean = db.conn().query_row_and_then_named(
         "SELECT
-            id,
+            id, guid, url,
             (foreign_count != 0) AS has_foreign,
             ((last_visit_date_local + last_visit_date_remote) != 0) as has_visits
         FROM moz_places
@@ -353,12 +561,16 @@ fn delete_place_visit_at_time_in_tx(db: &PlacesDb, url: &str, visit_date: Timest
         true,
     )?;
 
-    cleanup_pages(db, &[to_clean])?;
+    let events = cleanup_pages(db, &[to_clean])?;
     delete_pending_temp_tables(db)?;
-    Ok(())
+    Ok(events)
 }
 
-pub fn delete_visits_between_in_tx(db: &PlacesDb, start: Timestamp, end: Timestamp) -> Result<()> {
+pub fn delete_visits_between_in_tx(
+    db: &PlacesDb,
+    start: Timestamp,
+    end: Timestamp,
+) -> Result<Vec<HistoryEvent>> {
     // Like desktop's removeVisitsByFilter, we query the visit and place ids
     // affected, then delete all visits, then delete all place ids in the set
     // which are orphans after the delete.
@@ -408,12 +620,13 @@ pub fn delete_visits_between_in_tx(db: &PlacesDb, start: Timestamp, end: Timesta
     }
 
     // Find out which pages have been possibly orphaned and clean them up.
+    let mut events = Vec::new();
     sql_support::each_chunk_mapped(
         &visits,
         |(_, place_id, _)| place_id.0,
         |chunk, _| -> Result<()> {
             let query = format!(
-                "SELECT id, -- url, url_hash, guid
+                "SELECT id, guid, url,
                     (foreign_count != 0) AS has_foreign,
                     ((last_visit_date_local + last_visit_date_remote) != 0) as has_visits
                 FROM moz_places
@@ -424,16 +637,22 @@ pub fn delete_visits_between_in_tx(db: &PlacesDb, start: Timestamp, end: Timesta
             let mut stmt = db.conn().prepare(&query)?;
             let page_results = stmt.query_and_then(chunk, PageToClean::from_row)?;
             let pages: Vec<PageToClean> = page_results.collect::<Result<_>>()?;
-            cleanup_pages(db, &pages)
+            for page in &pages {
+                db.recent_visits().remove(page.url.as_str());
+            }
+            events.extend(cleanup_pages(db, &pages)?);
+            Ok(())
         },
     )?;
     delete_pending_temp_tables(db)?;
-    Ok(())
+    Ok(events)
 }
 
 #[derive(Debug)]
 struct PageToClean {
     id: RowId,
+    guid: SyncGuid,
+    url: Url,
     has_foreign: bool,
     has_visits: bool,
 }
@@ -442,6 +661,8 @@ impl PageToClean {
     pub fn from_row(row: &Row) -> Result<Self> {
         Ok(Self {
             id: row.get_checked("id")?,
+            guid: row.get_checked("guid")?,
+            url: Url::parse(&row.get_checked::<_, String>("url")?)?,
             has_foreign: row.get_checked("has_foreign")?,
             has_visits: row.get_checked("has_visits")?,
         })
@@ -452,17 +673,21 @@ impl PageToClean {
 /// removing them entirely (if they are marked for removal,
 /// typically because all visits have been removed and there
 /// are no more foreign keys such as bookmarks) or updating
-/// their frecency.
-fn cleanup_pages(db: &PlacesDb, pages: &[PageToClean]) -> Result<()> {
+/// their frecency. Returns events for the changed and removed pages.
+fn cleanup_pages(db: &PlacesDb, pages: &[PageToClean]) -> Result<Vec<HistoryEvent>> {
+    let mut events = Vec::new();
     // desktop does this frecency work using a function in a single sql
     // statement - we should see if we can do that too.
-    let frec_ids = pages
-        .iter()
-        .filter(|&p| p.has_foreign || p.has_visits)
-        .map(|p| p.id);
-
-    for id in frec_ids {
-        update_frecency(db, id, None)?;
+    let frec_pages = pages.iter().filter(|&p| p.has_foreign || p.has_visits);
+
+    for page in frec_pages {
+        if let Some(frecency) = update_frecency(db, page.id, None)? {
+            events.push(HistoryEvent::FrecencyChanged {
+                guid: page.guid.clone(),
+                url: page.url.clone(),
+                frecency,
+            });
+        }
     }
 
     // Like desktop, we do "AND foreign_count = 0 AND last_visit_date ISNULL"
@@ -470,11 +695,11 @@ fn cleanup_pages(db: &PlacesDb, pages: &[PageToClean]) -> Result<()> {
     // case, it reads the pages before starting a write transaction, so that
     // probably is possible. We don't currently do that, but might later, so
     // we do it anyway.
-    let remove_ids: Vec<RowId> = pages
+    let remove_pages: Vec<&PageToClean> = pages
         .iter()
         .filter(|p| !p.has_foreign && !p.has_visits)
-        .map(|p| p.id)
         .collect();
+    let remove_ids: Vec<RowId> = remove_pages.iter().map(|p| p.id).collect();
     sql_support::each_chunk(&remove_ids, |chunk, _| -> Result<()> {
         // tombstones first.
         db.conn().execute(
@@ -509,7 +734,11 @@ fn cleanup_pages(db: &PlacesDb, pages: &[PageToClean]) -> Result<()> {
     // desktop now updates moz_updateoriginsdelete_temp, icons, annos, etc
     // some of which might end up making sense for us too.
     // XXX - moz_updateoriginsdelete_temp is part of https://github.com/mozilla/application-services/pull/429
-    Ok(())
+    events.extend(remove_pages.into_iter().map(|p| HistoryEvent::Deleted {
+        guid: p.guid.clone(),
+        url: p.url.clone(),
+    }));
+    Ok(events)
 }
 
 // Support for Sync - in its own module to try and keep a delineation
@@ -610,7 +839,7 @@ pub mod history_sync {
         url: &Url,
         title: &Option<String>,
         visits: &[HistoryRecordVisit],
-    ) -> Result<()> {
+    ) -> Result<Vec<HistoryEvent>> {
         // At some point we may have done a local wipe of all visits. We skip applying
         // incoming visits that could have been part of that deletion, to avoid them
         // trickling back in.
@@ -649,12 +878,13 @@ pub mod history_sync {
                 // Before we insert a new page_info, make sure we actually will
                 // have any visits to add.
                 if visits.is_empty() {
-                    return Ok(());
+                    return Ok(Vec::new());
                 }
                 new_page_info(db, &url, Some(incoming_guid.clone()))?
             }
         };
 
+        let mut events = Vec::new();
         if !visits.is_empty() {
             // Skip visits that are in tombstones, or that happen at the same time
             // as visit that's already present. The 2nd lets us avoid inserting
@@ -696,6 +926,13 @@ pub mod history_sync {
                 let transition = VisitTransition::from_primitive(visit.transition)
                     .expect("these should already be validated");
                 add_visit(db, page_info.row_id, None, timestamp, transition, false)?;
+                events.push(HistoryEvent::Visited {
+                    guid: page_info.guid.clone(),
+                    url: url.clone(),
+                    visit_date: timestamp,
+                    visit_type: transition,
+                    is_local: false,
+                });
                 // Make sure that even if a history entry weirdly has the same visit
                 // twice, we don't insert it twice. (This avoids us needing to
                 // recompute visits_to_skip in each step of the iteration)
@@ -704,10 +941,24 @@ pub mod history_sync {
         }
         // XXX - we really need a better story for frecency-boost than
         // Option<bool> - None vs Some(false) is confusing. We should use an enum.
-        update_frecency(&db, page_info.row_id, None)?;
+        let frecency = update_frecency(&db, page_info.row_id, None)?;
 
         // and the place itself if necessary.
         let new_title = title.as_ref().unwrap_or(&page_info.title);
+        if new_title != &page_info.title {
+            events.push(HistoryEvent::TitleChanged {
+                guid: page_info.guid.clone(),
+                url: url.clone(),
+                title: new_title.clone(),
+            });
+        }
+        if let Some(frecency) = frecency {
+            events.push(HistoryEvent::FrecencyChanged {
+                guid: page_info.guid.clone(),
+                url: url.clone(),
+                frecency,
+            });
+        }
         // We set the Status to Normal, otherwise we will re-upload it as
         // outgoing even if nothing has changed. Note that we *do not* reset
         // the change counter - if it is non-zero now, we want it to remain
@@ -729,7 +980,7 @@ pub mod history_sync {
             ],
         )?;
 
-        Ok(())
+        Ok(events)
     }
 
     pub fn apply_synced_reconciliation(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
@@ -743,14 +994,23 @@ pub mod history_sync {
         Ok(())
     }
 
-    pub fn apply_synced_deletion(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
+    pub fn apply_synced_deletion(db: &PlacesDb, guid: &SyncGuid) -> Result<Option<HistoryEvent>> {
         // Note that we don't use delete_place_by_guid because we do not want
         // a local tombstone for this item.
+        let url = db.try_query_row(
+            "SELECT url FROM moz_places WHERE guid = :guid",
+            &[(":guid", guid)],
+            |row| -> Result<_> { Ok(Url::parse(&row.get_checked::<_, String>(0)?)?) },
+            true,
+        )?;
         db.execute_named_cached(
             "DELETE FROM moz_places WHERE guid = :guid",
             &[(":guid", guid)],
         )?;
-        Ok(())
+        Ok(url.map(|url| HistoryEvent::Deleted {
+            guid: guid.clone(),
+            url,
+        }))
     }
 
     #[derive(Debug)]
@@ -1057,6 +1317,61 @@ pub fn get_visit_infos(
     Ok(HistoryVisitInfos { infos })
 }
 
+/// Returns up to `count` visits, newest first, that happened at or before
+/// `bound`, skipping the first `offset` of them, and the types of which
+/// aren't in `excluded_types`. To fetch the next page, pass the returned
+/// `bound` and `offset`. Unlike `get_visit_infos`, this can be used to page
+/// through all of history without loading it into memory at once.
+pub fn get_visit_page(
+    db: &PlacesDb,
+    bound: Timestamp,
+    offset: i64,
+    count: i64,
+    excluded_types: VisitTransitionSet,
+) -> Result<HistoryVisitInfosWithBound> {
+    let infos = db.query_rows_and_then_named_cached(
+        "SELECT h.url, h.title, v.visit_date, v.visit_type
+         FROM moz_places h
+         JOIN moz_historyvisits v
+           ON h.id = v.place_id
+         WHERE v.visit_date <= :bound AND
+               ((1 << v.visit_type) & :excluded_types) = 0
+         ORDER BY v.visit_date DESC, v.id DESC
+         LIMIT :count
+         OFFSET :offset",
+        &[
+            (":bound", &bound),
+            (":excluded_types", &excluded_types),
+            (":count", &count),
+            (":offset", &offset),
+        ],
+        HistoryVisitInfo::from_row,
+    )?;
+    // The next page starts after the last visit we returned. Several visits
+    // can happen at the same time, so we also need to skip the ones at that
+    // time that we've already returned.
+    let (bound, offset) = match infos.last() {
+        Some(last) => {
+            let same_time = infos
+                .iter()
+                .rev()
+                .take_while(|info| info.timestamp == last.timestamp)
+                .count() as i64;
+            if last.timestamp == bound.0 as i64 {
+                (last.timestamp, offset + same_time)
+            } else {
+                (last.timestamp, same_time)
+            }
+        }
+        None => (bound.0 as i64, offset),
+    };
+    Ok(HistoryVisitInfosWithBound {
+        infos,
+        bound,
+        offset,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::history_sync::*;
@@ -1184,6 +1499,141 @@ mod tests {
             .expect("should have got a value")
     }
 
+    #[test]
+    fn test_recent_reloads_ignored() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = PlacesDb::open_in_memory(None)?;
+        let url = Url::parse("https://www.example.com/refreshes").unwrap();
+        let start = Timestamp::now();
+        let after = |secs: u64| Timestamp(start.0 + secs * 1000);
+        let visit_count = || {
+            fetch_page_info(&conn, &url)
+                .expect("should work")
+                .expect("should have the page")
+                .page
+                .visit_count_local
+        };
+
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(start),
+        )?
+        .expect("first visit should be recorded");
+
+        // An explicit reload a minute later is ignored, but a title change
+        // in the same observation is still applied.
+        let rid = apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Reload)
+                .with_title("new title".to_string())
+                .with_at(after(60)),
+        )?;
+        assert!(rid.is_none(), "reload should be ignored");
+        assert_eq!(visit_count(), 1);
+        let page = fetch_page_info(&conn, &url)?.unwrap().page;
+        assert_eq!(page.title, "new title");
+
+        // Same for a page which is its own referrer.
+        let rid = apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Link)
+                .with_referrer(url.clone())
+                .with_at(after(120)),
+        )?;
+        assert!(rid.is_none(), "self-referral should be ignored");
+        assert_eq!(visit_count(), 1);
+
+        // A normal visit is still recorded, even if the page is its own
+        // referrer.
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Typed)
+                .with_referrer(url.clone())
+                .with_at(after(180)),
+        )?
+        .expect("typed visit should be recorded");
+        assert_eq!(visit_count(), 2);
+
+        // As are remote visits.
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Reload)
+                .with_is_remote(true)
+                .with_at(after(190)),
+        )?
+        .expect("remote visit should be recorded");
+
+        // The window slides with each visit, so we need to wait 6 minutes
+        // from the last one before a reload counts.
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Reload)
+                .with_at(after(180 + 7 * 60)),
+        )?
+        .expect("reload outside the window should be recorded");
+        assert_eq!(visit_count(), 3);
+
+        // Disabling the cache means reloads are always recorded.
+        conn.recent_visits().set_window(Duration::from_secs(0));
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Reload)
+                .with_at(after(180 + 7 * 60 + 1)),
+        )?
+        .expect("reload should be recorded with no window");
+        assert_eq!(visit_count(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent_visits_cleared_by_delete() -> Result<()> {
+        let _ = env_logger::try_init();
+        let conn = PlacesDb::open_in_memory(None)?;
+        let url = Url::parse("https://www.example.com/").unwrap();
+        let now = Timestamp::now();
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(now),
+        )?
+        .expect("should be recorded");
+        let other_url = Url::parse("https://www.example.com/other").unwrap();
+        let other_at = Timestamp(now.0 - 1000);
+        apply_observation(
+            &conn,
+            VisitObservation::new(other_url.clone())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(other_at),
+        )?
+        .expect("should be recorded");
+        assert!(conn.recent_visits().is_recent(&url, now));
+        delete_place_visit_at_time(&conn, &url, now)?;
+        assert!(!conn.recent_visits().is_recent(&url, now));
+        // Only the deleted page should be forgotten.
+        assert!(conn.recent_visits().is_recent(&other_url, now));
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Reload)
+                .with_at(now),
+        )?
+        .expect("reload after delete should be recorded");
+
+        delete_visits_between(&conn, other_at, other_at)?;
+        assert!(!conn.recent_visits().is_recent(&other_url, now));
+        assert!(conn.recent_visits().is_recent(&url, now));
+        Ok(())
+    }
+
     #[test]
     fn test_visit_counts() -> Result<()> {
         let _ = env_logger::try_init();
@@ -2108,4 +2558,311 @@ mod tests {
         assert_eq!(db_title.len(), crate::storage::TITLE_LENGTH_MAX);
         assert!(title.starts_with(&db_title));
     }
+
+    #[test]
+    fn test_get_visit_page() {
+        let _ = env_logger::try_init();
+        let conn = PlacesDb::open_in_memory(None).expect("no memory db");
+        let now = Timestamp::now().as_millis();
+        let visits = [
+            (
+                "https://www.example.com/1",
+                now - 3000,
+                VisitTransition::Link,
+            ),
+            (
+                "https://www.example.com/2",
+                now - 2000,
+                VisitTransition::Link,
+            ),
+            (
+                "https://www.example.com/3",
+                now - 2000,
+                VisitTransition::Typed,
+            ),
+            (
+                "https://www.example.com/4",
+                now - 2000,
+                VisitTransition::Link,
+            ),
+            (
+                "https://www.example.com/5",
+                now - 1000,
+                VisitTransition::Reload,
+            ),
+            ("https://www.example.com/6", now, VisitTransition::Link),
+        ];
+        for (url, at, visit_type) in &visits {
+            apply_observation(
+                &conn,
+                VisitObservation::new(Url::parse(url).unwrap())
+                    .with_title(format!("Title {}", url))
+                    .with_visit_type(*visit_type)
+                    .with_at(Timestamp(*at)),
+            )
+            .expect("Should apply visit");
+        }
+        let excluded: VisitTransitionSet = vec![VisitTransition::Reload].into_iter().collect();
+
+        let mut bound = Timestamp(now);
+        let mut offset = 0;
+        let mut pages = Vec::new();
+        loop {
+            let page = get_visit_page(&conn, bound, offset, 2, excluded).expect("Should get page");
+            if page.infos.is_empty() {
+                break;
+            }
+            pages.push(
+                page.infos
+                    .iter()
+                    .map(|info| info.url.clone())
+                    .collect::<Vec<_>>(),
+            );
+            bound = Timestamp(page.bound as u64);
+            offset = page.offset;
+        }
+        // Visits at the same time are returned newest first, and are split
+        // across pages without repeating or skipping any.
+        assert_eq!(
+            pages,
+            vec![
+                vec!["https://www.example.com/6", "https://www.example.com/4"],
+                vec!["https://www.example.com/3", "https://www.example.com/2"],
+                vec!["https://www.example.com/1"],
+            ]
+        );
+
+        let page = get_visit_page(&conn, Timestamp(now), 0, 1, VisitTransitionSet::empty())
+            .expect("Should get page");
+        assert_eq!(
+            page.infos[0].title,
+            Some("Title https://www.example.com/6".to_string())
+        );
+    }
+
+    #[test]
+    fn test_recalculate_stale_frecencies() {
+        let _ = env_logger::try_init();
+        let conn = PlacesDb::open_in_memory(None).expect("no memory db");
+        let urls = [
+            "https://www.example.com/1",
+            "https://www.example.com/2",
+            "https://www.example.com/3",
+        ];
+        for (i, &url) in urls.iter().enumerate() {
+            apply_observation(
+                &conn,
+                VisitObservation::new(Url::parse(url).unwrap())
+                    .with_visit_type(VisitTransition::Link)
+                    .with_at(Timestamp::now()),
+            )
+            .expect("Should apply visit");
+            conn.execute_named(
+                "UPDATE moz_places SET frecency = 0 WHERE url_hash = hash(:url) AND url = :url",
+                &[(":url", &url)],
+            )
+            .expect("Should reset frecency");
+            conn.execute_named(
+                "INSERT INTO moz_places_stale_frecencies(place_id, stale_at)
+                 SELECT id, :stale_at FROM moz_places
+                 WHERE url_hash = hash(:url) AND url = :url",
+                &[(":url", &url), (":stale_at", &(i as i64 + 1))],
+            )
+            .expect("Should mark frecency as stale");
+        }
+        let get_frecency = |url: &str| -> i64 {
+            conn.query_row_and_then_named(
+                "SELECT frecency FROM moz_places WHERE url_hash = hash(:url) AND url = :url",
+                &[(":url", &url)],
+                |row| row.get_checked(0),
+                false,
+            )
+            .expect("Should fetch frecency")
+        };
+        let far_future = Timestamp(Timestamp::now().as_millis() + 60_000);
+
+        // A deadline that already passed shouldn't recalculate anything.
+        assert_eq!(
+            recalculate_stale_frecencies(&conn, 10, Timestamp(0)).expect("Should return remaining"),
+            3
+        );
+        assert_eq!(get_frecency(urls[0]), 0);
+
+        // The oldest stale frecencies should be recalculated first.
+        assert_eq!(
+            recalculate_stale_frecencies(&conn, 2, far_future).expect("Should return remaining"),
+            1
+        );
+        assert!(get_frecency(urls[0]) > 0);
+        assert!(get_frecency(urls[1]) > 0);
+        assert_eq!(get_frecency(urls[2]), 0);
+        let url = Url::parse(urls[2]).unwrap();
+        assert!(frecency_stale_at(&conn, &url).unwrap().is_some());
+
+        assert_eq!(
+            recalculate_stale_frecencies(&conn, 10, far_future).expect("Should return remaining"),
+            0
+        );
+        assert!(get_frecency(urls[2]) > 0);
+        assert!(frecency_stale_at(&conn, &url).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_recalculate_stale_frecencies_read_only() {
+        use crate::api::places_api::{test::new_mem_api, ConnectionType};
+        use crate::error::ErrorKind;
+
+        let api = new_mem_api();
+        let reader = api
+            .open_connection(ConnectionType::ReadOnly)
+            .expect("should get reader");
+        let far_future = Timestamp(Timestamp::now().as_millis() + 60_000);
+        match recalculate_stale_frecencies(&reader, 10, far_future)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::InvalidConnectionType => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_recalculate_stale_frecencies_interrupted() -> Result<()> {
+        use crate::api::places_api::{test::new_mem_api, ConnectionType};
+        use crate::error::ErrorKind;
+        use crate::observers::HistoryObserver;
+        use std::sync::{Arc, Mutex};
+
+        let _ = env_logger::try_init();
+        let api = new_mem_api();
+        let conn = api.open_connection(ConnectionType::ReadWrite)?;
+        let count = RECALCULATE_FRECENCIES_CHUNK_SIZE + 10;
+        for i in 0..count {
+            apply_observation(
+                &conn,
+                VisitObservation::new(Url::parse(&format!("https://www.example.com/{}", i))?)
+                    .with_visit_type(VisitTransition::Link)
+                    .with_at(Timestamp::now()),
+            )?;
+        }
+        conn.execute_all(&[
+            "UPDATE moz_places SET frecency = 0",
+            "INSERT INTO moz_places_stale_frecencies(place_id, stale_at)
+             SELECT id, 1 FROM moz_places",
+        ])?;
+
+        // Interrupt the recalculation once the first chunk is reported.
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observer: Arc<dyn HistoryObserver> = {
+            let events = events.clone();
+            let handle = conn.new_interrupt_handle();
+            Arc::new(move |_: ChangeSource, new_events: &[HistoryEvent]| {
+                events.lock().unwrap().extend_from_slice(new_events);
+                handle.interrupt();
+            })
+        };
+        api.register_history_observer(observer);
+
+        let far_future = Timestamp(Timestamp::now().as_millis() + 60_000);
+        match recalculate_stale_frecencies(&conn, count as u32, far_future)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::InterruptedError => {}
+            e => panic!("Wrong error: {:?}", e),
+        }
+        // The first chunk was committed, so its changes should be reported.
+        assert_eq!(
+            events.lock().unwrap().len(),
+            RECALCULATE_FRECENCIES_CHUNK_SIZE
+        );
+        assert_eq!(
+            conn.query_one::<i64>("SELECT COUNT(*) FROM moz_places_stale_frecencies")?,
+            10
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_observers() -> Result<()> {
+        use crate::api::places_api::{test::new_mem_api, ConnectionType};
+        use crate::observers::HistoryObserver;
+        use std::sync::{Arc, Mutex};
+
+        let _ = env_logger::try_init();
+        let api = new_mem_api();
+        let conn = api.open_connection(ConnectionType::ReadWrite)?;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observer: Arc<dyn HistoryObserver> = {
+            let events = events.clone();
+            Arc::new(move |source: ChangeSource, new_events: &[HistoryEvent]| {
+                assert_eq!(source, ChangeSource::Local);
+                events.lock().unwrap().extend_from_slice(new_events);
+            })
+        };
+        let id = api.register_history_observer(observer);
+
+        let url = Url::parse("https://www.example.com/")?;
+        let now = Timestamp::now();
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone())
+                .with_title("the title".to_string())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(now),
+        )?;
+        let guid = url_to_guid(&conn, &url)?.expect("should exist");
+        {
+            let mut events = events.lock().unwrap();
+            assert_eq!(events.len(), 3);
+            assert_eq!(
+                events[0],
+                HistoryEvent::Visited {
+                    guid: guid.clone(),
+                    url: url.clone(),
+                    visit_date: now,
+                    visit_type: VisitTransition::Link,
+                    is_local: true,
+                }
+            );
+            assert_eq!(
+                events[1],
+                HistoryEvent::TitleChanged {
+                    guid: guid.clone(),
+                    url: url.clone(),
+                    title: "the title".into(),
+                }
+            );
+            match &events[2] {
+                HistoryEvent::FrecencyChanged { guid: changed, .. } => assert_eq!(changed, &guid),
+                event => panic!("Unexpected event {:?}", event),
+            }
+            events.clear();
+        }
+
+        // Deleting the only visit should remove the page.
+        delete_place_visit_at_time(&conn, &url, now)?;
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![HistoryEvent::Deleted {
+                guid: guid.clone(),
+                url: url.clone(),
+            }]
+        );
+        events.lock().unwrap().clear();
+
+        // Deleting a page that doesn't exist shouldn't report anything.
+        delete_place_by_guid(&conn, &guid)?;
+        assert!(events.lock().unwrap().is_empty());
+
+        assert!(api.unregister_history_observer(id));
+        assert!(!api.unregister_history_observer(id));
+        apply_observation(
+            &conn,
+            VisitObservation::new(url.clone()).with_visit_type(VisitTransition::Link),
+        )?;
+        assert!(events.lock().unwrap().is_empty());
+        Ok(())
+    }
 }
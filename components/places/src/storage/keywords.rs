@@ -0,0 +1,237 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use super::fetch_page_info;
+use crate::db::PlacesDb;
+use crate::error::{InvalidPlaceInfo, Result};
+use sql_support::ConnExt;
+use url::Url;
+
+/// Normalizes a keyword the same way as Desktop, by trimming and lowercasing
+/// it. Returns `None` if the keyword is empty, or contains whitespace.
+pub fn normalize_keyword(keyword: &str) -> Option<String> {
+    let k = keyword.trim();
+    if k.is_empty() || k.find(char::is_whitespace).is_some() {
+        None
+    } else {
+        Some(k.to_lowercase())
+    }
+}
+
+/// Flags all bookmarks for URLs whose keywords are about to change for
+/// upload, since keywords are synced as part of the bookmark record.
+fn bump_change_counters(db: &PlacesDb, keyword: &str, place_id: i64) -> Result<()> {
+    db.execute_named_cached(
+        "UPDATE moz_bookmarks SET
+             syncChangeCounter = syncChangeCounter + 1
+         WHERE fk IN (SELECT place_id FROM moz_keywords
+                      WHERE keyword = :keyword OR
+                            place_id = :place_id)
+            OR fk = :place_id",
+        &[(":keyword", &keyword), (":place_id", &place_id)],
+    )?;
+    Ok(())
+}
+
+/// Sets the keyword for the specified URL. A URL can only have one keyword,
+/// and a keyword can only be used for one URL, so this replaces the URL's
+/// existing keyword, and removes the keyword from any other URL.
+///
+/// # Arguments
+///
+/// * `conn` - A database connection on which to operate.
+///
+/// * `url` - The URL for the keyword. This can contain `%s`, which is replaced
+///   with the rest of the search string when the keyword is used.
+///
+/// * `keyword` - The keyword.
+///
+/// # Returns
+///
+/// There is no success return value.
+pub fn set_keyword(db: &PlacesDb, url: &Url, keyword: &str) -> Result<()> {
+    let tx = db.coop_transaction()?;
+    set_keyword_in_tx(db, url, keyword)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Sets the keyword for the specified URL inside an existing transaction.
+pub(crate) fn set_keyword_in_tx(db: &PlacesDb, url: &Url, keyword: &str) -> Result<()> {
+    let keyword = normalize_keyword(keyword).ok_or(InvalidPlaceInfo::InvalidKeyword)?;
+
+    // Like tags, this function will not create a new place.
+    let place_id = match fetch_page_info(db, url)? {
+        Some(info) => info.page.row_id.0,
+        None => return Err(InvalidPlaceInfo::NoSuchUrl.into()),
+    };
+
+    bump_change_counters(db, &keyword, place_id)?;
+    db.execute_named_cached(
+        "DELETE FROM moz_keywords
+         WHERE keyword = :keyword OR
+               place_id = :place_id",
+        &[(":keyword", &keyword), (":place_id", &place_id)],
+    )?;
+    db.execute_named_cached(
+        "INSERT INTO moz_keywords(keyword, place_id)
+         VALUES(:keyword, :place_id)",
+        &[(":keyword", &keyword), (":place_id", &place_id)],
+    )?;
+    Ok(())
+}
+
+/// Removes the specified keyword.
+///
+/// # Arguments
+///
+/// * `conn` - A database connection on which to operate.
+///
+/// * `keyword` - The keyword to remove.
+///
+/// # Returns
+///
+/// There is no success return value - the operation is ignored if the keyword
+/// doesn't exist.
+pub fn remove_keyword(db: &PlacesDb, keyword: &str) -> Result<()> {
+    let keyword = match normalize_keyword(keyword) {
+        Some(keyword) => keyword,
+        None => return Ok(()),
+    };
+    let tx = db.coop_transaction()?;
+    db.execute_named_cached(
+        "UPDATE moz_bookmarks SET
+             syncChangeCounter = syncChangeCounter + 1
+         WHERE fk = (SELECT place_id FROM moz_keywords
+                     WHERE keyword = :keyword)",
+        &[(":keyword", &keyword)],
+    )?;
+    db.execute_named_cached(
+        "DELETE FROM moz_keywords
+         WHERE keyword = :keyword",
+        &[(":keyword", &keyword)],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Retrieves the keyword for the specified URL.
+///
+/// # Arguments
+///
+/// * `conn` - A database connection on which to operate.
+///
+/// * `url` - The URL to query.
+///
+/// # Returns
+///
+/// * The keyword, or `None` if the URL doesn't have one.
+pub fn get_keyword_for_url(db: &PlacesDb, url: &Url) -> Result<Option<String>> {
+    Ok(db.try_query_one(
+        "SELECT k.keyword
+         FROM moz_keywords k
+         JOIN moz_places h ON h.id = k.place_id
+         WHERE h.url_hash = hash(:url) AND h.url = :url",
+        &[(":url", &url.as_str())],
+        true,
+    )?)
+}
+
+/// Retrieves the URL for the specified keyword.
+///
+/// # Arguments
+///
+/// * `conn` - A database connection on which to operate.
+///
+/// * `keyword` - The keyword to query.
+///
+/// # Returns
+///
+/// * The URL, including any `%s` placeholders, or `None` if the keyword
+///   doesn't exist.
+pub fn get_url_for_keyword(db: &PlacesDb, keyword: &str) -> Result<Option<Url>> {
+    let keyword = match normalize_keyword(keyword) {
+        Some(keyword) => keyword,
+        None => return Ok(None),
+    };
+    let href = db.try_query_one::<String>(
+        "SELECT h.url
+         FROM moz_keywords k
+         JOIN moz_places h ON h.id = k.place_id
+         WHERE k.keyword = :keyword",
+        &[(":keyword", &keyword)],
+        true,
+    )?;
+    Ok(href.map(|href| Url::parse(&href)).transpose()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::new_page_info;
+
+    #[test]
+    fn test_normalize_keyword() {
+        assert_eq!(normalize_keyword(" W "), Some("w".to_string()));
+        assert_eq!(normalize_keyword("Wiki"), Some("wiki".to_string()));
+        assert_eq!(normalize_keyword(""), None);
+        assert_eq!(normalize_keyword("   "), None);
+        assert_eq!(normalize_keyword("w p"), None);
+    }
+
+    #[test]
+    fn test_keywords() {
+        let conn = new_mem_connection();
+        let url1 = Url::parse("https://en.wikipedia.org/wiki/%s").expect("valid url");
+        let url2 = Url::parse("https://duckduckgo.com/?q=%s").expect("valid url");
+        let url3 = Url::parse("https://example.com/").expect("valid url");
+
+        new_page_info(&conn, &url1, None).expect("should create the page");
+        new_page_info(&conn, &url2, None).expect("should create the page");
+
+        set_keyword(&conn, &url3, "e").expect_err("should fail for unknown URLs");
+        set_keyword(&conn, &url1, "w p").expect_err("should fail for invalid keywords");
+
+        set_keyword(&conn, &url1, "W").expect("should work");
+        assert_eq!(
+            get_keyword_for_url(&conn, &url1).expect("should work"),
+            Some("w".to_string())
+        );
+        assert_eq!(
+            get_url_for_keyword(&conn, "w").expect("should work"),
+            Some(url1.clone())
+        );
+
+        // Setting a new keyword replaces the old one.
+        set_keyword(&conn, &url1, "wiki").expect("should work");
+        assert_eq!(get_url_for_keyword(&conn, "w").expect("should work"), None);
+        assert_eq!(
+            get_url_for_keyword(&conn, "wiki").expect("should work"),
+            Some(url1.clone())
+        );
+
+        // Using the keyword for another URL moves it.
+        set_keyword(&conn, &url2, "wiki").expect("should work");
+        assert_eq!(
+            get_keyword_for_url(&conn, &url1).expect("should work"),
+            None
+        );
+        assert_eq!(
+            get_url_for_keyword(&conn, "wiki").expect("should work"),
+            Some(url2.clone())
+        );
+
+        remove_keyword(&conn, "WIKI").expect("should work");
+        assert_eq!(
+            get_keyword_for_url(&conn, &url2).expect("should work"),
+            None
+        );
+        assert_eq!(
+            get_url_for_keyword(&conn, "wiki").expect("should work"),
+            None
+        );
+        remove_keyword(&conn, "wiki").expect("removing a missing keyword should work");
+    }
+}
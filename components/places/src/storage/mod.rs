@@ -6,11 +6,14 @@
 // API and the database.
 
 pub mod bookmarks;
+pub mod expiration;
 pub mod history;
+pub mod keywords;
 pub mod tags;
 
 use crate::db::PlacesDb;
 use crate::error::{ErrorKind, InvalidPlaceInfo, Result};
+use crate::maintenance::BookmarkRepairs;
 use crate::msg_types::HistoryVisitInfo;
 use crate::types::{SyncGuid, SyncStatus, Timestamp, VisitTransition};
 use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
@@ -145,7 +148,11 @@ fn fetch_page_info(db: &PlacesDb, url: &Url) -> Result<Option<FetchedPageInfo>>
     )?)
 }
 
-fn new_page_info(db: &PlacesDb, url: &Url, new_guid: Option<SyncGuid>) -> Result<PageInfo> {
+pub(crate) fn new_page_info(
+    db: &PlacesDb,
+    url: &Url,
+    new_guid: Option<SyncGuid>,
+) -> Result<PageInfo> {
     let guid = match new_guid {
         Some(guid) => guid,
         None => SyncGuid::new(),
@@ -192,9 +199,15 @@ impl HistoryVisitInfo {
     }
 }
 
-pub fn run_maintenance(conn: &PlacesDb) -> Result<()> {
+/// Repairs the bookmarks tree, then vacuums and optimizes the database.
+/// Returns what was wrong with the bookmarks tree.
+pub fn run_maintenance(conn: &PlacesDb) -> Result<BookmarkRepairs> {
+    let repairs = crate::maintenance::check_and_repair_bookmarks(conn)?;
+    if !repairs.is_empty() {
+        log::warn!("Repaired corrupt bookmarks: {:?}", repairs);
+    }
     conn.execute_all(&["VACUUM", "PRAGMA optimize"])?;
-    Ok(())
+    Ok(repairs)
 }
 
 pub(crate) fn put_meta(db: &PlacesDb, key: &str, value: &ToSql) -> Result<()> {
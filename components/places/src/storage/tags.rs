@@ -69,8 +69,15 @@ pub fn validate_tag(tag: &str) -> ValidatedTag {
 ///
 /// There is no success return value.
 pub fn tag_url(db: &PlacesDb, url: &Url, tag: &str) -> Result<()> {
-    let tag = validate_tag(&tag).ensure_valid()?;
     let tx = db.coop_transaction()?;
+    tag_url_in_tx(db, url, tag)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Tags the specified URL inside an existing transaction.
+pub(crate) fn tag_url_in_tx(db: &PlacesDb, url: &Url, tag: &str) -> Result<()> {
+    let tag = validate_tag(&tag).ensure_valid()?;
 
     // This function will not create a new place.
     // Fetch the place id, so we (a) avoid creating a new tag when we aren't
@@ -91,7 +98,6 @@ pub fn tag_url(db: &PlacesDb, url: &Url, tag: &str) -> Result<()> {
          VALUES((SELECT id FROM moz_tags WHERE tag = :tag), :place_id)",
         &[(":tag", &tag), (":place_id", &place_id)],
     )?;
-    tx.commit()?;
     Ok(())
 }
 
@@ -166,6 +172,66 @@ pub fn remove_tag(db: &PlacesDb, tag: &str) -> Result<()> {
     Ok(())
 }
 
+/// Renames a tag for all URLs which have it. If a tag with the new name
+/// already exists, the two tags are merged.
+///
+/// # Arguments
+///
+/// * `conn` - A database connection on which to operate.
+///
+/// * `old_tag` - The tag to rename.
+///
+/// * `new_tag` - The new name for the tag.
+///
+/// # Returns
+///
+/// There is no success return value - the operation is ignored if no URLs
+/// have the old tag.
+pub fn rename_tag(db: &PlacesDb, old_tag: &str, new_tag: &str) -> Result<()> {
+    let old_tag = validate_tag(&old_tag).ensure_valid()?;
+    let new_tag = validate_tag(&new_tag).ensure_valid()?;
+    if old_tag == new_tag {
+        return Ok(());
+    }
+    let tx = db.coop_transaction()?;
+
+    let new_tag_exists = db
+        .try_query_one::<i64>(
+            "SELECT id FROM moz_tags WHERE tag = :tag",
+            &[(":tag", &new_tag)],
+            true,
+        )?
+        .is_some();
+
+    if new_tag_exists {
+        // Move the URLs over to the existing tag, then remove the old one,
+        // which also removes its remaining relations.
+        db.execute_named_cached(
+            "INSERT OR IGNORE INTO moz_tags_relation(tag_id, place_id)
+             SELECT (SELECT id FROM moz_tags WHERE tag = :new_tag), r.place_id
+             FROM moz_tags_relation r
+             JOIN moz_tags t ON t.id = r.tag_id
+             WHERE t.tag = :old_tag",
+            &[(":old_tag", &old_tag), (":new_tag", &new_tag)],
+        )?;
+        db.execute_named_cached(
+            "DELETE FROM moz_tags
+             WHERE tag = :old_tag",
+            &[(":old_tag", &old_tag)],
+        )?;
+    } else {
+        db.execute_named_cached(
+            "UPDATE moz_tags SET
+                 tag = :new_tag,
+                 lastModified = now()
+             WHERE tag = :old_tag",
+            &[(":old_tag", &old_tag), (":new_tag", &new_tag)],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
 /// Retrieves a list of URLs which have the specified tag.
 ///
 /// # Arguments
@@ -352,6 +418,21 @@ mod tests {
         );
         assert_eq!(count.unwrap().unwrap(), 0);
 
+        // renaming merges into an existing tag.
+        tag_url(&conn, &url1, "tag-1").expect("should work");
+        tag_url(&conn, &url2, "tag-2").expect("should work");
+        rename_tag(&conn, "tag-1", "renamed").expect("should work");
+        check_urls_with_tag(&conn, "tag-1", vec![]);
+        check_urls_with_tag(&conn, "renamed", vec![url1.clone()]);
+        rename_tag(&conn, "tag-2", "renamed").expect("should work");
+        check_urls_with_tag(&conn, "tag-2", vec![]);
+        check_urls_with_tag(&conn, "renamed", vec![url1.clone(), url2.clone()]);
+        check_tags_for_url(&conn, &url1, vec!["renamed".to_string()]);
+        assert_eq!(get_foreign_count(&conn, &url1), 1);
+        assert_eq!(get_foreign_count(&conn, &url2), 1);
+        rename_tag(&conn, "renamed", "foo bar").expect_err("should be invalid");
+        remove_tag(&conn, "renamed").expect("should work");
+
         // places should still exist.
         fetch_page_info(&conn, &url1)
             .expect("should work")
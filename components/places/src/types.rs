@@ -223,6 +223,56 @@ impl<'de> serde::Deserialize<'de> for VisitTransition {
     }
 }
 
+/// A set of `VisitTransition`s. This is stored as a bitfield, where the bit
+/// for each transition is `1 << (transition as u8)`, so it can be passed over
+/// the FFI as an integer, and checked in SQL with
+/// `(1 << visit_type) & :set`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct VisitTransitionSet {
+    bits: u16,
+}
+
+impl VisitTransitionSet {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Returns a set from its bitfield, ignoring any bits that don't
+    /// correspond to a transition.
+    pub fn from_u16(bits: u16) -> Self {
+        let all = (1..=9).fold(0u16, |all, t| all | (1 << t));
+        Self { bits: bits & all }
+    }
+
+    pub fn into_u16(self) -> u16 {
+        self.bits
+    }
+
+    pub fn insert(&mut self, transition: VisitTransition) {
+        self.bits |= 1 << (transition as u8);
+    }
+
+    pub fn contains(self, transition: VisitTransition) -> bool {
+        self.bits & (1 << (transition as u8)) != 0
+    }
+}
+
+impl std::iter::FromIterator<VisitTransition> for VisitTransitionSet {
+    fn from_iter<I: IntoIterator<Item = VisitTransition>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        for transition in iter {
+            set.insert(transition);
+        }
+        set
+    }
+}
+
+impl ToSql for VisitTransitionSet {
+    fn to_sql(&self) -> RusqliteResult<ToSqlOutput> {
+        Ok(ToSqlOutput::from(i64::from(self.bits)))
+    }
+}
+
 /// Bookmark types.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 #[repr(u8)]
@@ -337,4 +387,21 @@ mod tests {
         );
         assert_eq!(None, VisitTransition::from_primitive(99));
     }
+
+    #[test]
+    fn test_visit_transition_set() {
+        let set: VisitTransitionSet = vec![VisitTransition::Link, VisitTransition::Reload]
+            .into_iter()
+            .collect();
+        assert!(set.contains(VisitTransition::Link));
+        assert!(set.contains(VisitTransition::Reload));
+        assert!(!set.contains(VisitTransition::Typed));
+        assert_eq!(set.into_u16(), (1 << 1) | (1 << 9));
+        assert_eq!(VisitTransitionSet::from_u16(set.into_u16()), set);
+        // Unknown transitions are ignored.
+        assert_eq!(
+            VisitTransitionSet::from_u16(1 | (1 << 10)),
+            VisitTransitionSet::empty()
+        );
+    }
 }
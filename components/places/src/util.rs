@@ -18,6 +18,22 @@ pub fn slice_up_to(s: &str, max_len: usize) -> &str {
     &s[..idx]
 }
 
+/// Appends a suffix to a path, like SQLite does for the `-wal` and `-shm`
+/// files next to a database.
+pub(crate) fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Removes the file at `path`, if there is one.
+pub(crate) fn remove_file_if_exists(path: &Path) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
 /// `Path` is basically just a `str` with no validation, and so in practice it
 /// could contain a file URL. Rusqlite takes advantage of this a bit, and says
 /// `AsRef<Path>` but really means "anything sqlite can take as an argument".
@@ -9,12 +9,14 @@ mod each_chunk;
 mod maybe_cached;
 mod query_plan;
 mod repeat;
+mod sqlcipher;
 
 pub use crate::conn_ext::*;
 pub use crate::each_chunk::*;
 pub use crate::maybe_cached::*;
 pub use crate::query_plan::*;
 pub use crate::repeat::*;
+pub use crate::sqlcipher::*;
 
 /// In PRAGMA foo='bar', `'bar'` must be a constant string (it cannot be a
 /// bound parameter), so we need to escape manually. According to
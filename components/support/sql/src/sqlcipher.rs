@@ -0,0 +1,44 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::conn_ext::ConnExt;
+use rusqlite::{Connection, Result as SqlResult};
+use std::path::Path;
+
+/// Copies the database open on `conn` into a new database at `path`, using
+/// SQLCipher's `sqlcipher_export`. This is the only way to encrypt or decrypt
+/// an existing database. The new database is encrypted with
+/// `encryption_key`, or unencrypted if it's `None`.
+///
+/// `pragmas`, like `"cipher_page_size = 4096"`, are applied to the new
+/// database before anything is written to it, since settings like the page
+/// size of an encrypted database can't be changed later. They should match
+/// the pragmas used to open the database. The schema version is copied,
+/// too, since `sqlcipher_export` doesn't copy it.
+pub fn export_database(
+    conn: &Connection,
+    path: &Path,
+    encryption_key: Option<&str>,
+    pragmas: &[&str],
+) -> SqlResult<()> {
+    let path = path.to_string_lossy().into_owned();
+    let export_pragmas = pragmas
+        .iter()
+        .map(|pragma| format!("PRAGMA export.{};", pragma))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let user_version = conn.query_one::<i64>("PRAGMA user_version")?;
+    conn.execute_named(
+        "ATTACH DATABASE :path AS export KEY :key",
+        &[(":path", &path), (":key", &encryption_key.unwrap_or(""))],
+    )?;
+    let result = conn.execute_batch(&format!(
+        "{}
+         SELECT sqlcipher_export('export');
+         PRAGMA export.user_version = {};",
+        export_pragmas, user_version
+    ));
+    conn.execute_batch("DETACH DATABASE export")?;
+    result
+}